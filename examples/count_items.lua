@@ -0,0 +1,21 @@
+-- Structure test that checks the generated object has one item per line of the test's description.
+-- Written for the default LUA_INPUT=string, where test receives the raw candidate and the context table.
+function test(_, ctx)
+    local value = ctx.value
+    if typeof(value) ~= "object" then
+        return false, "expected a JSON object"
+    end
+    if typeof(value.items) ~= "array" then
+        return false, "missing array field 'items'"
+    end
+    local lines = 0
+    for line in ctx.input:gmatch("[^\n]+") do
+        if line:match("%S") then
+            lines = lines + 1
+        end
+    end
+    if #value.items ~= lines then
+        return false, string.format("expected %d items, one per description line, got %d", lines, #value.items)
+    end
+    return true
+end
@@ -0,0 +1,26 @@
+-- Structure test shared across suites that applies stricter rules to tests tagged "strict".
+-- The context table is built per call, so the name and tags always belong to the test being checked.
+local function has_tag(ctx, tag)
+    for _, t in ipairs(ctx.tags) do
+        if t == tag then
+            return true
+        end
+    end
+    return false
+end
+
+function test(raw, ctx)
+    assert(type(ctx.name) == "string" and ctx.name ~= "", "context is missing the test name")
+    local ok, value = pcall(json.decode, raw)
+    if not ok then
+        return false, "not valid JSON: " .. value
+    end
+    if has_tag(ctx, "strict") and typeof(value) == "object" then
+        for key, v in pairs(value) do
+            if v == json.null then
+                return false, string.format("%s: strict tests may not contain null fields (%s)", ctx.name, key)
+            end
+        end
+    end
+    return true
+end
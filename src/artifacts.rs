@@ -0,0 +1,68 @@
+use std::{fs, future::Future, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use async_openai::types::CreateChatCompletionRequest;
+use crate::{error::AppError, generation::Generation, output, redact::Redactor};
+
+tokio::task_local! {
+    /// The files of the test being run, when ARTIFACTS is on.
+    static CURRENT: Arc<Mutex<Vec<(String, String)>>>;
+}
+
+/// Runs a test's future with its artifacts collected, returning them with its output as file names and contents, in
+/// the order they were made.
+pub async fn collect<T>(test: impl Future<Output = T>) -> (T, Vec<(String, String)>) {
+    let files = Arc::new(Mutex::new(Vec::new()));
+    let output = CURRENT.scope(files.clone(), test).await;
+    let files = std::mem::take(&mut *files.lock().unwrap());
+    (output, files)
+}
+
+/// Keeps `contents` as the current test's `name`, numbered `name-2`, `name-3` and on for each repeat, such as another
+/// candidate or another judge. Outside a test, or with ARTIFACTS off, it is dropped.
+pub fn record(name: &str, contents: impl Into<String>) {
+    let _ = CURRENT.try_with(|files| {
+        let mut files = files.lock().unwrap();
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+        let mut unique = name.to_string();
+        for n in 2.. {
+            if !files.iter().any(|(f, _)| *f == unique) {
+                break;
+            }
+            unique = format!("{}-{}.{}", stem, n, extension);
+        }
+        files.push((unique, contents.into()));
+    });
+}
+
+/// Keeps a chat request as `<stage>_prompt.md`, each message under its role, and the response as
+/// `<stage>_response.json` with every choice, the usage and the response id.
+pub fn exchange(stage: &str, req: &CreateChatCompletionRequest, response: Option<&Generation>) {
+    if CURRENT.try_with(|_| ()).is_err() {
+        return;
+    }
+    let prompt: Vec<String> = req.messages.iter().map(|message| {
+        let (role, text) = output::message_text(message);
+        format!("## {}\n\n{}\n", role, text)
+    }).collect();
+    record(&format!("{}_prompt.md", stage), prompt.join("\n"));
+    if let Some(response) = response {
+        record(&format!("{}_response.json", stage), serde_json::to_string_pretty(response).unwrap_or_default());
+    }
+}
+
+/// The directory of a test's artifacts under `dir`: its name, with a structured test's `#` case made a path segment
+/// and anything outside letters, digits and `-_.` replaced.
+pub fn test_dir(dir: &Path, name: &str) -> PathBuf {
+    let safe: String = name.chars().map(|c| if c.is_alphanumeric() || "-_./".contains(c) { c } else if c == '#' { '/' } else { '_' }).collect();
+    safe.split('/').filter(|s| !s.is_empty() && *s != "." && *s != "..").fold(dir.to_path_buf(), |path, segment| path.join(segment))
+}
+
+/// Writes a test's artifacts into its directory under `dir`, redacted, returning the directory.
+pub fn write(dir: &Path, name: &str, files: &[(String, String)], redactor: &Redactor) -> Result<PathBuf, AppError> {
+    let path = test_dir(dir, name);
+    fs::create_dir_all(&path).map_err(AppError::io(path.display().to_string()))?;
+    for (file, contents) in files {
+        let file = path.join(file);
+        fs::write(&file, redactor.redact(contents).as_bytes()).map_err(AppError::io(file.display().to_string()))?;
+    }
+    Ok(path)
+}
@@ -0,0 +1,225 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+use async_openai::{config::Config, error::OpenAIError, types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart, ChatCompletionToolChoiceOption, CreateChatCompletionRequest, CreateEmbeddingRequest, EmbeddingInput, FinishReason, FunctionCall}, Client};
+use futures::{future::BoxFuture, FutureExt};
+use crate::generation::{self, GenChoice, Generation};
+
+/// The model API the runner talks to: the PROVIDER's client for real runs (see [`crate::provider`]), [`ScriptedBackend`]
+/// for DRY_RUN.
+pub trait ChatBackend: Send + Sync {
+    /// Sends a chat request, streamed when `stream` is given.
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>>;
+
+    /// The embedding of each input, in input order.
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>>;
+}
+
+/// How a chat request is streamed.
+#[derive(Clone, Copy)]
+pub struct Stream<'a> {
+    /// The first choice is printed to the console under this label as it arrives, when there is one.
+    pub label: Option<&'a str>,
+    /// The request fails when this long passes without a chunk, the first included (STREAM_IDLE_TIMEOUT_MS).
+    pub idle_timeout: Option<Duration>,
+}
+
+impl<C: Config + Send + Sync> ChatBackend for Client<C> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        generation::generate(self, req, stream).boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move {
+            let mut data = self.embeddings().create(req).await?.data;
+            data.sort_by_key(|e| e.index);
+            Ok(data.into_iter().map(|e| e.embedding).collect())
+        }.boxed()
+    }
+}
+
+/// Answers chat requests with scripted replies in order, then with `fallback` once they run out. Every request gets as
+/// many identical choices as it asks for, and every input embeds to the same vector.
+pub struct ScriptedBackend {
+    replies: Mutex<VecDeque<String>>,
+    fallback: String,
+    generating: Option<Generating>,
+    finish_reason: FinishReason,
+    /// Whether responses come with no choices at all.
+    empty: bool,
+}
+
+/// A reply for every request whose prompt fits a generation prompt template.
+struct Generating {
+    prefix: String,
+    suffix: String,
+    reply: String,
+    /// Replies to conversations whose last user turn is the first of the pair.
+    turns: Vec<(String, String)>,
+}
+
+impl ScriptedBackend {
+    pub fn new(replies: impl IntoIterator<Item = String>, fallback: String) -> ScriptedBackend {
+        ScriptedBackend { replies: Mutex::new(replies.into_iter().collect()), fallback, generating: None, finish_reason: FinishReason::Stop, empty: false }
+    }
+
+    /// Ends every choice with `reason` instead of `stop`, as a reply cut off at GEN_MAX_TOKENS is with `length`.
+    pub fn finishing(self, reason: FinishReason) -> ScriptedBackend {
+        ScriptedBackend { finish_reason: reason, ..self }
+    }
+
+    /// Answers every chat request with a response that has no choices, as some providers do on an overloaded or
+    /// filtered request.
+    pub fn without_choices(self) -> ScriptedBackend {
+        ScriptedBackend { empty: true, ..self }
+    }
+
+    /// Answers every request made from `gen_prompt`, whatever its `__description__`, with `reply` instead of the
+    /// scripted replies, however many generation requests a test makes.
+    pub fn generating(self, gen_prompt: &str, reply: String) -> ScriptedBackend {
+        let (prefix, suffix) = gen_prompt.split_once("__description__").unwrap_or((gen_prompt, ""));
+        ScriptedBackend { generating: Some(Generating { prefix: prefix.to_string(), suffix: suffix.to_string(), reply, turns: Vec::new() }), ..self }
+    }
+
+    /// Answers the generation requests of a conversation whose last user turn is `user` with `reply`, after a call to
+    /// [`ScriptedBackend::generating`].
+    pub fn replying(mut self, user: &str, reply: String) -> ScriptedBackend {
+        if let Some(g) = &mut self.generating {
+            g.turns.push((user.to_string(), reply));
+        }
+        self
+    }
+
+    fn reply(&self, req: &CreateChatCompletionRequest) -> String {
+        let prompt = req.messages.iter().find_map(user_text);
+        match (&self.generating, prompt) {
+            (Some(g), Some(prompt)) if prompt.len() >= g.prefix.len() + g.suffix.len() && prompt.starts_with(&g.prefix) && prompt.ends_with(&g.suffix) => {
+                let last = req.messages.last().and_then(user_text).filter(|_| req.messages.len() > 1);
+                g.turns.iter().find(|(user, _)| Some(user.as_str()) == last).map_or(&g.reply, |(_, reply)| reply).clone()
+            }
+            _ => self.replies.lock().unwrap().pop_front().unwrap_or_else(|| self.fallback.clone()),
+        }
+    }
+}
+
+/// A scripted reply to a request with tools as the calls it describes: a JSON array of `{"name", "arguments"}` objects,
+/// as a test with `<tools>` expects, or the arguments of the one tool the request names.
+fn scripted_calls(req: &CreateChatCompletionRequest, reply: &str) -> Vec<FunctionCall> {
+    if req.tools.is_none() {
+        return Vec::new();
+    }
+    if let Ok(calls) = serde_json::from_str::<Vec<serde_json::Value>>(reply) {
+        return calls.iter().filter_map(|c| Some(FunctionCall { name: c["name"].as_str()?.to_string(), arguments: c["arguments"].to_string() })).collect();
+    }
+    match &req.tool_choice {
+        Some(ChatCompletionToolChoiceOption::Named(named)) => vec![FunctionCall { name: named.function.name.clone(), arguments: reply.to_string() }],
+        _ => Vec::new(),
+    }
+}
+
+fn user_text(message: &ChatCompletionRequestMessage) -> Option<&str> {
+    match message {
+        ChatCompletionRequestMessage::User(user) => match &user.content {
+            ChatCompletionRequestUserMessageContent::Text(text) => Some(text.as_str()),
+            ChatCompletionRequestUserMessageContent::Array(parts) => parts.iter().find_map(|p| match p {
+                ChatCompletionRequestUserMessageContentPart::Text(t) => Some(t.text.as_str()),
+                _ => None,
+            }),
+        },
+        _ => None,
+    }
+}
+
+impl ChatBackend for ScriptedBackend {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, _stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        let reply = self.reply(&req);
+        let tool_calls = scripted_calls(&req, &reply);
+        let content = Some(reply).filter(|_| tool_calls.is_empty());
+        let n = if self.empty { 0 } else { req.n.unwrap_or(1) };
+        let choices = (0..n).map(|_| GenChoice { content: content.clone(), tool_calls: tool_calls.clone(), finish_reason: Some(self.finish_reason), ..Default::default() }).collect();
+        async move { Ok(Generation { choices, usage: None, id: None, retries: 0, first_token: None, fallback: None }) }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        let count = match req.input {
+            EmbeddingInput::String(_) => 1,
+            EmbeddingInput::StringArray(inputs) => inputs.len(),
+            EmbeddingInput::IntegerArray(_) => 1,
+            EmbeddingInput::ArrayOfIntegerArray(inputs) => inputs.len(),
+        };
+        async move { Ok(vec![vec![1.0, 0.0]; count]) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionToolArgs, CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs, FunctionObjectArgs};
+    use futures::executor::block_on;
+    use super::*;
+
+    fn request(turns: &[&str], n: u8) -> CreateChatCompletionRequest {
+        let messages: Vec<ChatCompletionRequestMessage> = turns.iter().enumerate().map(|(i, text)| if i % 2 == 0 {
+            ChatCompletionRequestUserMessageArgs::default().content(*text).build().unwrap().into()
+        } else {
+            ChatCompletionRequestAssistantMessageArgs::default().content(*text).build().unwrap().into()
+        }).collect();
+        CreateChatCompletionRequestArgs::default().model("scripted").messages(messages).n(n).build().unwrap()
+    }
+
+    fn contents(generation: &Generation) -> Vec<&str> {
+        generation.choices.iter().map(|c| c.content.as_deref().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn replies_in_order_then_with_the_fallback() {
+        let backend = ScriptedBackend::new(["one".to_string(), "two".to_string()], "rest".to_string());
+        let replies: Vec<String> = (0..4).map(|_| block_on(backend.complete(request(&["hi"], 1), None)).unwrap().choices[0].content.clone().unwrap()).collect();
+        assert_eq!(replies, ["one", "two", "rest", "rest"]);
+    }
+
+    #[test]
+    fn gives_as_many_choices_as_asked_for() {
+        let backend = ScriptedBackend::new(["one".to_string()], "rest".to_string());
+        let generation = block_on(backend.complete(request(&["hi"], 3), None)).unwrap();
+        assert_eq!(contents(&generation), ["one", "one", "one"]);
+        assert!(generation.choices.iter().all(|c| c.finish_reason == Some(FinishReason::Stop)));
+    }
+
+    #[test]
+    fn answers_generation_prompts_apart_from_the_scripted_replies() {
+        let backend = ScriptedBackend::new(["judged".to_string()], "fallback".to_string())
+            .generating("Summarize: __description__.", "summary".to_string())
+            .replying("and now in French", "résumé".to_string());
+        assert_eq!(contents(&block_on(backend.complete(request(&["Summarize: a long text."], 1), None)).unwrap()), ["summary"]);
+        assert_eq!(contents(&block_on(backend.complete(request(&["Summarize: a long text.", "summary", "and now in French"], 1), None)).unwrap()), ["résumé"]);
+        assert_eq!(contents(&block_on(backend.complete(request(&["Is this right?"], 1), None)).unwrap()), ["judged"]);
+        assert_eq!(contents(&block_on(backend.complete(request(&["Summarize: another."], 1), None)).unwrap()), ["summary"]);
+    }
+
+    #[test]
+    fn scripts_truncated_and_empty_responses() {
+        let truncated = ScriptedBackend::new([], "{\"a\": ".to_string()).finishing(FinishReason::Length);
+        let generation = block_on(truncated.complete(request(&["hi"], 2), None)).unwrap();
+        assert!(generation.choices.iter().all(|c| c.finish_reason == Some(FinishReason::Length)));
+        assert_eq!(contents(&generation), ["{\"a\": ", "{\"a\": "]);
+        let empty = ScriptedBackend::new([], "unused".to_string()).without_choices();
+        assert!(block_on(empty.complete(request(&["hi"], 2), None)).unwrap().choices.is_empty());
+    }
+
+    #[test]
+    fn replies_to_requests_with_tools_as_tool_calls() {
+        let tool = ChatCompletionToolArgs::default().function(FunctionObjectArgs::default().name("lookup").build().unwrap()).build().unwrap();
+        let mut req = request(&["hi"], 1);
+        req.tools = Some(vec![tool]);
+        let backend = ScriptedBackend::new([r#"[{"name": "lookup", "arguments": {"id": 7}}]"#.to_string()], String::new());
+        let choice = block_on(backend.complete(req, None)).unwrap().choices.remove(0);
+        assert_eq!(choice.content, None);
+        assert_eq!(choice.tool_calls.len(), 1);
+        assert_eq!((choice.tool_calls[0].name.as_str(), choice.tool_calls[0].arguments.as_str()), ("lookup", r#"{"id":7}"#));
+    }
+
+    #[test]
+    fn embeds_every_input_alike() {
+        let backend = ScriptedBackend::new([], String::new());
+        let req = CreateEmbeddingRequestArgs::default().model("embed").input(vec!["a", "b", "c"]).build().unwrap();
+        assert_eq!(block_on(backend.embed(req)).unwrap(), vec![vec![1.0, 0.0]; 3]);
+    }
+}
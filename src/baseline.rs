@@ -0,0 +1,99 @@
+use std::{fs, path::Path};
+use inline_colorization::*;
+use crate::{client, compare, discover, is_case, select, dry_run_backend, error::AppError, generate_baseline, snapshot, test_case, test_file, ErrorLocation, RunConfig, RunError};
+
+/// Where `--update-baselines` puts a new baseline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Nowhere: only the diffs are shown (`--dry-run`).
+    None,
+    /// The test's `<output>` section.
+    TestFile,
+    /// A pending snapshot next to the test, for `accept` or `reject` (`--snapshot`).
+    Snapshot,
+}
+
+/// The answer to whether a new baseline is written (`--confirm`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confirm {
+    Yes,
+    No,
+    /// No, and no more tests are regenerated.
+    Quit,
+}
+
+/// What `--update-baselines` did with one test.
+pub enum BaselineUpdate {
+    /// The baseline changed; `diff` is from the old one to the new, and `written` is whether it went to the target.
+    Changed { name: String, diff: String, written: bool },
+    Unchanged { name: String },
+    /// No candidate passed the structure test, so the file was left alone.
+    Failed { name: String, location: ErrorLocation, error: Option<String> },
+}
+
+/// Regenerates the `<output>` section of every test from the first candidate that passes the structure test and, for
+/// each that changed and `confirm` agrees to, writes it to `target`. In a test file only the section's contents change;
+/// the rest of the file is kept byte for byte. The cases of structured test files are compared but never written.
+pub async fn update(config: &RunConfig, target: Target, mut confirm: impl FnMut(&str, &str) -> Result<Confirm, AppError>) -> Result<Vec<BaselineUpdate>, RunError> {
+    let client = client(config);
+    let (tests, skipped) = discover(config)?;
+    for s in &skipped {
+        println!("{color_yellow}Skipping {}: {}{color_reset}", s.path, s.reason);
+    }
+    let (tests, _) = select::select(config, tests);
+    let mut updates = Vec::new();
+    for test in tests {
+        let generated = if config.dry_run {
+            generate_baseline(&test, config, &dry_run_backend(&test, config)).await?
+        } else {
+            generate_baseline(&test, config, &*client).await?
+        };
+        let baseline = match generated {
+            Ok(baseline) => baseline,
+            Err(e) => {
+                updates.push(BaselineUpdate::Failed { error: e.message(), location: e.location, name: test.name });
+                continue;
+            }
+        };
+        let Some((old, updated)) = replace_output(&test.contents, &baseline) else {
+            updates.push(BaselineUpdate::Failed { name: test.name, location: ErrorLocation::MatchInput, error: Some("missing <output> section".to_string()) });
+            continue;
+        };
+        if old.trim() == baseline.trim() {
+            updates.push(BaselineUpdate::Unchanged { name: test.name });
+            continue;
+        }
+        if target != Target::None && is_case(&test.name) {
+            let error = Some(format!("the new baseline is not written to {}; edit its output there", test_file(&test.name)));
+            updates.push(BaselineUpdate::Failed { name: test.name, location: ErrorLocation::MatchInput, error });
+            continue;
+        }
+        let diff = compare::diff(old, &baseline);
+        let answer = if target == Target::None { Confirm::No } else { confirm(&test.name, &diff)? };
+        match (answer, target) {
+            (Confirm::Yes, Target::TestFile) => {
+                let path = Path::new(&config.test_dir).join(&test.name);
+                fs::write(&path, updated).map_err(AppError::io(path.display().to_string()))?;
+            }
+            (Confirm::Yes, Target::Snapshot) => snapshot::propose(&config.test_dir, &test.name, &baseline)?,
+            _ => {}
+        }
+        updates.push(BaselineUpdate::Changed { diff, written: answer == Confirm::Yes && target != Target::None, name: test.name });
+        if answer == Confirm::Quit {
+            break;
+        }
+    }
+    Ok(updates)
+}
+
+/// The current contents of the first `<output>` section and the file with them replaced by `baseline`. The new
+/// contents keep the old section's leading and trailing whitespace.
+pub fn replace_output<'a>(contents: &'a str, baseline: &str) -> Option<(&'a str, String)> {
+    let start = test_case::find_tag(contents, "<output>")? + "<output>".len();
+    let end = start + contents[start..].find("</output>")?;
+    let old = &contents[start..end];
+    let leading = &old[..old.len() - old.trim_start().len()];
+    let trailing = &old[old.trim_end().len()..];
+    let trailing = if old.trim().is_empty() { "" } else { trailing };
+    Some((old, format!("{}{}{}{}{}", &contents[..start], leading, baseline.trim(), trailing, &contents[end..])))
+}
@@ -0,0 +1,178 @@
+use std::{mem, sync::Mutex};
+use async_openai::types::ResponseFormat;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use crate::{backend::ChatBackend, error::AppError, judge::{self, JudgeMode, Judgement, TokenUsage}, pipeline::Stage, RunConfig};
+
+/// Shares judge calls between the tests of a run with JUDGE_BATCH_SIZE: up to `size` tests run together, and once each
+/// of them is either waiting for a verdict or done, the waiting pairs go to the judge in one request per model.
+pub struct JudgeBatch {
+    pub size: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Tests of the current group that have not finished yet.
+    live: usize,
+    waiting: Vec<Waiting>,
+}
+
+struct Waiting {
+    test: String,
+    model: String,
+    prompt: String,
+    reply: oneshot::Sender<Verdict>,
+}
+
+/// A pair's share of a batched judge call.
+pub struct Verdict {
+    /// `None` when the batch reply had no single, usable verdict for the pair, which is then judged on its own.
+    pub judgement: Option<Judgement>,
+    pub request_id: Option<String>,
+    pub retries: u32,
+    /// The batch's tokens divided between its pairs.
+    pub tokens: u32,
+    pub usage: TokenUsage,
+    /// The other tests in the same request.
+    pub shared_with: Vec<String>,
+}
+
+impl Verdict {
+    fn none() -> Verdict {
+        Verdict { judgement: None, request_id: None, retries: 0, tokens: 0, usage: TokenUsage::default(), shared_with: Vec::new() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchReply {
+    Wrapped { verdicts: Vec<CaseVerdict> },
+    Bare(Vec<CaseVerdict>),
+}
+
+#[derive(Deserialize)]
+struct CaseVerdict {
+    case: Option<serde_json::Value>,
+    #[serde(alias = "pass", alias = "passed")]
+    verdict: Option<serde_json::Value>,
+    score: Option<serde_json::Value>,
+    #[serde(alias = "rationale")]
+    reason: Option<String>,
+}
+
+impl JudgeBatch {
+    pub fn new(size: usize) -> JudgeBatch {
+        JudgeBatch { size, state: Mutex::new(State::default()) }
+    }
+
+    /// Starts a group of `tests` tests; every one of them must call [`JudgeBatch::leave`] when it is done.
+    pub fn start(&self, tests: usize) {
+        *self.state.lock().unwrap() = State { live: tests, waiting: Vec::new() };
+    }
+
+    /// Marks one test of the group as done, sending the waiting pairs if every remaining test is now waiting.
+    pub async fn leave(&self, config: &RunConfig, client: &dyn ChatBackend) -> Result<(), AppError> {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.live = state.live.saturating_sub(1);
+            state.ready()
+        };
+        send(ready, config, client).await
+    }
+
+    /// The verdict on one judge prompt for `model`, once the batch it joins has been judged. Outside a group the pair is
+    /// not batched and the verdict comes back empty.
+    pub async fn judge(&self, test: &str, model: &str, prompt: &str, config: &RunConfig, client: &dyn ChatBackend) -> Result<Verdict, AppError> {
+        let (tx, rx) = oneshot::channel();
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            if state.live == 0 {
+                return Ok(Verdict::none());
+            }
+            state.waiting.push(Waiting { test: test.to_string(), model: model.to_string(), prompt: prompt.to_string(), reply: tx });
+            state.ready()
+        };
+        send(ready, config, client).await?;
+        // A sender dropped by a failed batch leaves the pair to be judged on its own.
+        Ok(rx.await.unwrap_or_else(|_| Verdict::none()))
+    }
+}
+
+impl State {
+    fn ready(&mut self) -> Vec<Waiting> {
+        if !self.waiting.is_empty() && self.waiting.len() >= self.live { mem::take(&mut self.waiting) } else { Vec::new() }
+    }
+}
+
+/// One request per judge model for the pairs waiting on it. A model with a single pair waiting gets no batch; the pair
+/// is judged on its own as usual.
+async fn send(mut waiting: Vec<Waiting>, config: &RunConfig, client: &dyn ChatBackend) -> Result<(), AppError> {
+    while let Some(first) = waiting.first() {
+        let model = first.model.clone();
+        let (group, rest): (Vec<Waiting>, Vec<Waiting>) = waiting.into_iter().partition(|w| w.model == model);
+        waiting = rest;
+        if group.len() == 1 {
+            group.into_iter().for_each(|w| { let _ = w.reply.send(Verdict::none()); });
+            continue;
+        }
+        send_group(group, &model, config, client).await?;
+    }
+    Ok(())
+}
+
+async fn send_group(group: Vec<Waiting>, model: &str, config: &RunConfig, client: &dyn ChatBackend) -> Result<(), AppError> {
+    let mut req = judge::request(&prompt(&group, config.judge_mode), config.judge_system_prompt.as_deref(), model, config)?;
+    req.response_format = Some(ResponseFormat::JsonObject);
+    let (response, retries) = config.retry.run_counted(|| config.pipeline.run(Stage::Judge, client.complete(req.clone(), None))).await;
+    let names: Vec<String> = group.iter().map(|w| w.test.clone()).collect();
+    let Ok(response) = response else {
+        group.into_iter().for_each(|w| { let _ = w.reply.send(Verdict { retries, ..Verdict::none() }); });
+        return Ok(());
+    };
+    let share = group.len() as u32;
+    let tokens = response.usage.as_ref().map_or(0, |u| u.total_tokens) / share;
+    let usage = response.usage.as_ref().map(TokenUsage::from).map_or(TokenUsage::default(), |u| TokenUsage { prompt: u.prompt / share, completion: u.completion / share });
+    let reply = response.choices.first().and_then(|c| c.content.as_deref()).unwrap_or("");
+    let verdicts = match serde_json::from_str::<BatchReply>(reply) {
+        Ok(BatchReply::Wrapped { verdicts } | BatchReply::Bare(verdicts)) => verdicts,
+        Err(_) => Vec::new(),
+    };
+    for (i, w) in group.into_iter().enumerate() {
+        let judgement = verdict_for(i + 1, &verdicts, config)?;
+        let shared_with = names.iter().filter(|n| **n != w.test).cloned().collect();
+        let _ = w.reply.send(Verdict { judgement, request_id: response.id.clone(), retries, tokens, usage, shared_with });
+    }
+    Ok(())
+}
+
+/// The verdict for case `number`, matched by the number the judge gave it rather than by position, so a reply that
+/// skips or reorders cases cannot hand one test's verdict to another. A case with no verdict, or more than one, has
+/// none.
+fn verdict_for(number: usize, verdicts: &[CaseVerdict], config: &RunConfig) -> Result<Option<Judgement>, regex::Error> {
+    let mut matching = verdicts.iter().filter(|v| match &v.case {
+        Some(serde_json::Value::Number(n)) => n.as_u64() == Some(number as u64),
+        Some(serde_json::Value::String(s)) => s.trim().parse::<usize>().ok() == Some(number),
+        _ => false,
+    });
+    let (Some(v), None) = (matching.next(), matching.next()) else { return Ok(None) };
+    let reason = v.reason.clone();
+    Ok(match config.judge_mode {
+        JudgeMode::Boolean => v.verdict.as_ref().and_then(judge::json_verdict).map(|passed| Judgement { passed, reason, ..Default::default() }),
+        JudgeMode::Score => v.score.as_ref().map(judge::json_score).transpose()?.flatten()
+            .map(|score| Judgement { passed: score >= config.pass_threshold, score: Some(score), reason, ..Default::default() }),
+    })
+}
+
+fn prompt(group: &[Waiting], mode: JudgeMode) -> String {
+    let mut prompt = format!("Judge each of the following {} cases on its own; they are unrelated.", group.len());
+    for (i, w) in group.iter().enumerate() {
+        prompt.push_str(&format!("\n\n=== Case {} ===\n{}", i + 1, w.prompt));
+    }
+    let field = match mode {
+        JudgeMode::Boolean => "\"verdict\": true or false",
+        JudgeMode::Score => "\"score\": 0 to 100",
+    };
+    prompt.push_str(&format!("\n\n=== End of cases ===\nRespond with a JSON object of the form {{\"verdicts\": [{{\"case\": case number, {}, \"reason\": \"one sentence\"}}, ...]}} with exactly one entry for every case.", field));
+    prompt
+}
@@ -0,0 +1,58 @@
+use std::{fs, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+use async_openai::types::CreateChatCompletionRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::{error::AppError, generation::Generation, reference::fnv1a};
+
+/// Generations kept in CACHE_DIR with `--cache`, one JSON file per request, so a run that only changed what happens
+/// after the generation, the structure test, the comparison or the judge prompt, reuses them instead of paying again.
+/// A request is found by its whole body: the model, every parameter and the rendered messages.
+pub struct GenerationCache {
+    pub dir: String,
+    /// Generations this run took from the cache.
+    hits: AtomicUsize,
+}
+
+/// One cached generation. The request is kept whole so that a hash collision is never taken for a hit.
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    request: Value,
+    attempt: u32,
+    generation: Generation,
+}
+
+impl GenerationCache {
+    pub fn new(dir: String) -> GenerationCache {
+        GenerationCache { dir, hits: AtomicUsize::new(0) }
+    }
+
+    /// The file of a request's `attempt`th generation, counted from 0; the attempts of PASS_AT_K are cached apart so
+    /// that they stay independent samples.
+    fn path(&self, request: &Value, attempt: u32) -> PathBuf {
+        Path::new(&self.dir).join(format!("{:016x}.json", fnv1a(format!("{}\0{}", attempt, request).as_bytes())))
+    }
+
+    /// The cached generation for `req`, if any. Its tokens were paid for by the run that cached it, so it has no usage.
+    pub fn get(&self, req: &CreateChatCompletionRequest, attempt: u32) -> Option<Generation> {
+        let request = serde_json::to_value(req).ok()?;
+        let cached = fs::read_to_string(self.path(&request, attempt)).ok().and_then(|c| serde_json::from_str::<Cached>(&c).ok())?;
+        if cached.request != request || cached.attempt != attempt {
+            return None;
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(Generation { usage: None, ..cached.generation })
+    }
+
+    pub fn put(&self, req: &CreateChatCompletionRequest, attempt: u32, generation: Generation) -> Result<Generation, AppError> {
+        let cached = Cached { request: serde_json::to_value(req)?, attempt, generation };
+        let path = self.path(&cached.request, attempt);
+        fs::create_dir_all(&self.dir).map_err(AppError::io(format!("CACHE_DIR {}", self.dir)))?;
+        fs::write(&path, serde_json::to_string_pretty(&cached)?).map_err(AppError::io(path.display().to_string()))?;
+        Ok(cached.generation)
+    }
+
+    /// How many generations were taken from the cache since the last call.
+    pub fn take_hits(&self) -> usize {
+        self.hits.swap(0, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::{HashMap, VecDeque}, fs::{self, File}, io::Write, str::FromStr, sync::Mutex};
+use async_openai::{error::OpenAIError, types::{CreateChatCompletionRequest, CreateEmbeddingRequest}};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use crate::{backend::{ChatBackend, Stream}, error::AppError, generation::Generation};
+
+/// Whether CASSETTE is written from the API's responses or read back in their place (CASSETTE_MODE).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+impl FromStr for CassetteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "record" => Ok(CassetteMode::Record),
+            "replay" => Ok(CassetteMode::Replay),
+            other => Err(format!("unknown CASSETTE_MODE {:?}, expected record or replay", other)),
+        }
+    }
+}
+
+/// A JSON lines file of API requests, each with the response it got: written while recording, and served back in
+/// replay so a run goes through extraction, the structure tests and judging without calling the API. Identical requests
+/// get their recorded responses in the order they were recorded.
+pub struct Cassette {
+    pub path: String,
+    pub mode: CassetteMode,
+    /// In replay, the responses not served yet, by request.
+    recorded: Mutex<HashMap<String, VecDeque<Response>>>,
+    /// While recording, the file, created over any earlier one at the first response.
+    file: Mutex<Option<File>>,
+}
+
+/// One line of the cassette.
+#[derive(Deserialize)]
+struct Entry {
+    request: serde_json::Value,
+    response: Response,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Chat(Generation),
+    Embedding(Vec<Vec<f32>>),
+}
+
+/// A response as it is written, borrowed from the one handed on.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Recorded<'a> {
+    Chat(&'a Generation),
+    Embedding(&'a [Vec<f32>]),
+}
+
+impl Cassette {
+    /// The cassette at `path`; in replay it has to exist and is read at once.
+    pub fn load(path: &str, mode: CassetteMode) -> Result<Cassette, AppError> {
+        let mut recorded: HashMap<String, VecDeque<Response>> = HashMap::new();
+        if mode == CassetteMode::Replay {
+            let contents = fs::read_to_string(path).map_err(AppError::io(path))?;
+            for (i, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                let entry: Entry = serde_json::from_str(line).map_err(|e| format!("CASSETTE {} line {} is not a recording: {}", path, i + 1, e))?;
+                recorded.entry(entry.request.to_string()).or_default().push_back(entry.response);
+            }
+        }
+        Ok(Cassette { path: path.to_string(), mode, recorded: Mutex::new(recorded), file: Mutex::new(None) })
+    }
+
+    fn record(&self, request: serde_json::Value, response: Recorded) -> Result<(), String> {
+        let line = serde_json::json!({ "request": request, "response": response }).to_string();
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(File::create(&self.path).map_err(|e| format!("cannot create CASSETTE {}: {}", self.path, e))?);
+        }
+        let file = file.as_mut().expect("the file was just created");
+        writeln!(file, "{}", line).map_err(|e| format!("cannot write CASSETTE {}: {}", self.path, e))
+    }
+
+    fn replay(&self, request: serde_json::Value, model: &str) -> Result<Response, OpenAIError> {
+        self.recorded.lock().unwrap().get_mut(&request.to_string()).and_then(VecDeque::pop_front)
+            .ok_or_else(|| OpenAIError::InvalidArgument(format!("CASSETTE {} has no recorded response left for this request to {}", self.path, model)))
+    }
+}
+
+fn json(request: &impl Serialize) -> Result<serde_json::Value, OpenAIError> {
+    serde_json::to_value(request).map_err(|e| OpenAIError::InvalidArgument(format!("cannot record the request: {}", e)))
+}
+
+/// A backend that records every successful response of `inner` to the cassette. A response that cannot be written
+/// fails its request, so a cassette never silently misses one.
+pub struct Recorder<'a> {
+    pub inner: Box<dyn ChatBackend + 'a>,
+    pub cassette: &'a Cassette,
+}
+
+impl ChatBackend for Recorder<'_> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        async move {
+            let request = json(&req)?;
+            let generation = self.inner.complete(req, stream).await?;
+            self.cassette.record(request, Recorded::Chat(&generation)).map_err(OpenAIError::InvalidArgument)?;
+            Ok(generation)
+        }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move {
+            let request = json(&req)?;
+            let embeddings = self.inner.embed(req).await?;
+            self.cassette.record(request, Recorded::Embedding(&embeddings)).map_err(OpenAIError::InvalidArgument)?;
+            Ok(embeddings)
+        }.boxed()
+    }
+}
+
+/// A backend that answers every request from the cassette, and fails one it has no recording for.
+pub struct Replay<'a> {
+    pub cassette: &'a Cassette,
+}
+
+impl ChatBackend for Replay<'_> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, _stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        async move {
+            match self.cassette.replay(json(&req)?, &req.model)? {
+                Response::Chat(generation) => Ok(generation),
+                Response::Embedding(_) => Err(OpenAIError::InvalidArgument(format!("CASSETTE {} recorded an embedding for a chat request", self.cassette.path))),
+            }
+        }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move {
+            match self.cassette.replay(json(&req)?, &req.model)? {
+                Response::Embedding(embeddings) => Ok(embeddings),
+                Response::Chat(_) => Err(OpenAIError::InvalidArgument(format!("CASSETTE {} recorded a chat response for an embedding request", self.cassette.path))),
+            }
+        }.boxed()
+    }
+}
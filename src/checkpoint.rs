@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, fs::{self, File}, io::Write, path::Path};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::{config::RunConfig, error::AppError, Status, TestResult};
+
+/// A test the run finished, one line of its manifest.
+#[derive(Serialize, Deserialize)]
+struct Finished {
+    test: String,
+    status: Status,
+}
+
+/// The run manifest, `<results>.manifest.jsonl`: a line for every test the run finished, written once the test's
+/// results row is, so that however the run stops it names exactly the tests `run --resume` need not run again.
+pub struct Manifest {
+    file: File,
+    path: String,
+}
+
+impl Manifest {
+    pub fn create(results_path: &str) -> Result<Manifest, AppError> {
+        let path = format!("{}.manifest.jsonl", results_path);
+        let file = File::create(&path).map_err(AppError::io(&path))?;
+        Ok(Manifest { file, path })
+    }
+
+    pub fn finished(&mut self, result: &TestResult) -> Result<(), AppError> {
+        let line = serde_json::to_string(&Finished { test: result.name.clone(), status: result.status })? + "\n";
+        // A File is unbuffered, so the line is written whole or, when the run is killed, cut short and skipped.
+        self.file.write_all(line.as_bytes()).map_err(AppError::io(&self.path))
+    }
+}
+
+/// The results `run --resume` keeps of the run at `results_path`: those of the tests its manifest names, read back
+/// from its raw results CSV when there is one. Tests skipped for MAX_COST or MAX_FAILURES are left to run, and the
+/// run must have been with `config.model`.
+pub fn resume(results_path: &str, config: &RunConfig) -> Result<BTreeMap<String, TestResult>, AppError> {
+    let manifest = format!("{}.manifest.jsonl", results_path);
+    if !Path::new(&manifest).exists() {
+        return Err(format!("{} has no manifest, so there is no run to resume there", results_path).into());
+    }
+    if let Some(meta) = fs::read_to_string(format!("{}.meta.json", results_path)).ok().and_then(|m| serde_json::from_str::<Value>(&m).ok()) {
+        let model = meta["model"].as_str().unwrap_or_default();
+        if model != config.model {
+            return Err(format!("{} ran {}, not {}; resume it with the same model", results_path, model, config.model).into());
+        }
+    }
+    let text = fs::read_to_string(&manifest).map_err(AppError::io(&manifest))?;
+    let finished: BTreeMap<String, Status> = text.lines().filter_map(|line| serde_json::from_str::<Finished>(line).ok()).map(|f| (f.test, f.status)).collect();
+    let raw = format!("{}.raw.csv", results_path);
+    let path = if Path::new(&raw).exists() { raw } else { format!("{}.csv", results_path) };
+    let mut reader = csv::Reader::from_path(&path).map_err(AppError::csv(&path))?;
+    // A row the run was writing when it was killed may be cut off; its test is not in the manifest.
+    let results = reader.deserialize::<TestResult>().filter_map(Result::ok)
+        .filter(|r| finished.get(&r.name) == Some(&r.status) && !matches!(r.status, Status::Budget | Status::Stopped))
+        .map(|r| (r.name.clone(), r))
+        .collect();
+    Ok(results)
+}
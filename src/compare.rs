@@ -0,0 +1,337 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+use inline_colorization::*;
+use regex::Regex;
+use serde_json::Value;
+use similar::TextDiff;
+use crate::format::Format;
+
+/// How a structurally valid candidate is checked against the expected output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Judge,
+    Exact,
+    JsonEqual,
+    Regex,
+    Embedding,
+    /// Identical apart from leading and trailing whitespace.
+    Strict,
+    /// A string metric between 0 and 1 that has to reach METRIC_THRESHOLD.
+    Levenshtein,
+    Bleu,
+    Rouge,
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "judge" => Ok(Comparison::Judge),
+            "exact" => Ok(Comparison::Exact),
+            "json-equal" => Ok(Comparison::JsonEqual),
+            "regex" => Ok(Comparison::Regex),
+            "embedding" => Ok(Comparison::Embedding),
+            "strict" => Ok(Comparison::Strict),
+            "levenshtein" => Ok(Comparison::Levenshtein),
+            "bleu" => Ok(Comparison::Bleu),
+            "rouge" => Ok(Comparison::Rouge),
+            other => Err(format!("unknown comparison {:?}, expected judge, exact, strict, json-equal, regex, levenshtein, bleu, rouge or embedding", other)),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Comparison::Judge => "judge",
+            Comparison::Exact => "exact",
+            Comparison::JsonEqual => "json-equal",
+            Comparison::Regex => "regex",
+            Comparison::Embedding => "embedding",
+            Comparison::Strict => "strict",
+            Comparison::Levenshtein => "levenshtein",
+            Comparison::Bleu => "bleu",
+            Comparison::Rouge => "rouge",
+        })
+    }
+}
+
+/// Compares with all runs of whitespace collapsed, returning a line diff on mismatch.
+pub fn exact(expected: &str, actual: &str) -> Result<(), String> {
+    if normalize_whitespace(expected) == normalize_whitespace(actual) {
+        return Ok(());
+    }
+    Err(diff(expected, actual))
+}
+
+/// Compares the outputs as they are, apart from leading and trailing whitespace, returning a line diff on mismatch.
+pub fn strict(expected: &str, actual: &str) -> Result<(), String> {
+    if expected.trim() == actual.trim() {
+        return Ok(());
+    }
+    Err(diff(expected, actual))
+}
+
+/// The score of a string metric comparison, from 0 for nothing in common to 1 for the same text, or `None` for a
+/// comparison that is not a metric. Levenshtein compares characters with whitespace normalized; BLEU and ROUGE compare
+/// words, lowercased with punctuation ignored.
+pub fn metric(comparison: Comparison, expected: &str, actual: &str) -> Option<f32> {
+    match comparison {
+        Comparison::Levenshtein => Some(levenshtein(&normalize_whitespace(expected), &normalize_whitespace(actual))),
+        Comparison::Bleu => Some(bleu(&words(expected), &words(actual))),
+        Comparison::Rouge => Some(rouge_l(&words(expected), &words(actual))),
+        _ => None,
+    }
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(str::to_lowercase).collect()
+}
+
+/// One minus the edit distance over the length of the longer text.
+fn levenshtein(expected: &str, actual: &str) -> f32 {
+    let (a, b): (Vec<char>, Vec<char>) = (expected.chars().collect(), actual.chars().collect());
+    let longer = a.len().max(b.len());
+    if longer == 0 {
+        return 1.0;
+    }
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    1.0 - row[b.len()] as f32 / longer as f32
+}
+
+/// BLEU-4 of the generation against the expected output as the one reference, with the brevity penalty. Bigram to
+/// 4-gram precisions are add-one smoothed, so a short text that shares words but no 4-gram does not score 0.
+fn bleu(reference: &[String], candidate: &[String]) -> f32 {
+    if reference.is_empty() || candidate.is_empty() {
+        return if reference.len() == candidate.len() { 1.0 } else { 0.0 };
+    }
+    let mut log_precision = 0.0;
+    for n in 1..=4 {
+        let counts = |words: &[String]| {
+            let mut counts = std::collections::HashMap::new();
+            for gram in words.windows(n) {
+                *counts.entry(gram.to_vec()).or_insert(0usize) += 1;
+            }
+            counts
+        };
+        let (reference, candidate) = (counts(reference), counts(candidate));
+        let total: usize = candidate.values().sum();
+        let matched: usize = candidate.iter().map(|(gram, count)| (*count).min(reference.get(gram).copied().unwrap_or(0))).sum();
+        let (matched, total) = if n == 1 { (matched, total) } else { (matched + 1, total + 1) };
+        if matched == 0 {
+            return 0.0;
+        }
+        log_precision += (matched as f64 / total as f64).ln() / 4.0;
+    }
+    let (r, c) = (reference.len() as f64, candidate.len() as f64);
+    let brevity = if c >= r { 1.0 } else { (1.0 - r / c).exp() };
+    (brevity * log_precision.exp()) as f32
+}
+
+/// ROUGE-L: the F1 of the longest common subsequence of words.
+fn rouge_l(reference: &[String], candidate: &[String]) -> f32 {
+    if reference.is_empty() || candidate.is_empty() {
+        return if reference.len() == candidate.len() { 1.0 } else { 0.0 };
+    }
+    let mut row = vec![0usize; candidate.len() + 1];
+    for x in reference {
+        let mut diagonal = 0;
+        for (j, y) in candidate.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if x == y { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+    let lcs = row[candidate.len()] as f32;
+    if lcs == 0.0 {
+        return 0.0;
+    }
+    let (precision, recall) = (lcs / candidate.len() as f32, lcs / reference.len() as f32);
+    2.0 * precision * recall / (precision + recall)
+}
+
+/// Unified line diff from the expected to the generated output.
+pub fn diff(expected: &str, actual: &str) -> String {
+    TextDiff::from_lines(expected.trim(), actual.trim()).unified_diff().missing_newline_hint(false).header("expected", "generated").to_string()
+}
+
+/// Like [`diff`], but when both outputs are JSON they are pretty-printed with their keys sorted first, so a long
+/// single-line object diffs field by field and a difference in formatting or key order shows as none.
+pub fn json_diff(expected: &str, actual: &str) -> String {
+    match (pretty(expected), pretty(actual)) {
+        (Some(expected), Some(actual)) => diff(&expected, &actual),
+        _ => diff(expected, actual),
+    }
+}
+
+/// JSON output pretty-printed with its keys sorted, or `None` for output that is not JSON.
+pub fn pretty(text: &str) -> Option<String> {
+    serde_json::from_str::<Value>(text).ok().and_then(|v| serde_json::to_string_pretty(&v).ok())
+}
+
+/// A unified diff for the terminal: removed lines red, added lines green and hunk headers cyan.
+pub fn colored(diff: &str) -> String {
+    diff.lines().map(|line| match line.chars().next() {
+        Some('+') if !line.starts_with("+++") => format!("{color_green}{}{color_reset}", line),
+        Some('-') if !line.starts_with("---") => format!("{color_red}{}{color_reset}", line),
+        Some('@') => format!("{color_cyan}{}{color_reset}", line),
+        _ => line.to_string(),
+    }).collect::<Vec<_>>().join("\n")
+}
+
+fn normalize_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Each non-blank line of the expected output is a pattern.
+pub fn patterns(expected: &str) -> Result<Vec<Regex>, String> {
+    expected.lines().map(str::trim).filter(|l| !l.is_empty())
+        .map(|l| Regex::new(l).map_err(|e| format!("invalid pattern {:?}: {}", l, e)))
+        .collect()
+}
+
+/// Requires every pattern to match somewhere in the output, reporting the first that doesn't.
+pub fn regex(expected: &str, actual: &str) -> Result<(), String> {
+    match patterns(expected)?.into_iter().find(|p| !p.is_match(actual)) {
+        None => Ok(()),
+        Some(p) => Err(format!("pattern did not match: {}", p.as_str())),
+    }
+}
+
+/// Compares the outputs as read in `format`, as JSON values, ignoring object key order and integer/float spelling, and skipping any path in `ignore`.
+/// On mismatch the error names the first differing JSON pointer.
+pub fn json_equal(expected: &str, actual: &str, format: Format, ignore: &[Vec<String>]) -> Result<(), String> {
+    let expected = format.parse(expected).map_err(|e| format!("expected output is not valid {}: {}", format.title(), e))?;
+    // What `normalize` returned as a table is JSON whatever the format.
+    let actual = format.parse(actual).or_else(|e| serde_json::from_str(actual).map_err(|_| e)).map_err(|e| format!("generated output is not valid {}: {}", format.title(), e))?;
+    match first_difference(&expected, &actual, &mut Vec::new(), ignore) {
+        None => Ok(()),
+        Some(path) => Err(format!("first difference at {}: expected {}, got {}",
+            if path.is_empty() { "(root)" } else { &path },
+            expected.pointer(&path).map(|v| v.to_string()).unwrap_or("nothing".to_string()),
+            actual.pointer(&path).map(|v| v.to_string()).unwrap_or("nothing".to_string()))),
+    }
+}
+
+/// Splits a JSON pointer like `/items/*/id` into unescaped segments; `*` matches any key or index.
+pub fn parse_pointer(pointer: &str) -> Vec<String> {
+    pointer.trim().trim_start_matches('/').split('/').filter(|s| !s.is_empty()).map(|s| s.replace("~1", "/").replace("~0", "~")).collect()
+}
+
+fn first_difference(expected: &Value, actual: &Value, path: &mut Vec<String>, ignore: &[Vec<String>]) -> Option<String> {
+    if is_ignored(path, ignore) {
+        return None;
+    }
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            let keys: BTreeSet<&String> = e.keys().chain(a.keys()).collect();
+            keys.into_iter().find_map(|key| child_difference(e.get(key), a.get(key), key.clone(), path, ignore))
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            (0..e.len().max(a.len())).find_map(|i| child_difference(e.get(i), a.get(i), i.to_string(), path, ignore))
+        }
+        (Value::Number(e), Value::Number(a)) => (e != a && e.as_f64() != a.as_f64()).then(|| pointer(path)),
+        _ => (expected != actual).then(|| pointer(path)),
+    }
+}
+
+fn child_difference(expected: Option<&Value>, actual: Option<&Value>, segment: String, path: &mut Vec<String>, ignore: &[Vec<String>]) -> Option<String> {
+    path.push(segment);
+    let difference = match (expected, actual) {
+        (Some(e), Some(a)) => first_difference(e, a, path, ignore),
+        _ => (!is_ignored(path, ignore)).then(|| pointer(path)),
+    };
+    path.pop();
+    difference
+}
+
+fn is_ignored(path: &[String], ignore: &[Vec<String>]) -> bool {
+    ignore.iter().any(|p| p.len() == path.len() && p.iter().zip(path).all(|(p, s)| p == "*" || p == s))
+}
+
+fn pointer(path: &[String]) -> String {
+    path.iter().map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1"))).collect()
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 { 0.0 } else { dot / denominator }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_scores(comparison: Comparison, cases: &[(&str, &str, f32)]) {
+        for (expected, actual, score) in cases {
+            let got = metric(comparison, expected, actual).unwrap();
+            assert!((got - score).abs() < 1e-4, "{} of {:?} and {:?} is {}, not {}", comparison, expected, actual, got, score);
+        }
+    }
+
+    #[test]
+    fn levenshtein_is_one_minus_the_edits_over_the_longer_length() {
+        assert_scores(Comparison::Levenshtein, &[
+            ("kitten", "sitting", 1.0 - 3.0 / 7.0),
+            ("same text", "same text", 1.0),
+            ("abc", "xyz", 0.0),
+            ("", "", 1.0),
+            ("a  b\n c", "a b c", 1.0),
+        ]);
+    }
+
+    #[test]
+    fn bleu_smooths_short_candidates_and_penalizes_brevity() {
+        assert_scores(Comparison::Bleu, &[
+            ("the cat sat on the mat", "The cat sat on the mat.", 1.0),
+            ("the cat sat on the mat", "dogs bark loudly at night", 0.0),
+            // No 4-gram at all, which the smoothing counts as matched.
+            ("the cat sat", "the cat sat", 1.0),
+            ("the cat sat on the mat", "the cat", (-2.0f32).exp()),
+            ("the cat sat on the mat all day", "the cat sat on", (-1.0f32).exp()),
+            ("", "", 1.0),
+            ("the cat", "", 0.0),
+        ]);
+    }
+
+    #[test]
+    fn regex_needs_every_pattern_line_to_match_and_reports_the_first_that_does_not() {
+        let expected = "^\\{\n\n   \"items\": \\[\n\t\n\\d+\n\"total\"\n\"missing\"\n";
+        assert_eq!(patterns(expected).unwrap().len(), 5, "blank lines are not patterns");
+        assert_eq!(regex(expected, "{\"items\": [1, 2], \"total\": 2, \"missing\": 0}"), Ok(()));
+        assert_eq!(regex(expected, "{\"items\": [1, 2]}"), Err("pattern did not match: \"total\"".to_string()));
+        assert_eq!(regex(expected, "items: none"), Err("pattern did not match: ^\\{".to_string()));
+        assert_eq!(regex("\n \n", "anything"), Ok(()));
+    }
+
+    #[test]
+    fn an_invalid_pattern_is_rejected() {
+        let error = patterns("^ok$\n(unclosed\n").unwrap_err();
+        assert!(error.starts_with("invalid pattern \"(unclosed\""), "{}", error);
+        assert_eq!(regex("(unclosed", "(unclosed"), Err(error));
+    }
+
+    #[test]
+    fn rouge_l_is_the_f1_of_the_longest_common_subsequence() {
+        assert_scores(Comparison::Rouge, &[
+            ("the cat sat on the mat", "the cat sat on the mat", 1.0),
+            ("the cat sat on the mat", "dogs bark loudly", 0.0),
+            // LCS 5: precision 1, recall 5/6.
+            ("the cat sat on the mat", "the cat on the mat", 10.0 / 11.0),
+            // LCS "the cat on mat": precision and recall 4/6.
+            ("the cat sat on the mat", "the cat lay on a mat", 4.0 / 6.0),
+            ("", "", 1.0),
+        ]);
+    }
+}
@@ -0,0 +1,628 @@
+use std::{collections::{BTreeMap, BTreeSet}, env, fmt, fs, path::{Path, PathBuf}, str::FromStr, time::Duration};
+use async_openai::types::{FunctionObject, ResponseFormat, ResponseFormatJsonSchema};
+use jsonschema::Validator;
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+use crate::{backend::Stream, batch::JudgeBatch, cache::GenerationCache, cassette::{Cassette, CassetteMode}, compare::{self, Comparison}, error::AppError, extract::Strategy, format::Format, generated::Generated, generation::GenPolicy, judge::{JudgeFormat, JudgeMode, JudgePolicy}, output::{Console, Verbosity}, pipeline::Pipeline, plugin::Plugins, retry::RetryPolicy, report::{Column, ReportFormat, TestResult}, review::Overrides, rubric::Rubric, structure::{Limits, LuaInput, LuaSource, MB}, telemetry::Otlp, test_case, truncate::TruncateStrategy, pricing::PriceTable, provider::{self, Provider}, ratelimit::RateLimit, redact::Redactor, select::{ResultOrder, TestFilter}};
+
+/// Everything a run needs: where the tests and results live, the prompt contents and the model settings.
+pub struct RunConfig {
+    pub test_dir: String,
+    pub results_dir: String,
+    pub gen_prompt: String,
+    /// The generation prompts of a prompt matrix (GEN_PROMPTS), each run over the whole suite in turn. Without
+    /// GEN_PROMPT the first of them is the one a single run uses.
+    pub gen_prompts: Vec<PromptVariant>,
+    pub test_prompt: String,
+    /// GEN_SYSTEM_PROMPT and JUDGE_SYSTEM_PROMPT, sent as a system message ahead of the prompt.
+    pub gen_system_prompt: Option<String>,
+    pub judge_system_prompt: Option<String>,
+    pub structure_test: Option<LuaSource>,
+    pub structure_schema: Option<Validator>,
+    /// VALIDATOR and VALIDATOR_TIMEOUT_MS.
+    pub plugins: Plugins,
+    /// Which model API the run talks to (PROVIDER).
+    pub provider: Provider,
+    pub api_url: String,
+    pub api_key: String,
+    /// The Azure OpenAI API version (API_VERSION).
+    pub api_version: String,
+    /// The Azure deployment every request goes to (AZURE_DEPLOYMENT), instead of one named after each model.
+    pub azure_deployment: Option<String>,
+    /// What model API requests go through: API_PROXY, API_HEADERS and API_TIMEOUT_MS.
+    pub http: reqwest::Client,
+    pub model: String,
+    /// The models of a matrix run (MODELS), each run over the whole suite in turn. Without `model` the first of them is
+    /// also the default judge.
+    pub models: Vec<String>,
+    /// The models a failed generation falls back to in turn (FALLBACK_MODELS), once the retries of the one before are
+    /// used up.
+    pub fallback_models: Vec<String>,
+    pub response_format: Option<ResponseFormat>,
+    pub gen_n: u8,
+    /// How many of the GEN_N completions have to pass (GEN_POLICY).
+    pub gen_policy: GenPolicy,
+    pub stream: bool,
+    /// Longest wait for the next chunk of a streamed generation (STREAM_IDLE_TIMEOUT_MS).
+    pub stream_idle_timeout: Option<Duration>,
+    /// GEN_TEMPERATURE, GEN_TOP_P, GEN_MAX_TOKENS and GEN_SEED, which a test's `<params>` can override.
+    pub gen_params: GenParams,
+    pub gen_tool: Option<FunctionObject>,
+    pub retry: RetryPolicy,
+    pub logprobs: bool,
+    pub weak_pass_threshold: f32,
+    pub gen_stop: Vec<String>,
+    pub comparison: Comparison,
+    /// How candidates are found in a generated message that is not JSON mode or a tool call (EXTRACT).
+    pub extract: Strategy,
+    /// The format the tests' output is written in, when a test has no `<format>` (FORMAT).
+    pub format: Format,
+    /// Whether YAML and XML candidates reach the structure test and validator as JSON text (CANONICAL_JSON).
+    pub canonical_json: bool,
+    pub ignore_paths: Vec<Vec<String>>,
+    pub embed_model: String,
+    pub similarity_threshold: f32,
+    /// The score a `levenshtein`, `bleu` or `rouge` comparison has to reach.
+    pub metric_threshold: f32,
+    /// Whether judged tests are embedded first for their similarity (EMBED_SIMILARITY, or either threshold below).
+    pub embed_similarity: bool,
+    /// A similarity at or above SIMILARITY_PASS passes without the judge, one below SIMILARITY_FAIL fails without it.
+    pub similarity_pass: Option<f32>,
+    pub similarity_fail: Option<f32>,
+    pub judge_format: JudgeFormat,
+    pub judge_cot: bool,
+    pub judge_mode: JudgeMode,
+    pub pass_threshold: f64,
+    pub rubric: Option<Rubric>,
+    pub judge_votes: u32,
+    pub judge_models: Vec<String>,
+    pub judge_policy: JudgePolicy,
+    pub judge_temperature: Option<f32>,
+    pub judge_top_p: Option<f32>,
+    pub judge_max_tokens: Option<u32>,
+    pub judge_seed: Option<i64>,
+    pub lua_input: LuaInput,
+    pub sandbox: bool,
+    pub lua_limits: Limits,
+    pub lua_libs: Vec<LuaSource>,
+    pub lua_smoke_test: bool,
+    /// The Lua file defining `before_all` and `after_all` (HOOKS_SCRIPT), when not STRUCTURE_TEST.
+    pub hooks_script: Option<LuaSource>,
+    /// What `before_all` returned, set before the tests run and handed to each as `ctx.shared`.
+    pub shared: Option<serde_json::Value>,
+    /// Failed outputs a reviewer accepted earlier, from ACCEPTED_OVERRIDES.
+    pub overrides: Option<Overrides>,
+    pub dry_run: bool,
+    /// Requests and responses recorded to, or replayed from, CASSETTE.
+    pub cassette: Option<Cassette>,
+    /// Generations reused across runs with CACHE, or `--cache`, kept in CACHE_DIR.
+    pub cache: Option<GenerationCache>,
+    /// Secrets replaced in the results, reports, log and artifacts (REDACT and REDACT_PATTERNS).
+    pub redactor: Redactor,
+    /// Whether the results CSV is made safe for spreadsheets, with a raw copy next to it (RESULTS_SANITIZE).
+    pub results_sanitize: bool,
+    /// Longest sanitized results cell, in characters (RESULTS_MAX_CELL_LEN).
+    pub results_max_cell_len: Option<usize>,
+    /// Only the tests whose file name matches are run (TEST_FILTER, or `--filter`).
+    pub filter: Option<TestFilter>,
+    /// Only the tests with one of these tags are run (TAGS, or `--tag`).
+    pub tags: Vec<String>,
+    /// Tests with any of these tags are left out (EXCLUDE_TAGS, or `--exclude-tag`).
+    pub exclude_tags: Vec<String>,
+    /// The tags tests may have, from KNOWN_TAGS; `validate` reports any other.
+    pub known_tags: Vec<String>,
+    /// Whether symlinks in TEST_DIR are read as tests (FOLLOW_SYMLINKS).
+    pub follow_symlinks: bool,
+    /// Test names from QUARANTINE_FILE.
+    pub quarantine: Vec<String>,
+    /// Whether comparison failures leave a snapshot of their generation (SNAPSHOTS).
+    pub snapshots: bool,
+    /// Whether each test's prompts, responses, payloads and structure test output are kept as files (ARTIFACTS).
+    pub artifacts: bool,
+    /// Where they go, `<results>.artifacts`, set by the run when `artifacts` is.
+    pub artifacts_dir: Option<PathBuf>,
+    /// The results CSV columns in order, from RESULTS_COLUMNS.
+    pub results_columns: Vec<Column>,
+    /// Reports written besides the results CSV, from REPORT_FORMAT.
+    pub report_formats: Vec<ReportFormat>,
+    /// Generates the expected output of tests without one (BASELINE_MODEL).
+    pub baseline_model: Option<String>,
+    /// Where generated baselines are cached, BASELINE_CACHE or `baselines` in RESULTS_DIR.
+    pub baseline_cache: String,
+    /// Saved generations to judge instead of generating, set by `--phase judge`.
+    pub generated: Option<BTreeMap<String, Generated>>,
+    /// The passing results of the run given to `run --retry-failed`, reported again instead of running those tests.
+    pub carried: Option<BTreeMap<String, TestResult>>,
+    /// The attempt of each test that did not pass in the run given to `run --retry-failed`, so its new result counts one
+    /// more.
+    pub retried: BTreeMap<String, u32>,
+    /// The run `run --resume` continues, by its name in RESULTS_DIR; its finished results are `carried`.
+    pub resume: Option<String>,
+    /// The tests a change affected in `--watch`; the others are not selected.
+    pub only: Option<BTreeSet<String>>,
+    /// Price in dollars per million tokens, for the estimated cost of a run.
+    pub prices: PriceTable,
+    /// Once the run's estimated cost reaches this many dollars, the tests not yet started are skipped (MAX_COST).
+    pub max_cost: Option<f64>,
+    /// Once this many tests have failed, the tests not yet started are skipped (MAX_FAILURES, or `--max-failures` and
+    /// `--fail-fast`).
+    pub max_failures: Option<usize>,
+    /// With it the run fails only when its pass rate is below it, rather than on any failure (MIN_PASS_RATE, or
+    /// `--min-pass-rate`).
+    pub min_pass_rate: Option<f64>,
+    /// Where each run is appended for `history` (HISTORY_FILE).
+    pub history_file: Option<String>,
+    /// Slack incoming webhooks told of each finished run (NOTIFY_SLACK).
+    pub notify_slack: Vec<String>,
+    /// URLs each finished run's notification is posted to as JSON (NOTIFY_WEBHOOKS).
+    pub notify_webhooks: Vec<String>,
+    /// Where `serve` shows the runs, for the link in a notification (NOTIFY_REPORT_URL).
+    pub notify_report_url: Option<String>,
+    /// Throttles every API request of the run (RATE_LIMIT_RPM, RATE_LIMIT_TPM).
+    pub rate_limit: Option<RateLimit>,
+    /// Longest a test may take, its API calls and retries included (TEST_TIMEOUT_MS).
+    pub test_timeout: Option<Duration>,
+    /// How much is printed per test (VERBOSITY, or `--quiet` and `--verbose`).
+    pub verbosity: Verbosity,
+    /// Where the run's notes to the user are printed, such as a skipped test file or a model falling back; the binary
+    /// sets it, and a run from another program has none, so they only reach tracing.
+    pub console: Option<Console>,
+    /// The least severe events written to the run's log file, or no log file (LOG_LEVEL).
+    pub log_level: Option<Level>,
+    /// Where the run's traces and metrics are exported (OTEL_EXPORTER_OTLP_ENDPOINT).
+    pub otlp: Option<Otlp>,
+    /// How many tests run at once (JOBS, or `--jobs`); by default 1, or with GEN_JOBS or JUDGE_JOBS enough to keep both
+    /// stages busy.
+    pub jobs: usize,
+    /// The request slots of the generation and judge stages (GEN_JOBS, JUDGE_JOBS).
+    pub pipeline: Pipeline,
+    /// The seed the tests are shuffled with before they run (SHUFFLE and SHUFFLE_SEED, or `--shuffle` and `--seed`),
+    /// or none to run them by name.
+    pub shuffle: Option<u64>,
+    /// The order of a shuffled run's results (RESULT_ORDER, or `--sort`).
+    pub result_order: ResultOrder,
+    /// Shares judge requests between tests when JUDGE_BATCH_SIZE is above 1.
+    pub judge_batch: Option<JudgeBatch>,
+    /// Independent generation attempts per test, for pass@1 and pass@k (PASS_AT_K).
+    pub pass_at_k: Option<u32>,
+    /// Inputs longer than this many tokens are truncated (INPUT_MAX_TOKENS).
+    pub input_max_tokens: Option<usize>,
+    pub input_truncate_strategy: TruncateStrategy,
+}
+
+impl RunConfig {
+    pub fn from_env() -> Result<RunConfig, AppError> {
+        let models = list("MODELS");
+        let model = match models.first() {
+            Some(first) if env::var("model").is_err() => first.clone(),
+            _ => required("model")?,
+        };
+        let mut judge_models: Vec<String> = env::var("JUDGE_MODELS").unwrap_or_default().split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect();
+        if judge_models.is_empty() {
+            judge_models.push(model.clone());
+        }
+        let provider: Provider = parse_var("PROVIDER")?.unwrap_or(Provider::OpenAI);
+        let mut gen_prompts: Vec<PromptVariant> = Vec::new();
+        for path in list("GEN_PROMPTS") {
+            let name = Path::new(&path).file_stem().map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned());
+            if gen_prompts.iter().any(|v| v.name == name) {
+                return Err(format!("GEN_PROMPTS names two prompts called {}; give the files different names", name).into());
+            }
+            gen_prompts.push(PromptVariant { name, text: read("GEN_PROMPTS", &path)? });
+        }
+        let gen_prompt = match gen_prompts.first() {
+            Some(first) if env::var("GEN_PROMPT").is_err() => first.text.clone(),
+            _ => read("GEN_PROMPT", &required("GEN_PROMPT")?)?,
+        };
+        let (gen_jobs, judge_jobs): (Option<usize>, Option<usize>) = (parse_var("GEN_JOBS")?, parse_var("JUDGE_JOBS")?);
+        if gen_jobs == Some(0) || judge_jobs == Some(0) {
+            return Err("GEN_JOBS and JUDGE_JOBS must be at least 1".into());
+        }
+        let config = RunConfig {
+            test_dir: required("TEST_DIR")?,
+            results_dir: required("RESULTS_DIR")?,
+            gen_prompt,
+            gen_prompts,
+            test_prompt: read("TEST_PROMPT", &required("TEST_PROMPT")?)?,
+            gen_system_prompt: env::var("GEN_SYSTEM_PROMPT").ok().map(|path| read("GEN_SYSTEM_PROMPT", &path)).transpose()?,
+            judge_system_prompt: env::var("JUDGE_SYSTEM_PROMPT").ok().map(|path| read("JUDGE_SYSTEM_PROMPT", &path)).transpose()?,
+            structure_test: env::var("STRUCTURE_TEST").ok().map(|path| -> Result<LuaSource, AppError> {
+                let source = read("STRUCTURE_TEST", &path)?;
+                Ok(LuaSource { path, source })
+            }).transpose()?,
+            structure_schema: structure_schema()?,
+            plugins: Plugins {
+                validator: match env::var("VALIDATOR") {
+                    Ok(path) if !Path::new(&path).is_file() => return Err(format!("VALIDATOR {} is not a file", path).into()),
+                    Ok(path) => Some(PathBuf::from(path)),
+                    Err(_) => None,
+                },
+                timeout: Duration::from_millis(parse_var("VALIDATOR_TIMEOUT_MS")?.unwrap_or(10_000)),
+            },
+            provider,
+            api_url: env::var("API_URL").unwrap_or_else(|_| provider.default_url().to_string()),
+            api_key: env::var("API_KEY").unwrap_or_else(|_| provider.default_key()),
+            api_version: env::var("API_VERSION").unwrap_or("2024-10-21".to_string()),
+            http: provider::http_client(parse_var("API_TIMEOUT_MS")?.filter(|ms| *ms > 0).map(Duration::from_millis))?,
+            azure_deployment: env::var("AZURE_DEPLOYMENT").ok().filter(|d| !d.is_empty()),
+            model,
+            models,
+            fallback_models: list("FALLBACK_MODELS"),
+            response_format: response_format()?,
+            gen_n: parse_var("GEN_N")?.unwrap_or(1),
+            gen_policy: parse_var("GEN_POLICY")?.unwrap_or(GenPolicy::Any),
+            stream: parse_var("STREAM")?.unwrap_or(false),
+            stream_idle_timeout: parse_var("STREAM_IDLE_TIMEOUT_MS")?.filter(|ms| *ms > 0).map(Duration::from_millis),
+            gen_params: GenParams {
+                temperature: parse_var("GEN_TEMPERATURE")?,
+                top_p: parse_var("GEN_TOP_P")?,
+                max_tokens: parse_var("GEN_MAX_TOKENS")?,
+                seed: parse_var("GEN_SEED")?,
+            },
+            gen_tool: gen_tool()?,
+            retry: RetryPolicy {
+                max_retries: parse_var("MAX_RETRIES")?.unwrap_or(2),
+                base_delay: Duration::from_millis(parse_var("RETRY_DELAY_MS")?.unwrap_or(1000)),
+                max_delay: Duration::from_millis(parse_var("RETRY_MAX_DELAY_MS")?.unwrap_or(30_000)),
+                jitter: parse_var("RETRY_JITTER")?.unwrap_or(0.5),
+            },
+            logprobs: parse_var("LOGPROBS")?.unwrap_or(false),
+            weak_pass_threshold: parse_var("WEAK_PASS_THRESHOLD")?.unwrap_or(0.9),
+            gen_stop: gen_stop()?,
+            comparison: parse_var("COMPARISON")?.unwrap_or(Comparison::Judge),
+            extract: parse_var("EXTRACT")?.unwrap_or_default(),
+            format: parse_var("FORMAT")?.unwrap_or_default(),
+            canonical_json: parse_var("CANONICAL_JSON")?.unwrap_or(false),
+            ignore_paths: env::var("IGNORE_PATHS").unwrap_or_default().split(',').filter(|p| !p.trim().is_empty()).map(compare::parse_pointer).collect(),
+            embed_model: env::var("EMBED_MODEL").unwrap_or("text-embedding-3-small".to_string()),
+            similarity_threshold: parse_var("SIMILARITY_THRESHOLD")?.unwrap_or(0.9),
+            metric_threshold: parse_var("METRIC_THRESHOLD")?.unwrap_or(0.8),
+            embed_similarity: parse_var("EMBED_SIMILARITY")?.unwrap_or(false) || env::var("SIMILARITY_PASS").is_ok() || env::var("SIMILARITY_FAIL").is_ok(),
+            similarity_pass: parse_var("SIMILARITY_PASS")?,
+            similarity_fail: parse_var("SIMILARITY_FAIL")?,
+            judge_format: parse_var("JUDGE_FORMAT")?.unwrap_or(JudgeFormat::Plain),
+            judge_cot: parse_var("JUDGE_COT")?.unwrap_or(false),
+            judge_mode: parse_var("JUDGE_MODE")?.unwrap_or(JudgeMode::Boolean),
+            pass_threshold: parse_var("PASS_THRESHOLD")?.unwrap_or(80.0),
+            rubric: env::var("RUBRIC").ok().map(|p| Rubric::load(&p)).transpose()?,
+            judge_votes: parse_var("JUDGE_VOTES")?.unwrap_or(1),
+            judge_models,
+            judge_policy: parse_var("JUDGE_POLICY")?.unwrap_or(JudgePolicy::Majority),
+            judge_temperature: parse_var("JUDGE_TEMPERATURE")?,
+            judge_top_p: parse_var("JUDGE_TOP_P")?,
+            judge_max_tokens: parse_var("JUDGE_MAX_TOKENS")?,
+            judge_seed: parse_var("JUDGE_SEED")?,
+            lua_input: parse_var("LUA_INPUT")?.unwrap_or(LuaInput::String),
+            sandbox: match env::var("SANDBOX").unwrap_or_default().as_str() {
+                "" | "on" => true,
+                "off" => false,
+                other => return Err(format!("invalid SANDBOX {:?}, expected on or off", other).into()),
+            },
+            lua_limits: Limits {
+                max_instructions: parse_var("LUA_MAX_INSTRUCTIONS")?.unwrap_or(100_000_000),
+                timeout: Duration::from_millis(parse_var("LUA_TIMEOUT_MS")?.unwrap_or(10_000)),
+                max_memory: parse_var::<usize>("LUA_MAX_MEMORY_MB")?.unwrap_or(256) * MB,
+            },
+            lua_libs: lua_libs()?,
+            lua_smoke_test: parse_var("LUA_SMOKE_TEST")?.unwrap_or(false),
+            hooks_script: env::var("HOOKS_SCRIPT").ok().map(|path| -> Result<LuaSource, AppError> {
+                let source = read("HOOKS_SCRIPT", &path)?;
+                Ok(LuaSource { path, source })
+            }).transpose()?,
+            shared: None,
+            overrides: env::var("ACCEPTED_OVERRIDES").ok().map(|p| Overrides::load(&p)).transpose()?,
+            dry_run: parse_var("DRY_RUN")?.unwrap_or(false),
+            cassette: env::var("CASSETTE").ok().map(|path| -> Result<Cassette, AppError> {
+                Cassette::load(&path, parse_var("CASSETTE_MODE")?.unwrap_or(CassetteMode::Replay))
+            }).transpose()?,
+            redactor: Redactor::from_env(parse_var("REDACT")?.unwrap_or(true))?,
+            cache: parse_var("CACHE")?.unwrap_or(false).then(|| {
+                GenerationCache::new(env::var("CACHE_DIR").unwrap_or_else(|_| format!("{}/cache", env::var("RESULTS_DIR").unwrap_or_default())))
+            }),
+            prices: PriceTable::load(env::var("PRICE_TABLE").ok().as_deref(), parse_var("PRICE_PER_MTOK")?)?,
+            max_cost: parse_var("MAX_COST")?,
+            max_failures: match parse_var("MAX_FAILURES")? {
+                Some(0) => return Err("MAX_FAILURES must be at least 1".to_string().into()),
+                max => max,
+            },
+            min_pass_rate: parse_var("MIN_PASS_RATE")?,
+            history_file: env::var("HISTORY_FILE").ok().filter(|p| !p.is_empty()),
+            notify_slack: list("NOTIFY_SLACK"),
+            notify_webhooks: list("NOTIFY_WEBHOOKS"),
+            notify_report_url: env::var("NOTIFY_REPORT_URL").ok().filter(|u| !u.is_empty()),
+            rate_limit: match (parse_var("RATE_LIMIT_RPM")?, parse_var("RATE_LIMIT_TPM")?) {
+                (None, None) => None,
+                (rpm, tpm) => Some(RateLimit::new(rpm, tpm)),
+            },
+            test_timeout: parse_var("TEST_TIMEOUT_MS")?.filter(|ms| *ms > 0).map(Duration::from_millis),
+            verbosity: parse_var("VERBOSITY")?.unwrap_or_default(),
+            console: None,
+            log_level: match env::var("LOG_LEVEL") {
+                Ok(level) if level.trim() == "off" => None,
+                _ => Some(parse_var("LOG_LEVEL")?.unwrap_or(Level::INFO)),
+            },
+            otlp: Otlp::from_env(),
+            jobs: match (parse_var("JOBS")?, gen_jobs, judge_jobs) {
+                (Some(jobs), _, _) => jobs,
+                (None, None, None) => 1,
+                // A stage without a limit of its own takes as many tests as the other.
+                (None, generation, judge) => generation.or(judge).unwrap_or(0) + judge.or(generation).unwrap_or(0),
+            },
+            pipeline: Pipeline::new(gen_jobs, judge_jobs),
+            shuffle: match parse_var("SHUFFLE_SEED")? {
+                Some(seed) => Some(seed),
+                None if parse_var("SHUFFLE")?.unwrap_or(false) => Some(fastrand::u64(..)),
+                None => None,
+            },
+            result_order: parse_var("RESULT_ORDER")?.unwrap_or_default(),
+            judge_batch: parse_var::<usize>("JUDGE_BATCH_SIZE")?.filter(|size| *size > 1).map(JudgeBatch::new),
+            pass_at_k: match parse_var("PASS_AT_K")? {
+                Some(0) => return Err("PASS_AT_K must be at least 1".to_string().into()),
+                k => k,
+            },
+            input_max_tokens: parse_var("INPUT_MAX_TOKENS")?,
+            input_truncate_strategy: parse_var("INPUT_TRUNCATE_STRATEGY")?.unwrap_or(TruncateStrategy::Head),
+            generated: None,
+            carried: None,
+            retried: BTreeMap::new(),
+            resume: None,
+            only: None,
+            baseline_model: env::var("BASELINE_MODEL").ok().filter(|m| !m.trim().is_empty()),
+            baseline_cache: env::var("BASELINE_CACHE").unwrap_or_else(|_| format!("{}/baselines", env::var("RESULTS_DIR").unwrap_or_default())),
+            results_columns: results_columns()?,
+            report_formats: report_formats()?,
+            quarantine: match env::var("QUARANTINE_FILE") {
+                Ok(path) => read("QUARANTINE_FILE", &path)?.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).map(str::to_string).collect(),
+                Err(_) => Vec::new(),
+            },
+            snapshots: parse_var("SNAPSHOTS")?.unwrap_or(false),
+            artifacts: parse_var("ARTIFACTS")?.unwrap_or(false),
+            artifacts_dir: None,
+            results_sanitize: parse_var("RESULTS_SANITIZE")?.unwrap_or(true),
+            results_max_cell_len: parse_var("RESULTS_MAX_CELL_LEN")?,
+            follow_symlinks: parse_var("FOLLOW_SYMLINKS")?.unwrap_or(true),
+            filter: parse_var("TEST_FILTER")?,
+            tags: list("TAGS"),
+            exclude_tags: list("EXCLUDE_TAGS"),
+            known_tags: list("KNOWN_TAGS"),
+        };
+        if config.structure_test.is_none() && config.structure_schema.is_none() && config.plugins.validator.is_none() {
+            return Err("set STRUCTURE_TEST, STRUCTURE_SCHEMA, VALIDATOR or any of them together".into());
+        }
+        if config.judge_cot && config.judge_format == JudgeFormat::Json {
+            return Err("JUDGE_COT cannot be combined with JUDGE_FORMAT=json, whose reason field already carries the reasoning".into());
+        }
+        if config.rubric.is_some() && (config.judge_cot || config.judge_mode == JudgeMode::Score) {
+            return Err("RUBRIC replaces the single verdict and cannot be combined with JUDGE_COT or JUDGE_MODE=score".into());
+        }
+        if config.judge_batch.is_some() && (config.rubric.is_some() || config.judge_cot) {
+            return Err("JUDGE_BATCH_SIZE asks for one JSON verdict per pair and cannot be combined with RUBRIC or JUDGE_COT".into());
+        }
+        if config.models.len() > 1 && config.gen_prompts.len() > 1 {
+            return Err("MODELS and GEN_PROMPTS cannot be combined; run one matrix at a time".into());
+        }
+        if let Some(model) = config.fallback_models.iter().find(|m| **m == config.model || config.models.contains(m)) {
+            return Err(format!("FALLBACK_MODELS names {}, which is a model the run generates with already", model).into());
+        }
+        if let Some(model) = config.fallback_models.iter().enumerate().find(|(i, m)| config.fallback_models[..*i].contains(m)).map(|(_, m)| m) {
+            return Err(format!("FALLBACK_MODELS names {} twice", model).into());
+        }
+        if config.cache.is_some() && config.dry_run {
+            return Err("CACHE cannot be combined with DRY_RUN, whose answers are not generations to keep".into());
+        }
+        if config.cassette.is_some() && config.dry_run {
+            return Err("CASSETTE cannot be combined with DRY_RUN, which calls no API to record or replay".into());
+        }
+        if config.provider == Provider::Azure && config.api_url.is_empty() {
+            return Err("PROVIDER=azure needs API_URL, the resource endpoint such as https://<resource>.openai.azure.com".into());
+        }
+        if !(0.0..=1.0).contains(&config.retry.jitter) {
+            return Err(format!("RETRY_JITTER must be between 0 and 1, got {}", config.retry.jitter).into());
+        }
+        if let Some(limit) = &config.rate_limit {
+            if limit.rpm == Some(0) || limit.tpm == Some(0) {
+                return Err("RATE_LIMIT_RPM and RATE_LIMIT_TPM must be at least 1".into());
+            }
+        }
+        if let Some(rate) = config.min_pass_rate.filter(|r| !(0.0..=1.0).contains(r)) {
+            return Err(format!("MIN_PASS_RATE must be between 0 and 1, got {}", rate).into());
+        }
+        if let Some(max_cost) = config.max_cost {
+            if max_cost.is_nan() || max_cost <= 0.0 {
+                return Err(format!("MAX_COST must be above 0, got {}", max_cost).into());
+            }
+            if config.prices.is_empty() {
+                return Err("MAX_COST needs prices, set PRICE_TABLE or PRICE_PER_MTOK".into());
+            }
+        }
+        if config.jobs == 0 {
+            return Err("JOBS must be at least 1".into());
+        }
+        if config.judge_votes == 0 {
+            return Err("JUDGE_VOTES must be at least 1".into());
+        }
+        Ok(config)
+    }
+
+    /// JUDGE_TEMPERATURE, defaulting to 0 for a single vote and to 1 when JUDGE_VOTES needs independent samples.
+    pub fn judge_temperature(&self) -> f32 {
+        self.judge_temperature.unwrap_or(if self.judge_votes > 1 { 1.0 } else { 0.0 })
+    }
+
+    /// Prints `line` on the run's [`Console`], if it has one.
+    pub fn say(&self, line: &str) {
+        if let Some(console) = &self.console {
+            console.print(line);
+        }
+    }
+
+    /// How the generation of test `name` is streamed: printed as it arrives with STREAM or VERBOSITY=verbose, and
+    /// watched for stalls with STREAM_IDLE_TIMEOUT_MS, which streams it without printing otherwise.
+    pub fn generation_stream<'a>(&self, name: &'a str) -> Option<Stream<'a>> {
+        let printed = self.stream || self.verbosity == Verbosity::Verbose;
+        (printed || self.stream_idle_timeout.is_some()).then_some(Stream { label: printed.then_some(name), idle_timeout: self.stream_idle_timeout })
+    }
+
+    pub fn json_mode(&self) -> bool {
+        matches!(self.response_format, Some(ResponseFormat::JsonObject | ResponseFormat::JsonSchema { .. }))
+    }
+
+    pub fn response_format_name(&self) -> Option<&'static str> {
+        self.response_format.as_ref().map(|f| match f {
+            ResponseFormat::Text => "text",
+            ResponseFormat::JsonObject => "json_object",
+            ResponseFormat::JsonSchema { .. } => "json_schema",
+        })
+    }
+}
+
+/// A comma-separated variable, empty when unset.
+fn list(name: &str) -> Vec<String> {
+    env::var(name).unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()
+}
+
+/// One generation prompt of GEN_PROMPTS, named after its file without the extension.
+#[derive(Clone, Debug)]
+pub struct PromptVariant {
+    pub name: String,
+    pub text: String,
+}
+
+/// Sampling parameters of the generation request; those not set are left to the provider's defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Sent for providers that support reproducible sampling, ignored by the others.
+    pub seed: Option<i64>,
+}
+
+impl GenParams {
+    /// These parameters, with those not set taken from `defaults`.
+    pub fn or(self, defaults: GenParams) -> GenParams {
+        GenParams {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+}
+
+fn required(name: &str) -> Result<String, AppError> {
+    env::var(name).map_err(|_| AppError::MissingEnv(name.to_string()))
+}
+
+fn read(var: &'static str, path: &str) -> Result<String, AppError> {
+    fs::read_to_string(path).map_err(|source| AppError::ConfigRead { var, path: path.to_string(), source })
+}
+
+fn parse_var<T: FromStr>(name: &str) -> Result<Option<T>, AppError> where T::Err: fmt::Display {
+    match env::var(name) {
+        Ok(v) if !v.trim().is_empty() => v.trim().parse().map(Some).map_err(|e| format!("invalid {} {:?}: {}", name, v, e).into()),
+        _ => Ok(None),
+    }
+}
+
+fn results_columns() -> Result<Vec<Column>, AppError> {
+    let columns = env::var("RESULTS_COLUMNS").unwrap_or_default();
+    if columns.trim().is_empty() {
+        return Ok(Column::all());
+    }
+    let columns: Vec<Column> = columns.split(',').map(|c| c.trim().parse()).collect::<Result<_, String>>().map_err(|e| format!("invalid RESULTS_COLUMNS: {}", e))?;
+    Ok(columns)
+}
+
+/// The CSV is always written, so `csv` in REPORT_FORMAT adds nothing.
+fn report_formats() -> Result<Vec<ReportFormat>, AppError> {
+    let formats = env::var("REPORT_FORMAT").unwrap_or_default();
+    let formats: Vec<ReportFormat> = formats.split(',').map(str::trim).filter(|f| !f.is_empty() && *f != "csv")
+        .map(str::parse).collect::<Result<_, String>>().map_err(|e| format!("invalid REPORT_FORMAT: {}", e))?;
+    Ok(formats)
+}
+
+fn response_format() -> Result<Option<ResponseFormat>, AppError> {
+    match env::var("RESPONSE_FORMAT").unwrap_or_default().as_str() {
+        "" | "text" => Ok(None),
+        "json_object" => Ok(Some(ResponseFormat::JsonObject)),
+        "json_schema" => {
+            let path = env::var("RESPONSE_SCHEMA").map_err(|_| "RESPONSE_FORMAT=json_schema requires RESPONSE_SCHEMA")?;
+            let schema: serde_json::Value = serde_json::from_str(&read("RESPONSE_SCHEMA", &path)?)
+                .map_err(|e| format!("RESPONSE_SCHEMA {} is not valid JSON: {}", path, e))?;
+            Ok(Some(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema { description: None, name: "structure".to_string(), schema: Some(schema), strict: None },
+            }))
+        }
+        other => Err(format!("unknown RESPONSE_FORMAT {:?}, expected json_object or json_schema", other).into()),
+    }
+}
+
+/// GEN_TOOL_SCHEMA may hold either a bare function definition or a full `{"type": "function", "function": ...}` tool.
+fn gen_tool() -> Result<Option<FunctionObject>, AppError> {
+    let Ok(path) = env::var("GEN_TOOL_SCHEMA") else {
+        return Ok(None);
+    };
+    let tool: serde_json::Value = serde_json::from_str(&read("GEN_TOOL_SCHEMA", &path)?)
+        .map_err(|e| format!("GEN_TOOL_SCHEMA {} is not valid JSON: {}", path, e))?;
+    let function = test_case::function(tool).map_err(|e| format!("GEN_TOOL_SCHEMA {} is not a function definition: {}", path, e))?;
+    Ok(Some(function))
+}
+
+fn structure_schema() -> Result<Option<Validator>, AppError> {
+    let Ok(path) = env::var("STRUCTURE_SCHEMA") else {
+        return Ok(None);
+    };
+    let schema: serde_json::Value = serde_json::from_str(&read("STRUCTURE_SCHEMA", &path)?)
+        .map_err(|e| format!("STRUCTURE_SCHEMA {} is not valid JSON: {}", path, e))?;
+    let validator = jsonschema::validator_for(&schema).map_err(|e| format!("STRUCTURE_SCHEMA {} is not a valid schema: {}", path, e))?;
+    Ok(Some(validator))
+}
+
+/// LUA_LIBS is a list of files separated like PATH (`:` on Unix, `;` on Windows).
+fn lua_libs() -> Result<Vec<LuaSource>, AppError> {
+    let Some(value) = env::var_os("LUA_LIBS") else {
+        return Ok(Vec::new());
+    };
+    env::split_paths(&value).filter(|p| !p.as_os_str().is_empty()).map(|p| {
+        let path = p.to_string_lossy().to_string();
+        let source = read("LUA_LIBS", &path)?;
+        Ok(LuaSource { path, source })
+    }).collect()
+}
+
+/// GEN_STOP is either a JSON array of strings or a comma-separated list where `\,` is a literal comma and
+/// `\n`, `\t` and `\\` are the usual escapes.
+fn gen_stop() -> Result<Vec<String>, AppError> {
+    let value = env::var("GEN_STOP").unwrap_or_default();
+    let stops: Vec<String> = if value.trim_start().starts_with('[') {
+        serde_json::from_str(&value).map_err(|e| format!("GEN_STOP is not a JSON array of strings: {}", e))?
+    } else {
+        split_escaped(&value)
+    };
+    if stops.len() > 4 {
+        return Err(format!("GEN_STOP has {} stop sequences, the API accepts at most 4", stops.len()).into());
+    }
+    Ok(stops)
+}
+
+fn split_escaped(value: &str) -> Vec<String> {
+    let mut stops = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => current.push('\n'),
+                Some('t') => current.push('\t'),
+                Some(other) => current.push(other),
+                None => current.push('\\'),
+            },
+            ',' => stops.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    stops.push(current);
+    stops.into_iter().filter(|s| !s.is_empty()).collect()
+}
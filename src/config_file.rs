@@ -0,0 +1,41 @@
+use std::{env, fs, path::Path};
+use serde_json::{Map, Value};
+use crate::error::AppError;
+
+/// Looked for in the working directory, in this order, when `--config` does not name a file.
+pub const DEFAULT_FILES: [&str; 3] = ["ai_test.toml", "ai_test.yaml", "ai_test.yml"];
+
+/// Sets the environment variables named in a config file that are not set already, so the environment and `.env` win
+/// over the file and the command line wins over both. Each top-level key is a setting: `test_dir = "tests"` sets
+/// TEST_DIR, `model` sets `model`, and a list such as `judge_models = ["a", "b"]` is joined with commas (or, for
+/// `gen_stop`, written as a JSON array, and for `api_headers`, one header per line). Returns the file used, if any; a `path` that does not exist is an error.
+/// Must run while the process is still single-threaded.
+pub fn apply(path: Option<&str>) -> Result<Option<String>, AppError> {
+    let Some(path) = path.map(str::to_string).or_else(|| DEFAULT_FILES.iter().find(|f| Path::new(f).is_file()).map(|f| f.to_string())) else {
+        return Ok(None);
+    };
+    let contents = fs::read_to_string(&path).map_err(AppError::io(format!("config file {}", &path)))?;
+    let settings: Map<String, Value> = if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?
+    } else {
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?
+    };
+    for (key, value) in settings {
+        let var = if key.eq_ignore_ascii_case("model") { "model".to_string() } else { key.to_ascii_uppercase() };
+        let value = match value {
+            Value::String(s) => s,
+            Value::Bool(_) | Value::Number(_) => value.to_string(),
+            Value::Array(items) if var == "GEN_STOP" => Value::Array(items).to_string(),
+            Value::Array(items) => items.iter().map(|item| match item {
+                Value::String(s) => Ok(s.clone()),
+                Value::Bool(_) | Value::Number(_) => Ok(item.to_string()),
+                _ => Err(format!("{}: {} must be a list of strings or numbers", path, key)),
+            }).collect::<Result<Vec<String>, String>>()?.join(if var == "API_HEADERS" { "\n" } else { "," }),
+            Value::Null | Value::Object(_) => return Err(format!("{}: {} must be a string, number, boolean or list; settings are top-level keys", path, key).into()),
+        };
+        if env::var_os(&var).is_none() {
+            env::set_var(var, value);
+        }
+    }
+    Ok(Some(path))
+}
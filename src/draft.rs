@@ -0,0 +1,134 @@
+use std::{fs, path::Path};
+use async_openai::types::{ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::{backend::ChatBackend, error::AppError, extract, is_case, retry, snapshot, test_case::TestCase, RunConfig};
+
+/// Extension of a drafted test, added to its file name so that it is not run before it is reviewed.
+pub const EXTENSION: &str = "draft";
+/// Existing test names listed in the prompt, so the model does not propose them again.
+const KNOWN_TESTS: usize = 50;
+
+/// The model's reply: the tests it proposes.
+#[derive(Deserialize)]
+struct Proposal {
+    tests: Vec<Proposed>,
+}
+
+#[derive(Deserialize)]
+struct Proposed {
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    input: String,
+    /// The expected output; a JSON answer may come as the value rather than as a string.
+    output: Value,
+}
+
+/// What `generate` made of the model's proposal.
+pub struct Drafted {
+    /// The draft files written, relative to TEST_DIR.
+    pub written: Vec<String>,
+    /// The proposed tests that were not usable, with why.
+    pub rejected: Vec<(String, String)>,
+}
+
+/// Asks the model for `count` test cases covering `spec`, a description of the feature under test, and writes each
+/// as `<name>.txt.draft` in TEST_DIR, in the test file format and tagged `generated`, for review. The prompt also has
+/// GEN_PROMPT, an existing test to follow and the names of the others, so that the drafts fit the suite.
+pub async fn generate(spec: &str, count: usize, config: &RunConfig, client: &dyn ChatBackend) -> Result<Drafted, AppError> {
+    let (tests, _) = crate::discover(config)?;
+    let example = tests.iter().find(|t| !is_case(&t.name) && TestCase::parse(&t.name, &t.contents).is_ok_and(|c| c.examples.is_empty() && c.history.is_empty()));
+    let example = example.map_or(String::new(), |t| format!("An existing test, for the file format and the style of the expected output:\n<test>\n{}\n</test>\n\n", t.contents.trim()));
+    let known: Vec<&str> = tests.iter().take(KNOWN_TESTS).map(|t| t.name.as_str()).collect();
+    let known = if known.is_empty() { String::new() } else { format!("The suite already has the tests {}; do not repeat them.\n\n", known.join(", ")) };
+    let prompt = format!(
+        "You write test cases for a feature built on a language model. Each test is an input for the feature and the output \
+         a correct answer would be, in {format}.\n\n\
+         The specification of the feature:\n<spec>\n{spec}\n</spec>\n\n\
+         The feature's prompt, which each test's input is filled into:\n<prompt>\n{prompt}\n</prompt>\n\n\
+         {example}{known}\
+         Propose {count} new test cases that together cover the specification: typical inputs, edge cases, and inputs the \
+         feature must handle with care. Each expected output must be exactly the answer the prompt asks for, in {format}.\n\n\
+         Reply with a JSON object only, of the form {{\"tests\": [{{\"name\": \"short_snake_case_name\", \"tags\": [\"tag\"], \
+         \"input\": \"the input\", \"output\": \"the expected output\"}}]}}.",
+        format = config.format.title(), spec = spec.trim(), prompt = config.gen_prompt.trim(),
+    );
+    let req = CreateChatCompletionRequestArgs::default()
+        .model(&config.model)
+        .messages(vec![ChatCompletionRequestUserMessageArgs::default().content(prompt).build().map_err(AppError::api("generate"))?.into()])
+        .build().map_err(AppError::api("generate"))?;
+    let generation = config.retry.run(|| client.complete(req.clone(), None)).await.map_err(|e| format!("the model could not draft tests: {}", retry::describe(&e)))?;
+    let reply = generation.choices.first().and_then(|c| c.content.as_deref()).unwrap_or_default();
+    let proposal = extract::balanced_spans(reply).into_iter().find_map(|span| serde_json::from_str::<Proposal>(span).ok())
+        .ok_or_else(|| format!("the model's reply has no {{\"tests\": [...]}} object: {}", reply))?;
+    let mut drafted = Drafted { written: Vec::new(), rejected: Vec::new() };
+    for test in proposal.tests {
+        let output = match test.output {
+            Value::String(output) => output,
+            output => output.to_string(),
+        };
+        let tags: Vec<&str> = std::iter::once("generated").chain(test.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty() && *t != "generated")).collect();
+        let contents = format!("<tags>{}</tags>\n<input>{}</input>\n<output>{}</output>\n", tags.join(", "), test.input.trim(), output.trim());
+        let name = file_name(&config.test_dir, &test.name);
+        if let Err(e) = TestCase::parse(&name, &contents) {
+            drafted.rejected.push((test.name, e));
+            continue;
+        }
+        let path = Path::new(&config.test_dir).join(format!("{}.{}", name, EXTENSION));
+        fs::write(&path, contents).map_err(AppError::io(path.display().to_string()))?;
+        drafted.written.push(format!("{}.{}", name, EXTENSION));
+    }
+    Ok(drafted)
+}
+
+/// A test file name for the proposed `name` that neither a test nor a draft in TEST_DIR has yet.
+fn file_name(test_dir: &str, name: &str) -> String {
+    let stem: String = name.trim().to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).take(60).collect();
+    let stem = stem.trim_matches('_');
+    let stem = if stem.is_empty() { "draft" } else { stem };
+    let taken = |name: &str| Path::new(test_dir).join(name).exists() || Path::new(test_dir).join(format!("{}.{}", name, EXTENSION)).exists();
+    (1..).map(|n| if n == 1 { format!("{}.txt", stem) } else { format!("{}_{}.txt", stem, n) }).find(|name| !taken(name)).expect("some name is free")
+}
+
+/// The drafts in TEST_DIR, by the name of the test each becomes, in order.
+pub fn pending(test_dir: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    snapshot::pending_in(Path::new(test_dir), "", EXTENSION, &mut names);
+    names.sort();
+    names
+}
+
+/// Makes the drafts whose name contains `filter`, or all of them, into tests by dropping their extension; a draft for
+/// a test that exists by now is an error. Returns the tests made.
+pub fn accept(test_dir: &str, filter: Option<&str>) -> Result<Vec<String>, AppError> {
+    let mut accepted = Vec::new();
+    for name in matching(test_dir, filter) {
+        let test = Path::new(test_dir).join(&name);
+        if test.exists() {
+            return Err(format!("{} exists already; rename its draft {}.{}", test.display(), name, EXTENSION).into());
+        }
+        let draft = Path::new(test_dir).join(format!("{}.{}", name, EXTENSION));
+        fs::rename(&draft, &test).map_err(AppError::io(draft.display().to_string()))?;
+        accepted.push(name);
+    }
+    Ok(accepted)
+}
+
+/// Deletes the drafts whose name contains `filter`, or all of them. Returns the tests whose draft was deleted.
+pub fn reject(test_dir: &str, filter: Option<&str>) -> Result<Vec<String>, AppError> {
+    let rejected = matching(test_dir, filter);
+    for name in &rejected {
+        let draft = Path::new(test_dir).join(format!("{}.{}", name, EXTENSION));
+        fs::remove_file(&draft).map_err(AppError::io(draft.display().to_string()))?;
+    }
+    Ok(rejected)
+}
+
+pub fn is_draft(file_name: &str) -> bool {
+    Path::new(file_name).extension().is_some_and(|e| e == EXTENSION)
+}
+
+fn matching(test_dir: &str, filter: Option<&str>) -> Vec<String> {
+    pending(test_dir).into_iter().filter(|name| filter.is_none_or(|f| name.contains(f))).collect()
+}
@@ -0,0 +1,78 @@
+use std::{fmt, io};
+use async_openai::error::OpenAIError;
+
+/// Everything that stops a run, as opposed to a failed test. Messages name the env var or file involved.
+#[derive(thiserror::Error)]
+pub enum AppError {
+    #[error("{0} is not set")]
+    MissingEnv(String),
+    #[error("cannot read {var} {path}: {source}")]
+    ConfigRead { var: &'static str, path: String, source: io::Error },
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{path}: {source}")]
+    Io { path: String, source: io::Error },
+    #[error("results file {path}: {source}")]
+    Csv { path: String, source: csv::Error },
+    #[error("HISTORY_FILE {path}: {source}")]
+    History { path: String, source: rusqlite::Error },
+    #[error("{phase} request failed: {source}")]
+    Api { phase: &'static str, source: OpenAIError },
+    #[error("{context}: {source}")]
+    Lua { context: String, source: mlua::Error },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+impl AppError {
+    pub fn io(path: impl Into<String>) -> impl FnOnce(io::Error) -> AppError {
+        let path = path.into();
+        move |source| AppError::Io { path, source }
+    }
+
+    pub fn csv(path: impl Into<String>) -> impl FnOnce(csv::Error) -> AppError {
+        let path = path.into();
+        move |source| AppError::Csv { path, source }
+    }
+
+    pub fn history(path: impl Into<String>) -> impl FnOnce(rusqlite::Error) -> AppError {
+        let path = path.into();
+        move |source| AppError::History { path, source }
+    }
+
+    pub fn api(phase: &'static str) -> impl FnOnce(OpenAIError) -> AppError {
+        move |source| AppError::Api { phase, source }
+    }
+
+    pub fn lua(context: impl Into<String>) -> impl FnOnce(mlua::Error) -> AppError {
+        let context = context.into();
+        move |source| AppError::Lua { context, source }
+    }
+}
+
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        AppError::Invalid(message)
+    }
+}
+
+impl From<&str> for AppError {
+    fn from(message: &str) -> Self {
+        AppError::Invalid(message.to_string())
+    }
+}
+
+impl From<mlua::Error> for AppError {
+    fn from(source: mlua::Error) -> Self {
+        AppError::Lua { context: "structure test".to_string(), source }
+    }
+}
+
+/// `main` reports a returned error with Debug, so it shows the same message as Display.
+impl fmt::Debug for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,294 @@
+use std::{fmt, str::FromStr};
+use regex::Regex;
+use crate::format::{self, Format};
+
+#[derive(Debug, PartialEq)]
+pub enum Extraction<'a> {
+    Found(Vec<&'a str>),
+    Invalid(String),
+    Missing,
+}
+
+/// How the candidates are found in a generated message, from EXTRACT or a test's `<extract>` section. The spans of a
+/// message are its top-level `{...}` and `[...]` for JSON, its top-level elements for XML, and the whole message for
+/// YAML, which has no delimiters to find it by.
+#[derive(Clone, Debug, Default)]
+pub enum Strategy {
+    /// A fenced code block's body, or else every span of the message.
+    #[default]
+    Auto,
+    /// Only the body of a fence tagged with the format, such as ```json, or of a bare ``` fence.
+    Fenced,
+    /// The first span that is valid in the format, skipping any placeholder like `{name}` in the explanation before it.
+    First,
+    /// The last span that is valid in the format, as models tend to explain first and answer last.
+    Last,
+    /// The whole message, trimmed, whether or not it is JSON.
+    Whole,
+    /// Every match of the pattern, or of its first group when it has one.
+    Regex(Regex),
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Strategy::Auto),
+            "fenced" => Ok(Strategy::Fenced),
+            "first" => Ok(Strategy::First),
+            "last" => Ok(Strategy::Last),
+            "whole" => Ok(Strategy::Whole),
+            other => match other.strip_prefix("regex:") {
+                Some(pattern) => Regex::new(pattern).map(Strategy::Regex).map_err(|e| format!("invalid extraction pattern: {}", e)),
+                None => Err(format!("unknown extraction {:?}, expected auto, fenced, first, last, whole or regex:<pattern>", other)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Auto => write!(f, "auto"),
+            Strategy::Fenced => write!(f, "fenced"),
+            Strategy::First => write!(f, "first"),
+            Strategy::Last => write!(f, "last"),
+            Strategy::Whole => write!(f, "whole"),
+            Strategy::Regex(r) => write!(f, "regex:{}", r),
+        }
+    }
+}
+
+/// The candidates `strategy` finds in `message` for output in `format`.
+pub fn extract<'m>(message: &'m str, strategy: &Strategy, format: Format) -> Extraction<'m> {
+    match strategy {
+        Strategy::Auto => {
+            if let Some(block) = fenced_block(message, format) {
+                return checked_block(block, format);
+            }
+            let spans = spans(message, format);
+            if spans.is_empty() { Extraction::Missing } else { Extraction::Found(spans) }
+        }
+        Strategy::Fenced => match fenced_block(message, format) {
+            Some(block) => checked_block(block, format),
+            None => Extraction::Invalid("no fenced code block in the message".to_string()),
+        },
+        Strategy::First | Strategy::Last => {
+            let spans = spans(message, format);
+            let valid: Vec<&str> = spans.iter().copied().filter(|span| format.parse(span).is_ok()).collect();
+            match if matches!(strategy, Strategy::First) { valid.first() } else { valid.last() } {
+                Some(span) => Extraction::Found(vec![span]),
+                None if spans.is_empty() => Extraction::Missing,
+                None => Extraction::Invalid(format!("none of the {} {}-like spans in the message is valid {}", spans.len(), format.title(), format.title())),
+            }
+        }
+        Strategy::Whole => match message.trim() {
+            "" => Extraction::Missing,
+            whole => Extraction::Found(vec![whole]),
+        },
+        Strategy::Regex(r) => {
+            let found: Vec<&str> = r.captures_iter(message).filter_map(|c| c.get(1).or_else(|| c.get(0))).map(|m| m.as_str().trim()).collect();
+            if found.is_empty() { Extraction::Invalid(format!("extraction pattern {} matched nothing in the message", r)) } else { Extraction::Found(found) }
+        }
+    }
+}
+
+fn spans(message: &str, format: Format) -> Vec<&str> {
+    match format {
+        Format::Json => balanced_spans(message),
+        Format::Xml => format::xml_spans(message),
+        Format::Yaml => Some(message.trim()).filter(|m| !m.is_empty()).into_iter().collect(),
+    }
+}
+
+fn checked_block(block: &str, format: Format) -> Extraction<'_> {
+    match format.parse(block) {
+        Ok(_) => Extraction::Found(vec![block]),
+        Err(e) => Extraction::Invalid(format!("fenced code block is not valid {}: {}", format.title(), e)),
+    }
+}
+
+/// Returns the body of the first fence tagged with the format, or of the first bare ``` fence if none is.
+fn fenced_block(message: &str, format: Format) -> Option<&str> {
+    let mut bare = None;
+    for (lang, body) in fences(message) {
+        if format.fence_tags().iter().any(|tag| lang.eq_ignore_ascii_case(tag)) {
+            return Some(body);
+        }
+        if lang.is_empty() && bare.is_none() {
+            bare = Some(body);
+        }
+    }
+    bare
+}
+
+/// The language and trimmed body of every fenced code block, in order. A fence opens with a run of three or more
+/// backticks and closes at the next line that is a run at least as long, so a body quoting a shorter fence, such as a
+/// JSON string holding one, is not cut short. A fence closed at the end of its last line rather than on a line of its
+/// own closes at the next long enough run.
+fn fences(message: &str) -> Vec<(&str, &str)> {
+    let mut fences = Vec::new();
+    let mut rest = message;
+    while let Some(open) = rest.find("```") {
+        let ticks = rest[open..].find(|c| c != '`').unwrap_or(rest.len() - open);
+        let after = &rest[open + ticks..];
+        let Some(newline) = after.find('\n') else { break };
+        let info = after[..newline].trim();
+        let lang = &info[..info.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '+' | '-'))).unwrap_or(info.len())];
+        let body = &after[newline + 1..];
+        let Some((end, next)) = closing_fence(body, ticks) else { break };
+        fences.push((lang, body[..end].trim()));
+        rest = &body[next..];
+    }
+    fences
+}
+
+/// Where the fence closing a body opened with `ticks` backticks starts and ends.
+fn closing_fence(body: &str, ticks: usize) -> Option<(usize, usize)> {
+    let mut start = 0;
+    for line in body.split_inclusive('\n') {
+        let fence = line.trim();
+        if fence.len() >= ticks && fence.chars().all(|c| c == '`') {
+            return Some((start, start + line.len()));
+        }
+        start += line.len();
+    }
+    let start = body.find(&"`".repeat(ticks))?;
+    let len = body[start..].find(|c| c != '`').unwrap_or(body.len() - start);
+    Some((start, start + len))
+}
+
+/// Every top-level `{...}` or `[...]` span in order, skipping brackets that appear inside JSON strings.
+pub fn balanced_spans(message: &str) -> Vec<&str> {
+    let bytes = message.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if matches!(bytes[i], b'{' | b'[') {
+            if let Some(end) = span_end(bytes, i) {
+                spans.push(&message[i..end]);
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    spans
+}
+
+fn span_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &b) in bytes.iter().enumerate().skip(start) {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' => closers.push(b'}'),
+            b'[' => closers.push(b']'),
+            b'}' | b']' => {
+                if closers.pop() != Some(b) {
+                    return None;
+                }
+                if closers.is_empty() {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto(message: &str) -> Extraction<'_> {
+        extract(message, &Strategy::Auto, Format::Json)
+    }
+
+    #[test]
+    fn the_first_json_fence_wins_over_an_earlier_bare_fence() {
+        let message = "Run this first:\n```\nnpm install\n```\nThe answer:\n```json\n{\"a\": 1}\n```\nOr:\n```json\n{\"a\": 2}\n```\n";
+        assert_eq!(auto(message), Extraction::Found(vec!["{\"a\": 1}"]));
+        assert_eq!(extract(message, &Strategy::Fenced, Format::Json), Extraction::Found(vec!["{\"a\": 1}"]));
+    }
+
+    #[test]
+    fn a_fence_tag_matches_in_any_case() {
+        assert_eq!(auto("```\n[1]\n```\n```JSON\n[2]\n```"), Extraction::Found(vec!["[2]"]));
+    }
+
+    #[test]
+    fn a_bare_fence_is_used_when_none_is_tagged() {
+        let message = "Note the {placeholder} here.\n```\n{\"a\": 1}\n```\n```\n{\"a\": 2}\n```";
+        assert_eq!(auto(message), Extraction::Found(vec!["{\"a\": 1}"]));
+    }
+
+    #[test]
+    fn a_fence_of_another_language_is_not_used() {
+        assert_eq!(auto("```python\nprint({})\n```\nThen {\"a\": 1}"), Extraction::Found(vec!["{}", "{\"a\": 1}"]));
+    }
+
+    #[test]
+    fn an_invalid_fence_is_reported_rather_than_skipped() {
+        let message = "```json\n{\"a\": 1,}\n```\nAlso {\"a\": 1} in prose.";
+        let Extraction::Invalid(reason) = auto(message) else { panic!("expected the fence to be reported as invalid") };
+        assert!(reason.starts_with("fenced code block is not valid JSON"), "{}", reason);
+    }
+
+    #[test]
+    fn a_message_without_a_fence_falls_back_to_its_spans() {
+        assert_eq!(auto("Here: {\"a\": 1}"), Extraction::Found(vec!["{\"a\": 1}"]));
+        assert_eq!(auto("No JSON here."), Extraction::Missing);
+        assert!(matches!(extract("No JSON here.", &Strategy::Fenced, Format::Json), Extraction::Invalid(_)));
+    }
+
+    #[test]
+    fn a_longer_fence_holds_a_shorter_one() {
+        let message = "````json\n{\"doc\": \"```json\\n{}\\n```\"}\n````";
+        assert_eq!(auto(message), Extraction::Found(vec!["{\"doc\": \"```json\\n{}\\n```\"}"]));
+    }
+
+    #[test]
+    fn balanced_spans_keep_nested_objects_whole() {
+        assert_eq!(balanced_spans(r#"{"a":{"b":1}}"#), [r#"{"a":{"b":1}}"#]);
+        assert_eq!(balanced_spans(r#"Result: {"a": [{"b": [1, {"c": {}}]}], "d": 2}."#), [r#"{"a": [{"b": [1, {"c": {}}]}], "d": 2}"#]);
+    }
+
+    #[test]
+    fn balanced_spans_ignore_brackets_inside_strings() {
+        let message = r#"{"s": "a } ] { [", "t": "say \"}\" and \\", "u": "\\\"{"}"#;
+        assert_eq!(balanced_spans(message), [message]);
+        assert_eq!(balanced_spans(r#"["]", "[", "\"]"]"#), [r#"["]", "[", "\"]"]"#]);
+    }
+
+    #[test]
+    fn balanced_spans_find_every_candidate_in_order() {
+        let message = r#"Either {"a": 1} or [1, 2], and finally {"b": {"c": []}} with {name} as a placeholder"#;
+        assert_eq!(balanced_spans(message), [r#"{"a": 1}"#, "[1, 2]", r#"{"b": {"c": []}}"#, "{name}"]);
+    }
+
+    #[test]
+    fn balanced_spans_find_nothing_in_unbalanced_input() {
+        for message in [r#"{"a": 1"#, r#"{"a": [1, 2}"#, "]} ) {", r#"{"a": "}"#, "[[[", "", "}"] {
+            assert!(balanced_spans(message).is_empty(), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn balanced_spans_slice_around_multibyte_text() {
+        assert_eq!(balanced_spans(r#"é {"ü": "ß"} ✓ [ "ñ" ]"#), [r#"{"ü": "ß"}"#, r#"[ "ñ" ]"#]);
+    }
+}
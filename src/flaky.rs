@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use crate::{history::{self, RunRecord}, ErrorLocation, Status, TestResult};
+
+/// One outcome of a test: a pass, or a failure at where it happened when that is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(Option<ErrorLocation>),
+}
+
+impl From<Option<ErrorLocation>> for Outcome {
+    /// A PASS_AT_K attempt, `None` when it passed.
+    fn from(attempt: Option<ErrorLocation>) -> Outcome {
+        attempt.map_or(Outcome::Passed, |location| Outcome::Failed(Some(location)))
+    }
+}
+
+/// A test that both passed and failed over runs with the same settings, or over the PASS_AT_K attempts of one run.
+pub struct Flaky {
+    pub name: String,
+    /// One `P` or `F` per outcome, oldest first.
+    pub outcomes: String,
+    pub passed: usize,
+    /// How many times an outcome differs from the one before it.
+    pub flips: usize,
+    /// The location most of its failures were at, with how many were; none when no failure has one.
+    pub dominant: Option<(ErrorLocation, usize)>,
+}
+
+impl Flaky {
+    pub fn failed(&self) -> usize {
+        self.outcomes.len() - self.passed
+    }
+
+    /// The share of consecutive outcomes that flip, 1 for a test that alternates.
+    pub fn instability(&self) -> f64 {
+        self.flips as f64 / (self.outcomes.len() - 1) as f64
+    }
+}
+
+/// The flaky tests among `outcomes`, each test's outcomes in order, most unstable first. Tests that flip as often are
+/// ranked by how close they are to passing half the time, which is where a rerun tells least.
+pub fn flaky(outcomes: BTreeMap<String, Vec<Outcome>>) -> Vec<Flaky> {
+    let mut flaky: Vec<Flaky> = outcomes.into_iter().filter_map(|(name, outcomes)| {
+        let passed = outcomes.iter().filter(|o| **o == Outcome::Passed).count();
+        if passed == 0 || passed == outcomes.len() {
+            return None;
+        }
+        let mut locations: BTreeMap<&'static str, (ErrorLocation, usize)> = BTreeMap::new();
+        for outcome in &outcomes {
+            if let Outcome::Failed(Some(location)) = outcome {
+                locations.entry(location.name()).or_insert((*location, 0)).1 += 1;
+            }
+        }
+        // The first of equally common locations by name, so that the report does not change from one listing to the next.
+        let dominant = locations.into_values().fold(None, |best: Option<(ErrorLocation, usize)>, (location, n)| match best {
+            Some((_, most)) if most >= n => best,
+            _ => Some((location, n)),
+        });
+        Some(Flaky {
+            name,
+            outcomes: outcomes.iter().map(|o| if *o == Outcome::Passed { 'P' } else { 'F' }).collect(),
+            passed,
+            flips: outcomes.windows(2).filter(|w| (w[0] == Outcome::Passed) != (w[1] == Outcome::Passed)).count(),
+            dominant,
+        })
+    }).collect();
+    let balance = |f: &Flaky| (f.passed as f64 / f.outcomes.len() as f64 - 0.5).abs();
+    flaky.sort_by(|a, b| b.instability().total_cmp(&a.instability()).then(balance(a).total_cmp(&balance(b))).then_with(|| a.name.cmp(&b.name)));
+    flaky
+}
+
+/// The runs of `runs` with the same settings as the latest, the `last` most recent of them or all, and the flaky tests
+/// over their outcomes. A run recorded with PASS_AT_K adds every attempt's outcome; tests not run or not counted in
+/// the pass rate add none.
+pub fn from_history(runs: &[RunRecord], last: Option<usize>) -> (Vec<&RunRecord>, Vec<Flaky>) {
+    let Some(latest) = runs.last() else {
+        return (Vec::new(), Vec::new());
+    };
+    let same: Vec<&RunRecord> = runs.iter().filter(|r| same_settings(r, latest)).collect();
+    let same = same[same.len().saturating_sub(last.unwrap_or(same.len()))..].to_vec();
+    let mut outcomes: BTreeMap<String, Vec<Outcome>> = BTreeMap::new();
+    for run in &same {
+        for test in run.tests.iter().filter(|t| counted(t.status)) {
+            let test_outcomes = outcomes.entry(test.name.clone()).or_default();
+            if test.attempts.is_empty() {
+                test_outcomes.push(if test.passed { Outcome::Passed } else { Outcome::Failed(test.location) });
+            } else {
+                test_outcomes.extend(test.attempts.iter().copied().map(Outcome::from));
+            }
+        }
+    }
+    (same, flaky(outcomes))
+}
+
+/// The flaky tests over the PASS_AT_K attempts of one run's results.
+pub fn from_results(results: &[TestResult]) -> Vec<Flaky> {
+    flaky(results.iter().filter(|r| counted(r.status)).map(|r| (r.name.clone(), history::attempts(r).into_iter().map(Outcome::from).collect())).collect())
+}
+
+/// Whether two recorded runs are comparable: the same fingerprint, or for runs recorded without one the same model and
+/// judges.
+pub fn same_settings(a: &RunRecord, b: &RunRecord) -> bool {
+    match (&a.fingerprint, &b.fingerprint) {
+        (None, None) => a.model == b.model && a.judge_models == b.judge_models,
+        (a, b) => a == b,
+    }
+}
+
+/// Whether a status is a pass or a failure of the test itself, as opposed to one it was skipped with.
+fn counted(status: Status) -> bool {
+    matches!(status, Status::Passed | Status::Human | Status::Failed | Status::Missing)
+}
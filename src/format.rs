@@ -0,0 +1,273 @@
+use std::{fmt, str::FromStr};
+use serde_json::{Map, Value};
+
+/// The format a test's output is written in, from FORMAT or a test's `<format>` section.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Json,
+    Yaml,
+    Xml,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "xml" => Ok(Format::Xml),
+            other => Err(format!("unknown format {:?}, expected json, yaml or xml", other)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::Yaml => write!(f, "yaml"),
+            Format::Xml => write!(f, "xml"),
+        }
+    }
+}
+
+impl Format {
+    /// The format's name in messages.
+    pub fn title(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Yaml => "YAML",
+            Format::Xml => "XML",
+        }
+    }
+
+    /// The languages a fenced code block in this format is tagged with.
+    pub fn fence_tags(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["json"],
+            Format::Yaml => &["yaml", "yml"],
+            Format::Xml => &["xml"],
+        }
+    }
+
+    /// `text` read into the structure the schema and `LUA_INPUT=table` check: a YAML document as the JSON it maps to,
+    /// and an XML document as [`xml`] reads it.
+    pub fn parse(self, text: &str) -> Result<Value, String> {
+        match self {
+            Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            Format::Xml => xml(text),
+        }
+    }
+
+    /// `text` as compact JSON, what the structure test and validator see with CANONICAL_JSON.
+    pub fn canonical(self, text: &str) -> Result<String, String> {
+        self.parse(text).map(|value| value.to_string())
+    }
+}
+
+/// An XML document as JSON, `{"root": ...}` for its root element `root`. An element with neither attributes nor child
+/// elements is its text. Any other is an object of its attributes as `"@name"`, its child elements by name, those
+/// named more than once as an array in document order, and its text, when not blank, as `"#text"`. XML has no numbers
+/// or booleans, so every value is a string. The declaration, comments, processing instructions and a DOCTYPE are
+/// skipped; CDATA sections are text.
+pub fn xml(text: &str) -> Result<Value, String> {
+    let mut reader = Reader { text, at: 0 };
+    reader.misc()?;
+    if !reader.rest().starts_with('<') {
+        return Err(reader.error("expected the root element"));
+    }
+    let (name, value) = reader.element()?;
+    reader.misc()?;
+    if !reader.rest().is_empty() {
+        return Err(reader.error("text after the root element"));
+    }
+    Ok(Value::Object(Map::from_iter([(name, value)])))
+}
+
+/// Every top-level span of `message` that is a well-formed XML element, in order.
+pub fn xml_spans(message: &str) -> Vec<&str> {
+    let mut spans = Vec::new();
+    let mut at = 0;
+    while let Some(i) = message[at..].find('<') {
+        let start = at + i;
+        let mut reader = Reader { text: message, at: start };
+        if message[start + 1..].starts_with(is_name_start) && reader.element().is_ok() {
+            spans.push(&message[start..reader.at]);
+            at = reader.at;
+        } else {
+            at = start + 1;
+        }
+    }
+    spans
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.at..]
+    }
+
+    fn error(&self, what: impl fmt::Display) -> String {
+        format!("{} on line {}", what, self.text[..self.at].matches('\n').count() + 1)
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.at += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str, what: &str) -> Result<(), String> {
+        let i = self.rest().find(end).ok_or_else(|| self.error(format!("{} is not closed", what)))?;
+        self.at += i + end.len();
+        Ok(())
+    }
+
+    /// Skips the whitespace, declaration, comments, processing instructions and DOCTYPE around the root element.
+    fn misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>", "a processing instruction")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "a comment")?;
+            } else if rest.starts_with("<!DOCTYPE") {
+                self.skip_past(">", "the DOCTYPE")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest();
+        if !rest.starts_with(is_name_start) {
+            return Err(self.error("expected a name"));
+        }
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))).unwrap_or(rest.len());
+        self.at += len;
+        Ok(&rest[..len])
+    }
+
+    /// The element at `<`, with the reader left after its end.
+    fn element(&mut self) -> Result<(String, Value), String> {
+        self.at += 1;
+        let name = self.name()?;
+        let mut fields = Map::new();
+        loop {
+            self.skip_space();
+            if self.rest().starts_with("/>") {
+                self.at += 2;
+                return Ok((name.to_string(), if fields.is_empty() { Value::String(String::new()) } else { Value::Object(fields) }));
+            }
+            if self.rest().starts_with('>') {
+                self.at += 1;
+                break;
+            }
+            let attribute = self.name()?;
+            self.skip_space();
+            if !self.rest().starts_with('=') {
+                return Err(self.error(format!("expected = after the attribute {}", attribute)));
+            }
+            self.at += 1;
+            self.skip_space();
+            let Some(quote) = self.rest().chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+                return Err(self.error(format!("expected a quoted value for the attribute {}", attribute)));
+            };
+            self.at += 1;
+            let len = self.rest().find(quote).ok_or_else(|| self.error(format!("the value of the attribute {} is not closed", attribute)))?;
+            let value = unescape(&self.rest()[..len]).map_err(|e| self.error(e))?;
+            self.at += len + 1;
+            if fields.insert(format!("@{}", attribute), Value::String(value)).is_some() {
+                return Err(self.error(format!("<{}> has the attribute {} twice", name, attribute)));
+            }
+        }
+        let mut text = String::new();
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return Err(self.error(format!("<{}> is not closed", name)));
+            } else if rest.starts_with("</") {
+                self.at += 2;
+                let close = self.name()?;
+                if close != name {
+                    return Err(self.error(format!("</{}> closes <{}>", close, name)));
+                }
+                self.skip_space();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error(format!("expected > to end </{}>", name)));
+                }
+                self.at += 1;
+                break;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "a comment")?;
+            } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let len = cdata.find("]]>").ok_or_else(|| self.error("a CDATA section is not closed"))?;
+                text.push_str(&cdata[..len]);
+                self.at += "<![CDATA[".len() + len + "]]>".len();
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", "a processing instruction")?;
+            } else if rest.starts_with('<') {
+                let (child, value) = self.element()?;
+                let value = match fields.remove(&child) {
+                    None => value,
+                    Some(Value::Array(mut items)) => {
+                        items.push(value);
+                        Value::Array(items)
+                    }
+                    Some(first) => Value::Array(vec![first, value]),
+                };
+                fields.insert(child, value);
+            } else {
+                let len = rest.find('<').unwrap_or(rest.len());
+                text.push_str(&unescape(&rest[..len]).map_err(|e| self.error(e))?);
+                self.at += len;
+            }
+        }
+        let text = text.trim();
+        if fields.is_empty() {
+            return Ok((name.to_string(), Value::String(text.to_string())));
+        }
+        if !text.is_empty() {
+            fields.insert("#text".to_string(), Value::String(text.to_string()));
+        }
+        Ok((name.to_string(), Value::Object(fields)))
+    }
+}
+
+/// Replaces the predefined entities and character references.
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        let len = rest[i..].find(';').ok_or("an & that starts no entity")?;
+        let entity = &rest[i + 1..i + len];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok()),
+            }.and_then(char::from_u32),
+        };
+        unescaped.push(c.ok_or_else(|| format!("unknown entity &{};", entity))?);
+        rest = &rest[i + len + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, fs::{self, File}, io::Write, path::Path};
+use async_openai::types::CompletionUsage;
+use inline_colorization::*;
+use serde::{Deserialize, Serialize};
+use crate::{backend::ChatBackend, client, discover, dry_run_backend, error::AppError, extract_candidates, fit_input, generate, generation, select, test_case::{self, TestCase}, ErrorLocation, RunConfig, RunError, TestError, TestInfo};
+
+/// One test's generation as `--phase generate` saves it, one JSON object per line: the raw message and extracted
+/// candidates of every choice, or where the test failed before the structure test.
+#[derive(Serialize, Deserialize)]
+pub struct Generated {
+    pub name: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<CompletionUsage>,
+    /// The response id of the generation request.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// The input as it was sent, when INPUT_MAX_TOKENS truncated it.
+    #[serde(default)]
+    pub input: Option<String>,
+    /// The test file could not be parsed or the generation request failed, so there are no choices.
+    pub error: Option<SavedError>,
+    pub choices: Vec<SavedChoice>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedChoice {
+    pub message: String,
+    pub candidates: Vec<String>,
+    /// Why no candidates could be extracted.
+    pub error: Option<SavedError>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedError {
+    pub location: ErrorLocation,
+    pub content: String,
+    pub message: Option<String>,
+}
+
+impl From<TestError> for SavedError {
+    fn from(e: TestError) -> Self {
+        SavedError { message: e.message(), location: e.location, content: e.content }
+    }
+}
+
+impl SavedError {
+    pub fn to_error(&self) -> TestError {
+        TestError::new(self.location, self.content.clone(), self.message.clone())
+    }
+}
+
+impl Generated {
+    /// The first error that leaves the test without a candidate, if every choice failed extraction.
+    pub fn failure(&self) -> Option<&SavedError> {
+        if self.error.is_some() {
+            return self.error.as_ref();
+        }
+        if self.choices.iter().all(|c| c.error.is_some()) { self.choices.first().and_then(|c| c.error.as_ref()) } else { None }
+    }
+}
+
+/// Generates and extracts the candidates of a single test without running the structure test or the judge.
+pub async fn generate_only(test: &TestInfo, config: &RunConfig, client: &dyn ChatBackend) -> Result<Generated, RunError> {
+    let failed = |e: TestError| Generated { name: test.name.clone(), finish_reason: None, usage: None, id: None, input: None, error: Some(e.into()), choices: Vec::new() };
+    let case = match TestCase::parse(&test.name, &test.contents) {
+        Ok(case) => case,
+        Err(e) => return Ok(failed(TestError::because(ErrorLocation::MatchInput, test.contents.clone(), e))),
+    };
+    let conversation = match test_case::conversation(&test.contents) {
+        Ok(None) => None,
+        Ok(Some(_)) => Some("a conversation is generated and judged checkpoint by checkpoint, so it cannot be split into phases".to_string()),
+        Err(e) => Some(e),
+    };
+    if let Some(err) = conversation {
+        return Ok(failed(TestError::because(ErrorLocation::MatchInput, test.contents.clone(), err)));
+    }
+    let truncated = match fit_input(&case, config) {
+        Ok(truncated) => truncated,
+        Err(e) => return Ok(failed(e)),
+    };
+    let case = match &truncated {
+        Some(t) => TestCase { input: &t.text, ..case },
+        None => case,
+    };
+    let generation = match generate(&case, config, client, &config.model, config.gen_n, 0).await? {
+        Ok(generation) => generation,
+        Err(e) => return Ok(failed(e)),
+    };
+    let mut choices = Vec::new();
+    for choice in &generation.choices {
+        choices.push(match extract_candidates(choice, generation.usage.as_ref(), &case, config)? {
+            Ok(extracted) => SavedChoice { message: extracted.message, candidates: extracted.candidates, error: None },
+            Err(e) => SavedChoice { message: choice.content.clone().unwrap_or_default(), candidates: Vec::new(), error: Some(e.into()) },
+        });
+    }
+    let reasons: Vec<&str> = generation.choices.iter().map(|c| c.finish_reason.map(generation::finish_reason_name).unwrap_or("")).collect();
+    Ok(Generated { name: test.name.clone(), finish_reason: Some(reasons.join(", ")), usage: generation.usage, id: generation.id, input: truncated.map(|t| t.text), error: None, choices })
+}
+
+/// `--phase generate`: generates every test in filename order and appends each to the JSONL file at `path` as soon as
+/// it is done.
+pub async fn generate_suite(config: &RunConfig, path: &str) -> Result<Vec<Generated>, RunError> {
+    let client = client(config);
+    let (tests, skipped) = discover(config)?;
+    for s in &skipped {
+        println!("{color_yellow}Skipping {}: {}{color_reset}", s.path, s.reason);
+    }
+    let (tests, _) = select::select(config, tests);
+    let mut file = File::create(path).map_err(AppError::io(path))?;
+    let mut generated = Vec::new();
+    for test in tests {
+        let g = if config.dry_run {
+            generate_only(&test, config, &dry_run_backend(&test, config)).await?
+        } else {
+            generate_only(&test, config, &*client).await?
+        };
+        writeln!(file, "{}", serde_json::to_string(&g)?).map_err(AppError::io(path))?;
+        generated.push(g);
+    }
+    Ok(generated)
+}
+
+/// The generations saved by `--phase generate`, by test name, from a `.generated.jsonl` file or the newest one in a
+/// directory.
+pub fn load(path: &str) -> Result<BTreeMap<String, Generated>, AppError> {
+    let path = if Path::new(path).is_dir() {
+        let mut files: Vec<String> = fs::read_dir(path).map_err(AppError::io(path))?.flatten()
+            .filter_map(|e| e.file_name().to_str().filter(|n| n.ends_with(".generated.jsonl")).map(str::to_string))
+            .collect();
+        files.sort();
+        let newest = files.pop().ok_or_else(|| format!("{} contains no .generated.jsonl file", path))?;
+        Path::new(path).join(newest).display().to_string()
+    } else {
+        path.to_string()
+    };
+    let contents = fs::read_to_string(&path).map_err(AppError::io(&path))?;
+    let mut generated = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let g: Generated = serde_json::from_str(line).map_err(|e| format!("{} line {}: {}", path, i + 1, e))?;
+        generated.insert(g.name.clone(), g);
+    }
+    Ok(generated)
+}
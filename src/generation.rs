@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fmt, io::{self, Write}, str::FromStr, time::{Duration, Instant}};
+use async_openai::{config::Config, error::OpenAIError, types::{ChatCompletionStreamOptions, ChatCompletionTokenLogprob, CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionResponse, FinishReason, FunctionCall}, Client};
+use futures::StreamExt;
+use inline_colorization::*;
+use serde::{Deserialize, Serialize};
+use crate::backend::Stream;
+
+/// A chat completion assembled either from a single response or from a stream of chunks.
+#[derive(Serialize, Deserialize)]
+pub struct Generation {
+    pub choices: Vec<GenChoice>,
+    pub usage: Option<CompletionUsage>,
+    /// The response id the API assigned, for tracing a result back to its request.
+    pub id: Option<String>,
+    /// How many times the request was retried before this response.
+    #[serde(skip)]
+    pub retries: u32,
+    /// How long a streamed response took to its first token.
+    #[serde(skip)]
+    pub first_token: Option<Duration>,
+    /// The FALLBACK_MODELS model that generated it, when the model asked for failed.
+    #[serde(skip)]
+    pub fallback: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenChoice {
+    pub content: Option<String>,
+    pub refusal: Option<String>,
+    pub finish_reason: Option<FinishReason>,
+    pub tool_calls: Vec<FunctionCall>,
+    pub logprobs: Vec<ChatCompletionTokenLogprob>,
+}
+
+impl From<CreateChatCompletionResponse> for Generation {
+    fn from(res: CreateChatCompletionResponse) -> Self {
+        Generation {
+            choices: res.choices.into_iter().map(|c| GenChoice {
+                content: c.message.content,
+                refusal: c.message.refusal,
+                finish_reason: c.finish_reason,
+                tool_calls: c.message.tool_calls.unwrap_or_default().into_iter().map(|t| t.function).collect(),
+                logprobs: c.logprobs.and_then(|l| l.content).unwrap_or_default(),
+            }).collect(),
+            usage: res.usage,
+            id: Some(res.id),
+            retries: 0,
+            first_token: None,
+            fallback: None,
+        }
+    }
+}
+
+impl GenChoice {
+    /// Explains why a choice carried no content, using whatever the API reported.
+    pub fn tool_arguments(&self, name: &str) -> Option<&str> {
+        self.tool_calls.iter().find(|t| t.name == name).map(|t| t.arguments.as_str())
+    }
+
+    /// A short description of what the choice contained, for reporting a response that lacked the expected tool call.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(content) = &self.content {
+            let preview: String = content.chars().take(200).collect();
+            parts.push(format!("content: {:?}", preview));
+        }
+        if let Some(refusal) = &self.refusal {
+            parts.push(format!("refusal: {:?}", refusal));
+        }
+        if !self.tool_calls.is_empty() {
+            parts.push(format!("tool calls: {}", self.tool_calls.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", ")));
+        }
+        if let Some(reason) = self.finish_reason {
+            parts.push(format!("finish_reason: {}", finish_reason_name(reason)));
+        }
+        if parts.is_empty() { "empty response".to_string() } else { parts.join("; ") }
+    }
+
+    pub fn missing_content(&self) -> String {
+        match (&self.refusal, self.finish_reason) {
+            (Some(refusal), _) => format!("model refused: {}", refusal),
+            (None, Some(reason)) => format!("response had no content (finish_reason: {})", finish_reason_name(reason)),
+            (None, None) => "response had no content".to_string(),
+        }
+    }
+}
+
+pub fn finish_reason_name(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls => "tool_calls",
+        FinishReason::ContentFilter => "content_filter",
+        FinishReason::FunctionCall => "function_call",
+    }
+}
+
+/// How many of a request's GEN_N completions have to pass for the request to pass (GEN_POLICY), as in a
+/// self-consistency pipeline that only trusts an answer most samples agree on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GenPolicy {
+    /// Any one of them.
+    Any,
+    /// More than half.
+    Majority,
+    Unanimous,
+    /// At least this share of them.
+    Share(f64),
+}
+
+impl GenPolicy {
+    pub fn passes(self, passed: usize, total: usize) -> bool {
+        match self {
+            GenPolicy::Any => passed > 0,
+            GenPolicy::Majority => passed * 2 > total,
+            GenPolicy::Unanimous => passed == total,
+            GenPolicy::Share(share) => passed > 0 && passed as f64 >= share * total as f64,
+        }
+    }
+}
+
+impl FromStr for GenPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(GenPolicy::Any),
+            "majority" => Ok(GenPolicy::Majority),
+            "unanimous" => Ok(GenPolicy::Unanimous),
+            other => match other.parse::<f64>() {
+                Ok(share) if share > 0.0 && share <= 1.0 => Ok(GenPolicy::Share(share)),
+                _ => Err(format!("unknown generation policy {:?}, expected any, majority, unanimous or a share between 0 and 1", other)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for GenPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenPolicy::Any => write!(f, "any"),
+            GenPolicy::Majority => write!(f, "majority"),
+            GenPolicy::Unanimous => write!(f, "unanimous"),
+            GenPolicy::Share(share) => write!(f, "{}", share),
+        }
+    }
+}
+
+/// Sends the generation request, streamed when `stream` is given.
+pub async fn generate<C: Config>(client: &Client<C>, req: CreateChatCompletionRequest, stream: Option<Stream<'_>>) -> Result<Generation, OpenAIError> {
+    match stream {
+        Some(options) => self::stream(client, req, options).await,
+        None => Ok(client.chat().create(req).await?.into()),
+    }
+}
+
+async fn stream<C: Config>(client: &Client<C>, mut req: CreateChatCompletionRequest, options: Stream<'_>) -> Result<Generation, OpenAIError> {
+    req.stream_options = Some(ChatCompletionStreamOptions { include_usage: true });
+    let started = Instant::now();
+    let mut stream = client.chat().create_stream(req).await?;
+    let mut choices: BTreeMap<u32, GenChoice> = BTreeMap::new();
+    let mut usage = None;
+    let mut id = None;
+    let mut first_token = None;
+    if let Some(name) = options.label {
+        println!("{color_bright_black}{}:", name);
+    }
+    loop {
+        let next = match options.idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, stream.next()).await
+                .map_err(|_| OpenAIError::StreamError(format!("no token for {} ms (STREAM_IDLE_TIMEOUT_MS)", idle.as_millis())))?,
+            None => stream.next().await,
+        };
+        let Some(chunk) = next else { break };
+        let chunk = chunk?;
+        id.get_or_insert_with(|| chunk.id.clone());
+        if chunk.usage.is_some() {
+            usage = chunk.usage;
+        }
+        for c in chunk.choices {
+            let choice = choices.entry(c.index).or_default();
+            if first_token.is_none() && (c.delta.content.is_some() || c.delta.tool_calls.is_some() || c.delta.refusal.is_some()) {
+                let elapsed = started.elapsed();
+                tracing::debug!(first_token_ms = elapsed.as_millis() as u64, "first token");
+                first_token = Some(elapsed);
+            }
+            if let Some(delta) = c.delta.content {
+                if c.index == 0 && options.label.is_some() {
+                    print!("{}", delta);
+                    io::stdout().flush().ok();
+                }
+                choice.content.get_or_insert_with(String::new).push_str(&delta);
+            }
+            for call in c.delta.tool_calls.unwrap_or_default() {
+                let index = call.index as usize;
+                if choice.tool_calls.len() <= index {
+                    choice.tool_calls.resize(index + 1, FunctionCall { name: String::new(), arguments: String::new() });
+                }
+                if let Some(function) = call.function {
+                    choice.tool_calls[index].name.push_str(&function.name.unwrap_or_default());
+                    choice.tool_calls[index].arguments.push_str(&function.arguments.unwrap_or_default());
+                }
+            }
+            if let Some(refusal) = c.delta.refusal {
+                choice.refusal.get_or_insert_with(String::new).push_str(&refusal);
+            }
+            if let Some(logprobs) = c.logprobs.and_then(|l| l.content) {
+                choice.logprobs.extend(logprobs);
+            }
+            if c.finish_reason.is_some() {
+                choice.finish_reason = c.finish_reason;
+            }
+        }
+    }
+    let generation = Generation { choices: choices.into_values().collect(), usage, id, retries: 0, first_token, fallback: None };
+    match (&generation.usage, options.label) {
+        (_, None) => {}
+        (Some(u), Some(_)) => println!("\n[{} prompt + {} completion tokens]{color_reset}", u.prompt_tokens, u.completion_tokens),
+        (None, Some(_)) => println!("{color_reset}"),
+    }
+    Ok(generation)
+}
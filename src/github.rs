@@ -0,0 +1,71 @@
+use std::{env, fs::OpenOptions, io::Write, path::Path};
+use crate::{config::RunConfig, error::AppError, markdown::{self, cell}, summary::Summary, test_file, ErrorLocation, SuiteReport, TestResult};
+
+/// Failures listed in the step summary; the rest are counted, as GitHub cuts a summary off at 1 MiB.
+const MAX_SUMMARY_FAILURES: usize = 100;
+
+/// Errors are cut to this many characters in the step summary.
+const MAX_SUMMARY_ERROR: usize = 200;
+
+/// Whether the run is a GitHub Actions step that should report to it: GITHUB_ACTIONS is `true` and
+/// GITHUB_ANNOTATIONS is not `false`.
+pub fn enabled() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true") && !env::var("GITHUB_ANNOTATIONS").is_ok_and(|v| v == "false")
+}
+
+/// Prints an `::error` workflow command for every failed test, pointing at its file and, when it failed at the
+/// comparison, at the line of its `<output>`, so the failure shows in the pull request's diff. A quarantined test's
+/// failure is a `::warning`.
+pub fn annotate(report: &SuiteReport, config: &RunConfig) {
+    for result in report.results.iter().filter(|r| markdown::failed(r)) {
+        let file = Path::new(&config.test_dir).join(test_file(&result.name));
+        let mut properties = format!("file={}", property(&file.display().to_string()));
+        if result.location == Some(ErrorLocation::Test) {
+            if let Some(line) = result.input.lines().position(|l| l.contains("<output>")) {
+                properties.push_str(&format!(",line={}", line + 1));
+            }
+        }
+        let at = result.location.map_or(String::new(), |location| format!(" at {}", location.name()));
+        properties.push_str(&format!(",title={}", property(&format!("{} failed{}", result.name, at))));
+        let mut message = result.error.clone().unwrap_or_default();
+        if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+            message.push_str(&format!("\nJudge: {}", reason));
+        }
+        println!("::{} {}::{}", if result.quarantined { "warning" } else { "error" }, properties, data(&message));
+    }
+}
+
+/// Appends the run's summary table and its failures to the GITHUB_STEP_SUMMARY file, when there is one.
+pub fn write_summary(report: &SuiteReport, summary: &Summary, config: &RunConfig, results_file: &str) -> Result<(), AppError> {
+    let Some(path) = env::var("GITHUB_STEP_SUMMARY").ok().filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    let mut markdown = format!("### {} with `{}`{}\n\n", cell(results_file), config.model, if report.interrupted { " (interrupted)" } else { "" });
+    markdown.push_str(&summary.markdown());
+    let failures: Vec<&TestResult> = report.results.iter().filter(|r| markdown::failed(r)).collect();
+    if !failures.is_empty() {
+        markdown.push_str("\n| Test | Failed at | Error |\n|---|---|---|\n");
+        for result in failures.iter().take(MAX_SUMMARY_FAILURES) {
+            let error = result.error.as_deref().and_then(|e| e.lines().next()).unwrap_or("");
+            let error: String = error.chars().take(MAX_SUMMARY_ERROR).collect();
+            let quarantined = if result.quarantined { " (quarantined)" } else { "" };
+            markdown.push_str(&format!("| {}{} | {} | {} |\n", cell(&result.name), quarantined, result.location.map_or("", ErrorLocation::name), cell(&error)));
+        }
+        if failures.len() > MAX_SUMMARY_FAILURES {
+            markdown.push_str(&format!("\n{} more failures are in the results CSV.\n", failures.len() - MAX_SUMMARY_FAILURES));
+        }
+    }
+    markdown.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).map_err(AppError::io(&path))?;
+    file.write_all(markdown.as_bytes()).map_err(AppError::io(&path))
+}
+
+/// Escapes the message of a workflow command.
+fn data(text: &str) -> String {
+    text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escapes a property of a workflow command, which also ends at `,` and `:`.
+fn property(text: &str) -> String {
+    data(text).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,352 @@
+use std::{fs, io::ErrorKind, path::Path};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use crate::{error::AppError, metadata::RunMetadata, reference::fnv1a, ErrorLocation, RunConfig, Status, SuiteReport, TestResult};
+
+/// The tables of the HISTORY_FILE database: a row per run, and a row per test of each run.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        started TEXT NOT NULL,
+        model TEXT NOT NULL,
+        judge_models TEXT NOT NULL,
+        fingerprint TEXT,
+        duration_ms INTEGER NOT NULL,
+        passed INTEGER NOT NULL,
+        failed INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tests (
+        run INTEGER NOT NULL REFERENCES runs(id),
+        name TEXT NOT NULL,
+        status TEXT NOT NULL,
+        passed INTEGER NOT NULL,
+        location TEXT,
+        duration_ms INTEGER NOT NULL,
+        attempts TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tests_by_name ON tests (name, run);
+";
+
+/// One run as recorded in the HISTORY_FILE.
+#[derive(Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started: String,
+    pub model: String,
+    pub judge_models: Vec<String>,
+    /// A hash of the settings and prompts the results depend on, see [`fingerprint`]; runs recorded before it was have
+    /// none.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    pub duration_ms: u64,
+    pub passed: usize,
+    pub failed: usize,
+    pub tests: Vec<TestRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TestRecord {
+    pub name: String,
+    pub status: Status,
+    pub passed: bool,
+    pub location: Option<ErrorLocation>,
+    pub duration_ms: u64,
+    /// With PASS_AT_K, each attempt's outcome in order: none when it passed, where it failed otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Option<ErrorLocation>>,
+}
+
+impl RunRecord {
+    pub fn new(report: &SuiteReport, config: &RunConfig, started: String) -> RunRecord {
+        let tests: Vec<TestRecord> = report.results.iter().map(|r| TestRecord {
+            name: r.name.clone(),
+            status: r.status,
+            passed: r.passed(),
+            location: r.location,
+            duration_ms: r.duration_ms,
+            attempts: attempts(r),
+        }).collect();
+        let passed = tests.iter().filter(|t| t.passed).count();
+        RunRecord {
+            started,
+            model: config.model.clone(),
+            judge_models: config.judge_models.clone(),
+            fingerprint: Some(fingerprint(config)),
+            duration_ms: report.duration.as_millis() as u64,
+            passed,
+            failed: tests.len() - passed,
+            tests,
+        }
+    }
+}
+
+/// The SQLite database at HISTORY_FILE, such as `results/results.db`.
+pub struct History {
+    path: String,
+    db: Connection,
+    /// What opening it left out, such as the lines of a JSON-lines history that could not be read.
+    pub warnings: Vec<String>,
+}
+
+impl History {
+    /// Opens the database at `path`, creating it on the first run. A history file from before the database, one JSON
+    /// object per run and line, is moved to `<path>.jsonl` and its runs are copied into a new database in its place.
+    pub fn open(path: &str) -> Result<History, AppError> {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(AppError::io(path))?;
+        }
+        let lines = match fs::read(path) {
+            Ok(bytes) if !bytes.is_empty() && !bytes.starts_with(b"SQLite format 3\0") => {
+                let legacy = format!("{}.jsonl", path);
+                fs::rename(path, &legacy).map_err(AppError::io(path))?;
+                Some((legacy, String::from_utf8_lossy(&bytes).into_owned()))
+            }
+            Ok(_) => None,
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(AppError::io(path)(e)),
+        };
+        let db = Connection::open(path).map_err(AppError::history(path))?;
+        db.execute_batch(SCHEMA).map_err(AppError::history(path))?;
+        let mut history = History { path: path.to_string(), db, warnings: Vec::new() };
+        if let Some((legacy, contents)) = lines {
+            let mut imported = 0;
+            for (i, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                match serde_json::from_str::<RunRecord>(line) {
+                    Ok(run) => {
+                        history.append(&run)?;
+                        imported += 1;
+                    }
+                    Err(e) => history.warnings.push(format!("skipped {} line {}: {}", legacy, i + 1, e)),
+                }
+            }
+            history.warnings.push(format!("copied {} runs from the JSON-lines history, now {}, into {}", imported, legacy, path));
+        }
+        Ok(history)
+    }
+
+    /// Opens the database at `path` to read it back, which unlike [`History::open`] has to exist.
+    pub fn read(path: &str) -> Result<History, AppError> {
+        fs::metadata(path).map_err(AppError::io(format!("HISTORY_FILE {}", path)))?;
+        History::open(path)
+    }
+
+    /// Records the run and its tests, all or nothing.
+    pub fn append(&mut self, run: &RunRecord) -> Result<(), AppError> {
+        let tx = self.db.transaction().map_err(AppError::history(&self.path))?;
+        insert(&tx, run).and_then(|()| tx.commit()).map_err(AppError::history(&self.path))
+    }
+
+    /// The `last` most recent runs, or all of them, oldest first. A test whose row cannot be read, such as one
+    /// written by a later version, is left out with a warning.
+    pub fn runs(&mut self, last: Option<usize>) -> Result<Vec<RunRecord>, AppError> {
+        let path = self.path.clone();
+        let (runs, skipped) = read_runs(&self.db, last).map_err(AppError::history(&path))?;
+        self.warnings.extend(skipped);
+        Ok(runs)
+    }
+
+    /// The trend of every test over the `last` most recent runs, or all of them, by test name. `filter` keeps only the
+    /// tests whose name contains it.
+    pub fn trends(&self, last: Option<usize>, filter: Option<&str>) -> Result<Vec<Trend>, AppError> {
+        read_trends(&self.db, last, filter).map_err(AppError::history(&self.path))
+    }
+}
+
+fn insert(tx: &Transaction, run: &RunRecord) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO runs (started, model, judge_models, fingerprint, duration_ms, passed, failed) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![run.started, run.model, run.judge_models.join(","), run.fingerprint, run.duration_ms as i64, run.passed as i64, run.failed as i64],
+    )?;
+    let id = tx.last_insert_rowid();
+    let mut test = tx.prepare("INSERT INTO tests (run, name, status, passed, location, duration_ms, attempts) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+    for t in &run.tests {
+        let attempts: Vec<&str> = t.attempts.iter().map(|a| a.map_or("passed", ErrorLocation::name)).collect();
+        test.execute(params![id, t.name, status_name(t.status), t.passed, t.location.map(ErrorLocation::name), t.duration_ms as i64, attempts.join(", ")])?;
+    }
+    Ok(())
+}
+
+/// A status as the database stores it, its name in the results CSV.
+fn status_name(status: Status) -> String {
+    serde_json::to_value(status).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// The id of the oldest of the `last` most recent runs, 0 for all of them.
+fn first_run(db: &Connection, last: Option<usize>) -> rusqlite::Result<i64> {
+    let limit = last.map_or(-1, |n| n as i64);
+    db.query_row("SELECT MIN(id) FROM (SELECT id FROM runs ORDER BY id DESC LIMIT ?1)", [limit], |row| row.get::<_, Option<i64>>(0))
+        .optional().map(|id| id.flatten().unwrap_or(0))
+}
+
+fn read_runs(db: &Connection, last: Option<usize>) -> rusqlite::Result<(Vec<RunRecord>, Vec<String>)> {
+    let first = first_run(db, last)?;
+    let mut runs = db.prepare("SELECT id, started, model, judge_models, fingerprint, duration_ms, passed, failed FROM runs WHERE id >= ?1 ORDER BY id")?;
+    let mut tests = db.prepare("SELECT name, status, passed, location, duration_ms, attempts FROM tests WHERE run = ?1 ORDER BY rowid")?;
+    let mut records = Vec::new();
+    let mut skipped = Vec::new();
+    let rows: Vec<(i64, RunRecord)> = runs.query_map([first], |row| Ok((row.get(0)?, RunRecord {
+        started: row.get(1)?,
+        model: row.get(2)?,
+        judge_models: row.get::<_, String>(3)?.split(',').filter(|m| !m.is_empty()).map(str::to_string).collect(),
+        fingerprint: row.get(4)?,
+        duration_ms: row.get::<_, i64>(5)? as u64,
+        passed: row.get::<_, i64>(6)? as usize,
+        failed: row.get::<_, i64>(7)? as usize,
+        tests: Vec::new(),
+    })))?.collect::<rusqlite::Result<_>>()?;
+    for (id, mut run) in rows {
+        for row in tests.query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get::<_, Option<String>>(3)?, row.get::<_, i64>(4)?, row.get::<_, String>(5)?)))? {
+            let (name, status, passed, location, duration_ms, attempts) = row?;
+            let record = (|| Ok::<_, String>(TestRecord {
+                status: serde_json::from_value(serde_json::Value::String(status.clone())).map_err(|_| format!("unknown status {:?}", status))?,
+                location: location.map(|l| l.parse()).transpose()?,
+                attempts: attempts.split(", ").filter(|a| !a.is_empty()).map(|a| match a {
+                    "passed" => Ok(None),
+                    location => location.parse().map(Some),
+                }).collect::<Result<_, _>>()?,
+                name: name.clone(),
+                passed,
+                duration_ms: duration_ms as u64,
+            }))();
+            match record {
+                Ok(record) => run.tests.push(record),
+                Err(e) => skipped.push(format!("skipped {} in the run of {}: {}", name, run.started, e)),
+            }
+        }
+        records.push(run);
+    }
+    Ok((records, skipped))
+}
+
+fn read_trends(db: &Connection, last: Option<usize>, filter: Option<&str>) -> rusqlite::Result<Vec<Trend>> {
+    let first = first_run(db, last)?;
+    let mut trends = db.prepare(
+        "SELECT name, COUNT(*), SUM(passed), group_concat(CASE WHEN passed THEN 'P' ELSE 'F' END, '' ORDER BY run),
+            (SELECT location FROM tests f WHERE f.name = t.name AND f.run >= ?1 AND NOT f.passed AND f.location IS NOT NULL ORDER BY f.run DESC LIMIT 1)
+        FROM tests t WHERE run >= ?1 AND (?2 IS NULL OR instr(name, ?2) > 0) GROUP BY name ORDER BY name",
+    )?;
+    let rows = trends.query_map(params![first, filter], |row| Ok(Trend {
+        name: row.get(0)?,
+        runs: row.get::<_, i64>(1)? as usize,
+        passed: row.get::<_, i64>(2)? as usize,
+        recent: row.get(3)?,
+        last_failure: row.get::<_, Option<String>>(4)?.and_then(|l| l.parse().ok()),
+    }))?;
+    rows.collect()
+}
+
+/// The outcome of each of a result's PASS_AT_K attempts, read from its `Attempts` column; none without PASS_AT_K.
+pub fn attempts(result: &TestResult) -> Vec<Option<ErrorLocation>> {
+    result.attempts.iter().flat_map(|a| a.split(", ")).filter_map(|a| match a {
+        "passed" => Some(None),
+        location => location.parse().ok().map(Some),
+    }).collect()
+}
+
+/// A hash of what a run's results depend on besides the test files: the settings its metadata records, but not its
+/// start or shuffle seed, and the generation, judge and system prompts and the structure test. Two runs with the same
+/// fingerprint ran the same configuration.
+pub fn fingerprint(config: &RunConfig) -> String {
+    let meta = RunMetadata { shuffle_seed: None, ..RunMetadata::new(config, String::new()) };
+    let prompts = [Some(&config.gen_prompt), Some(&config.test_prompt), config.gen_system_prompt.as_ref(), config.judge_system_prompt.as_ref(), config.structure_test.as_ref().map(|s| &s.source)];
+    let mut text = serde_json::to_string(&meta).unwrap_or_default();
+    for prompt in prompts {
+        text.push('\0');
+        text.push_str(prompt.map_or("", String::as_str));
+    }
+    format!("{:016x}", fnv1a(text.as_bytes()))
+}
+
+/// A test's record over the runs it appeared in.
+pub struct Trend {
+    pub name: String,
+    pub runs: usize,
+    pub passed: usize,
+    /// One `P` or `F` per run, oldest first, over the runs shown.
+    pub recent: String,
+    /// Where the latest failure happened, if the test failed in the runs shown.
+    pub last_failure: Option<ErrorLocation>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ai_test_util-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path.join("results.db").display().to_string()
+    }
+
+    fn test(name: &str, location: Option<ErrorLocation>) -> TestRecord {
+        let status = if location.is_some() { Status::Failed } else { Status::Passed };
+        TestRecord { name: name.to_string(), status, passed: location.is_none(), location, duration_ms: 10, attempts: Vec::new() }
+    }
+
+    fn run(started: &str, tests: Vec<TestRecord>) -> RunRecord {
+        let passed = tests.iter().filter(|t| t.passed).count();
+        RunRecord { started: started.to_string(), model: "m".to_string(), judge_models: vec!["j1".to_string(), "j2".to_string()], fingerprint: Some("f".to_string()), duration_ms: 100, passed, failed: tests.len() - passed, tests }
+    }
+
+    fn record(history: &mut History) {
+        history.append(&run("1", vec![test("a.txt", None), test("b.txt", Some(ErrorLocation::Judge))])).unwrap();
+        history.append(&run("2", vec![test("a.txt", Some(ErrorLocation::Parse)), test("b.txt", None)])).unwrap();
+        history.append(&run("3", vec![test("a.txt", None), test("c.txt", Some(ErrorLocation::Test))])).unwrap();
+    }
+
+    #[test]
+    fn runs_are_read_back_as_recorded() {
+        let path = path("round_trip");
+        let mut history = History::open(&path).unwrap();
+        let mut attempted = test("a.txt", None);
+        attempted.attempts = vec![Some(ErrorLocation::Judge), None];
+        history.append(&run("2026-01-01T00:00:00Z", vec![attempted, test("b.txt", Some(ErrorLocation::Schema))])).unwrap();
+        let runs = History::read(&path).unwrap().runs(None).unwrap();
+        assert_eq!(serde_json::to_value(&runs).unwrap(), serde_json::json!([{
+            "started": "2026-01-01T00:00:00Z", "model": "m", "judge_models": ["j1", "j2"], "fingerprint": "f", "duration_ms": 100, "passed": 1, "failed": 1,
+            "tests": [
+                {"name": "a.txt", "status": "Passed", "passed": true, "location": null, "duration_ms": 10, "attempts": ["judge", null]},
+                {"name": "b.txt", "status": "Failed", "passed": false, "location": "schema", "duration_ms": 10},
+            ],
+        }]));
+    }
+
+    #[test]
+    fn trends_cover_the_last_runs_and_the_matching_tests() {
+        let mut history = History::open(&path("trends")).unwrap();
+        record(&mut history);
+        let trends: Vec<_> = history.trends(None, None).unwrap().into_iter().map(|t| (t.name, t.passed, t.runs, t.recent, t.last_failure)).collect();
+        assert_eq!(trends, [
+            ("a.txt".to_string(), 2, 3, "PFP".to_string(), Some(ErrorLocation::Parse)),
+            ("b.txt".to_string(), 1, 2, "FP".to_string(), Some(ErrorLocation::Judge)),
+            ("c.txt".to_string(), 0, 1, "F".to_string(), Some(ErrorLocation::Test)),
+        ]);
+        let trends: Vec<_> = history.trends(Some(2), Some("b")).unwrap().into_iter().map(|t| (t.name, t.recent, t.last_failure)).collect();
+        assert_eq!(trends, [("b.txt".to_string(), "P".to_string(), None)]);
+        let started: Vec<_> = history.runs(Some(2)).unwrap().into_iter().map(|r| r.started).collect();
+        assert_eq!(started, ["2", "3"]);
+    }
+
+    #[test]
+    fn a_json_lines_history_is_copied_skipping_lines_it_cannot_read() {
+        let path = path("legacy");
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        let line = |started| serde_json::to_string(&run(started, vec![test("a.txt", None)])).unwrap();
+        // The last line is an append cut short by a crash.
+        let partial = &line("3")[..40];
+        fs::write(&path, format!("{}\n{}\n\n{}", line("1"), line("2"), partial)).unwrap();
+        let mut history = History::open(&path).unwrap();
+        let started: Vec<_> = history.runs(None).unwrap().into_iter().map(|r| r.started).collect();
+        assert_eq!(started, ["1", "2"]);
+        assert_eq!(history.warnings.len(), 2);
+        assert!(history.warnings[0].starts_with(&format!("skipped {}.jsonl line 4: ", path)), "{}", history.warnings[0]);
+        assert!(Path::new(&format!("{}.jsonl", path)).exists());
+        assert_eq!(History::open(&path).unwrap().runs(None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reading_a_missing_history_is_an_error() {
+        let path = path("missing");
+        assert!(History::read(&path).is_err());
+        assert!(!Path::new(&path).exists());
+    }
+}
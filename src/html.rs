@@ -0,0 +1,94 @@
+use std::fs;
+use crate::{compare, error::AppError, serve::{escape, status_class, status_label, STYLE}, summary::Summary, test_case::TestCase, ErrorLocation, SuiteReport, TestResult};
+
+const DIFF_STYLE: &str = "details{margin:1em 0;border:1px solid #ccc;padding:4px 8px}summary{cursor:pointer;font-weight:bold}.add{color:#070}.del{color:#b00}.hunk{color:#888}";
+
+/// Writes the run as one self-contained HTML page: the run's summary and a table of the tests, then a collapsible
+/// section per test with its error stage and error, the judge's reason, its rubric scores, the input, the expected and generated output
+/// and a diff of the two. Failed tests start expanded.
+pub fn write(path: &str, report: &SuiteReport, summary: &Summary, started: &str) -> Result<(), AppError> {
+    let results = &report.results;
+    let mut body = format!("<h1>Results {}</h1>{}", escape(started), summary.html());
+    body.push_str("<table><tr><th>Test</th><th>Status</th><th>Failed at</th><th>Error</th><th>Duration ms</th></tr>");
+    for (i, result) in results.iter().enumerate() {
+        body.push_str(&format!(
+            "<tr><td><a href=\"#test-{}\">{}</a></td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            i, escape(&result.name), status_class(result.status), status_label(result.status),
+            result.location.map_or("", ErrorLocation::name),
+            escape(result.error.as_deref().and_then(|e| e.lines().next()).unwrap_or("")),
+            result.duration_ms,
+        ));
+    }
+    body.push_str("</table>");
+    for (i, result) in results.iter().enumerate() {
+        body.push_str(&details(i, result));
+    }
+    let page = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Results {}</title><style>{}{}</style></head><body>{}</body></html>",
+        escape(started), STYLE, DIFF_STYLE, body,
+    );
+    fs::write(path, page).map_err(AppError::io(path))?;
+    Ok(())
+}
+
+fn details(i: usize, result: &TestResult) -> String {
+    let open = if result.passed() { "" } else { " open" };
+    let mut section = format!(
+        "<details id=\"test-{}\"{}><summary>{} <span class=\"{}\">{}</span></summary>",
+        i, open, escape(&result.name), status_class(result.status), status_label(result.status),
+    );
+    if let Some(location) = result.location {
+        section.push_str(&format!("<p>Failed at <code>{}</code></p>", location));
+    }
+    if let Some(error) = result.error.as_deref().filter(|e| !e.is_empty()) {
+        section.push_str(&format!("<h3>Error</h3><pre>{}</pre>", escape(error)));
+    }
+    if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+        section.push_str(&format!("<h3>Judge reason</h3><p>{}</p>", escape(reason)));
+    }
+    if let Some(judgement) = result.judgement.as_ref().filter(|j| !j.criteria.is_empty()) {
+        section.push_str("<h3>Rubric</h3><table><tr><th>Criterion</th><th>Weight</th><th>Score</th><th>Passed</th></tr>");
+        for c in &judgement.criteria {
+            section.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", escape(&c.name), c.weight, escape(&c.display()), c.passed));
+        }
+        section.push_str("</table>");
+        if let Some(score) = judgement.score {
+            section.push_str(&format!("<p>Weighted score {:.1} of 100</p>", score));
+        }
+    }
+    match TestCase::parse(&result.name, &result.input) {
+        Ok(case) => {
+            section.push_str(&format!("<h3>Input</h3><pre>{}</pre>", escape(case.input.trim())));
+            section.push_str(&format!("<h3>Expected</h3><pre>{}</pre>", escape(case.expected_output.trim())));
+            section.push_str(&format!("<h3>Generated</h3><pre>{}</pre>", escape(result.output.trim())));
+            match diff(case.expected_output, &result.output) {
+                _ if result.output.trim().is_empty() => {}
+                diff if diff.is_empty() => section.push_str("<h3>Diff</h3><p>No differences.</p>"),
+                diff => section.push_str(&format!("<h3>Diff</h3><pre>{}</pre>", diff)),
+            }
+        }
+        Err(_) => {
+            section.push_str(&format!("<h3>Test file</h3><pre>{}</pre>", escape(result.input.trim())));
+            section.push_str(&format!("<h3>Generated</h3><pre>{}</pre>", escape(result.output.trim())));
+        }
+    }
+    if let Some(artifacts) = &result.artifacts {
+        section.push_str(&format!("<p>Artifacts in <code>{}</code></p>", escape(artifacts)));
+    }
+    section.push_str("</details>");
+    section
+}
+
+/// The unified diff of the expected and generated output, colored by line; JSON is pretty-printed first, see
+/// [`compare::json_diff`].
+fn diff(expected: &str, generated: &str) -> String {
+    compare::json_diff(expected, generated).lines().map(|line| {
+        let class = match line.chars().next() {
+            Some('+') if !line.starts_with("+++") => "add",
+            Some('-') if !line.starts_with("---") => "del",
+            Some('@') => "hunk",
+            _ => "",
+        };
+        if class.is_empty() { escape(line) } else { format!("<span class=\"{}\">{}</span>", class, escape(line)) }
+    }).collect::<Vec<_>>().join("\n")
+}
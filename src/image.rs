@@ -0,0 +1,41 @@
+use std::{fs, path::Path};
+use async_openai::types::{ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText, ChatCompletionRequestUserMessageContentPart, ImageUrl};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::{resolve, test_case::TestCase, ErrorLocation, RunConfig, TestError};
+
+/// The content parts of a test's first user message: its text, then each `<image>` in file order. An image is a path
+/// relative to the test file's directory, read and sent as a base64 `data:` URL, or an `http(s)://` or `data:` URL
+/// sent as it is.
+pub(crate) fn parts(case: &TestCase<'_>, config: &RunConfig, text: String) -> Result<Vec<ChatCompletionRequestUserMessageContentPart>, TestError> {
+    let mut parts = vec![ChatCompletionRequestMessageContentPartText { text }.into()];
+    for image in &case.images {
+        let url = url(case, config, image.trim()).map_err(|e| TestError::because(ErrorLocation::MatchInput, *image, e))?;
+        parts.push(ChatCompletionRequestMessageContentPartImage { image_url: ImageUrl { url, detail: None } }.into());
+    }
+    Ok(parts)
+}
+
+fn url(case: &TestCase<'_>, config: &RunConfig, image: &str) -> Result<String, String> {
+    if ["http://", "https://", "data:"].iter().any(|scheme| image.starts_with(scheme)) {
+        return Ok(image.to_string());
+    }
+    let path = resolve(config, case.name, image);
+    let mime = mime(&path).ok_or_else(|| format!("image {} is not a .png, .jpg, .gif or .webp file", image))?;
+    let bytes = fs::read(&path).map_err(|e| format!("cannot read image {}: {}", path.display(), e))?;
+    Ok(format!("data:{};base64,{}", mime, STANDARD.encode(bytes)))
+}
+
+/// Whether a file is an image by its extension, which test discovery leaves for tests to attach.
+pub(crate) fn is_image(path: &Path) -> bool {
+    mime(path).is_some()
+}
+
+fn mime(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("png") => Some("image/png"),
+        Some("jpg" | "jpeg") => Some("image/jpeg"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        _ => None,
+    }
+}
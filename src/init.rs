@@ -0,0 +1,88 @@
+use std::{fs, path::Path};
+use crate::error::AppError;
+
+const CONFIG: &str = r#"# Settings for ai_test_util. Each key is an environment variable in lower case, and the environment, .env and the
+# command line win over this file. Set OPENAI_API_KEY in the environment or .env rather than here.
+model = "gpt-4o-mini"
+test_dir = "tests"
+results_dir = "results"
+gen_prompt = "prompts/gen_prompt.md"
+test_prompt = "prompts/test_prompt.md"
+structure_test = "structure_test.lua"
+"#;
+
+const EXAMPLE_TEST: &str = r#"<tags>example</tags>
+<input>A customer named Ada Lovelace, ada@example.com, ordered two notebooks and a pen.</input>
+<output>{"name": "Ada Lovelace", "email": "ada@example.com", "items": [{"product": "notebook", "quantity": 2}, {"product": "pen", "quantity": 1}]}</output>
+"#;
+
+const GEN_PROMPT: &str = r#"Extract the customer and their order from the text below. Reply with a single JSON object with the fields
+"name", "email" and "items", a list of objects with "product" (singular, lower case) and "quantity".
+
+{{ description }}
+"#;
+
+const TEST_PROMPT: &str = r#"You are checking a JSON extraction against the expected result.
+
+Expected:
+{{ baseline }}
+
+Generated:
+{{ input }}
+
+Do both describe the same customer and order? Differences in key order, whitespace or wording that do not change the
+meaning are fine. Answer true or false.
+"#;
+
+const STRUCTURE_TEST: &str = r#"-- Every global function whose name starts with "test" checks each candidate before the judge sees it.
+function test(raw)
+    local v = json.decode(raw)
+    assert_field(v, "name", "string")
+    assert_field(v, "email", "string")
+    local items = assert_field(v, "items", "array")
+    if #items == 0 then fail("no items") end
+    for i = 1, #items do
+        assert_field(v, "items." .. i .. ".product", "string")
+        assert_field(v, "items." .. i .. ".quantity", "integer")
+    end
+    return true
+end
+"#;
+
+/// The files of a new project, relative to its directory.
+const FILES: [(&str, &str); 5] = [
+    ("ai_test.toml", CONFIG),
+    ("tests/example.txt", EXAMPLE_TEST),
+    ("prompts/gen_prompt.md", GEN_PROMPT),
+    ("prompts/test_prompt.md", TEST_PROMPT),
+    ("structure_test.lua", STRUCTURE_TEST),
+];
+
+/// Whether `init` wrote a file of the skeleton or left the one already there.
+pub enum Scaffolded {
+    Created(String),
+    Kept(String),
+}
+
+/// Writes a project skeleton into `dir`: the config file, an example test, generation and judge prompts, a structure
+/// test and an empty results directory, enough for a first run with only an API key to add. Files that exist already
+/// are kept unless `force` is set.
+pub fn init(dir: &Path, force: bool) -> Result<Vec<Scaffolded>, AppError> {
+    let mut files = Vec::new();
+    for (name, contents) in FILES {
+        let path = dir.join(name);
+        let shown = path.display().to_string();
+        if path.exists() && !force {
+            files.push(Scaffolded::Kept(shown));
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(AppError::io(parent.display().to_string()))?;
+        }
+        fs::write(&path, contents).map_err(AppError::io(shown.as_str()))?;
+        files.push(Scaffolded::Created(shown));
+    }
+    let results = dir.join("results");
+    fs::create_dir_all(&results).map_err(AppError::io(results.display().to_string()))?;
+    Ok(files)
+}
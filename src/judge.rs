@@ -0,0 +1,666 @@
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+use async_openai::{error::OpenAIError, types::{ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage, ChatCompletionTokenLogprob, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs, FinishReason, ResponseFormat}};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::{artifacts, backend::ChatBackend, compare, config::RunConfig, error::AppError, pipeline::Stage, retry, rubric::{CriterionScore, Rubric, Scale}, test_case::TestCase, ErrorLocation, TestError};
+
+#[derive(Clone, Debug, Default)]
+pub struct Judgement {
+    pub passed: bool,
+    /// Probability the judge assigned to its verdict token, when LOGPROBS is on and the backend reports it.
+    pub confidence: Option<f32>,
+    /// Cosine similarity between the embedded expected and generated outputs in embedding comparison mode.
+    pub similarity: Option<f32>,
+    pub reason: Option<String>,
+    /// The judge's 0–100 score in score mode, or the weighted total of the criteria against a rubric.
+    pub score: Option<f64>,
+    /// The score a JSON judge gives next to its verdict in boolean mode. It is recorded in the results, but unlike
+    /// `score` it never decides the test, its failure message or its grade.
+    pub stated_score: Option<f64>,
+    /// Per-criterion scores when judging against a RUBRIC or the test's `<rubric>`.
+    pub criteria: Vec<CriterionScore>,
+    /// True votes and total votes when JUDGE_VOTES is above 1.
+    pub votes: Option<(usize, usize)>,
+    /// Tokens used across all judge calls for the test.
+    pub tokens: Option<u32>,
+    /// Each JUDGE_MODELS judge's verdict, when more than one model judges.
+    pub panel: Vec<PanelVote>,
+}
+
+/// One JUDGE_MODELS judge's verdict and score; neither where its call failed.
+#[derive(Clone, Debug, Serialize)]
+pub struct PanelVote {
+    pub model: String,
+    pub passed: Option<bool>,
+    pub score: Option<f64>,
+}
+
+impl PanelVote {
+    /// The vote as its `Judge <model>` column shows it: `true`, `true 85` with a score, or `error`.
+    pub fn display(&self) -> String {
+        match (self.passed, self.score) {
+            (Some(passed), Some(score)) => format!("{} {}", passed, score),
+            (Some(passed), None) => passed.to_string(),
+            (None, _) => "error".to_string(),
+        }
+    }
+}
+
+/// The response ids and retries of a test's judge calls, kept whether or not the judge reached a verdict.
+#[derive(Clone, Debug, Default)]
+pub struct JudgeCalls {
+    pub request_ids: Vec<String>,
+    pub retries: u32,
+    /// The other tests whose pairs went in the same JUDGE_BATCH_SIZE requests.
+    pub batched_with: Vec<String>,
+    /// Tokens by judge model, over every call including those that reached no verdict.
+    pub usage: BTreeMap<String, TokenUsage>,
+    /// How long the test waited on the judge and embeddings, its batch's requests included.
+    pub time: Duration,
+}
+
+impl JudgeCalls {
+    pub(crate) fn count(&mut self, model: &str, usage: TokenUsage) {
+        *self.usage.entry(model.to_string()).or_default() += usage;
+    }
+
+    /// Adds up another set of calls, such as another candidate's.
+    pub(crate) fn merge(&mut self, other: JudgeCalls) {
+        self.request_ids.extend(other.request_ids);
+        self.retries += other.retries;
+        self.time += other.time;
+        for name in other.batched_with {
+            if !self.batched_with.contains(&name) {
+                self.batched_with.push(name);
+            }
+        }
+        for (model, usage) in other.usage {
+            self.count(&model, usage);
+        }
+    }
+
+    /// The prompt and completion tokens of all models together.
+    pub fn total(&self) -> TokenUsage {
+        self.usage.values().fold(TokenUsage::default(), |total, u| total + *u)
+    }
+}
+
+/// Prompt and completion tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenUsage {
+    pub prompt: u32,
+    pub completion: u32,
+}
+
+impl From<&CompletionUsage> for TokenUsage {
+    fn from(usage: &CompletionUsage) -> Self {
+        TokenUsage { prompt: usage.prompt_tokens, completion: usage.completion_tokens }
+    }
+}
+
+impl std::ops::Add for TokenUsage {
+    type Output = TokenUsage;
+
+    fn add(self, other: TokenUsage) -> TokenUsage {
+        TokenUsage { prompt: self.prompt + other.prompt, completion: self.completion + other.completion }
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        *self = *self + other;
+    }
+}
+
+/// How the verdicts of several JUDGE_MODELS combine into the test result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JudgePolicy {
+    Unanimous,
+    Majority,
+    Any,
+    /// The judges' scores averaged and held against PASS_THRESHOLD; the majority where no judge gave a score.
+    Average,
+}
+
+impl FromStr for JudgePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unanimous" => Ok(JudgePolicy::Unanimous),
+            "majority" => Ok(JudgePolicy::Majority),
+            "any" => Ok(JudgePolicy::Any),
+            "average" => Ok(JudgePolicy::Average),
+            other => Err(format!("unknown judge policy {:?}, expected unanimous, majority, any or average", other)),
+        }
+    }
+}
+
+/// Whether the judge returns a boolean verdict or a 0–100 score that is compared against PASS_THRESHOLD.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JudgeMode {
+    Boolean,
+    Score,
+}
+
+impl FromStr for JudgeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "boolean" => Ok(JudgeMode::Boolean),
+            "score" => Ok(JudgeMode::Score),
+            other => Err(format!("unknown judge mode {:?}, expected boolean or score", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JudgeFormat {
+    Plain,
+    Json,
+}
+
+impl FromStr for JudgeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(JudgeFormat::Plain),
+            "json" => Ok(JudgeFormat::Json),
+            other => Err(format!("unknown judge format {:?}, expected plain or json", other)),
+        }
+    }
+}
+
+const JSON_INSTRUCTIONS: &str = "\n\nRespond with a JSON object of the form {\"verdict\": true or false, \"score\": how well the output matches the baseline from 0 (unrelated) to 100 (equivalent), \"reason\": \"one or two sentences explaining the verdict\"}.";
+
+const COT_INSTRUCTIONS: &str = "\n\nThink through the comparison step by step, then finish with a final line of the form \"VERDICT: true\" or \"VERDICT: false\".";
+
+const SCORE_INSTRUCTIONS: &str = "\n\nInstead of true or false, rate how well the output matches the baseline with a score from 0 (unrelated) to 100 (equivalent). Respond with only the number.";
+
+const JSON_SCORE_INSTRUCTIONS: &str = "\n\nInstead of true or false, rate how well the output matches the baseline from 0 (unrelated) to 100 (equivalent). Respond with a JSON object of the form {\"score\": number, \"reason\": \"one or two sentences explaining the score\"}.";
+
+const COT_SCORE_INSTRUCTIONS: &str = "\n\nInstead of true or false, rate how well the output matches the baseline from 0 (unrelated) to 100 (equivalent). Think through the comparison step by step, then finish with a final line of the form \"SCORE: 85\".";
+
+/// A JSON judge reply. `pass` and `rationale` are read as `verdict` and `reason`, as some judges word them.
+#[derive(Deserialize)]
+struct JsonVerdict {
+    #[serde(alias = "pass", alias = "passed")]
+    verdict: Option<serde_json::Value>,
+    score: Option<serde_json::Value>,
+    #[serde(alias = "rationale")]
+    reason: Option<String>,
+}
+
+/// Asks the judge model whether `jzml` matches the expected output, JUDGE_VOTES times when set, and takes the majority.
+/// Failures of the judge call itself come back as the inner error so the generated content still reaches the results.
+/// With JUDGE_BATCH_SIZE the test's pair is judged together with those of other tests where it can be.
+pub async fn judge(case: &TestCase<'_>, jzml: &str, config: &RunConfig, client: &dyn ChatBackend, calls: &mut JudgeCalls) -> Result<Result<Judgement, TestError>, AppError> {
+    let name = case.name;
+    let (pair, system) = match case.render_test_prompt(config, jzml).and_then(|pair| Ok((pair, case.render_judge_system_prompt(config)?))) {
+        Ok(prompts) => prompts,
+        Err(e) => return Ok(Err(TestError::because(ErrorLocation::MatchInput, jzml, e))),
+    };
+    let mut prompt = pair.clone();
+    let rubric = case.rubric(config);
+    if let Some(rubric) = rubric {
+        prompt.push_str(&rubric.instructions());
+    } else {
+        prompt.push_str(match (config.judge_mode, config.judge_format, config.judge_cot) {
+            (JudgeMode::Boolean, JudgeFormat::Json, _) => JSON_INSTRUCTIONS,
+            (JudgeMode::Boolean, JudgeFormat::Plain, true) => COT_INSTRUCTIONS,
+            (JudgeMode::Boolean, JudgeFormat::Plain, false) => "",
+            (JudgeMode::Score, JudgeFormat::Json, _) => JSON_SCORE_INSTRUCTIONS,
+            (JudgeMode::Score, JudgeFormat::Plain, true) => COT_SCORE_INSTRUCTIONS,
+            (JudgeMode::Score, JudgeFormat::Plain, false) => SCORE_INSTRUCTIONS,
+        });
+    }
+    let mut verdicts = Vec::new();
+    for model in &config.judge_models {
+        let pair = Pair { name, prompt: &pair, jzml, system: system.as_deref(), own_system: case.judge_system.is_some(), rubric };
+        verdicts.push((model.clone(), vote(&pair, &prompt, model, config, client, calls).await?));
+    }
+    if verdicts.len() == 1 {
+        return Ok(verdicts.remove(0).1);
+    }
+    Ok(ensemble(verdicts, config.judge_policy, config.pass_threshold))
+}
+
+/// One test's pair as the judge sees it: `prompt` is its test prompt filled in, without the reply format instructions.
+struct Pair<'a> {
+    name: &'a str,
+    prompt: &'a str,
+    jzml: &'a str,
+    /// The judge's system message, and whether it is the test's own rather than JUDGE_SYSTEM_PROMPT.
+    system: Option<&'a str>,
+    own_system: bool,
+    /// The test's `<rubric>`, or else RUBRIC.
+    rubric: Option<&'a Rubric>,
+}
+
+/// A judge request for `prompt`, after the `system` message if there is one.
+pub(crate) fn request(prompt: &str, system: Option<&str>, model: &str, config: &RunConfig) -> Result<CreateChatCompletionRequest, AppError> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+    if let Some(system) = system {
+        messages.push(ChatCompletionRequestSystemMessageArgs::default().content(system).build().map_err(AppError::api("judge"))?.into());
+    }
+    messages.push(ChatCompletionRequestUserMessageArgs::default().content(prompt).build().map_err(AppError::api("judge"))?.into());
+    let mut req = CreateChatCompletionRequestArgs::default();
+    req.model(model).messages(messages);
+    if config.judge_format == JudgeFormat::Json || config.rubric.is_some() {
+        req.response_format(ResponseFormat::JsonObject);
+    }
+    if config.logprobs {
+        req.logprobs(true);
+    }
+    req.temperature(config.judge_temperature());
+    if let Some(top_p) = config.judge_top_p {
+        req.top_p(top_p);
+    }
+    if let Some(max_tokens) = config.judge_max_tokens {
+        req.max_tokens(max_tokens);
+    }
+    if let Some(seed) = config.judge_seed {
+        req.seed(seed);
+    }
+    req.build().map_err(AppError::api("judge"))
+}
+
+/// JUDGE_VOTES calls to one judge model combined into its verdict.
+async fn vote(pair: &Pair<'_>, prompt: &str, model: &str, config: &RunConfig, client: &dyn ChatBackend, calls: &mut JudgeCalls) -> Result<Result<Judgement, TestError>, AppError> {
+    let mut req = request(prompt, pair.system, model, config)?;
+    if pair.rubric.is_some() {
+        req.response_format = Some(ResponseFormat::JsonObject);
+    }
+    let mut votes = Vec::new();
+    let mut tokens = 0;
+    for _ in 0..config.judge_votes {
+        // A batch is judged under JUDGE_SYSTEM_PROMPT with single verdicts, so a test with its own system prompt or
+        // rubric goes on its own.
+        if let Some(batch) = config.judge_batch.as_ref().filter(|_| !pair.own_system && pair.rubric.is_none()) {
+            // The batch's share of tokens counts even when the pair falls back to its own call.
+            let verdict = batch.judge(pair.name, model, pair.prompt, config, client).await?;
+            calls.request_ids.extend(verdict.request_id);
+            calls.retries += verdict.retries;
+            for name in verdict.shared_with {
+                if !calls.batched_with.contains(&name) {
+                    calls.batched_with.push(name);
+                }
+            }
+            tokens += verdict.tokens;
+            calls.count(model, verdict.usage);
+            if let Some(judgement) = verdict.judgement {
+                votes.push(Ok(judgement));
+                continue;
+            }
+        }
+        let (vote, used) = ask(&req, pair.jzml, pair.rubric, config, client, calls).await?;
+        tokens += used;
+        votes.push(vote);
+    }
+    let mut judged = combine(votes);
+    if let Ok(judgement) = &mut judged {
+        judgement.tokens = Some(tokens);
+    }
+    Ok(judged)
+}
+
+/// A reply that passes in the configured judge mode and format, or against the test's rubric, for DRY_RUN.
+pub fn dry_run_reply(rubric: Option<&Rubric>, config: &RunConfig) -> String {
+    if let Some(rubric) = rubric {
+        let mut reply: serde_json::Map<String, serde_json::Value> = rubric.criteria.iter().map(|c| (c.name.clone(), match rubric.scale {
+            Scale::Boolean => true.into(),
+            Scale::Ten => 10.into(),
+        })).collect();
+        reply.insert("reason".to_string(), "dry run".into());
+        return serde_json::Value::Object(reply).to_string();
+    }
+    match (config.judge_mode, config.judge_format) {
+        (JudgeMode::Boolean, JudgeFormat::Json) => r#"{"verdict": true, "score": 100, "reason": "dry run"}"#,
+        (JudgeMode::Boolean, JudgeFormat::Plain) if config.judge_cot => "Dry run.\nVerdict: true",
+        (JudgeMode::Boolean, JudgeFormat::Plain) => "true",
+        (JudgeMode::Score, JudgeFormat::Json) => r#"{"score": 100, "reason": "dry run"}"#,
+        (JudgeMode::Score, JudgeFormat::Plain) => "Score: 100",
+    }.to_string()
+}
+
+/// A single judge call and the tokens it used.
+async fn ask(req: &CreateChatCompletionRequest, jzml: &str, rubric: Option<&Rubric>, config: &RunConfig, client: &dyn ChatBackend, calls: &mut JudgeCalls) -> Result<(Result<Judgement, TestError>, u32), AppError> {
+    let (response, retries) = config.retry.run_counted(|| config.pipeline.run(Stage::Judge, client.complete(req.clone(), None))).await;
+    calls.retries += retries;
+    artifacts::exchange("judge", req, response.as_ref().ok());
+    let response = match response {
+        Ok(res) => res,
+        Err(e) => return Ok((Err(api_failure(jzml, "judge", &e)), 0)),
+    };
+    calls.request_ids.extend(response.id.clone());
+    calls.count(&req.model, response.usage.as_ref().map(TokenUsage::from).unwrap_or_default());
+    let tokens = response.usage.as_ref().map(|u| u.total_tokens).unwrap_or(0);
+    let Some(choice) = response.choices.first() else {
+        return Ok((Err(TestError::because(ErrorLocation::EmptyResponse, jzml, "judge response contained no choices")), tokens));
+    };
+    let Some(test_message) = choice.content.as_ref().filter(|c| !c.trim().is_empty()) else {
+        let location = if choice.refusal.is_some() { ErrorLocation::Judge } else { ErrorLocation::EmptyResponse };
+        return Ok((Err(TestError::because(location, jzml, format!("judge {}", choice.missing_content()))), tokens));
+    };
+    let confidence = verdict_confidence(&choice.logprobs);
+    let mut judged = interpret(test_message, confidence, jzml, rubric, config)?;
+    match (&mut judged, choice.finish_reason) {
+        (Err(e), Some(FinishReason::Length)) => {
+            e.location = ErrorLocation::JudgeUnparseable;
+            e.err = Some(format!("judge reply was truncated at JUDGE_MAX_TOKENS {}: {}", config.judge_max_tokens.unwrap_or_default(), test_message).into());
+        }
+        (Err(e), _) if rubric.is_none() && matches!(e.location, ErrorLocation::JudgeUnparseable | ErrorLocation::ScoreUnparseable) => {
+            let (reasked, used) = reask(req, test_message, config, client, calls).await?;
+            if let Some(reasked) = reasked {
+                judged = Ok(reasked);
+            }
+            return Ok((judged, tokens + used));
+        }
+        _ => {}
+    }
+    Ok((judged, tokens))
+}
+
+/// The probability of the last true or false token of a reply: the verdict [`parse_verdict`] and the `VERDICT:` line of
+/// JUDGE_COT decide on, rather than one in the reasoning before it.
+fn verdict_confidence(logprobs: &[ChatCompletionTokenLogprob]) -> Option<f32> {
+    logprobs.iter().rev()
+        .find(|t| matches!(t.token.trim().to_lowercase().as_str(), "true" | "false"))
+        .map(|t| t.logprob.exp())
+}
+
+/// One follow-up in the same conversation asking for nothing but the verdict (or score), used when the first reply had
+/// none. The original reply is kept as the reason so the detour shows up in the results.
+async fn reask(req: &CreateChatCompletionRequest, reply: &str, config: &RunConfig, client: &dyn ChatBackend, calls: &mut JudgeCalls) -> Result<(Option<Judgement>, u32), AppError> {
+    let mut req = req.clone();
+    req.response_format = None;
+    req.messages.push(ChatCompletionRequestAssistantMessageArgs::default().content(reply).build().map_err(AppError::api("judge"))?.into());
+    req.messages.push(ChatCompletionRequestUserMessageArgs::default().content(match config.judge_mode {
+        JudgeMode::Boolean => "Answer with exactly one word: true or false",
+        JudgeMode::Score => "Answer with exactly one number from 0 to 100",
+    }).build().map_err(AppError::api("judge"))?.into());
+    let (response, retries) = config.retry.run_counted(|| config.pipeline.run(Stage::Judge, client.complete(req.clone(), None))).await;
+    calls.retries += retries;
+    artifacts::exchange("judge_reask", &req, response.as_ref().ok());
+    let response = match response {
+        Ok(res) => res,
+        Err(_) => return Ok((None, 0)),
+    };
+    calls.request_ids.extend(response.id.clone());
+    calls.count(&req.model, response.usage.as_ref().map(TokenUsage::from).unwrap_or_default());
+    let tokens = response.usage.as_ref().map(|u| u.total_tokens).unwrap_or(0);
+    let Some(answer) = response.choices.first().and_then(|c| c.content.as_deref()) else {
+        return Ok((None, tokens));
+    };
+    let reason = Some(format!("re-asked after an unparseable reply: {}", reply));
+    let judgement = match config.judge_mode {
+        JudgeMode::Boolean => parse_verdict(answer).map(|passed| Judgement { passed, reason, ..Default::default() }),
+        JudgeMode::Score => parse_score(answer)?.map(|score| Judgement { passed: score >= config.pass_threshold, score: Some(score), reason, ..Default::default() }),
+    };
+    Ok((judgement, tokens))
+}
+
+fn interpret(test_message: &str, confidence: Option<f32>, jzml: &str, rubric: Option<&Rubric>, config: &RunConfig) -> Result<Result<Judgement, TestError>, AppError> {
+    if let Some(rubric) = rubric {
+        return Ok(match rubric.evaluate(test_message) {
+            Ok((criteria, score, passed, reason)) => Ok(Judgement { passed, confidence, reason, score: Some(score), criteria, ..Default::default() }),
+            Err(e) => Err(TestError::because(ErrorLocation::JudgeUnparseable, jzml, e)),
+        });
+    }
+    if config.judge_mode == JudgeMode::Score {
+        let (score, reason) = match config.judge_format {
+            JudgeFormat::Plain => (parse_score(test_message)?, config.judge_cot.then(|| test_message.to_string())),
+            JudgeFormat::Json => match serde_json::from_str::<JsonVerdict>(test_message) {
+                Ok(v) => (v.score.as_ref().map(json_score).transpose()?.flatten(), v.reason),
+                Err(e) => (parse_score(test_message)?, Some(format!("judge reply was not valid score JSON ({}), used the plain score instead", e))),
+            },
+        };
+        let Some(score) = score else {
+            return Ok(Err(TestError::because(ErrorLocation::ScoreUnparseable, jzml, format!("no score in judge reply: {}", test_message))));
+        };
+        return Ok(Ok(Judgement { passed: score >= config.pass_threshold, confidence, reason, score: Some(score), ..Default::default() }));
+    }
+    // In boolean mode a JSON reply's score is only recorded, as the verdict alone decides.
+    let (verdict, score, reason) = match config.judge_format {
+        JudgeFormat::Plain if config.judge_cot => (cot_verdict(test_message)?, None, Some(test_message.to_string())),
+        JudgeFormat::Plain => (parse_verdict(test_message), None, None),
+        JudgeFormat::Json => match serde_json::from_str::<JsonVerdict>(test_message) {
+            Ok(v) => (v.verdict.as_ref().and_then(json_verdict), v.score.as_ref().map(json_score).transpose()?.flatten(), v.reason),
+            Err(e) => (parse_verdict(test_message), None, Some(format!("judge reply was not valid verdict JSON ({}), used the plain verdict instead", e))),
+        },
+    };
+    let Some(passed) = verdict else {
+        return Ok(Err(TestError::because(ErrorLocation::JudgeUnparseable, jzml, format!("no verdict in judge reply: {}", test_message))));
+    };
+    Ok(Ok(Judgement { passed, confidence, reason, stated_score: score, ..Default::default() }))
+}
+
+/// Majority over the votes that produced a verdict; ties fail. Scores and criterion values are averaged and the reason
+/// comes from a vote on the winning side. Only when no vote produced a verdict does the first failure come back.
+fn combine(votes: Vec<Result<Judgement, TestError>>) -> Result<Judgement, TestError> {
+    let total = votes.len();
+    let mut judgements = Vec::new();
+    let mut first_failure = None;
+    for vote in votes {
+        match vote {
+            Ok(judgement) => judgements.push(judgement),
+            Err(e) => { first_failure.get_or_insert(e); }
+        }
+    }
+    if let (true, Some(e)) = (judgements.is_empty(), first_failure) {
+        return Err(e);
+    }
+    if total == 1 {
+        return Ok(judgements.remove(0));
+    }
+    let count = judgements.len();
+    let yes = judgements.iter().filter(|j| j.passed).count();
+    let passed = yes * 2 > count;
+    let scores: Vec<f64> = judgements.iter().filter_map(|j| j.score).collect();
+    let score = (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64);
+    let criteria = (0..judgements[0].criteria.len()).map(|i| {
+        let first = &judgements[0].criteria[i];
+        let passes = judgements.iter().filter(|j| j.criteria[i].passed).count();
+        let value = judgements.iter().map(|j| j.criteria[i].value).sum::<f64>() / count as f64;
+        CriterionScore { value, passed: passes * 2 > count, ..first.clone() }
+    }).collect();
+    let winner = judgements.into_iter().find(|j| j.passed == passed);
+    Ok(Judgement {
+        passed,
+        score,
+        criteria,
+        votes: Some((yes, total)),
+        ..winner.unwrap_or_default()
+    })
+}
+
+/// The verdict on the last `VERDICT:` line; anything other than a single verdict word there counts as no verdict.
+fn cot_verdict(reply: &str) -> Result<Option<bool>, regex::Error> {
+    let marker = Regex::new(r"(?im)^[\s*#]*verdict[\s*]*:[\s*]*([^\n]*?)[\s*.]*$")?;
+    Ok(marker.captures_iter(reply).last().and_then(|c| match c[1].to_lowercase().as_str() {
+        "true" | "yes" => Some(true),
+        "false" | "no" => Some(false),
+        _ => None,
+    }))
+}
+
+pub(crate) fn json_verdict(verdict: &serde_json::Value) -> Option<bool> {
+    match verdict {
+        serde_json::Value::Bool(b) => Some(*b),
+        serde_json::Value::String(s) => parse_verdict(s),
+        _ => None,
+    }
+}
+
+pub(crate) fn json_score(score: &serde_json::Value) -> Result<Option<f64>, regex::Error> {
+    match score {
+        serde_json::Value::Number(n) => Ok(n.as_f64().filter(|s| (0.0..=100.0).contains(s))),
+        serde_json::Value::String(s) => parse_score(s),
+        _ => Ok(None),
+    }
+}
+
+/// Finds a 0–100 score in replies like "85", "85/100", "85 out of 100" or "Score: 85", preferring the last mention.
+pub fn parse_score(reply: &str) -> Result<Option<f64>, regex::Error> {
+    let out_of = Regex::new(r"(?i)(\d+(?:\.\d+)?)\s*(?:/|out of)\s*100\b")?;
+    let labelled = Regex::new(r"(?i)score\W{0,3}(\d+(?:\.\d+)?)")?;
+    let bare = Regex::new(r"^\s*(\d+(?:\.\d+)?)[\s.]*$")?;
+    let found = out_of.captures_iter(reply).last()
+        .or_else(|| labelled.captures_iter(reply).last())
+        .or_else(|| bare.captures(reply));
+    Ok(found.and_then(|c| c[1].parse::<f64>().ok()).filter(|s| (0.0..=100.0).contains(s)))
+}
+
+/// Accepts true/false and yes/no in any case, ignoring surrounding whitespace and punctuation. An explicit true or
+/// false word anywhere decides, so "The outputs differ only in order, so: True." and "True, there are no differences."
+/// are passes; without one, yes or no counts only as the first or last word, since in the middle of a sentence it is
+/// rarely the verdict. A reply whose verdict words disagree has no verdict, and is re-asked like any other.
+pub fn parse_verdict(reply: &str) -> Option<bool> {
+    let words: Vec<String> = reply.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(str::to_lowercase).collect();
+    let verdicts = |words: &[&String], yes: &str, no: &str| -> Vec<bool> {
+        words.iter().filter_map(|w| if *w == yes { Some(true) } else if *w == no { Some(false) } else { None }).collect()
+    };
+    let mut found = verdicts(&words.iter().collect::<Vec<_>>(), "true", "false");
+    if found.is_empty() {
+        found = verdicts(&[words.first(), words.last()].into_iter().flatten().collect::<Vec<_>>(), "yes", "no");
+    }
+    match found.split_first() {
+        Some((first, rest)) if rest.iter().all(|v| v == first) => Some(*first),
+        _ => None,
+    }
+}
+
+/// Embeds the expected and generated outputs and passes when their cosine similarity reaches SIMILARITY_THRESHOLD.
+pub async fn embedding(expected_output: &str, jzml: &str, config: &RunConfig, client: &dyn ChatBackend, calls: &mut JudgeCalls) -> Result<Result<Judgement, TestError>, AppError> {
+    let req = CreateEmbeddingRequestArgs::default()
+        .model(&config.embed_model)
+        .input(vec![expected_output.to_string(), jzml.to_string()])
+        .build().map_err(AppError::api("embedding"))?;
+    let (data, retries) = config.retry.run_counted(|| config.pipeline.run(Stage::Judge, client.embed(req.clone()))).await;
+    calls.retries += retries;
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return Ok(Err(api_failure(jzml, "embedding", &e))),
+    };
+    let [expected, actual] = data.as_slice() else {
+        return Ok(Err(failure(jzml, format!("embedding response contained {} embeddings, expected 2", data.len()))));
+    };
+    let similarity = compare::cosine_similarity(expected, actual);
+    Ok(Ok(Judgement { passed: similarity >= config.similarity_threshold, similarity: Some(similarity), ..Default::default() }))
+}
+
+/// Decides across JUDGE_MODELS by JUDGE_POLICY, counting only judges that reached a verdict. The per-judge verdicts are
+/// kept for the results; tokens add up and scores are averaged.
+fn ensemble(verdicts: Vec<(String, Result<Judgement, TestError>)>, policy: JudgePolicy, pass_threshold: f64) -> Result<Judgement, TestError> {
+    let panel = verdicts.iter().map(|(model, v)| PanelVote {
+        model: model.clone(),
+        passed: v.as_ref().ok().map(|j| j.passed),
+        score: v.as_ref().ok().and_then(|j| j.score.or(j.stated_score)),
+    }).collect();
+    let tokens = verdicts.iter().filter_map(|(_, v)| v.as_ref().ok().and_then(|j| j.tokens)).sum();
+    let mut judgements = Vec::new();
+    let mut first_failure = None;
+    for (_, verdict) in verdicts {
+        match verdict {
+            Ok(judgement) => judgements.push(judgement),
+            Err(e) => { first_failure.get_or_insert(e); }
+        }
+    }
+    if let (true, Some(e)) = (judgements.is_empty(), first_failure) {
+        return Err(e);
+    }
+    let yes = judgements.iter().filter(|j| j.passed).count();
+    let scores: Vec<f64> = judgements.iter().filter_map(|j| j.score).collect();
+    let score = (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64);
+    let passed = match policy {
+        JudgePolicy::Unanimous => yes == judgements.len(),
+        JudgePolicy::Majority => yes * 2 > judgements.len(),
+        JudgePolicy::Any => yes > 0,
+        JudgePolicy::Average => score.map_or(yes * 2 > judgements.len(), |score| score >= pass_threshold),
+    };
+    let winner = judgements.into_iter().find(|j| j.passed == passed);
+    Ok(Judgement { passed, score, panel, tokens: Some(tokens), ..winner.unwrap_or_default() })
+}
+
+fn failure(jzml: &str, err: String) -> TestError {
+    TestError::because(ErrorLocation::Judge, jzml, err)
+}
+
+fn api_failure(jzml: &str, call: &str, e: &OpenAIError) -> TestError {
+    TestError::because(ErrorLocation::JudgeApi, jzml, format!("{} request failed: {}", call, retry::describe(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boolean(passed: bool, stated_score: f64) -> (String, Result<Judgement, TestError>) {
+        (String::new(), Ok(Judgement { passed, stated_score: Some(stated_score), ..Default::default() }))
+    }
+
+    #[test]
+    fn average_takes_the_majority_of_boolean_judges_whatever_their_scores() {
+        let judged = ensemble(vec![boolean(false, 90.0), boolean(false, 95.0), boolean(true, 10.0)], JudgePolicy::Average, 80.0).unwrap();
+        assert!(!judged.passed);
+        assert_eq!(judged.score, None);
+        assert_eq!(judged.panel.iter().map(PanelVote::display).collect::<Vec<_>>(), ["false 90", "false 95", "true 10"]);
+        let scored = |score| (String::new(), Ok(Judgement { passed: score >= 80.0, score: Some(score), ..Default::default() }));
+        let judged = ensemble(vec![scored(70.0), scored(70.0), scored(100.0)], JudgePolicy::Average, 80.0).unwrap();
+        assert!(judged.passed);
+    }
+
+    #[test]
+    fn parse_verdict_reads_a_bare_verdict() {
+        for (reply, verdict) in [("true", true), ("True.", true), ("FALSE\n", false), ("Yes", true), ("no.", false), ("The answer is true", true)] {
+            assert_eq!(parse_verdict(reply), Some(verdict), "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn parse_verdict_prefers_true_and_false_over_yes_and_no() {
+        assert_eq!(parse_verdict("True, there are no differences."), Some(true));
+        assert_eq!(parse_verdict("False. Yes, the keys match, but the values differ."), Some(false));
+        assert_eq!(parse_verdict("Verdict: true - no issues found"), Some(true));
+    }
+
+    #[test]
+    fn parse_verdict_reads_yes_and_no_only_at_either_end() {
+        assert_eq!(parse_verdict("Yes, they describe the same order"), Some(true));
+        assert_eq!(parse_verdict("They describe the same order, so no"), Some(false));
+        assert_eq!(parse_verdict("They match, with no differences"), None);
+    }
+
+    #[test]
+    fn parse_verdict_finds_none_in_conflicting_or_missing_verdicts() {
+        assert_eq!(parse_verdict("It is true that the names match, but the totals make it false."), None);
+        assert_eq!(parse_verdict("Yes and no."), None);
+        assert_eq!(parse_verdict("The outputs are equivalent."), None);
+        assert_eq!(parse_verdict(""), None);
+    }
+
+    fn tokens(tokens: &[(&str, f32)]) -> Vec<ChatCompletionTokenLogprob> {
+        tokens.iter().map(|(token, p)| ChatCompletionTokenLogprob { token: token.to_string(), logprob: p.ln(), bytes: None, top_logprobs: Vec::new() }).collect()
+    }
+
+    #[test]
+    fn verdict_confidence_is_that_of_the_final_verdict_token() {
+        // "The key `active` is false in both outputs, so they match.\nVERDICT: true"
+        let cot = tokens(&[("The", 0.9), (" key", 0.9), (" `active`", 0.8), (" is", 0.9), (" false", 0.99), (" in", 0.9), (" both", 0.9),
+            (" outputs", 0.9), (",", 0.9), (" so", 0.9), (" they", 0.9), (" match", 0.9), (".\n", 0.9), ("VER", 0.9), ("DICT", 0.9), (":", 0.9),
+            (" true", 0.6)]);
+        let confidence = verdict_confidence(&cot).unwrap();
+        assert!((confidence - 0.6).abs() < 1e-6, "{}", confidence);
+        assert!((verdict_confidence(&tokens(&[("True", 0.75), (".", 0.9)])).unwrap() - 0.75).abs() < 1e-6);
+        assert_eq!(verdict_confidence(&tokens(&[("Yes", 0.9)])), None);
+        assert_eq!(verdict_confidence(&[]), None);
+    }
+
+    #[test]
+    fn json_verdict_parses_strings_like_a_plain_reply() {
+        assert_eq!(json_verdict(&serde_json::json!(true)), Some(true));
+        assert_eq!(json_verdict(&serde_json::json!("False. Yes, the keys match")), Some(false));
+        assert_eq!(json_verdict(&serde_json::json!("true or false")), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, fs};
+use crate::{error::AppError, suite, ErrorLocation, RunConfig, Status, SuiteReport, TestResult};
+
+/// Writes the run as a JUnit XML report with one `<testsuite>` per suite and one `<testcase>` per test file. A failed test is a `<failure>` whose type
+/// is the location it failed at, or an `<error>` when the API or the runner failed rather than the model; the body has
+/// the error, the judge's reason and the generated output. A test with no saved generation, skipped or
+/// failing as its `<xfail>` expects is `<skipped>`.
+pub fn write(path: &str, report: &SuiteReport, config: &RunConfig, timestamp: &str) -> Result<(), AppError> {
+    let all: Vec<&TestResult> = report.results.iter().collect();
+    let (failures, errors, skipped) = counts(&all);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        all.len(), failures, errors, skipped, report.duration.as_secs_f64(),
+    ));
+    // A run without results still has its (empty) suite.
+    let mut suites: BTreeMap<&str, Vec<&TestResult>> = if all.is_empty() { BTreeMap::from([("", Vec::new())]) } else { BTreeMap::new() };
+    for result in &report.results {
+        suites.entry(suite(&result.name)).or_default().push(result);
+    }
+    for (suite, results) in &suites {
+        let name = if suite.is_empty() { config.test_dir.clone() } else { format!("{}/{}", config.test_dir.trim_end_matches('/'), suite) };
+        let (failures, errors, skipped) = counts(results);
+        // With one suite its time is the run's; with several, the sum of its tests' times.
+        let time = if suites.len() == 1 { report.duration.as_secs_f64() } else { results.iter().map(|r| r.duration_ms).sum::<u64>() as f64 / 1000.0 };
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">\n",
+            escape(&name), results.len(), failures, errors, skipped, time, escape(timestamp),
+        ));
+        write_cases(&mut xml, results, &name);
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    fs::write(path, xml).map_err(AppError::io(path))?;
+    Ok(())
+}
+
+/// The failures, errors and skipped tests among `results`.
+fn counts(results: &[&TestResult]) -> (usize, usize, usize) {
+    let errors = results.iter().filter(|r| !r.passed() && !is_skipped(r) && is_error(r)).count();
+    let skipped = results.iter().filter(|r| is_skipped(r)).count();
+    let failures = results.iter().filter(|r| !r.passed() && !is_skipped(r)).count() - errors;
+    (failures, errors, skipped)
+}
+
+fn write_cases(xml: &mut String, results: &[&TestResult], classname: &str) {
+    for result in results {
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&result.name), escape(classname), result.duration_ms as f64 / 1000.0,
+        ));
+        if result.passed() && !is_skipped(result) {
+            xml.push_str("/>\n");
+            continue;
+        }
+        xml.push_str(">\n");
+        if is_skipped(result) {
+            let message = match result.status {
+                Status::Missing => "no saved generation".to_string(),
+                Status::Budget => "MAX_COST spent".to_string(),
+                Status::Stopped => "MAX_FAILURES reached".to_string(),
+                Status::ExpectedFailure => format!("expected failure at {}", result.location.map_or("", ErrorLocation::name)),
+                _ => result.error.clone().unwrap_or_else(|| "skipped".to_string()),
+            };
+            xml.push_str(&format!("      <skipped message=\"{}\"/>\n", escape(&message)));
+        } else {
+            let location = result.location.map_or("failed", ErrorLocation::name);
+            let first_line = result.error.as_deref().and_then(|e| e.lines().next()).unwrap_or("");
+            let message = if first_line.is_empty() { location.to_string() } else { format!("{}: {}", location, first_line) };
+            let element = if is_error(result) { "error" } else { "failure" };
+            xml.push_str(&format!("      <{} message=\"{}\" type=\"{}\">{}</{0}>\n", element, escape(&message), location, escape(&details(result))));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+}
+
+fn is_skipped(result: &TestResult) -> bool {
+    matches!(result.status, Status::Missing | Status::Budget | Status::Stopped | Status::Skipped | Status::ExpectedFailure)
+}
+
+/// Failures of the API or the runner, which CI should show apart from tests the model failed.
+fn is_error(result: &TestResult) -> bool {
+    matches!(result.location, Some(ErrorLocation::GenerationApi | ErrorLocation::JudgeApi | ErrorLocation::EmptyResponse | ErrorLocation::Timeout | ErrorLocation::Internal))
+}
+
+fn details(result: &TestResult) -> String {
+    let mut details = Vec::new();
+    if let Some(error) = result.error.as_deref().filter(|e| !e.is_empty()) {
+        details.push(error.to_string());
+    }
+    if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+        details.push(format!("Judge reason: {}", reason));
+    }
+    if !result.output.is_empty() {
+        details.push(format!("Generated output:\n{}", result.output));
+    }
+    details.join("\n\n")
+}
+
+/// Escapes text for an XML attribute or element, replacing the control characters XML 1.0 cannot represent.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() && c < ' ' => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,1488 @@
+//! Runs a directory of generation tests against a model. The binary is a thin wrapper that builds the [`RunConfig`]
+//! from the environment and renders the [`SuiteReport`] to CSV and the console; other programs can drive a run with a
+//! [`TestRunner`] and their own [`Reporter`].
+
+use std::{any::Any, cell::Cell, collections::BTreeMap, error::Error, fmt, fs, future::Future, panic::AssertUnwindSafe, path::{Path, PathBuf}, str::FromStr, time::{Duration, Instant}};
+use async_openai::{types::{ChatCompletionNamedToolChoice, ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent, ChatCompletionTool, ChatCompletionToolChoiceOption, ChatCompletionToolType, CompletionUsage, CreateChatCompletionRequestArgs, FinishReason, FunctionName, Stop}};
+use futures::{FutureExt, StreamExt};
+use inline_colorization::*;
+use backend::{ChatBackend, ScriptedBackend};
+use cassette::CassetteMode;
+use compare::Comparison;
+use extract::Extraction;
+use generation::{GenChoice, Generation};
+use pipeline::Stage;
+use structure::Structured;
+use test_case::{ToolChoice, Turn};
+use judge::{JudgeCalls, Judgement, TokenUsage};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use output::Verbosity;
+use tracing::Instrument;
+use truncate::Truncated;
+
+pub use config::RunConfig;
+pub use report::{Status, TestResult};
+pub use runner::{Reporter, TestRunner};
+pub use test_case::TestCase;
+pub use error::AppError;
+
+pub mod artifacts;
+pub mod backend;
+pub mod baseline;
+pub mod batch;
+pub mod cache;
+pub mod cassette;
+pub mod checkpoint;
+pub mod compare;
+pub mod config;
+pub mod config_file;
+pub mod draft;
+pub mod error;
+pub mod extract;
+pub mod flaky;
+pub mod format;
+pub mod generated;
+pub mod generation;
+pub mod github;
+pub mod history;
+pub mod init;
+pub mod html;
+pub mod judge;
+pub mod junit;
+pub mod lint;
+pub mod markdown;
+pub mod matrix;
+pub mod metadata;
+pub mod notify;
+pub mod output;
+pub mod pipeline;
+pub mod plugin;
+pub mod pricing;
+pub mod provider;
+pub mod ratelimit;
+pub mod redact;
+mod image;
+mod reference;
+pub mod report;
+pub mod retry;
+pub mod review;
+pub mod rubric;
+pub mod runlog;
+pub mod runner;
+pub mod select;
+pub mod serve;
+pub mod snapshot;
+pub mod stats;
+pub mod structure;
+pub mod summary;
+pub mod telemetry;
+pub mod template;
+pub mod test_case;
+pub mod truncate;
+mod tui;
+pub mod watch;
+
+pub type RunError = AppError;
+
+/// A test file found in the test directory. `index` is its position in filename order, which is the order results are
+/// reported in.
+pub struct TestInfo {
+    pub index: usize,
+    pub name: String,
+    pub contents: String,
+}
+
+pub struct SuiteReport {
+    pub results: Vec<TestResult>,
+    pub skipped: Vec<Skipped>,
+    pub duration: Duration,
+    /// Tests in TEST_DIR that TEST_FILTER, TAGS or EXCLUDE_TAGS left out.
+    pub not_selected: Vec<String>,
+    /// The run was stopped before every test was done, so `results` has only the tests finished by then.
+    pub interrupted: bool,
+}
+
+impl SuiteReport {
+    /// Generation and judge tokens across all tests.
+    pub fn total_tokens(&self) -> u32 {
+        self.results.iter().map(|r| r.tokens.unwrap_or(0) + r.judge_tokens.unwrap_or(0)).sum()
+    }
+}
+
+/// The PROVIDER's backend, or with CASSETTE recording it or the cassette in its place, logging every request, behind
+/// the run's RATE_LIMIT_RPM and RATE_LIMIT_TPM when they are set, and printing every request and response with
+/// VERBOSITY=verbose.
+pub fn client(config: &RunConfig) -> Box<dyn ChatBackend + '_> {
+    let backend: Box<dyn ChatBackend + '_> = match &config.cassette {
+        Some(cassette) if cassette.mode == CassetteMode::Replay => Box::new(cassette::Replay { cassette }),
+        Some(cassette) => Box::new(cassette::Recorder { inner: provider::backend(config), cassette }),
+        None => provider::backend(config),
+    };
+    let backend: Box<dyn ChatBackend + '_> = Box::new(runlog::Traced { inner: backend });
+    let backend: Box<dyn ChatBackend + '_> = match &config.rate_limit {
+        Some(limit) => Box::new(ratelimit::Limited { inner: backend, limit }),
+        None => backend,
+    };
+    match config.verbosity {
+        Verbosity::Verbose => Box::new(output::Echo { inner: backend, redactor: &config.redactor }),
+        _ => backend,
+    }
+}
+
+/// A directory entry that could not be read as a test, with the reason.
+pub struct Skipped {
+    pub path: String,
+    pub kind: SkipKind,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipKind {
+    /// Listing or reading the entry failed.
+    Unreadable,
+    /// A symlink whose target does not exist.
+    BrokenSymlink,
+    /// A symlink while FOLLOW_SYMLINKS is off.
+    Symlink,
+    /// A FIFO, socket or device, which is never read.
+    Special,
+}
+
+/// The regular files in TEST_DIR and its subdirectories, and the entries that had to be skipped. A test is named by its
+/// path relative to TEST_DIR with `/` between directories, and each subdirectory is a suite (see [`suite`]). Symlinks
+/// are followed to their target unless FOLLOW_SYMLINKS is off, but only to files; a symlinked directory is skipped, so
+/// the walk cannot loop. Hidden directories and pending snapshots are ignored. Only a TEST_DIR that cannot be listed
+/// at all is an error.
+pub fn discover(config: &RunConfig) -> Result<(Vec<TestInfo>, Vec<Skipped>), RunError> {
+    let entries = fs::read_dir(&config.test_dir).map_err(|source| AppError::ConfigRead { var: "TEST_DIR", path: config.test_dir.clone(), source })?;
+    let mut tests = Vec::new();
+    let mut skipped = Vec::new();
+    discover_in(config, Path::new(&config.test_dir), entries, "", &mut tests, &mut skipped);
+    // The cases of a structured file stay in file order.
+    tests.sort_by(|a, b| test_file(&a.name).cmp(test_file(&b.name)));
+    skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    for (index, test) in tests.iter_mut().enumerate() {
+        test.index = index;
+    }
+    Ok((tests, skipped))
+}
+
+/// Adds the tests in `dir`, whose names start with `prefix`, and those in its subdirectories.
+fn discover_in(config: &RunConfig, dir: &Path, entries: fs::ReadDir, prefix: &str, tests: &mut Vec<TestInfo>, skipped: &mut Vec<Skipped>) {
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                skipped.push(Skipped { path: dir.display().to_string(), kind: SkipKind::Unreadable, reason: e.to_string() });
+                continue;
+            }
+        };
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        // Images, schemas, validators and structure tests are what tests attach, not tests.
+        let attached = image::is_image(&path) || [".schema.json", ".wasm", ".lua"].iter().any(|ext| file_name.ends_with(ext));
+        if snapshot::is_snapshot(&file_name) || draft::is_draft(&file_name) || attached {
+            continue;
+        }
+        let skip = |kind, reason: String| Skipped { path: path.display().to_string(), kind, reason };
+        let link = match fs::symlink_metadata(&path) {
+            Ok(m) => m,
+            Err(e) => {
+                skipped.push(skip(SkipKind::Unreadable, e.to_string()));
+                continue;
+            }
+        };
+        let is_symlink = link.file_type().is_symlink();
+        let metadata = if !is_symlink {
+            link
+        } else if !config.follow_symlinks {
+            skipped.push(skip(SkipKind::Symlink, "symlink, FOLLOW_SYMLINKS is off".to_string()));
+            continue;
+        } else {
+            match fs::metadata(&path) {
+                Ok(m) => m,
+                Err(e) => {
+                    skipped.push(skip(SkipKind::BrokenSymlink, format!("broken symlink: {}", e)));
+                    continue;
+                }
+            }
+        };
+        if metadata.is_dir() {
+            if is_symlink {
+                skipped.push(skip(SkipKind::Symlink, "symlink to a directory, which is not followed".to_string()));
+            } else if !file_name.starts_with('.') {
+                match fs::read_dir(&path) {
+                    Ok(entries) => discover_in(config, &path, entries, &format!("{}{}/", prefix, file_name), tests, skipped),
+                    Err(e) => skipped.push(skip(SkipKind::Unreadable, e.to_string())),
+                }
+            }
+            continue;
+        }
+        if !metadata.is_file() {
+            skipped.push(skip(SkipKind::Special, "not a regular file".to_string()));
+            continue;
+        }
+        match fs::read_to_string(&path) {
+            Ok(contents) => tests.extend(file_tests(format!("{}{}", prefix, file_name), contents)),
+            Err(e) => skipped.push(skip(SkipKind::Unreadable, e.to_string())),
+        }
+    }
+}
+
+/// The tests of the file `name`: itself, or each case of a structured file.
+pub fn file_tests(name: String, contents: String) -> Vec<TestInfo> {
+    if !test_case::is_structured(&name) {
+        return vec![TestInfo { index: 0, name, contents }];
+    }
+    match test_case::cases(&name, &contents) {
+        Ok(cases) => cases.into_iter().map(|(name, contents)| TestInfo { index: 0, name, contents }).collect(),
+        Err(e) => vec![TestInfo { index: 0, contents: test_case::invalid(&e), name }],
+    }
+}
+
+/// The suite a test is in: the directory of its file, relative to TEST_DIR, or `""` for a test directly inside it.
+pub fn suite(name: &str) -> &str {
+    test_file(name).rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// The file a test is read from, relative to TEST_DIR: its name, less the `#case` of a case in a structured file.
+pub fn test_file(name: &str) -> &str {
+    match name.split_once('#') {
+        Some((file, _)) if test_case::is_structured(file) => file,
+        _ => name,
+    }
+}
+
+/// A path a test refers to, relative to the directory of its test file.
+pub(crate) fn resolve(config: &RunConfig, name: &str, path: &str) -> PathBuf {
+    let dir = Path::new(&config.test_dir);
+    dir.join(test_file(name)).parent().unwrap_or(dir).join(path)
+}
+
+/// Whether a test is one case of a structured file, which is not rewritten by `update-baselines` or snapshots.
+pub fn is_case(name: &str) -> bool {
+    test_file(name) != name
+}
+
+/// Runs every test in the test directory, JOBS at a time, handing each result to `on_result` in filename order as soon
+/// as it and all earlier ones are done, so the results come out the same however the tests finish. With DRY_RUN no API is called;
+/// see [`dry_run_backend`]. Once the finished tests have cost MAX_COST, or MAX_FAILURES of them have failed, the tests
+/// not yet started are reported as skipped, while the running ones finish.
+pub async fn run_suite<F: Future<Output = Result<(), RunError>>>(config: &RunConfig, mut on_result: impl FnMut(TestResult) -> F) -> Result<SuiteReport, RunError> {
+    let started = Instant::now();
+    let client = client(config);
+    let mut results = Vec::new();
+    let mut pending = Reorder::default();
+    let progress = Progress::default();
+    let (tests, skipped) = discover(config)?;
+    for s in &skipped {
+        config.say(&format!("{color_yellow}Skipping {}: {}{color_reset}", s.path, s.reason));
+        tracing::warn!(path = s.path, reason = s.reason, "skipped test file");
+    }
+    let (tests, not_selected) = select::select(config, tests);
+    let tests = select::order(config, tests);
+    if let Some(seed) = config.shuffle {
+        config.say(&format!("Shuffled with seed {} (SHUFFLE_SEED={} runs this order again)", seed, seed));
+        tracing::info!(seed, "shuffled");
+    }
+    tracing::info!(model = config.model, judge_models = ?config.judge_models, tests = tests.len(), not_selected = not_selected.len(), jobs = config.jobs, "run started");
+    if let Some(batch) = &config.judge_batch {
+        let mut tests = tests.into_iter().peekable();
+        while tests.peek().is_some() {
+            if let Some(status) = progress.stop(config) {
+                for test in tests.by_ref() {
+                    let index = test.index;
+                    for result in pending.push(index, not_started(test, config, status)) {
+                        on_result(result.clone()).await?;
+                        results.push(result);
+                    }
+                }
+                break;
+            }
+            let group: Vec<TestInfo> = tests.by_ref().take(batch.size).collect();
+            batch.start(group.len());
+            let done = futures::future::join_all(group.into_iter().map(|test| async {
+                let index = test.index;
+                // Every test leaves the group, even one that failed, so the others are not kept waiting for it.
+                let result = if config.dry_run {
+                    let backend = dry_run_backend(&test, config);
+                    let result = run_single(test, config, &backend).await;
+                    batch.leave(config, &backend).await.and(result)
+                } else {
+                    let result = run_single(test, config, &*client).await;
+                    batch.leave(config, &*client).await.and(result)
+                };
+                result.map(|result| (index, result))
+            })).await;
+            for done in done {
+                let (index, result) = done?;
+                progress.record(&result);
+                for result in pending.push(index, result) {
+                    on_result(result.clone()).await?;
+                    results.push(result);
+                }
+            }
+        }
+        cache_hits(config);
+        stage_waits(config);
+        return Ok(SuiteReport { results, skipped, duration: started.elapsed(), not_selected, interrupted: false });
+    }
+    let mut running = futures::stream::iter(tests).map(|test| async {
+        let index = test.index;
+        let result = if let Some(status) = progress.stop(config) {
+            Ok(not_started(test, config, status))
+        } else if config.dry_run {
+            let backend = dry_run_backend(&test, config);
+            run_single(test, config, &backend).await
+        } else {
+            run_single(test, config, &*client).await
+        };
+        result.map(|result| (index, result))
+    }).buffer_unordered(config.jobs);
+    while let Some(done) = running.next().await {
+        let (index, result) = done?;
+        progress.record(&result);
+        for result in pending.push(index, result) {
+            on_result(result.clone()).await?;
+            results.push(result);
+        }
+    }
+    cache_hits(config);
+    stage_waits(config);
+    Ok(SuiteReport { results, skipped, duration: started.elapsed(), not_selected, interrupted: false })
+}
+
+/// Says how many of the run's generations `--cache` saved.
+fn cache_hits(config: &RunConfig) {
+    if let Some(cache) = &config.cache {
+        let hits = cache.take_hits();
+        config.say(&format!("{} generations from the cache in {}", hits, cache.dir));
+        tracing::info!(hits, dir = cache.dir, "generation cache");
+    }
+}
+
+/// Says how long the requests of each stage waited for one of its GEN_JOBS or JUDGE_JOBS slots: the stage that waited
+/// longest is the one to give more slots, or the one its provider holds back.
+fn stage_waits(config: &RunConfig) {
+    if config.pipeline.is_limited() {
+        let (generation, judge) = config.pipeline.take_waits();
+        config.say(&format!("Waited for request slots: generation {:.1} s, judge {:.1} s", generation.as_secs_f64(), judge.as_secs_f64()));
+        tracing::info!(generation_ms = generation.as_millis() as u64, judge_ms = judge.as_millis() as u64, "stage waits");
+    }
+}
+
+/// What the finished tests of a run have cost and how many failed, for MAX_COST and MAX_FAILURES.
+#[derive(Default)]
+struct Progress {
+    spent: Cell<f64>,
+    failures: Cell<usize>,
+}
+
+impl Progress {
+    fn record(&self, result: &TestResult) {
+        self.spent.set(self.spent.get() + result.cost.unwrap_or(0.0));
+        // Quarantined tests count towards neither passed nor failed.
+        if result.status == Status::Failed && !result.quarantined {
+            self.failures.set(self.failures.get() + 1);
+        }
+    }
+
+    /// The status of the tests not started yet once the run has to stop.
+    fn stop(&self, config: &RunConfig) -> Option<Status> {
+        if config.max_cost.is_some_and(|max| self.spent.get() >= max) {
+            Some(Status::Budget)
+        } else if config.max_failures.is_some_and(|max| self.failures.get() >= max) {
+            Some(Status::Stopped)
+        } else {
+            None
+        }
+    }
+}
+
+fn not_started(test: TestInfo, config: &RunConfig, status: Status) -> TestResult {
+    let error = Some(match status {
+        Status::Budget => format!("not run, the run had spent its MAX_COST of {}", config.max_cost.unwrap_or_default()),
+        _ => format!("not run, {} tests had failed (MAX_FAILURES)", config.max_failures.unwrap_or_default()),
+    });
+    let mut result = TestResult { status, error, cost: Some(0.0), quarantined: config.quarantine.contains(&test.name), name: test.name, input: test.contents, ..Default::default() };
+    config.redactor.result(&mut result);
+    result
+}
+
+/// Holds results that finished early until every result before them is in.
+struct Reorder<T> {
+    next: usize,
+    waiting: BTreeMap<usize, T>,
+}
+
+impl<T> Default for Reorder<T> {
+    fn default() -> Self {
+        Reorder { next: 0, waiting: BTreeMap::new() }
+    }
+}
+
+impl<T> Reorder<T> {
+    /// The results that are now ready, in order.
+    fn push(&mut self, index: usize, item: T) -> Vec<T> {
+        self.waiting.insert(index, item);
+        let mut ready = Vec::new();
+        while let Some(item) = self.waiting.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+/// Generates the test's own expected output and has the judge agree with it, which checks that every expected output
+/// passes the structure test and comparison without spending any tokens.
+pub fn dry_run_backend(test: &TestInfo, config: &RunConfig) -> ScriptedBackend {
+    let case = TestCase::parse(&test.name, &test.contents).ok();
+    let backend = ScriptedBackend::new([], judge::dry_run_reply(case.as_ref().and_then(|c| c.rubric(config)), config));
+    // Saved generations are judged as they are; only the judge is scripted.
+    let case = case.filter(|_| config.generated.is_none());
+    let Some(case) = case else { return backend };
+    // The prompt with its variables filled in matches the generation requests whatever their description.
+    let gen_prompt = case.render_gen_prompt(config, "__description__").unwrap_or_else(|_| case.gen_prompt(config).into_owned());
+    let mut backend = backend.generating(&gen_prompt, case.expected_output.to_string());
+    // Each checkpoint of a conversation after the first is answered with its own expected output.
+    let mut user = None;
+    for turn in test_case::conversation(&test.contents).ok().flatten().unwrap_or_default() {
+        match turn {
+            Turn::User(text) => user = Some(text),
+            Turn::Checkpoint(expected) => backend = backend.replying(user.unwrap_or_default(), expected.to_string()),
+            Turn::Assistant(_) => {}
+        }
+    }
+    backend
+}
+
+/// Generates and checks a single test. Failed outputs listed in the config's overrides count as passed, a panic
+/// fails just this test at `internal` and running past TEST_TIMEOUT_MS at `timeout`. Quarantined tests are flagged but
+/// otherwise run as usual. A test with a `<skip>` section is not run, and one with an `<xfail>` section is expected to
+/// fail. A test that passed in the run `--retry-failed` retries keeps that result. Everything logged while the test
+/// runs is in its `test` span, which ends with its outcome. With ARTIFACTS its prompts, responses, payloads and
+/// structure test output are written to its directory of the run's artifacts.
+pub async fn run_single(test: TestInfo, config: &RunConfig, client: &dyn ChatBackend) -> Result<TestResult, RunError> {
+    let span = tracing::info_span!("test", name = test.name);
+    // A carried result keeps the model, start and attempt of the run it came from.
+    let carried = config.carried.as_ref().is_some_and(|c| c.contains_key(&test.name));
+    let started = chrono::Local::now();
+    let run = run_test(test, config, client).instrument(span.clone());
+    let mut result = match &config.artifacts_dir {
+        Some(dir) => {
+            let (result, files) = artifacts::collect(run).await;
+            let mut result = result?;
+            if !files.is_empty() {
+                result.artifacts = Some(artifacts::write(dir, &result.name, &files, &config.redactor)?.display().to_string());
+            }
+            result
+        }
+        None => run.await?,
+    };
+    if !carried {
+        result.model.get_or_insert_with(|| config.model.clone());
+        result.started = Some(started.to_rfc3339_opts(chrono::SecondsFormat::Millis, false));
+        result.attempt = Some(config.retried.get(&result.name).map_or(1, |a| a + 1));
+    }
+    config.redactor.result(&mut result);
+    span.in_scope(|| {
+        let (status, location, error) = (result.status, result.location.map(ErrorLocation::name), result.error.as_deref());
+        if result.passed() {
+            tracing::info!(?status, duration_ms = result.duration_ms, tokens = result.tokens, judge_tokens = result.judge_tokens, cost = result.cost, "finished");
+        } else {
+            tracing::warn!(?status, location, error, duration_ms = result.duration_ms, tokens = result.tokens, judge_tokens = result.judge_tokens, cost = result.cost, "finished");
+        }
+    });
+    Ok(result)
+}
+
+async fn run_test(test: TestInfo, config: &RunConfig, client: &dyn ChatBackend) -> Result<TestResult, RunError> {
+    let started = Instant::now();
+    if let Some(carried) = config.carried.as_ref().and_then(|c| c.get(&test.name)) {
+        return Ok(carried.clone());
+    }
+    if let Some(reason) = test_case::section(&test.contents, "skip") {
+        let error = Some(reason.trim().to_string()).filter(|r| !r.is_empty());
+        return Ok(TestResult { status: Status::Skipped, error, cost: Some(0.0), quarantined: config.quarantine.contains(&test.name), name: test.name, input: test.contents, ..Default::default() });
+    }
+    if config.generated.as_ref().is_some_and(|g| !g.contains_key(&test.name)) {
+        let error = Some("no saved generation for this test".to_string());
+        return Ok(TestResult { status: Status::Missing, error, quarantined: config.quarantine.contains(&test.name), name: test.name, input: test.contents, ..Default::default() });
+    }
+    let processed = AssertUnwindSafe(process(&test.name, &test.contents, config, client, Steps::All)).catch_unwind();
+    let processed = match config.test_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, processed).await.ok(),
+        None => Some(processed.await),
+    };
+    let outcome = match processed {
+        Some(Ok(outcome)) => outcome?,
+        Some(Err(panic)) => TestOutcome::new(Err(TestError::because(ErrorLocation::Internal, "", format!("internal error: {}", panic_message(&*panic))))),
+        None => TestOutcome::new(Err(TestError::because(ErrorLocation::Timeout, "", format!("timed out after {} ms", started.elapsed().as_millis())))),
+    };
+    let judgement = outcome.judgement;
+    let grade = outcome.grade.or_else(|| outcome.result.as_ref().err().and_then(Grade::failure));
+    let generation_usage = outcome.usage.as_ref().map(TokenUsage::from);
+    let judge_usage = outcome.judge_calls.total();
+    // A test generated by more than one model has no single price for its tokens.
+    let generation_cost = generation_usage.map_or(Some(0.0), |u| match outcome.models.as_slice() {
+        [] => config.prices.cost(&config.model, u),
+        [model] => config.prices.cost(model, u),
+        _ => None,
+    });
+    let judge_cost: Option<f64> = outcome.judge_calls.usage.iter().map(|(model, u)| config.prices.cost(model, *u)).sum();
+    let mut result = TestResult {
+        candidates: outcome.candidates,
+        finish_reason: outcome.finish_reason,
+        confidence: judgement.as_ref().and_then(|j| j.confidence),
+        similarity: judgement.as_ref().and_then(|j| j.similarity),
+        reason: judgement.as_ref().and_then(|j| j.reason.clone()),
+        score: judgement.as_ref().and_then(|j| j.score.or(j.stated_score)),
+        grade: grade.as_ref().map(|g| g.value),
+        graded_by: grade.map(|g| g.stage),
+        votes: judgement.as_ref().and_then(|j| j.votes).map(|(yes, total)| format!("{}/{} true", yes, total)),
+        judge_tokens: judgement.as_ref().and_then(|j| j.tokens),
+        normalized: outcome.normalized,
+        duration_ms: started.elapsed().as_millis() as u64,
+        tokens: outcome.usage.map(|u| u.total_tokens),
+        generation_prompt_tokens: generation_usage.map(|u| u.prompt),
+        generation_completion_tokens: generation_usage.map(|u| u.completion),
+        judge_prompt_tokens: Some(judge_usage.prompt).filter(|_| !outcome.judge_calls.usage.is_empty()),
+        judge_completion_tokens: Some(judge_usage.completion).filter(|_| !outcome.judge_calls.usage.is_empty()),
+        cost: generation_cost.zip(judge_cost).map(|(g, j)| g + j).filter(|_| !config.prices.is_empty()),
+        baseline_source: Some(if outcome.baseline_model.is_some() { "reference" } else { "static" }.to_string()),
+        baseline_model: outcome.baseline_model,
+        attempts: Some(outcome.attempts.iter().map(|a| a.map_or("passed", ErrorLocation::name)).collect::<Vec<_>>().join(", ")).filter(|a| !a.is_empty()),
+        pass_at_1: outcome.attempts.first().map(Option::is_none),
+        pass_at_k: (!outcome.attempts.is_empty()).then(|| outcome.attempts.iter().any(Option::is_none)),
+        attempt_pass_rate: (!outcome.attempts.is_empty()).then(|| outcome.attempts.iter().filter(|a| a.is_none()).count() as f64 / outcome.attempts.len() as f64),
+        truncated_tokens: outcome.truncated.as_ref().map(|t| t.removed),
+        truncated_input: outcome.truncated.map(|t| t.text),
+        generation_request_id: outcome.generation_id,
+        generation_retries: outcome.generation_retries,
+        judge_request_ids: Some(outcome.judge_calls.request_ids.join(" ")).filter(|ids| !ids.is_empty()),
+        judge_batch: Some(outcome.judge_calls.batched_with.join(", ")).filter(|names| !names.is_empty()),
+        judge_retries: Some(outcome.judge_calls.retries).filter(|_| judgement.is_some() || !outcome.judge_calls.request_ids.is_empty()),
+        generation_ms: outcome.generation_time.map(|t| t.as_millis() as u64),
+        extraction_ms: outcome.extraction_time.map(|t| t.as_millis() as u64),
+        structure_ms: outcome.structure_time.map(|t| t.as_millis() as u64),
+        first_token_ms: outcome.first_token.map(|t| t.as_millis() as u64),
+        judge_ms: Some(outcome.judge_calls.time.as_millis() as u64).filter(|_| !outcome.judge_calls.time.is_zero()),
+        model: Some(outcome.models.join(", ")).filter(|m| !m.is_empty()),
+        judgement,
+        ..match outcome.result {
+            Ok(pass) => TestResult { status: Status::Passed, output: pass.content, ..Default::default() },
+            Err(e) => TestResult { status: Status::Failed, error: e.message(), output: e.content, location: Some(e.location), ..Default::default() },
+        }
+    };
+    if result.status == Status::Failed && config.overrides.as_ref().is_some_and(|o| o.accepts(&test.name, &result.output)) {
+        result.status = Status::Human;
+    }
+    if let Some(reason) = test_case::section(&test.contents, "xfail") {
+        if result.passed() {
+            result.status = Status::UnexpectedPass;
+            result.error = Some(format!("expected to fail: {}", reason.trim()));
+        } else {
+            result.status = Status::ExpectedFailure;
+        }
+    }
+    result.quarantined = config.quarantine.contains(&test.name);
+    Ok(TestResult { name: test.name, input: test.contents, ..result })
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic.downcast_ref::<&str>().copied().or_else(|| panic.downcast_ref::<String>().map(String::as_str)).unwrap_or("panic without a message")
+}
+
+/// Generates a new baseline for a test: the first candidate that passes the structure test, or where the test failed.
+/// Nothing is compared against the current expected output.
+pub async fn generate_baseline(test: &TestInfo, config: &RunConfig, client: &dyn ChatBackend) -> Result<Result<String, TestError>, RunError> {
+    Ok(process(&test.name, &test.contents, config, client, Steps::StructureOnly).await?.result.map(|pass| pass.content))
+}
+
+/// How far [`process`] takes each candidate.
+#[derive(Clone, Copy, PartialEq)]
+enum Steps {
+    All,
+    /// Stop once a candidate passes the structure test.
+    StructureOnly,
+}
+
+async fn process(name: &str, contents: &str, config: &RunConfig, client: &dyn ChatBackend, steps: Steps) -> Result<TestOutcome, AppError> {
+    // Only a full run fills in missing baselines; --update-baselines needs an <output> section to replace.
+    let reference_baselines = steps == Steps::All && config.baseline_model.is_some();
+    let parsed = if reference_baselines { TestCase::parse_without_output(name, contents) } else { TestCase::parse(name, contents) };
+    let case = match parsed {
+        Ok(case) => case,
+        Err(e) => return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, e)))),
+    };
+    match test_case::conversation(contents) {
+        Ok(None) => {}
+        Ok(Some(_)) if steps == Steps::StructureOnly => {
+            let err = "the baselines of a conversation are not regenerated; edit its <output> checkpoints";
+            return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, err))));
+        }
+        Ok(Some(turns)) => return process_conversation(&case, &turns, contents, config, client).await,
+        Err(e) => return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, e)))),
+    }
+    let truncated = match fit_input(&case, config) {
+        Ok(truncated) => truncated,
+        Err(e) => return Ok(TestOutcome::new(Err(e))),
+    };
+    let case = match &truncated {
+        Some(t) => TestCase { input: &t.text, ..case },
+        None => case,
+    };
+    let outcome = if !reference_baselines || !case.expected_output.trim().is_empty() {
+        process_case(&case, contents, config, client, steps).await?
+    } else {
+        let baseline = match reference::baseline(&case, config, client).instrument(tracing::info_span!("baseline")).await? {
+            Ok(baseline) => baseline,
+            Err(e) => return Ok(TestOutcome { truncated, ..TestOutcome::new(Err(e)) }),
+        };
+        let case = TestCase { expected_output: &baseline, ..case };
+        let outcome = process_case(&case, contents, config, client, steps).await?;
+        TestOutcome { baseline_model: config.baseline_model.clone(), ..outcome }
+    };
+    Ok(TestOutcome { truncated, ..outcome })
+}
+
+/// The test's input cut down to INPUT_MAX_TOKENS, warning that it was.
+fn fit_input(case: &TestCase<'_>, config: &RunConfig) -> Result<Option<Truncated>, TestError> {
+    let truncated = truncate::fit(case, config)?;
+    if let Some(t) = &truncated {
+        tracing::warn!(removed = t.removed, max_tokens = config.input_max_tokens, strategy = %config.input_truncate_strategy, "truncated the input");
+        config.say(&format!("{color_yellow}Truncated the input of {} by {} tokens to INPUT_MAX_TOKENS {} ({}){color_reset}",
+            case.name, t.removed, config.input_max_tokens.unwrap_or_default(), config.input_truncate_strategy));
+    }
+    Ok(truncated)
+}
+
+/// Runs a conversation one checkpoint at a time. Each checkpoint sends the turns up to it, with the expected output of
+/// every earlier checkpoint as the assistant's reply so that each sees the same context on every run, and checks the
+/// reply as a one-shot test would. The test stops at the first checkpoint that fails, and counts the tokens and judge
+/// calls of every checkpoint it ran. Inputs are not cut down to INPUT_MAX_TOKENS.
+async fn process_conversation(case: &TestCase<'_>, turns: &[Turn<'_>], contents: &str, config: &RunConfig, client: &dyn ChatBackend) -> Result<TestOutcome, AppError> {
+    let total = turns.iter().filter(|t| matches!(t, Turn::Checkpoint(_))).count();
+    let mut history = Vec::new();
+    let mut done: Option<TestOutcome> = None;
+    let mut checkpoint = 0;
+    for turn in turns {
+        let Turn::Checkpoint(expected) = *turn else {
+            history.push(*turn);
+            continue;
+        };
+        // conversation() only allows a checkpoint right after a user turn.
+        let Some(Turn::User(input)) = history.pop() else { continue };
+        checkpoint += 1;
+        let step = TestCase { input, expected_output: expected, history: history.clone(), ..case.clone() };
+        let mut outcome = process_case(&step, contents, config, client, Steps::All).await?;
+        if let Some(earlier) = done.take() {
+            outcome = earlier.then(outcome);
+        }
+        if let Err(e) = &mut outcome.result {
+            let message = format!("checkpoint {} of {}", checkpoint, total);
+            e.err = Some(match e.err.take() {
+                Some(source) => Box::new(Caused { message: format!("{}: {}", message, source), source }),
+                None => message.into(),
+            });
+            return Ok(outcome);
+        }
+        history.extend([Turn::User(input), Turn::Assistant(expected)]);
+        done = Some(outcome);
+    }
+    Ok(done.unwrap_or_else(|| TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, "conversation has no checkpoints")))))
+}
+
+async fn process_case(case: &TestCase<'_>, contents: &str, config: &RunConfig, client: &dyn ChatBackend, steps: Steps) -> Result<TestOutcome, AppError> {
+    let name = case.name;
+    // A baseline being regenerated need not suit the comparison yet.
+    if steps == Steps::All {
+        if let Err(e) = case.validate(config) {
+            return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, e))));
+        }
+        // A judge prompt with a variable the test lacks would only fail after the generation was paid for.
+        if case.comparison(config.comparison) == Comparison::Judge {
+            if let Err(e) = case.render_test_prompt(config, "").and_then(|_| case.render_judge_system_prompt(config)) {
+                return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::MatchInput, contents, e))));
+            }
+        }
+    }
+    let mut results = Vec::new();
+    let mut attempts = Vec::new();
+    let mut models = Vec::new();
+    let (finish_reason, usage, generation_id, generation_retries, generation_time, first_token) = match config.generated.as_ref().and_then(|g| g.get(name)) {
+        Some(saved) => {
+            if let Some(e) = &saved.error {
+                return Ok(TestOutcome::new(Err(e.to_error())));
+            }
+            for choice in &saved.choices {
+                results.push(match &choice.error {
+                    Some(e) => Err(e.to_error()).into(),
+                    None => check(&choice.message, choice.candidates.iter().map(String::as_str).collect(), case, config, client, steps).await?,
+                });
+            }
+            // The tokens and time were spent by the run that generated them.
+            (saved.finish_reason.clone(), None, saved.id.clone(), None, None, None)
+        }
+        None => {
+            let (mut reasons, mut usage, mut ids, mut retries, mut time, mut first_token) = (Vec::new(), None, Vec::new(), 0, Duration::ZERO, None);
+            let k = config.pass_at_k.unwrap_or(1);
+            for attempt in 0..k {
+                let started = Instant::now();
+                let generated = generate(case, config, client, &config.model, config.gen_n, attempt).await?;
+                time += started.elapsed();
+                let generation = match generated {
+                    Ok(generation) => generation,
+                    Err(e) if k == 1 => return Ok(TestOutcome { generation_time: Some(time), ..TestOutcome::new(Err(e)) }),
+                    Err(e) => {
+                        attempts.push(Some(e.location));
+                        results.push(Err(e).into());
+                        continue;
+                    }
+                };
+                let first = results.len();
+                for choice in &generation.choices {
+                    results.push(evaluate(choice, generation.usage.as_ref(), case, config, client, steps).await?);
+                }
+                let attempt = &results[first..];
+                let passed = attempt.iter().filter(|r| r.result.is_ok()).count();
+                let failure = attempt.iter().find_map(|r| r.result.as_ref().err().map(|e| e.location));
+                attempts.push(if config.gen_policy.passes(passed, attempt.len()) { None } else { failure });
+                reasons.extend(generation.choices.iter().map(|c| c.finish_reason.map(generation::finish_reason_name).unwrap_or("")));
+                usage = add_usage(usage, generation.usage);
+                ids.extend(generation.id);
+                retries += generation.retries;
+                first_token = first_token.or(generation.first_token);
+                let model = generation.fallback.unwrap_or_else(|| config.model.clone());
+                if !models.contains(&model) {
+                    models.push(model);
+                }
+            }
+            (Some(reasons.join(", ")), usage, Some(ids.join(" ")).filter(|ids| !ids.is_empty()), Some(retries), Some(time), first_token)
+        }
+    };
+    if results.is_empty() {
+        return Ok(TestOutcome::new(Err(TestError::because(ErrorLocation::EmptyResponse, "", "response contained no choices"))));
+    }
+    let passed = results.iter().filter(|r| r.result.is_ok()).count();
+    let total = results.len();
+    // Each fresh attempt was decided by GEN_POLICY already, the saved generations are one attempt.
+    let accepted = if attempts.is_empty() { config.gen_policy.passes(passed, total) } else { attempts.iter().any(Option::is_none) };
+    if config.pass_at_k.is_none() {
+        attempts.clear();
+    }
+    let mut failures: BTreeMap<&str, usize> = BTreeMap::new();
+    for e in results.iter().filter_map(|r| r.result.as_ref().err()) {
+        *failures.entry(e.location.name()).or_default() += 1;
+    }
+    let mut candidates = format!("{}/{} passed", passed, total);
+    if passed > 0 && !failures.is_empty() {
+        let breakdown: Vec<String> = failures.iter().map(|(location, n)| format!("{} {}", location, n)).collect();
+        candidates.push_str(&format!(" (failed at {})", breakdown.join(", ")));
+    }
+    let mut judge_calls = JudgeCalls::default();
+    let (mut extraction_time, mut structure_time) = (None, None);
+    for r in &mut results {
+        judge_calls.merge(std::mem::take(&mut r.judge_calls));
+        extraction_time = add_time(extraction_time, r.extraction);
+        structure_time = add_time(structure_time, r.structure);
+    }
+    let mut chosen = match results.iter().position(|r| r.result.is_ok() == accepted) {
+        Some(i) => results.swap_remove(i),
+        None => results.into_iter().next().unwrap(),
+    };
+    if let Err(e) = &mut chosen.result {
+        if passed > 0 {
+            let message = format!("{} of {} completions passed, too few for GEN_POLICY {}; the first that failed: {}", passed, total, config.gen_policy, e.message().unwrap_or_default());
+            *e = TestError::because(e.location, std::mem::take(&mut e.content), message);
+        }
+    }
+    Ok(TestOutcome { result: chosen.result, candidates: Some(candidates), finish_reason, judgement: chosen.judgement, normalized: chosen.normalized, usage, baseline_model: None,
+        generation_id, generation_retries, judge_calls, truncated: None, attempts, grade: chosen.grade, generation_time, extraction_time, structure_time,
+        first_token, models,
+    })
+}
+
+/// Two stages' times added up, or the one that ran.
+fn add_time(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The token usage of several generation requests added up.
+fn add_usage(total: Option<CompletionUsage>, usage: Option<CompletionUsage>) -> Option<CompletionUsage> {
+    match (total, usage) {
+        (Some(a), Some(b)) => Some(CompletionUsage {
+            prompt_tokens: a.prompt_tokens + b.prompt_tokens,
+            completion_tokens: a.completion_tokens + b.completion_tokens,
+            total_tokens: a.total_tokens + b.total_tokens,
+            prompt_tokens_details: None,
+            completion_tokens_details: None,
+        }),
+        (a, b) => a.or(b),
+    }
+}
+
+async fn evaluate(response: &GenChoice, usage: Option<&CompletionUsage>, case: &TestCase<'_>, config: &RunConfig, client: &dyn ChatBackend, steps: Steps) -> Result<Evaluation, AppError> {
+    let started = Instant::now();
+    let extracted = extract_candidates(response, usage, case, config)?;
+    let extraction = Some(started.elapsed());
+    let extracted = match extracted {
+        Ok(extracted) => extracted,
+        Err(e) => return Ok(Evaluation { extraction, ..Err(e).into() }),
+    };
+    for candidate in &extracted.candidates {
+        artifacts::record("payload.json", candidate.as_str());
+    }
+    let evaluation = check(&extracted.message, extracted.candidates.iter().map(String::as_str).collect(), case, config, client, steps).await?;
+    Ok(Evaluation { extraction, ..evaluation })
+}
+
+/// A generated message and the JSON candidates found in it, ready for the structure test.
+struct Extracted {
+    message: String,
+    candidates: Vec<String>,
+}
+
+/// Sends the generation request for a test to `model` for `n` choices, retrying as configured, or with `--cache` takes
+/// the response to the same request from the cache; `attempt` counts PASS_AT_K's attempts from 0. The first user turn,
+/// the input of a one-shot test, is sent in GEN_PROMPT; later turns of a conversation as they are.
+async fn generate(case: &TestCase<'_>, config: &RunConfig, client: &dyn ChatBackend, model: &str, n: u8, attempt: u32) -> Result<Result<Generation, TestError>, AppError> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+    match case.render_system_prompt(config) {
+        Ok(Some(system)) => messages.push(ChatCompletionRequestSystemMessageArgs::default().content(system).build().map_err(AppError::api("generation"))?.into()),
+        Ok(None) => {}
+        Err(e) => return Ok(Err(TestError::because(ErrorLocation::MatchInput, "", e))),
+    }
+    // Each example is a turn of its own, its input in the generation prompt like the test's.
+    for (input, output) in &case.examples {
+        let prompt = match case.render_gen_prompt(config, input) {
+            Ok(prompt) => prompt,
+            Err(e) => return Ok(Err(TestError::because(ErrorLocation::MatchInput, "", e))),
+        };
+        messages.push(ChatCompletionRequestUserMessageArgs::default().content(prompt).build().map_err(AppError::api("generation"))?.into());
+        messages.push(ChatCompletionRequestAssistantMessageArgs::default().content(*output).build().map_err(AppError::api("generation"))?.into());
+    }
+    let system = messages.len();
+    for turn in case.history.iter().copied().chain([Turn::User(case.input)]) {
+        messages.push(match turn {
+            Turn::User(text) if messages.len() == system => {
+                let text = match case.render_gen_prompt(config, text) {
+                    Ok(text) => text,
+                    Err(e) => return Ok(Err(TestError::because(ErrorLocation::MatchInput, "", e))),
+                };
+                let content = if case.images.is_empty() {
+                    ChatCompletionRequestUserMessageContent::Text(text)
+                } else {
+                    match image::parts(case, config, text) {
+                        Ok(parts) => ChatCompletionRequestUserMessageContent::Array(parts),
+                        Err(e) => return Ok(Err(e)),
+                    }
+                };
+                ChatCompletionRequestUserMessageArgs::default().content(content).build().map_err(AppError::api("generation"))?.into()
+            }
+            Turn::User(text) => ChatCompletionRequestUserMessageArgs::default().content(text).build().map_err(AppError::api("generation"))?.into(),
+            Turn::Assistant(text) | Turn::Checkpoint(text) => ChatCompletionRequestAssistantMessageArgs::default().content(text).build().map_err(AppError::api("generation"))?.into(),
+        });
+    }
+    let mut req = CreateChatCompletionRequestArgs::default();
+    req.model(model).messages(messages);
+    if let Some(format) = &config.response_format {
+        req.response_format(format.clone());
+    }
+    if n > 1 {
+        req.n(n);
+    }
+    let params = case.params.or(config.gen_params);
+    if let Some(temperature) = params.temperature {
+        req.temperature(temperature);
+    }
+    if let Some(top_p) = params.top_p {
+        req.top_p(top_p);
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        req.max_tokens(max_tokens);
+    }
+    if let Some(seed) = params.seed {
+        req.seed(seed);
+    }
+    if !config.gen_stop.is_empty() {
+        req.stop(Stop::StringArray(config.gen_stop.clone()));
+    }
+    let named = |name: &str| ChatCompletionToolChoiceOption::Named(ChatCompletionNamedToolChoice {
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionName { name: name.to_string() },
+    });
+    // A test's own tools take the place of GEN_TOOL_SCHEMA.
+    if !case.tools.is_empty() {
+        req.tools(case.tools.iter().map(|t| ChatCompletionTool { r#type: ChatCompletionToolType::Function, function: t.clone() }).collect::<Vec<_>>())
+            .tool_choice(match case.tool_choice {
+                ToolChoice::Required => ChatCompletionToolChoiceOption::Required,
+                ToolChoice::Auto => ChatCompletionToolChoiceOption::Auto,
+                ToolChoice::Named(name) => named(name),
+            });
+    } else if let Some(tool) = &config.gen_tool {
+        req.tools(vec![ChatCompletionTool { r#type: ChatCompletionToolType::Function, function: tool.clone() }])
+            .tool_choice(named(&tool.name));
+    }
+    let mut req = req.build().map_err(AppError::api("generation"))?;
+    // BASELINE_MODEL generates through here too, without FALLBACK_MODELS.
+    let fallbacks = if model == config.model { config.fallback_models.as_slice() } else { &[] };
+    let (mut used, mut retries, mut failures) = (0, 0, Vec::new());
+    let generation = loop {
+        let fallback = (used > 0).then(|| req.model.clone());
+        if let Some(generation) = config.cache.as_ref().and_then(|cache| cache.get(&req, attempt)) {
+            tracing::debug!(model = req.model, attempt, "generation from the cache");
+            if config.verbosity == Verbosity::Verbose {
+                config.say(&format!("{style_bold}Generation for {} from the cache{style_reset}", case.name));
+            }
+            return Ok(Ok(Generation { fallback, ..generation }));
+        }
+        let (generation, r) = config.retry.run_counted(|| config.pipeline.run(Stage::Generation, client.complete(req.clone(), config.generation_stream(case.name))))
+            .instrument(tracing::info_span!("generation", model = req.model)).await;
+        retries += r;
+        artifacts::exchange(if model == config.model { "generation" } else { "baseline" }, &req, generation.as_ref().ok());
+        let e = match generation {
+            Ok(generation) => break Generation { retries, fallback, ..generation },
+            Err(e) => e,
+        };
+        let retried = if r > 0 { format!(" (after {} retries)", r) } else { String::new() };
+        let failure = format!("{}{}", retry::describe(&e), retried);
+        let Some(next) = fallbacks.get(used) else {
+            let message = if failures.is_empty() { failure } else { format!("{}; {}: {}", failures.join("; "), req.model, failure) };
+            return Ok(Err(TestError::caused_by(ErrorLocation::GenerationApi, "", message, e)));
+        };
+        tracing::warn!(model = req.model, fallback = next, error = failure, "falling back");
+        config.say(&format!("{color_yellow}Generation for {} with {} failed, falling back to {}: {}{color_reset}", case.name, req.model, next, failure));
+        failures.push(format!("{}: {}", req.model, failure));
+        req.model = next.clone();
+        used += 1;
+    };
+    if generation.choices.is_empty() {
+        return Ok(Err(TestError::because(ErrorLocation::EmptyResponse, "", "response contained no choices")));
+    }
+    match &config.cache {
+        Some(cache) => Ok(Ok(cache.put(&req, attempt, generation)?)),
+        None => Ok(Ok(generation)),
+    }
+}
+
+/// Finds the JSON candidates in a generated choice: the tool calls of a test with `<tools>`, the tool call arguments
+/// with GEN_TOOL, the whole message in JSON mode and otherwise what the test's `<extract>` or EXTRACT finds in it.
+fn extract_candidates(response: &GenChoice, usage: Option<&CompletionUsage>, case: &TestCase<'_>, config: &RunConfig) -> Result<Result<Extracted, TestError>, AppError> {
+    match response.finish_reason {
+        Some(FinishReason::Length) => {
+            let max_tokens = case.params.or(config.gen_params).max_tokens.map(|m| m.to_string()).unwrap_or("unset".to_string());
+            let usage = usage.map(|u| format!("prompt_tokens: {}, completion_tokens: {}", u.prompt_tokens, u.completion_tokens)).unwrap_or("usage unavailable".to_string());
+            let err = format!("generation hit the token limit (max_tokens: {}, {})", max_tokens, usage);
+            return Ok(Err(TestError::because(ErrorLocation::Truncated, response.content.clone().unwrap_or_default(), err)));
+        }
+        Some(FinishReason::ContentFilter) => {
+            let err = "generation was stopped by the content filter".to_string();
+            return Ok(Err(TestError::because(ErrorLocation::ContentFilter, response.content.clone().unwrap_or_default(), err)));
+        }
+        _ => {}
+    }
+    if !case.tools.is_empty() {
+        return Ok(tool_calls(response, case));
+    }
+    if let Some(tool) = &config.gen_tool {
+        let Some(arguments) = response.tool_arguments(&tool.name) else {
+            let err = format!("expected a call to {}, got {}", tool.name, response.summary());
+            return Ok(Err(TestError::because(ErrorLocation::MatchJson, response.content.clone().unwrap_or_default(), err)));
+        };
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(arguments) {
+            return Ok(Err(TestError::caused_by(ErrorLocation::MatchJson, arguments, format!("tool call arguments are not valid JSON: {}", e), e)));
+        }
+        return Ok(Ok(Extracted { message: arguments.to_string(), candidates: vec![arguments.to_string()] }));
+    }
+    let Some(message) = response.content.clone().filter(|c| !c.trim().is_empty()) else {
+        let location = match response.refusal {
+            None => ErrorLocation::EmptyResponse,
+            Some(_) if config.json_mode() => ErrorLocation::MatchJson,
+            Some(_) => ErrorLocation::Generation,
+        };
+        return Ok(Err(TestError::because(location, "", response.missing_content())));
+    };
+    let candidates = if config.json_mode() {
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(&message) {
+            return Ok(Err(TestError::caused_by(ErrorLocation::MatchJson, message, format!("response is not valid JSON: {}", e), e)));
+        }
+        vec![message.clone()]
+    } else {
+        match extract::extract(&message, case.extract.as_ref().unwrap_or(&config.extract), case.format(config)) {
+            Extraction::Found(c) => c.into_iter().map(str::to_string).collect(),
+            Extraction::Invalid(e) => return Ok(Err(TestError::because(ErrorLocation::MatchJson, message, e))),
+            Extraction::Missing => return Ok(Err(TestError::new(ErrorLocation::MatchJson, message, None))),
+        }
+    };
+    Ok(Ok(Extracted { message, candidates }))
+}
+
+/// The tool calls of a choice as the one candidate, a JSON array of `{"name": ..., "arguments": {...}}` objects in the
+/// order they were made. A choice without calls is an empty array with `<tool_choice>auto`, and fails otherwise.
+fn tool_calls(response: &GenChoice, case: &TestCase<'_>) -> Result<Extracted, TestError> {
+    let content = response.content.clone().unwrap_or_default();
+    if response.tool_calls.is_empty() && case.tool_choice != ToolChoice::Auto {
+        return Err(TestError::because(ErrorLocation::MatchJson, content, format!("expected a tool call, got {}", response.summary())));
+    }
+    let mut calls = Vec::new();
+    for call in &response.tool_calls {
+        if !case.tools.iter().any(|t| t.name == call.name) {
+            return Err(TestError::because(ErrorLocation::MatchJson, &call.arguments, format!("called {}, which is not one of the test's tools", call.name)));
+        }
+        let arguments: serde_json::Value = serde_json::from_str(&call.arguments)
+            .map_err(|e| TestError::because(ErrorLocation::MatchJson, &call.arguments, format!("arguments of the call to {} are not valid JSON: {}", call.name, e)))?;
+        calls.push(serde_json::json!({ "name": call.name, "arguments": arguments }));
+    }
+    let calls = serde_json::Value::Array(calls).to_string();
+    Ok(Extracted { message: calls.clone(), candidates: vec![calls] })
+}
+
+async fn check(message: &str, candidates: Vec<&str>, case: &TestCase<'_>, config: &RunConfig, client: &dyn ChatBackend, steps: Steps) -> Result<Evaluation, AppError> {
+    let started = Instant::now();
+    let structured = tracing::info_span!("structure", candidates = candidates.len()).in_scope(|| structure::first_passing(&candidates, case, config))?;
+    let structure = Some(started.elapsed());
+    artifacts::record("structure.txt", match &structured {
+        Structured::Passed { normalized: Some(normalized), .. } => format!("passed, normalized to\n{}\n", normalized),
+        Structured::Passed { normalized: None, .. } => "passed\n".to_string(),
+        Structured::Failed { location, err } => format!("failed at {}: {}\n", location, err.as_deref().unwrap_or("")),
+        Structured::NormalizeFailed { err, .. } => format!("normalize failed: {}\n", err),
+    });
+    let (jzml, normalized) = match structured {
+        Structured::Passed { candidate, normalized } => (candidate, normalized),
+        Structured::Failed { location, err } => return Ok(Evaluation { structure, ..Err(TestError::new(location, message, err)).into() }),
+        Structured::NormalizeFailed { candidate, err } => return Ok(Evaluation { structure, ..Err(TestError::because(ErrorLocation::Normalize, candidate, err)).into() }),
+    };
+    if steps == Steps::StructureOnly {
+        return Ok(Evaluation { normalized, structure, ..Ok(TestPass { content: jzml.to_string() }).into() });
+    }
+    let evaluation = compare_candidate(jzml, normalized.as_deref().unwrap_or(jzml), case, config, client).await?;
+    Ok(Evaluation { normalized, structure, ..evaluation })
+}
+
+/// Compares the candidate, or its normalized form where the structure test provides one, against the expected output.
+/// The raw candidate is what the results record as the generation.
+async fn compare_candidate(jzml: &str, subject: &str, case: &TestCase<'_>, config: &RunConfig, client: &dyn ChatBackend) -> Result<Evaluation, AppError> {
+    let comparison = case.comparison(config.comparison);
+    let compared = match comparison {
+        Comparison::Judge | Comparison::Embedding => None,
+        Comparison::Levenshtein | Comparison::Bleu | Comparison::Rouge => {
+            let score = compare::metric(comparison, case.expected_output, subject).unwrap_or_default();
+            let passed = score >= config.metric_threshold;
+            let judgement = Judgement { passed, similarity: Some(score), reason: Some(format!("{} {:.4}", comparison, score)), ..Default::default() };
+            let result = if passed {
+                Ok(TestPass { content: jzml.to_string() })
+            } else {
+                Err(TestError::because(ErrorLocation::Test, jzml, format!("{} {:.4} is below METRIC_THRESHOLD {}", comparison, score, config.metric_threshold)))
+            };
+            return Ok(Evaluation { result, judgement: Some(judgement), normalized: None, judge_calls: JudgeCalls::default(), grade: Grade::new(score.into(), comparison), extraction: None, structure: None });
+        }
+        Comparison::Exact => Some(compare::exact(case.expected_output, subject)),
+        Comparison::Strict => Some(compare::strict(case.expected_output, subject)),
+        Comparison::JsonEqual => Some(compare::json_equal(case.expected_output, subject, case.format(config), &config.ignore_paths)),
+        Comparison::Regex => Some(compare::regex(case.expected_output, subject)),
+    };
+    if let Some(compared) = compared {
+        let grade = Grade::verdict(compared.is_ok(), comparison);
+        let result = match compared {
+            Ok(()) => Ok(TestPass { content: jzml.to_string() }),
+            Err(e) => Err(TestError::because(ErrorLocation::Test, jzml, e)),
+        };
+        return Ok(Evaluation { grade, ..result.into() });
+    }
+    let mut judge_calls = JudgeCalls::default();
+    // With EMBED_SIMILARITY a judged test is embedded first, and a clear enough similarity decides without the judge.
+    let mut similarity = None;
+    if comparison == Comparison::Judge && config.embed_similarity {
+        let started = Instant::now();
+        let embedded = judge::embedding(case.expected_output, subject, config, client, &mut judge_calls).instrument(tracing::info_span!("embedding")).await?;
+        judge_calls.time += started.elapsed();
+        let s = match embedded {
+            Ok(judgement) => judgement.similarity.unwrap_or_default(),
+            Err(e) => return Ok(Evaluation { judge_calls, ..Err(e).into() }),
+        };
+        let judgement = |passed| Some(Judgement { passed, similarity: Some(s), ..Default::default() });
+        if let Some(threshold) = config.similarity_pass.filter(|t| s >= *t) {
+            tracing::debug!(similarity = s, threshold, "passed on similarity, not judged");
+            return Ok(Evaluation { result: Ok(TestPass { content: jzml.to_string() }), judgement: judgement(true), normalized: None, judge_calls, grade: Grade::new(s.into(), "similarity"), extraction: None, structure: None });
+        }
+        if let Some(threshold) = config.similarity_fail.filter(|t| s < *t) {
+            let err = format!("similarity {:.4} is below SIMILARITY_FAIL {}, not judged", s, threshold);
+            return Ok(Evaluation { result: Err(TestError::because(ErrorLocation::Test, jzml, err)), judgement: judgement(false), normalized: None, judge_calls, grade: Grade::new(s.into(), "similarity"), extraction: None, structure: None });
+        }
+        similarity = Some(s);
+    }
+    let started = Instant::now();
+    let judged = if comparison == Comparison::Embedding {
+        judge::embedding(case.expected_output, subject, config, client, &mut judge_calls).instrument(tracing::info_span!("embedding")).await?
+    } else {
+        judge::judge(case, subject, config, client, &mut judge_calls).instrument(tracing::info_span!("judge")).await?
+    };
+    judge_calls.time += started.elapsed();
+    let judgement = match judged {
+        Ok(judgement) => Judgement { similarity: judgement.similarity.or(similarity), ..judgement },
+        Err(e) => return Ok(Evaluation { judge_calls, ..Err(e).into() }),
+    };
+    let result = if judgement.passed {
+        Ok(TestPass { content: jzml.to_string() })
+    } else {
+        let err = judgement.similarity.filter(|_| comparison == Comparison::Embedding).map(|s| format!("similarity {:.4} is below SIMILARITY_THRESHOLD {}", s, config.similarity_threshold))
+            .or(case.rubric(config).filter(|_| !judgement.criteria.is_empty()).map(|r| r.failure(&judgement.criteria)))
+            .or(judgement.score.map(|s| format!("score {} is below PASS_THRESHOLD {}", s, config.pass_threshold)));
+        Err(TestError::new(ErrorLocation::Test, jzml, err))
+    };
+    let grade = match judgement.similarity.filter(|_| comparison == Comparison::Embedding) {
+        Some(s) => Grade::new(s.into(), comparison),
+        None => Grade::judged(&judgement),
+    };
+    Ok(Evaluation { result, judgement: Some(judgement), normalized: None, judge_calls, grade, extraction: None, structure: None })
+}
+
+#[derive(Debug)]
+struct TestOutcome {
+    result: Result<TestPass, TestError>,
+    /// How many completions passed, and where the others failed when some passed.
+    candidates: Option<String>,
+    finish_reason: Option<String>,
+    judgement: Option<Judgement>,
+    normalized: Option<String>,
+    usage: Option<CompletionUsage>,
+    /// The BASELINE_MODEL that produced the expected output, when the test had none.
+    baseline_model: Option<String>,
+    generation_id: Option<String>,
+    generation_retries: Option<u32>,
+    /// The judge calls of every candidate, not just the chosen one.
+    judge_calls: JudgeCalls,
+    /// The input that was sent, when INPUT_MAX_TOKENS cut it down.
+    truncated: Option<Truncated>,
+    /// With PASS_AT_K, the outcome of each attempt in order: `None` when it passed, where it failed otherwise.
+    attempts: Vec<Option<ErrorLocation>>,
+    /// The chosen candidate's grade; a conversation's is that of its last checkpoint run.
+    grade: Option<Grade>,
+    /// How long the generation requests took, retries included, and extraction and the structure test over every choice.
+    generation_time: Option<Duration>,
+    extraction_time: Option<Duration>,
+    structure_time: Option<Duration>,
+    /// How long the first streamed generation took to its first token.
+    first_token: Option<Duration>,
+    /// The models that generated the output, in the order they were first used: `model`, or the FALLBACK_MODELS that
+    /// took over from it. Empty when nothing was generated.
+    models: Vec<String>,
+}
+
+impl TestOutcome {
+    fn new(result: Result<TestPass, TestError>) -> TestOutcome {
+        TestOutcome {
+            result, candidates: None, finish_reason: None, judgement: None, normalized: None, usage: None, baseline_model: None,
+            generation_id: None, generation_retries: None, judge_calls: JudgeCalls::default(), truncated: None, attempts: Vec::new(), grade: None,
+            generation_time: None, extraction_time: None, structure_time: None, first_token: None, models: Vec::new(),
+        }
+    }
+
+    /// The outcome of a conversation checkpoint after this one: `next`, with the usage, request ids, retries, judge
+    /// calls and stage times of both, and the judge tokens of both judgements.
+    fn then(self, mut next: TestOutcome) -> TestOutcome {
+        if let (Some(earlier), Some(judgement)) = (self.judgement.and_then(|j| j.tokens), next.judgement.as_mut()) {
+            judgement.tokens = Some(judgement.tokens.unwrap_or(0) + earlier);
+        }
+        let mut judge_calls = self.judge_calls;
+        judge_calls.merge(next.judge_calls);
+        let ids: Vec<String> = self.generation_id.into_iter().chain(next.generation_id).collect();
+        let mut models = self.models;
+        models.extend(next.models.into_iter().filter(|m| !models.contains(m)).collect::<Vec<_>>());
+        TestOutcome {
+            models,
+            usage: add_usage(self.usage, next.usage),
+            generation_id: Some(ids.join(" ")).filter(|ids| !ids.is_empty()),
+            generation_retries: match (self.generation_retries, next.generation_retries) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+            },
+            judge_calls,
+            generation_time: add_time(self.generation_time, next.generation_time),
+            extraction_time: add_time(self.extraction_time, next.extraction_time),
+            structure_time: add_time(self.structure_time, next.structure_time),
+            first_token: self.first_token.or(next.first_token),
+            ..next
+        }
+    }
+}
+
+/// The result of validating and judging a single generated choice.
+#[derive(Debug)]
+struct Evaluation {
+    result: Result<TestPass, TestError>,
+    judgement: Option<Judgement>,
+    normalized: Option<String>,
+    judge_calls: JudgeCalls,
+    grade: Option<Grade>,
+    /// How long extraction and the structure test took, when the choice got that far.
+    extraction: Option<Duration>,
+    structure: Option<Duration>,
+}
+
+impl From<Result<TestPass, TestError>> for Evaluation {
+    fn from(result: Result<TestPass, TestError>) -> Self {
+        Evaluation { result, judgement: None, normalized: None, judge_calls: JudgeCalls::default(), grade: None, extraction: None, structure: None }
+    }
+}
+
+/// Partial credit for a candidate, from 0 to 1 beside its verdict, and the stage that gave it: a comparison such as
+/// `exact` or `bleu`, `similarity`, `embedding`, `judge` or `rubric`, or where the candidate failed before any of them.
+#[derive(Clone, Debug)]
+struct Grade {
+    value: f64,
+    stage: String,
+}
+
+impl Grade {
+    fn new(value: f64, stage: impl ToString) -> Option<Grade> {
+        Some(Grade { value: value.clamp(0.0, 1.0), stage: stage.to_string() })
+    }
+
+    /// A passing candidate's full marks or a failing one's nothing, for a grader without partial credit.
+    fn verdict(passed: bool, stage: impl ToString) -> Option<Grade> {
+        Grade::new(if passed { 1.0 } else { 0.0 }, stage)
+    }
+
+    /// The judge's score, or the rubric's weighted total, as 0–1, or else its verdict.
+    fn judged(judgement: &Judgement) -> Option<Grade> {
+        let stage = if judgement.criteria.is_empty() { "judge" } else { "rubric" };
+        match judgement.score {
+            Some(score) => Grade::new(score / 100.0, stage),
+            None => Grade::verdict(judgement.passed, stage),
+        }
+    }
+
+    /// A test that failed before a grader saw it gets 0 where the candidate itself failed, and no grade where the API,
+    /// the test file or the runner did.
+    fn failure(e: &TestError) -> Option<Grade> {
+        let ungraded = matches!(e.location, ErrorLocation::MatchInput | ErrorLocation::GenerationApi | ErrorLocation::JudgeApi | ErrorLocation::EmptyResponse | ErrorLocation::JudgeUnparseable
+            | ErrorLocation::ScoreUnparseable | ErrorLocation::Baseline | ErrorLocation::InputTooLong | ErrorLocation::Timeout | ErrorLocation::Internal);
+        if ungraded { None } else { Grade::new(0.0, e.location.name()) }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestPass {
+    pub content: String,
+}
+
+/// Where in the pipeline a test failed. The lowercase names are what the results CSV records and what
+/// [`FromStr`](std::str::FromStr) and serde read back; new variants may be added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorLocation {
+    MatchInput,
+    MatchJson,
+    Parse,
+    /// The candidate does not satisfy STRUCTURE_SCHEMA or the test's `<schema>`.
+    Schema,
+    /// A structure test call ran over LUA_MAX_INSTRUCTIONS, LUA_TIMEOUT_MS or LUA_MAX_MEMORY_MB.
+    LuaLimit,
+    Test,
+    Generation,
+    Judge,
+    Truncated,
+    ContentFilter,
+    JudgeUnparseable,
+    ScoreUnparseable,
+    GenerationApi,
+    JudgeApi,
+    /// A generation or judge response with no choices, or whose reply is blank without being a refusal.
+    EmptyResponse,
+    Normalize,
+    /// Generating the expected output with BASELINE_MODEL failed.
+    Baseline,
+    /// The input is over INPUT_MAX_TOKENS and the test is tagged `no-truncate`.
+    InputTooLong,
+    /// The test took longer than TEST_TIMEOUT_MS.
+    Timeout,
+    /// A panic while running the test: a bug in the runner rather than a model failure.
+    Internal
+}
+
+impl ErrorLocation {
+    pub const ALL: [ErrorLocation; 20] = [
+        ErrorLocation::MatchInput,
+        ErrorLocation::MatchJson,
+        ErrorLocation::Parse,
+        ErrorLocation::Schema,
+        ErrorLocation::LuaLimit,
+        ErrorLocation::Test,
+        ErrorLocation::Generation,
+        ErrorLocation::Judge,
+        ErrorLocation::Truncated,
+        ErrorLocation::ContentFilter,
+        ErrorLocation::JudgeUnparseable,
+        ErrorLocation::ScoreUnparseable,
+        ErrorLocation::GenerationApi,
+        ErrorLocation::JudgeApi,
+        ErrorLocation::EmptyResponse,
+        ErrorLocation::Normalize,
+        ErrorLocation::Baseline,
+        ErrorLocation::InputTooLong,
+        ErrorLocation::Timeout,
+        ErrorLocation::Internal,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorLocation::MatchInput => "matchinput",
+            ErrorLocation::MatchJson => "matchjson",
+            ErrorLocation::Parse => "parse",
+            ErrorLocation::Schema => "schema",
+            ErrorLocation::LuaLimit => "lualimit",
+            ErrorLocation::Test => "test",
+            ErrorLocation::Generation => "generation",
+            ErrorLocation::Judge => "judge",
+            ErrorLocation::Truncated => "truncated",
+            ErrorLocation::ContentFilter => "contentfilter",
+            ErrorLocation::JudgeUnparseable => "judgeunparseable",
+            ErrorLocation::ScoreUnparseable => "scoreunparseable",
+            ErrorLocation::GenerationApi => "generationapi",
+            ErrorLocation::JudgeApi => "judgeapi",
+            ErrorLocation::EmptyResponse => "emptyresponse",
+            ErrorLocation::Normalize => "normalize",
+            ErrorLocation::Baseline => "baseline",
+            ErrorLocation::InputTooLong => "inputtoolong",
+            ErrorLocation::Timeout => "timeout",
+            ErrorLocation::Internal => "internal"
+        }
+    }
+}
+
+impl fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ErrorLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ErrorLocation::ALL.into_iter().find(|l| l.name() == s).ok_or_else(|| format!("unknown error location {:?}", s))
+    }
+}
+
+impl Serialize for ErrorLocation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorLocation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Why a test failed, with the output it failed on.
+#[derive(Debug)]
+pub struct TestError {
+    pub content: String,
+    pub location: ErrorLocation,
+    /// What went wrong; its source is the underlying serde or API error where there was one.
+    pub err: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl TestError {
+    pub fn new(location: ErrorLocation, content: impl Into<String>, err: Option<String>) -> TestError {
+        TestError { content: content.into(), location, err: err.map(Into::into) }
+    }
+
+    pub fn because(location: ErrorLocation, content: impl Into<String>, err: impl Into<String>) -> TestError {
+        TestError::new(location, content, Some(err.into()))
+    }
+
+    /// `err` describes the failure for the results, `source` is the error that caused it.
+    pub fn caused_by(location: ErrorLocation, content: impl Into<String>, err: impl Into<String>, source: impl Error + Send + Sync + 'static) -> TestError {
+        TestError { content: content.into(), location, err: Some(Box::new(Caused { message: err.into(), source: Box::new(source) })) }
+    }
+
+    /// The text of the Error column.
+    pub fn message(&self) -> Option<String> {
+        self.err.as_ref().map(|e| e.to_string())
+    }
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed at {}", self.location)?;
+        if let Some(err) = &self.err {
+            write!(f, ": {}", err)?;
+        }
+        if !self.content.is_empty() {
+            let preview: String = self.content.chars().take(80).collect();
+            write!(f, " (output: {:?}{})", preview, if self.content.chars().nth(80).is_some() { "..." } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for TestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.err.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+#[derive(Debug)]
+struct Caused {
+    message: String,
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl fmt::Display for Caused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Caused {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_error_location_round_trips() {
+        let column = TestResult::columns().iter().position(|c| c == "Error Location").expect("the results have an Error Location column");
+        for location in ErrorLocation::ALL {
+            assert_eq!(location.name().parse::<ErrorLocation>(), Ok(location));
+            assert_eq!(location.to_string(), location.name());
+
+            let json = serde_json::to_string(&location).unwrap();
+            assert_eq!(json, format!("\"{}\"", location.name()));
+            assert_eq!(serde_json::from_str::<ErrorLocation>(&json).unwrap(), location);
+
+            let result = TestResult { name: "t.txt".to_string(), status: Status::Failed, location: Some(location), ..Default::default() };
+            assert_eq!(result.fields()[column], location.name());
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.serialize(&result).unwrap();
+            let data = writer.into_inner().unwrap();
+            let read: Vec<TestResult> = csv::Reader::from_reader(data.as_slice()).deserialize().collect::<Result<_, _>>().unwrap();
+            assert_eq!(read[0].location, Some(location));
+        }
+    }
+
+    #[test]
+    fn error_location_names_are_distinct() {
+        let names: std::collections::BTreeSet<&str> = ErrorLocation::ALL.iter().map(|l| l.name()).collect();
+        assert_eq!(names.len(), ErrorLocation::ALL.len());
+    }
+
+    #[test]
+    fn unknown_error_locations_do_not_parse() {
+        assert!("MatchInput".parse::<ErrorLocation>().is_err());
+        assert!(serde_json::from_str::<ErrorLocation>("\"nowhere\"").is_err());
+    }
+}
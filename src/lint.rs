@@ -0,0 +1,61 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::{config::RunConfig, discover, structure, test_case::{self, TestCase}, test_file, truncate::NO_TRUNCATE_TAG, RunError};
+
+/// A problem `validate` found, in the test file or setting it names.
+pub struct Problem {
+    pub file: String,
+    pub message: String,
+}
+
+/// Reads every test in TEST_DIR as a run would and returns how many there are and the problems that would fail or
+/// skip them: entries that cannot be read, tests that do not parse or whose expected output the comparison cannot use,
+/// a test's own schema, structure test or validator that does not load, names that differ only in case, and tags
+/// outside KNOWN_TAGS. TAGS and EXCLUDE_TAGS naming a tag no test has are reported too, as they are likely typos.
+pub fn lint(config: &RunConfig) -> Result<(usize, Vec<Problem>), RunError> {
+    let (tests, skipped) = discover(config)?;
+    let mut problems: Vec<Problem> = skipped.into_iter().map(|s| Problem { file: s.path, message: s.reason }).collect();
+    let mut seen_tags = BTreeSet::new();
+    let mut by_lowercase: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for test in &tests {
+        let mut report = |message: String| problems.push(Problem { file: test.name.clone(), message });
+        let tags = test_case::tags(&test.contents);
+        for tag in &tags {
+            if !config.known_tags.is_empty() && *tag != NO_TRUNCATE_TAG && !config.known_tags.iter().any(|t| t == tag) {
+                report(format!("tag {:?} is not one of KNOWN_TAGS", tag));
+            }
+        }
+        seen_tags.extend(tags);
+        by_lowercase.entry(test.name.to_lowercase()).or_default().push(&test.name);
+        let case = match TestCase::parse(&test.name, &test.contents) {
+            Ok(case) => case,
+            Err(e) => {
+                report(e);
+                continue;
+            }
+        };
+        if let Err(e) = test_case::conversation(&test.contents) {
+            report(e);
+        }
+        if let Err(e) = case.validate(config) {
+            report(e);
+        }
+        if let Err(e) = structure::check(&case, config) {
+            report(e);
+        }
+    }
+    for names in by_lowercase.values().filter(|names| names.len() > 1) {
+        for name in names {
+            let others: Vec<&str> = names.iter().copied().filter(|n| n != name).collect();
+            problems.push(Problem { file: name.to_string(), message: format!("name differs only in case from {}, which clashes on case-insensitive file systems", others.join(", ")) });
+        }
+    }
+    for (var, wanted) in [("TAGS", &config.tags), ("EXCLUDE_TAGS", &config.exclude_tags)] {
+        for tag in wanted.iter().filter(|t| !seen_tags.contains(t.as_str())) {
+            problems.push(Problem { file: var.to_string(), message: format!("no test is tagged {:?}", tag) });
+        }
+    }
+    // Keep each file's problems together, the files in test order.
+    let order: BTreeMap<&str, usize> = tests.iter().enumerate().rev().map(|(i, t)| (test_file(&t.name), i)).collect();
+    problems.sort_by_key(|p| order.get(test_file(&p.file)).copied().unwrap_or(usize::MAX));
+    Ok((tests.len(), problems))
+}
@@ -1,147 +1,1090 @@
-use std::{env, error::Error, fmt, fs};
-use regex::Regex;
+use std::{collections::BTreeSet, env, fs::{self, File}, io::{self, Read, Write}, net::{IpAddr, Ipv4Addr, SocketAddr}, path::Path, process::ExitCode, time::{Duration, Instant}};
 use inline_colorization::*;
-use csv::Writer;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use csv::{Writer, WriterBuilder};
 use chrono::Local;
-use mlua::{Function, Lua};
-use async_openai::{config::OpenAIConfig, types::{ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs}, Client};
+use tokio::sync::mpsc;
+use tracing_subscriber::layer::SubscriberExt;
+use ai_test_util::{baseline::{self, BaselineUpdate, Confirm, Target}, checkpoint::{self, Manifest}, compare, config_file, draft, error::AppError, flaky, generated, github, history::{History, RunRecord}, html, init::{self, Scaffolded}, junit, lint, markdown, matrix::{self, MatrixRun}, metadata::RunMetadata, notify::{self, Notification}, output::{Console, ProgressBar, Verbosity}, report::{self, Column, ReportFormat, ResultLine}, review::{self, ReviewQueue}, runlog::RunLog, select, serve, snapshot, stats::{self, Significance}, structure, summary::Summary, telemetry, test_case::TestCase, watch, ErrorLocation, RunConfig, Status, SuiteReport, TestInfo, TestResult};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+/// Flags that override the environment variable of the same setting, by flag name.
+const OVERRIDES: [(&str, &str); 21] = [
+    ("test-dir", "TEST_DIR"),
+    ("results-dir", "RESULTS_DIR"),
+    ("gen-prompt", "GEN_PROMPT"),
+    ("gen-prompts", "GEN_PROMPTS"),
+    ("test-prompt", "TEST_PROMPT"),
+    ("structure-test", "STRUCTURE_TEST"),
+    ("model", "model"),
+    ("models", "MODELS"),
+    ("jobs", "JOBS"),
+    ("gen-jobs", "GEN_JOBS"),
+    ("judge-jobs", "JUDGE_JOBS"),
+    ("repeat", "PASS_AT_K"),
+    ("format", "REPORT_FORMAT"),
+    ("max-cost", "MAX_COST"),
+    ("max-failures", "MAX_FAILURES"),
+    ("min-pass-rate", "MIN_PASS_RATE"),
+    ("filter", "TEST_FILTER"),
+    ("tag", "TAGS"),
+    ("exclude-tag", "EXCLUDE_TAGS"),
+    ("seed", "SHUFFLE_SEED"),
+    ("sort", "RESULT_ORDER"),
+];
+
+/// The command line. Every subcommand also answers to its older `--<name>` form, and running without one runs the
+/// tests.
+fn cli() -> Command {
+    let overrides = OVERRIDES.map(|(flag, var)| Arg::new(flag).long(flag).global(true).value_name(var).help(format!("overrides {}", var)));
+    // Not global, as `update-baselines --dry-run` means something else.
+    let dry_run = Arg::new("dry-run").long("dry-run").action(ArgAction::SetTrue).help("answer each test with its expected output instead of calling the API, as DRY_RUN=true");
+    let watch = Arg::new("watch").long("watch").action(ArgAction::SetTrue).help("run the tests again as the test directory, prompts and structure test change");
+    Command::new("ai_test_util")
+        .about("Runs AI generation tests: generates each test's output, checks its structure in Lua and has a judge model compare it to the baseline")
+        .after_help(format!("Configuration is read from the environment, .env and ai_test.toml, in that order of precedence, see the README; the flags above override all of them.\n\n{}", structure::LUA_API))
+        .arg(Arg::new("config").long("config").global(true).value_name("path").help("the TOML or YAML config file, instead of ai_test.toml"))
+        .args(overrides)
+        .arg(Arg::new("fail-fast").long("fail-fast").global(true).action(ArgAction::SetTrue).help("stop after the first failure, as --max-failures 1"))
+        .arg(Arg::new("cache").long("cache").global(true).action(ArgAction::SetTrue).help("reuse the generations of earlier runs for the same requests, as CACHE=true"))
+        .arg(Arg::new("shuffle").long("shuffle").global(true).action(ArgAction::SetTrue).help("run the tests in a random order, printing its seed for --seed, as SHUFFLE=true"))
+        .arg(Arg::new("quiet").long("quiet").short('q').global(true).action(ArgAction::SetTrue).help("print only the summary, as VERBOSITY=quiet"))
+        .arg(Arg::new("verbose").long("verbose").short('v').global(true).action(ArgAction::SetTrue).help("also print every prompt and raw response, as VERBOSITY=verbose"))
+        .arg(dry_run.clone())
+        .arg(watch.clone())
+        .subcommand(Command::new("run").about("Run the tests (the default)")
+            .arg(dry_run)
+            .arg(watch)
+            .arg(Arg::new("tests").value_name("test").num_args(1..).help("run only these test files, or - for one read from stdin, printing each result in full; TEST_DIR and RESULTS_DIR are then optional"))
+            .arg(Arg::new("retry-failed").long("retry-failed").value_name("results.csv").help("run only the tests that did not pass in this results CSV, keeping its other results"))
+            .arg(Arg::new("resume").long("resume").value_name("run").help("continue a run that was stopped or crashed, by its name in RESULTS_DIR, running only the tests it did not finish and writing to its results files")))
+        .subcommand(Command::new("init").about("Create a project skeleton: config file, example test, prompts and structure test")
+            .arg(Arg::new("dir").value_name("dir").default_value(".").help("the project directory, created if need be"))
+            .arg(Arg::new("force").long("force").action(ArgAction::SetTrue).help("overwrite files that exist already")))
+        .subcommand(Command::new("validate").long_flag("check").about("Validate the configuration, structure test and test files without calling the API"))
+        .subcommand(Command::new("review").long_flag("review").about("Run the tests and review the failures, or review an existing results CSV")
+            .arg(Arg::new("results").value_name("results.csv"))
+            .arg(Arg::new("plain").long("plain").action(ArgAction::SetTrue).help("prompt for each failure in turn instead of opening the review interface")))
+        .subcommand(Command::new("report").about("Print the results of a saved run, the newest in RESULTS_DIR by default")
+            .arg(Arg::new("results").value_name("results.csv")))
+        .subcommand(Command::new("compare").about("Compare two runs' results, failing if a test that passed in the old run fails in the new one")
+            .arg(Arg::new("old").required(true).value_name("old.csv"))
+            .arg(Arg::new("new").required(true).value_name("new.csv")))
+        .subcommand(Command::new("history").about("Show each test's pass rate over the runs recorded in HISTORY_FILE")
+            .arg(Arg::new("last").long("last").value_name("N").value_parser(clap::value_parser!(usize)).help("only the N most recent runs"))
+            .arg(Arg::new("test").long("test").value_name("name").help("only the tests whose name contains this")))
+        .subcommand(Command::new("flaky").about("Rank the tests that both passed and failed over HISTORY_FILE's runs with the latest run's settings, or over the attempts of a --repeat run")
+            .arg(Arg::new("results").value_name("results.csv").help("a run with PASS_AT_K whose attempts to read instead of HISTORY_FILE"))
+            .arg(Arg::new("last").long("last").value_name("N").value_parser(clap::value_parser!(usize)).help("only the N most recent runs with those settings")))
+        .subcommand(Command::new("serve").long_flag("serve").about("Serve the runs in RESULTS_DIR over HTTP, on SERVE_HOST (localhost by default)")
+            .arg(Arg::new("port").required(true).value_parser(clap::value_parser!(u16)))
+            .arg(Arg::new("run").long("run").action(ArgAction::SetTrue).help("run the tests first"))
+            .arg(Arg::new("allow-runs").long("allow-runs").action(ArgAction::SetTrue).help("let POST /api/runs start runs")))
+        .subcommand(Command::new("phase").long_flag("phase").about("Run only the generation or only the judging half of a run")
+            .arg(Arg::new("phase").required(true).value_parser(["generate", "judge"]))
+            .arg(Arg::new("generated").long("generated").value_name("path").help("the .generated.jsonl file, or a directory with them, to judge")))
+        .subcommand(Command::new("update-baselines").long_flag("update-baselines").about("Rewrite each test's <output> with a new generation that passes the structure test")
+            .arg(Arg::new("dry-run").long("dry-run").action(ArgAction::SetTrue).help("only show the diffs"))
+            .arg(Arg::new("confirm").long("confirm").action(ArgAction::SetTrue).help("show each diff and ask before writing it"))
+            .arg(Arg::new("snapshot").long("snapshot").action(ArgAction::SetTrue).help("write the new baselines as pending snapshots for accept or reject")))
+        .subcommand(Command::new("generate").about("Draft test cases from a description of the feature with the model, as .draft files in TEST_DIR to review and accept")
+            .arg(Arg::new("spec").required(true).value_name("spec").help("the file describing the feature, or - to read it from stdin"))
+            .arg(Arg::new("count").long("count").value_name("N").default_value("5").value_parser(clap::value_parser!(usize)).help("how many tests to ask for")))
+        .subcommand(Command::new("accept").long_flag("accept").about("Promote the pending snapshots into the tests, and the drafts into tests")
+            .arg(Arg::new("name").help("only the tests whose name contains this")))
+        .subcommand(Command::new("reject").long_flag("reject").about("Discard the pending snapshots and drafts")
+            .arg(Arg::new("name").help("only the tests whose name contains this")))
+}
+
+/// Parses the command line, then loads the config file and applies the overrides before the runtime starts, while the
+/// process is still single-threaded.
+fn main() -> Result<ExitCode, AppError> {
     dotenv::dotenv().ok();
-    let tests_dir = env::var("TEST_DIR")?;
-    let results_dir = env::var("RESULTS_DIR")?;
-    match fs::read_dir(tests_dir) {
-        Ok(test_files) => {
-            let mut writer = Writer::from_path(format!("{}/results{}.csv", results_dir, Local::now().format("%Y-%m-%d %H%M")))?;
-            writer.write_record(&["Name", "Status", "Input", "Result", "Error Location", "Error"])?;
-            for path in test_files.map(|p| { p.unwrap() }).filter(|p| { p.file_type().unwrap().is_file() }) {
-                let name = path.file_name().to_str().unwrap_or("").to_string();
-                let contents = fs::read_to_string(path.path())?;
-                match process(&contents).await? {
-                    Ok(p) => {
-                        println!("Test {} passed", name);
-                        println!("{}", p.content);
-                        writer.write_record(&[name, "Passed".to_string(), contents, p.content, "".to_string(), "".to_string()])?;
-                    }
-                    Err(e) => {
-                        println!("{color_red}Test {} failed.", name);
-                        println!("Process: {}", e.location);
-                        if let Some(m) = &e.err {
-                            println!("{}", m)
-                        }
-                        println!("{}", e.content);
-                        print!("{color_reset}");
-                        writer.write_record(&[name, "Failed".to_string(), contents, e.content, e.location.to_string(), e.err.unwrap_or("".to_string())])?;
-                    }
+    let matches = cli().get_matches();
+    let sub = matches.subcommand().map(|(_, sub)| sub);
+    // A project being created may not have a config file yet, or have one that does not load.
+    if matches.subcommand_name() != Some("init") {
+        config_file::apply(sub.and_then(|m| m.get_one::<String>("config")).or(matches.get_one::<String>("config")).map(String::as_str))?;
+    }
+    for (flag, var) in OVERRIDES {
+        if let Some(value) = sub.and_then(|m| m.get_one::<String>(flag)).or(matches.get_one::<String>(flag)) {
+            env::set_var(var, value);
+        }
+    }
+    // Test files named on the command line need no project around them: their paths are taken from the working
+    // directory, and nothing is written to RESULTS_DIR unless CACHE or ARTIFACTS ask for it.
+    if matches.subcommand_name() == Some("run") && sub.is_some_and(|m| m.contains_id("tests")) {
+        if env::var_os("TEST_DIR").is_none() {
+            env::set_var("TEST_DIR", ".");
+        }
+        if env::var_os("RESULTS_DIR").is_none() {
+            env::set_var("RESULTS_DIR", env::temp_dir().join("ai_test_util"));
+        }
+    }
+    let flag = |id: &str| matches.get_flag(id) || sub.is_some_and(|m| m.get_flag(id));
+    if flag("fail-fast") {
+        env::set_var("MAX_FAILURES", "1");
+    }
+    if flag("cache") {
+        env::set_var("CACHE", "true");
+    }
+    if flag("shuffle") {
+        env::set_var("SHUFFLE", "true");
+    }
+    if matches.subcommand_name().is_none_or(|name| name == "run") && flag("dry-run") {
+        env::set_var("DRY_RUN", "true");
+    }
+    match (flag("quiet"), flag("verbose")) {
+        (true, true) => return Err("--quiet and --verbose cannot be combined".into()),
+        (true, false) => env::set_var("VERBOSITY", "quiet"),
+        (false, true) => env::set_var("VERBOSITY", "verbose"),
+        (false, false) => {}
+    }
+    tokio::runtime::Runtime::new().map_err(AppError::io("tokio runtime"))?.block_on(dispatch(matches))
+}
+
+async fn dispatch(matches: ArgMatches) -> Result<ExitCode, AppError> {
+    let path = |m: &ArgMatches, id: &str| m.get_one::<String>(id).cloned();
+    match matches.subcommand() {
+        None if matches.get_flag("watch") => watch_mode().await,
+        None => run(RunConfig::from_env()?, false).await,
+        Some(("run", m)) if m.contains_id("tests") => {
+            if m.get_flag("watch") || path(m, "retry-failed").is_some() || path(m, "resume").is_some() {
+                return Err("test files cannot be combined with --watch, --retry-failed or --resume, which run TEST_DIR".into());
+            }
+            run_files(RunConfig::from_env()?, m.get_many::<String>("tests").expect("tests are present").cloned().collect()).await
+        }
+        Some(("run", m)) if m.get_flag("watch") => {
+            if path(m, "retry-failed").is_some() || path(m, "resume").is_some() {
+                return Err("--watch cannot be combined with --retry-failed or --resume".into());
+            }
+            watch_mode().await
+        }
+        Some(("run", m)) => {
+            let mut config = RunConfig::from_env()?;
+            match (path(m, "retry-failed"), path(m, "resume")) {
+                (Some(_), Some(_)) => return Err("--retry-failed and --resume cannot be combined".into()),
+                (Some(previous), None) => carry(&previous, &mut config)?,
+                (None, Some(run)) => {
+                    let run = run_name(&run);
+                    let finished = checkpoint::resume(&format!("{}/{}", config.results_dir, run), &config)?;
+                    println!("Resuming {}, keeping the results of its {} finished tests", run, finished.len());
+                    config.carried = Some(finished);
+                    config.resume = Some(run);
                 }
+                (None, None) => {}
             }
-            writer.flush()?;
+            run(config, false).await
+        }
+        Some(("init", m)) => scaffold(m.get_one::<String>("dir").expect("dir has a default"), m.get_flag("force")),
+        Some(("validate", _)) => validate(RunConfig::from_env()?),
+        Some(("review", m)) => match path(m, "results") {
+            Some(results) => review::interactive(&results, m.get_flag("plain")).map(|_| ExitCode::SUCCESS),
+            None => run(RunConfig::from_env()?, true).await,
+        },
+        Some(("report", m)) => report(path(m, "results")),
+        Some(("compare", m)) => compare(m.get_one::<String>("old").expect("old is required"), m.get_one::<String>("new").expect("new is required")),
+        Some(("history", m)) => show_history(m.get_one::<usize>("last").copied(), path(m, "test")),
+        Some(("flaky", m)) => show_flaky(path(m, "results"), m.get_one::<usize>("last").copied()),
+        Some(("serve", m)) => serve_mode(*m.get_one::<u16>("port").expect("port is required"), m.get_flag("run"), m.get_flag("allow-runs")).await,
+        Some(("phase", m)) => phase(m.get_one::<String>("phase").map(String::as_str) == Some("judge"), path(m, "generated")).await,
+        Some(("update-baselines", m)) => {
+            let target = match (m.get_flag("dry-run"), m.get_flag("snapshot")) {
+                (true, true) => return Err("--dry-run and --snapshot cannot be combined".into()),
+                (true, false) => Target::None,
+                (false, true) => Target::Snapshot,
+                (false, false) => Target::TestFile,
+            };
+            update_baselines(target, m.get_flag("confirm")).await
         }
-        Err(e) => panic!("{}", e)
+        Some(("generate", m)) => draft_tests(m.get_one::<String>("spec").expect("spec is required"), *m.get_one::<usize>("count").expect("count has a default")).await,
+        Some((mode @ ("accept" | "reject"), m)) => snapshots(mode == "accept", path(m, "name")),
+        Some((other, _)) => unreachable!("subcommand {} is not defined", other),
     }
+}
+
+/// Carries the results that passed in a previous run's CSV, read from its raw copy when there is one so that outputs
+/// are not sanitized twice, and keeps the attempt each of the others was at.
+fn carry(path: &str, config: &mut RunConfig) -> Result<(), AppError> {
+    let raw = path.strip_suffix(".csv").map(|stem| format!("{}.raw.csv", stem)).filter(|raw| std::path::Path::new(raw).exists());
+    let (passed, failed): (Vec<TestResult>, Vec<TestResult>) = TestResult::read(raw.as_deref().unwrap_or(path))?.into_iter().partition(TestResult::passed);
+    println!("Retrying the {} tests that did not pass in {}, keeping {} results", failed.len(), path, passed.len());
+    config.retried = failed.into_iter().map(|r| (r.name, r.attempt.unwrap_or(1))).collect();
+    config.carried = Some(passed.into_iter().map(|r| (r.name.clone(), r)).collect());
     Ok(())
 }
 
-async fn process(contents: &String) -> Result<Result<TestPass, TestError>, Box<dyn Error>> {
-    let input_r = Regex::new(r"(?s)<input>(.*?)</input>")?;
-    let output_r = Regex::new(r"(?s)<output>(.*?)</output>")?;
-    let input =
-        if let Some(m) = input_r.captures(contents) {
-            m.get(1).unwrap().as_str()
-        } else {
-            return Ok(Err(TestError { content: contents.to_string(), location: ErrorLocation::MatchInput, err: None }));
-        };
-    let expected_output =
-        if let Some(m) = output_r.captures(contents) {
-            m.get(1).unwrap().as_str()
-        } else {
-            return Ok(Err(TestError { content: contents.to_string(), location: ErrorLocation::MatchInput, err: None }));
-        };
-    let gen_prompt = fs::read_to_string(env::var("GEN_PROMPT")?)?;
-    let test_prompt = fs::read_to_string(env::var("TEST_PROMPT")?)?;
-    let structure_test = fs::read_to_string(env::var("STRUCTURE_TEST")?)?;
-    let url = env::var("API_URL").unwrap_or_default();
-    let key = env::var("API_KEY").unwrap_or_default();
-    let model = env::var("model")?;
-    let config = OpenAIConfig::new().with_api_base(url).with_api_key(key);
-    let client = Client::with_config(config);
-    let req = CreateChatCompletionRequestArgs::default()
-        .model(&model)
-        .messages([
-            ChatCompletionRequestUserMessageArgs::default()
-            .content(gen_prompt.replace("__description__", &input))
-            .build()?.into()
-        ])
-        .build()?;
-    let res = client.chat().create(req).await?;
-    let message = res.choices.first().unwrap().message.content.clone().unwrap();
-    let r = Regex::new(r"(\{(.|\n)*?\}|\[(.|\n)*?\])")?;
-    if let Some(m) = r.find(&message) {
-        let jzml = m.as_str();
-        let lua = Lua::new();
-        let globals = lua.globals();
-        lua.load(structure_test).exec()?;
-        let test_func: Function = globals.get("test")?;
-        Ok(match test_func.call::<bool>(jzml) {
-            Ok(result) => {
-                if result {
-                    let req = CreateChatCompletionRequestArgs::default()
-                        .model(model)
-                        .messages([
-                            ChatCompletionRequestUserMessageArgs::default()
-                        .content(test_prompt
-                            .replace("__description__", input)
-                            .replace("__baseline__", expected_output)
-                            .replace("__input__", jzml))
-                        .build()?.into()
-                    ])
-                    .build()?;
-                    let res = client.chat().create(req).await?;
-                    let test_message = res.choices.first().unwrap().message.content.clone().unwrap();
-                    if test_message.to_lowercase() == "true" {
-                        Ok(TestPass { content: message })
-                    } else {
-                        Err(TestError { content: message, location: ErrorLocation::Test, err: None })
-                    }
-                } else {
-                    Err(TestError { content: message, location: ErrorLocation::Parse, err: None })
+/// The run `--resume` names, also taken as the path of one of its results files.
+fn run_name(run: &str) -> String {
+    let name = Path::new(run).file_name().map_or_else(|| run.to_string(), |name| name.to_string_lossy().into_owned());
+    let name = name.strip_suffix(".csv").unwrap_or(&name);
+    name.strip_suffix(".raw").unwrap_or(name).to_string()
+}
+
+/// `report [results.csv]`: prints a saved run's results as the run did, from its raw copy when there is one.
+fn report(path: Option<String>) -> Result<ExitCode, AppError> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let results_dir = env::var("RESULTS_DIR").map_err(|_| AppError::MissingEnv("RESULTS_DIR".to_string()))?;
+            let newest = report::runs(results_dir.as_ref()).into_iter().next().ok_or_else(|| format!("{} contains no results", results_dir))?;
+            let raw = format!("{}/{}.raw.csv", results_dir, newest);
+            if std::path::Path::new(&raw).exists() { raw } else { format!("{}/{}.csv", results_dir, newest) }
+        }
+    };
+    let results = TestResult::read(&path)?;
+    for result in &results {
+        show(result);
+    }
+    let passed = results.iter().filter(|r| r.passed()).count();
+    println!("{}: {} passed, {} failed", path, passed, results.len() - passed);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `compare <old> <new>`: the tests that regressed, were fixed, were added or removed, and how many are unchanged.
+/// Exits with 1 when any test regressed.
+fn compare(old: &str, new: &str) -> Result<ExitCode, AppError> {
+    let (old_results, new_results) = (TestResult::read(old)?, TestResult::read(new)?);
+    let changes = report::changes(&old_results, &new_results);
+    let failure = |r: &TestResult| match (r.location, r.error.as_deref().and_then(|e| e.lines().next())) {
+        (Some(location), Some(error)) => format!(" at {}: {}", location, error),
+        (Some(location), None) => format!(" at {}", location),
+        (None, _) => String::new(),
+    };
+    if !changes.regressed.is_empty() {
+        println!("{color_red}Newly failing:");
+        for r in &changes.regressed {
+            println!("  {}{}", r.name, failure(r));
+        }
+        print!("{color_reset}");
+    }
+    if !changes.fixed.is_empty() {
+        println!("{color_green}Newly passing:");
+        for r in &changes.fixed {
+            println!("  {}", r.name);
+        }
+        print!("{color_reset}");
+    }
+    for (label, results) in [("Added", &changes.added), ("Removed", &changes.removed)] {
+        if !results.is_empty() {
+            println!("{}:", label);
+            for r in results {
+                println!("  {} ({})", r.name, if r.passed() { "passed" } else { "failed" });
+            }
+        }
+    }
+    println!(
+        "{} newly failing, {} newly passing, {} still failing, {} still passing, {} added, {} removed",
+        changes.regressed.len(), changes.fixed.len(), changes.still_failing.len(), changes.still_passing.len(), changes.added.len(), changes.removed.len(),
+    );
+    if let Some(significance) = Significance::new(&stats::scores(&old_results), &stats::scores(&new_results)) {
+        println!("Pass rate {}", significance.line());
+    }
+    Ok(if changes.regressed.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(1) })
+}
+
+/// `history [--last N] [--test name]`: each run's pass rate, then every test's, least passing first.
+fn show_history(last: Option<usize>, filter: Option<String>) -> Result<ExitCode, AppError> {
+    let path = env::var("HISTORY_FILE").map_err(|_| AppError::MissingEnv("HISTORY_FILE".to_string()))?;
+    let mut history = History::read(&path)?;
+    let runs = history.runs(last)?;
+    let mut trends = history.trends(last, filter.as_deref())?;
+    warn(&history.warnings);
+    let (Some(first), Some(latest)) = (runs.first(), runs.last()) else {
+        println!("{} has no runs yet", path);
+        return Ok(ExitCode::SUCCESS);
+    };
+    println!("{} runs from {} to {}", runs.len(), first.started, latest.started);
+    for run in &runs {
+        let total = run.passed + run.failed;
+        println!("  {}  {:>3.0}% of {} tests  {}  {} ms", run.started, percent(run.passed, total), total, run.model, run.duration_ms);
+    }
+    trends.sort_by(|a, b| percent(a.passed, a.runs).total_cmp(&percent(b.passed, b.runs)).then_with(|| a.name.cmp(&b.name)));
+    let width = trends.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    for trend in &trends {
+        let failure = trend.last_failure.map_or(String::new(), |l| format!("  last failed at {}", l));
+        println!("{:width$}  {:>3.0}% ({}/{})  {}{}", trend.name, percent(trend.passed, trend.runs), trend.passed, trend.runs, trend.recent, failure);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `flaky [results.csv] [--last N]`: the tests whose outcome flips, most unstable first, with where they mostly fail.
+fn show_flaky(results: Option<String>, last: Option<usize>) -> Result<ExitCode, AppError> {
+    let flaky = match &results {
+        Some(path) => {
+            let results = TestResult::read(path)?;
+            if results.iter().all(|r| r.attempts.is_none()) {
+                return Err(format!("{} has no attempts; run with --repeat N (PASS_AT_K) to find flaky tests in one run", path).into());
+            }
+            println!("Attempts of the {} tests in {}", results.len(), path);
+            flaky::from_results(&results)
+        }
+        None => {
+            let path = env::var("HISTORY_FILE").map_err(|_| AppError::MissingEnv("HISTORY_FILE".to_string()))?;
+            let mut history = History::read(&path)?;
+            let runs = history.runs(None)?;
+            warn(&history.warnings);
+            let (same, flaky) = flaky::from_history(&runs, last);
+            let (Some(first), Some(latest)) = (same.first(), same.last()) else {
+                println!("{} has no runs yet", path);
+                return Ok(ExitCode::SUCCESS);
+            };
+            let other = runs.iter().filter(|r| !flaky::same_settings(r, latest)).count();
+            let left_out = if other > 0 { format!(", leaving out {} runs with other settings", other) } else { String::new() };
+            println!("{} runs with the settings of the latest, from {} to {}{}", same.len(), first.started, latest.started, left_out);
+            flaky
+        }
+    };
+    if flaky.is_empty() {
+        println!("No flaky tests");
+        return Ok(ExitCode::SUCCESS);
+    }
+    println!("{} flaky tests, most unstable first:", flaky.len());
+    let width = flaky.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    for test in &flaky {
+        let dominant = test.dominant.map_or(String::new(), |(location, n)| format!("  fails mostly at {} ({} of {})", location, n, test.failed()));
+        println!("{:width$}  {}  {}/{} passed  {} flips{}", test.name, test.outcomes, test.passed, test.outcomes.len(), test.flips, dominant);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// What reading or writing HISTORY_FILE left out.
+fn warn(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("{color_yellow}HISTORY_FILE: {}{color_reset}", warning);
+    }
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+/// `serve <port> [--run] [--allow-runs]`: serves RESULTS_DIR, after a run when `--run` is given, and with
+/// `--allow-runs` starts runs when asked over HTTP. Binds to SERVE_HOST, localhost by default.
+async fn serve_mode(port: u16, after_run: bool, allow_runs: bool) -> Result<ExitCode, AppError> {
+    let host: IpAddr = env::var("SERVE_HOST").map_or(Ok(Ipv4Addr::LOCALHOST.into()), |h| h.parse().map_err(|e| format!("SERVE_HOST {:?}: {}", h, e)))?;
+    if after_run {
+        run(RunConfig::from_env()?, false).await?;
+    }
+    let results_dir = env::var("RESULTS_DIR").map_err(|_| AppError::MissingEnv("RESULTS_DIR".to_string()))?;
+    serve::serve(&results_dir, SocketAddr::new(host, port), allow_runs.then_some(triggered_run)).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// A run `POST /api/runs` started: the settings as they are now, changed by the request, with its results named to
+/// the second, and numbered within it, so runs started close together do not overwrite each other. RUN_LOG is not opened, as the log
+/// subscriber is global to the process. Returns the run's name.
+async fn triggered_run(request: serve::RunRequest, progress: serve::Progress) -> Result<String, AppError> {
+    let mut config = RunConfig::from_env()?;
+    request.apply(&mut config)?;
+    structure::verify(&config)?;
+    let (tests, _) = ai_test_util::discover(&config)?;
+    let (tests, _) = select::select(&config, tests);
+    progress.started(&config.model, tests.len());
+    let stamp = format!("results{}", Local::now().format("%Y-%m-%d %H%M%S"));
+    let mut name = stamp.clone();
+    for n in 2.. {
+        if !Path::new(&config.results_dir).join(format!("{}.csv", name)).exists() {
+            break;
+        }
+        name = format!("{} {}", stamp, n);
+    }
+    let results_path = format!("{}/{}", config.results_dir, name);
+    run_model(&mut config, false, &results_path, Some(&progress)).await?;
+    Ok(name)
+}
+
+/// `update-baselines [--dry-run] [--confirm] [--snapshot]`: regenerates the expected outputs, listing the tests that
+/// were left alone because no candidate passed the structure test last so they are not missed. With `confirm`, each
+/// changed baseline's diff is shown and written only when the answer is yes.
+async fn update_baselines(target: Target, confirm: bool) -> Result<ExitCode, AppError> {
+    let config = RunConfig::from_env()?;
+    if confirm && target == Target::None {
+        return Err("--confirm and --dry-run cannot be combined".into());
+    }
+    structure::verify(&config)?;
+    let mut all = !confirm;
+    let updates = baseline::update(&config, target, |name, diff| {
+        if all {
+            return Ok(Confirm::Yes);
+        }
+        println!("{style_bold}{}{style_reset}\n{}", name, diff);
+        loop {
+            print!("Update {}? [y]es, [n]o, [a]ll, [q]uit? ", name);
+            io::stdout().flush().map_err(AppError::io("stdout"))?;
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).map_err(AppError::io("stdin"))? == 0 {
+                return Ok(Confirm::Quit);
+            }
+            match answer.trim() {
+                "y" => return Ok(Confirm::Yes),
+                "n" => return Ok(Confirm::No),
+                "a" => {
+                    all = true;
+                    return Ok(Confirm::Yes);
                 }
-            },
-            Err(e) => Err(TestError { content: message, location: ErrorLocation::Parse, err: Some(e.to_string()) })
-        })
+                "q" => return Ok(Confirm::Quit),
+                _ => {}
+            }
+        }
+    }).await?;
+    let (mut written, mut declined, mut unchanged) = (0, 0, 0);
+    let mut failed = Vec::new();
+    for update in &updates {
+        match update {
+            BaselineUpdate::Changed { name, diff, written: was_written } => {
+                match (target, was_written) {
+                    (Target::None, _) => println!("{style_bold}Would update {}{style_reset}\n{}", name, diff),
+                    (_, true) if !confirm => println!("{style_bold}{} {}{style_reset}", if target == Target::Snapshot { "Proposed" } else { "Updated" }, name),
+                    _ => {}
+                }
+                if *was_written { written += 1 } else { declined += 1 }
+            }
+            BaselineUpdate::Unchanged { .. } => unchanged += 1,
+            BaselineUpdate::Failed { name, location, error } => failed.push((name, location, error)),
+        }
+    }
+    match target {
+        Target::None => println!("{} would change, {} unchanged", declined, unchanged),
+        Target::TestFile => println!("{} updated, {} declined, {} unchanged", written, declined, unchanged),
+        Target::Snapshot => println!("{} snapshots proposed for accept or reject, {} declined, {} unchanged", written, declined, unchanged),
+    }
+    if !failed.is_empty() {
+        println!("{color_red}{style_bold}{} tests left untouched, no candidate passed the structure test:{style_reset}", failed.len());
+        for (name, location, error) in failed {
+            println!("{color_red}  {} failed at {}: {}{color_reset}", name, location, error.as_deref().unwrap_or(""));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `phase generate` is the generation half of a run, saved for judging later; the structure test is not loaded.
+/// `phase judge --generated <path>` is the other half, a full run over those saved generations.
+async fn phase(judge: bool, generated: Option<String>) -> Result<ExitCode, AppError> {
+    let generated = match (judge, generated) {
+        (true, Some(path)) => Some(generated::load(&path)?),
+        (true, None) => return Err("phase judge needs --generated <dir or .generated.jsonl>".into()),
+        (false, Some(_)) => return Err("--generated is only for phase judge".into()),
+        (false, None) => None,
+    };
+    let mut config = RunConfig::from_env()?;
+    if generated.is_some() {
+        config.generated = generated;
+        return run(config, false).await;
+    }
+    let started = Local::now();
+    let results_path = format!("{}/results{}", config.results_dir, started.format("%Y-%m-%d %H%M"));
+    RunMetadata::new(&config, started.to_rfc3339()).write(&format!("{}.meta.json", results_path))?;
+    let path = format!("{}.generated.jsonl", results_path);
+    let generated = generated::generate_suite(&config, &path).await?;
+    let mut failed = 0;
+    for g in &generated {
+        match g.failure() {
+            Some(e) => {
+                failed += 1;
+                println!("{color_red}Test {} failed at {}: {}{color_reset}", g.name, e.location, e.message.as_deref().unwrap_or(""));
+            }
+            None => println!("Generated {} ({} candidates)", g.name, g.choices.iter().map(|c| c.candidates.len()).sum::<usize>()),
+        }
+    }
+    println!("{} generated, {} failed before the structure test; saved to {}", generated.len() - failed, failed, path);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `--accept [name]` and `--reject [name]` for the snapshots SNAPSHOTS leaves in TEST_DIR.
+fn snapshots(accept: bool, filter: Option<String>) -> Result<ExitCode, AppError> {
+    let test_dir = env::var("TEST_DIR").map_err(|_| AppError::MissingEnv("TEST_DIR".to_string()))?;
+    let names = if accept { snapshot::accept(&test_dir, filter.as_deref())? } else { snapshot::reject(&test_dir, filter.as_deref())? };
+    for name in &names {
+        println!("{} {}", if accept { "Accepted" } else { "Rejected" }, name);
+    }
+    println!("{} snapshots {}, {} pending", names.len(), if accept { "accepted" } else { "rejected" }, snapshot::pending(&test_dir).len());
+    let drafts = if accept { draft::accept(&test_dir, filter.as_deref())? } else { draft::reject(&test_dir, filter.as_deref())? };
+    for name in &drafts {
+        println!("{} the draft of {}", if accept { "Accepted" } else { "Rejected" }, name);
+    }
+    let pending = draft::pending(&test_dir).len();
+    if !drafts.is_empty() || pending > 0 {
+        println!("{} drafts {}, {} pending", drafts.len(), if accept { "accepted" } else { "rejected" }, pending);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `generate <spec> [--count N]`: drafts tests for the feature `spec` describes, read from stdin for `-`, and lists
+/// them with what to do next.
+async fn draft_tests(spec: &str, count: usize) -> Result<ExitCode, AppError> {
+    let config = RunConfig::from_env()?;
+    if config.dry_run {
+        return Err("generate asks the model for tests, which DRY_RUN does not call".into());
+    }
+    let spec = if spec == "-" {
+        let mut spec = String::new();
+        io::stdin().read_to_string(&mut spec).map_err(AppError::io("stdin"))?;
+        spec
     } else {
-        Ok(Err(TestError { content: message, location: ErrorLocation::MatchJson, err: None }))
+        fs::read_to_string(spec).map_err(AppError::io(spec))?
+    };
+    if spec.trim().is_empty() {
+        return Err("the spec is empty; describe the feature the tests are for".into());
+    }
+    let client = ai_test_util::client(&config);
+    let drafted = draft::generate(&spec, count, &config, client.as_ref()).await?;
+    for name in &drafted.written {
+        println!("Drafted {}", Path::new(&config.test_dir).join(name).display());
+    }
+    for (name, reason) in &drafted.rejected {
+        println!("{color_yellow}Left out the proposed test {}: {}{color_reset}", name, reason);
+    }
+    if drafted.written.is_empty() {
+        return Err("the model proposed no usable tests".into());
     }
+    println!("{} drafts written. Check each input and expected output, try them with `run <draft>`, then `accept` them into tests or `reject` them", drafted.written.len());
+    Ok(ExitCode::SUCCESS)
 }
 
-#[derive(Debug)]
-struct TestPass {
-    content: String,
+/// How often `--watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `--watch`: runs every test, then after each change to TEST_DIR or a file the settings name runs the tests the
+/// change affects again, printing their results and a summary, until Ctrl+C. The settings are read again for every
+/// run, so an edited prompt is used at once, and a run that cannot start only reports why. No results files are
+/// written.
+async fn watch_mode() -> Result<ExitCode, AppError> {
+    let mut files = watch::Files::scan();
+    let mut changed = None;
+    loop {
+        tokio::select! {
+            ran = watched_run(changed.as_ref()) => if let Err(e) = ran {
+                println!("{color_red}{}{color_reset}", e);
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(ExitCode::from(INTERRUPTED)),
+        }
+        println!("{style_bold}Watching for changes, Ctrl+C to stop{style_reset}");
+        changed = Some(loop {
+            tokio::select! {
+                _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(ExitCode::SUCCESS),
+            }
+            let now = watch::Files::scan();
+            let found = now.changed(&files);
+            files = now;
+            if !found.is_empty() {
+                break found;
+            }
+        });
+    }
 }
 
-#[derive(Debug)]
-enum ErrorLocation {
-    MatchInput,
-    MatchJson,
-    Parse,
-    Test
+/// One run of `--watch`: every test at first, then those `changed` affects.
+async fn watched_run(changed: Option<&BTreeSet<std::path::PathBuf>>) -> Result<(), AppError> {
+    let mut config = RunConfig::from_env()?;
+    if config.models.len() > 1 {
+        return Err("--watch runs one model, MODELS names more".into());
+    }
+    if config.gen_prompts.len() > 1 {
+        return Err("--watch runs one generation prompt, GEN_PROMPTS names more".into());
+    }
+    structure::verify(&config)?;
+    if let Some(changed) = changed {
+        let names: Vec<String> = changed.iter().map(|path| path.display().to_string()).collect();
+        let (tests, _) = ai_test_util::discover(&config)?;
+        config.only = watch::affected(&config, &tests, changed);
+        match &config.only {
+            None => println!("{style_bold}Changed {}, running every test{style_reset}", names.join(", ")),
+            Some(only) if only.is_empty() => {
+                println!("{style_bold}Changed {}, which no test uses{style_reset}", names.join(", "));
+                return Ok(());
+            }
+            Some(only) => println!("{style_bold}Changed {}, running {} tests{style_reset}", names.join(", "), only.len()),
+        }
+    }
+    config.shared = structure::before_all(&config)?;
+    config.console = Some(Console::new(config.verbosity, None));
+    let config = &config;
+    let report = ai_test_util::run_suite(config, |result| {
+        if config.verbosity > Verbosity::Quiet {
+            show(&result);
+        }
+        async { Ok(()) }
+    }).await?;
+    Summary::new(&report, config, "").print();
+    structure::after_all(config, &report.results)
+}
+
+/// `init`: the project skeleton, and what to do next.
+fn scaffold(dir: &str, force: bool) -> Result<ExitCode, AppError> {
+    for file in init::init(std::path::Path::new(dir), force)? {
+        match file {
+            Scaffolded::Created(path) => println!("Created {}", path),
+            Scaffolded::Kept(path) => println!("{color_yellow}Kept {}, which exists already (--force overwrites it){color_reset}", path),
+        }
+    }
+    let cd = if dir == "." { String::new() } else { format!("cd {} && ", dir) };
+    println!("Set OPENAI_API_KEY, then run `{}ai_test_util validate` and `ai_test_util run`.", cd);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `validate`: the configuration and structure test as a run checks them, then every test file, printing each problem
+/// as `file: problem` and failing when there is any.
+fn validate(config: RunConfig) -> Result<ExitCode, AppError> {
+    structure::verify(&config)?;
+    println!("Configuration and structure test OK");
+    let (tests, problems) = lint::lint(&config)?;
+    for problem in &problems {
+        println!("{color_red}{}{color_reset}: {}", problem.file, problem.message);
+    }
+    if problems.is_empty() {
+        println!("{} tests OK", tests);
+        return Ok(ExitCode::SUCCESS);
+    }
+    let files: BTreeSet<&str> = problems.iter().map(|p| ai_test_util::test_file(&p.file)).collect();
+    println!("{} problems in {} files and settings, {} tests checked", problems.len(), files.len(), tests);
+    Ok(ExitCode::FAILURE)
+}
+
+async fn run(mut config: RunConfig, interactive: bool) -> Result<ExitCode, AppError> {
+    structure::verify(&config)?;
+    if config.dry_run {
+        println!("Dry run: no API calls, each test is answered with its expected output");
+    }
+    let started = Local::now();
+    let results_path = match &config.resume {
+        Some(run) => format!("{}/{}", config.results_dir, run),
+        None => format!("{}/results{}", config.results_dir, started.format("%Y-%m-%d %H%M")),
+    };
+    if config.log_level.is_some() || config.otlp.is_some() {
+        let log = config.log_level.map(|level| RunLog::create(&format!("{}.log", results_path), level, config.redactor.clone())).transpose()?;
+        let otel = config.otlp.as_ref().map(|otlp| telemetry::layer(otlp, config.redactor.clone())).transpose()?;
+        let subscriber = tracing_subscriber::registry().with(log).with(otel);
+        tracing::subscriber::set_global_default(subscriber).map_err(|e| AppError::Invalid(format!("could not start the run log: {}", e)))?;
+    }
+    if config.models.len() > 1 {
+        return model_matrix(config, interactive, &results_path).await;
+    }
+    if config.gen_prompts.len() > 1 {
+        return prompt_matrix(config, interactive, &results_path).await;
+    }
+    let (exit, _) = run_model(&mut config, interactive, &results_path, None).await?;
+    Ok(ExitCode::from(exit))
+}
+
+/// `run <test>...`: only these test files, and a test read from stdin for `-`, each result printed in full. Nothing
+/// but the tests' own output is written, and the exit code is a run's.
+async fn run_files(mut config: RunConfig, files: Vec<String>) -> Result<ExitCode, AppError> {
+    if files.iter().filter(|f| *f == "-").count() > 1 {
+        return Err("- reads a test from stdin, which can only be read once".into());
+    }
+    let mut tests: Vec<TestInfo> = Vec::new();
+    for file in &files {
+        if file == "-" {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents).map_err(AppError::io("stdin"))?;
+            tests.push(TestInfo { index: 0, name: "stdin".to_string(), contents });
+        } else {
+            let contents = fs::read_to_string(file).map_err(AppError::io(file.as_str()))?;
+            tests.extend(ai_test_util::file_tests(test_name(&config, file), contents));
+        }
+    }
+    for (index, test) in tests.iter_mut().enumerate() {
+        test.index = index;
+    }
+    structure::verify(&config)?;
+    let started = Instant::now();
+    config.artifacts_dir = config.artifacts.then(|| format!("{}/results{}.artifacts", config.results_dir, Local::now().format("%Y-%m-%d %H%M")).into());
+    config.shared = structure::before_all(&config)?;
+    config.console = Some(Console::new(config.verbosity, None));
+    let config = &config;
+    let client = ai_test_util::client(config);
+    let mut results = Vec::new();
+    for test in tests {
+        let result = ai_test_util::run_single(test, config, client.as_ref()).await?;
+        show(&result);
+        details(&result);
+        results.push(result);
+    }
+    let report = SuiteReport { results, skipped: Vec::new(), duration: started.elapsed(), not_selected: Vec::new(), interrupted: false };
+    let summary = Summary::new(&report, config, "");
+    if report.results.len() > 1 {
+        summary.print();
+    }
+    structure::after_all(config, &report.results)?;
+    Ok(ExitCode::from(exit_code(&summary, config)))
+}
+
+/// A test file's name in the run: its path within TEST_DIR, otherwise its full path, so that what it refers to is
+/// found next to it either way.
+fn test_name(config: &RunConfig, file: &str) -> String {
+    let path = std::path::absolute(file).unwrap_or_else(|_| file.into());
+    let dir = std::path::absolute(&config.test_dir).unwrap_or_else(|_| config.test_dir.clone().into());
+    match path.strip_prefix(&dir) {
+        Ok(name) => name.to_string_lossy().replace('\\', "/"),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// What [`show`] leaves out of a result, for `run <test>`: the model, the candidates, the judge's verdict on a pass,
+/// the grade, the tokens, cost and time of each stage.
+fn details(result: &TestResult) {
+    let mut lines: Vec<(&str, String)> = Vec::new();
+    let mut add = |label, value: Option<String>| lines.extend(value.filter(|v| !v.is_empty()).map(|v| (label, v)));
+    add("Model", result.model.clone());
+    add("Candidates", result.candidates.clone());
+    add("Finish reason", result.finish_reason.clone());
+    add("Attempts", result.attempts.clone());
+    add("Judge", result.reason.clone().filter(|_| result.passed()));
+    add("Votes", result.votes.clone());
+    add("Confidence", result.confidence.map(|c| format!("{:.2}", c)));
+    add("Similarity", result.similarity.map(|s| format!("{:.3}", s)));
+    add("Score", result.score.map(|s| s.to_string()));
+    add("Grade", result.grade.map(|g| format!("{:.2}{}", g, result.graded_by.as_deref().map_or(String::new(), |by| format!(" ({})", by)))));
+    add("Tokens", result.tokens.or(result.judge_tokens).map(|_| format!("{} generation, {} judge", result.tokens.unwrap_or(0), result.judge_tokens.unwrap_or(0))));
+    add("Cost", result.cost.map(|c| format!("{:.4}", c)));
+    let stages: Vec<String> = [("generation", result.generation_ms), ("extraction", result.extraction_ms), ("structure", result.structure_ms), ("judge", result.judge_ms)].iter()
+        .filter_map(|(stage, ms)| ms.map(|ms| format!("{} {} ms", stage, ms)))
+        .collect();
+    add("Took", Some(format!("{} ms{}", result.duration_ms, if stages.is_empty() { String::new() } else { format!(" ({})", stages.join(", ")) })));
+    add("Artifacts", result.artifacts.clone());
+    for (label, value) in lines {
+        println!("  {}: {}", label, value);
+    }
+}
+
+/// A run over every model of MODELS in turn, each with results files of its own named after the model, then the
+/// comparison table and `<results> matrix.csv`. The exit code is the worst of the models' runs.
+async fn model_matrix(mut config: RunConfig, interactive: bool, results_path: &str) -> Result<ExitCode, AppError> {
+    if config.generated.is_some() {
+        return Err("MODELS cannot be combined with phase judge, which judges one model's generations".into());
+    }
+    if config.carried.is_some() {
+        return Err("MODELS cannot be combined with --retry-failed or --resume, which keep one model's results".into());
+    }
+    let mut runs = Vec::new();
+    let mut worst = 0;
+    for model in config.models.clone() {
+        println!("{style_bold}Model {}{style_reset}", model);
+        config.model = model.clone();
+        let (exit, run) = run_model(&mut config, interactive, &format!("{} {}", results_path, file_name(&model)), None).await?;
+        runs.push(run);
+        worst = worst.max(exit);
+        if exit == INTERRUPTED {
+            break;
+        }
+    }
+    let matrix_path = format!("{} matrix.csv", results_path);
+    matrix::write(&matrix_path, &runs)?;
+    println!("{style_bold}Model matrix{style_reset} ({})", matrix_path);
+    matrix::print(&runs, "Model");
+    Ok(ExitCode::from(worst))
+}
+
+/// A run over every generation prompt of GEN_PROMPTS in turn, as [`model_matrix`] runs the models: each with results
+/// files of its own named after the prompt, then the comparison table and `<results> prompts.csv`.
+async fn prompt_matrix(mut config: RunConfig, interactive: bool, results_path: &str) -> Result<ExitCode, AppError> {
+    if config.generated.is_some() {
+        return Err("GEN_PROMPTS cannot be combined with phase judge, whose generations were made from one prompt".into());
+    }
+    if config.carried.is_some() {
+        return Err("GEN_PROMPTS cannot be combined with --retry-failed or --resume, which keep one prompt's results".into());
+    }
+    let mut runs = Vec::new();
+    let mut worst = 0;
+    for variant in config.gen_prompts.clone() {
+        println!("{style_bold}Prompt {}{style_reset}", variant.name);
+        config.gen_prompt = variant.text;
+        let (exit, mut run) = run_model(&mut config, interactive, &format!("{} {}", results_path, file_name(&variant.name)), None).await?;
+        run.label = variant.name;
+        runs.push(run);
+        worst = worst.max(exit);
+        if exit == INTERRUPTED {
+            break;
+        }
+    }
+    let matrix_path = format!("{} prompts.csv", results_path);
+    matrix::write(&matrix_path, &runs)?;
+    println!("{style_bold}Prompt matrix{style_reset} ({})", matrix_path);
+    matrix::print(&runs, "Prompt");
+    Ok(ExitCode::from(worst))
+}
+
+/// A model or prompt name as part of a file name.
+fn file_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || "-._".contains(c) { c } else { '_' }).collect()
+}
+
+/// The exit code of a run stopped with Ctrl+C.
+const INTERRUPTED: u8 = 130;
+
+/// Runs the suite with `config.model` and writes its results files at `results_path`, returning the run's exit code.
+/// Each result is also reported to `api`, for a run started over HTTP. A resumed run writes the results it keeps first
+/// and keeps the metadata of its first start.
+async fn run_model(config: &mut RunConfig, interactive: bool, results_path: &str, api: Option<&serve::Progress>) -> Result<(u8, MatrixRun), AppError> {
+    config.shared = structure::before_all(config)?;
+    config.artifacts_dir = config.artifacts.then(|| format!("{}.artifacts", results_path).into());
+    let progress = progress_bar(config)?;
+    config.console = Some(Console::new(config.verbosity, progress.as_ref()));
+    let config = &*config;
+    let started = Local::now();
+    if config.resume.is_none() {
+        RunMetadata::new(config, started.to_rfc3339()).write(&format!("{}.meta.json", results_path))?;
+    }
+    let csv_path = format!("{}.csv", results_path);
+    let raw_path = config.results_sanitize.then(|| format!("{}.raw.csv", results_path));
+    let mut review = env::var("REVIEW_DIR").ok().map(|d| ReviewQueue::new(&d)).transpose()?;
+    let panel: &[String] = if config.judge_models.len() > 1 { &config.judge_models } else { &[] };
+    let fixed = TestResult::columns();
+    let header: Vec<String> = config.results_columns.iter().flat_map(|column| match column {
+        Column::Field(i) => vec![fixed[*i].clone()],
+        Column::Panel => panel.iter().map(|m| format!("Judge {}", m)).collect(),
+        Column::Criteria => config.rubric.iter().flat_map(|r| r.criteria.iter().map(|c| c.name.clone())).collect(),
+    }).collect();
+    let mut files = vec![ResultsFile::create(&csv_path, &header, config.results_sanitize)?];
+    if let Some(raw_path) = &raw_path {
+        files.push(ResultsFile::create(raw_path, &header, false)?);
+    }
+    let ndjson_path = format!("{}.ndjson", results_path);
+    let mut ndjson = config.report_formats.contains(&ReportFormat::Ndjson)
+        .then(|| File::create(&ndjson_path).map(|file| (file, ndjson_path.as_str())).map_err(AppError::io(&ndjson_path))).transpose()?;
+    let mut manifest = Manifest::create(results_path)?;
+    if let Some(kept) = config.carried.as_ref().filter(|_| config.resume.is_some()) {
+        for result in kept.values() {
+            write_result(result, &mut files, &mut ndjson, &mut manifest, config, panel)?;
+        }
+    }
+    // The results file's name, which the run's telemetry is labelled with.
+    let run_name = Path::new(results_path).file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    if config.otlp.is_some() {
+        telemetry::start_run(config, &run_name);
+    }
+    let (tx, rx) = mpsc::channel(RESULTS_BUFFER);
+    let queue = &mut review;
+    let snapshots = config.snapshots.then_some(config.test_dir.as_str());
+    let bar = progress.as_ref();
+    let run = ai_test_util::run_suite(config, move |result| {
+        if config.verbosity > Verbosity::Quiet {
+            match bar {
+                Some(bar) => bar.suspend(|| show(&result)),
+                None => show(&result),
+            }
+        }
+        if let Some(api) = api {
+            api.result(&result);
+        }
+        let shown = match queue.as_mut() {
+            Some(review) if result.status == Status::Failed => review.add(&result),
+            _ => Ok(()),
+        }.and_then(|_| snapshots.map_or(Ok(()), |dir| snapshot::write(dir, &result)));
+        if let Some(bar) = bar {
+            bar.advance();
+        }
+        let tx = tx.clone();
+        async move {
+            shown?;
+            tx.send(result).await.map_err(|_| AppError::Invalid("the results writer stopped".to_string()))
+        }
+    });
+    // Ctrl+C drops the run, and with it the sender, so the writer still ends with every result finished so far.
+    let run = async {
+        tokio::select! {
+            report = run => Some(report),
+            _ = tokio::signal::ctrl_c() => None,
+        }
+    };
+    let (report, written) = tokio::join!(run, write_results(rx, files, ndjson, manifest, config, panel));
+    if let Some(bar) = &progress {
+        bar.finish();
+    }
+    let written = written?;
+    let report = match report {
+        Some(report) => report?,
+        None => {
+            println!("{color_yellow}Interrupted, writing the results of the {} finished tests{color_reset}", written.len());
+            if config.models.len() <= 1 && config.gen_prompts.len() <= 1 {
+                let run = Path::new(results_path).file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                println!("Run the rest with `ai_test_util run --resume \"{}\"`", run);
+            }
+            let (tests, skipped) = ai_test_util::discover(config)?;
+            let (_, not_selected) = select::select(config, tests);
+            SuiteReport { results: written, skipped, duration: (Local::now() - started).to_std().unwrap_or_default(), not_selected, interrupted: true }
+        }
+    };
+    if let Some(review) = &review {
+        review.write_index()?;
+    }
+    let summary = Summary::new(&report, config, &csv_path);
+    tracing::info!(tests = report.results.len(), failed = summary.failed(), pass_rate = summary.pass_rate(), duration_ms = report.duration.as_millis() as u64,
+        interrupted = report.interrupted, "run finished");
+    summary.write(&format!("{}.stats.json", results_path))?;
+    if config.report_formats.contains(&ReportFormat::Junit) {
+        junit::write(&format!("{}.junit.xml", results_path), &report, config, &started.to_rfc3339())?;
+    }
+    if config.report_formats.contains(&ReportFormat::Html) {
+        html::write(&format!("{}.html", results_path), &report, &summary, &started.to_rfc3339())?;
+    }
+    if config.report_formats.contains(&ReportFormat::Markdown) {
+        markdown::write(&format!("{}.md", results_path), &report, &summary, &config.model, &started.to_rfc3339())?;
+    }
+    // A partial run would skew the pass rates over time.
+    if let Some(path) = config.history_file.as_ref().filter(|_| !report.interrupted) {
+        let mut history = History::open(path)?;
+        history.append(&RunRecord::new(&report, config, started.to_rfc3339()))?;
+        warn(&history.warnings);
+    }
+    summary.print();
+    if github::enabled() {
+        github::annotate(&report, config);
+        if let Err(e) = github::write_summary(&report, &summary, config, &csv_path) {
+            eprintln!("{color_red}{}{color_reset}", e);
+        }
+    }
+    if let Err(e) = structure::after_all(config, &report.results) {
+        eprintln!("{color_red}{}{color_reset}", e);
+    }
+    if let Some(otlp) = &config.otlp {
+        if let Err(e) = telemetry::export(otlp, config, &report, &run_name).await {
+            eprintln!("{color_yellow}{}{color_reset}", e);
+        }
+    }
+    if notify::enabled(config) {
+        let report_file = if config.report_formats.contains(&ReportFormat::Html) { format!("{}.html", results_path) } else { csv_path.clone() };
+        for e in Notification::new(config, &report, &summary, results_path, &report_file).send(config).await {
+            eprintln!("{color_yellow}{}{color_reset}", e);
+        }
+    }
+    let run = MatrixRun::new(&config.model, &csv_path, &summary, &report.results);
+    if report.interrupted {
+        return Ok((INTERRUPTED, run));
+    }
+    if interactive {
+        // Overrides must match the generation exactly, so review the unsanitized copy.
+        review::interactive(raw_path.as_deref().unwrap_or(&csv_path), false)?;
+    }
+    Ok((exit_code(&summary, config), run))
+}
+
+/// 2 when a test hit an internal error, 1 when tests failed, or with MIN_PASS_RATE when the pass rate is below it.
+fn exit_code(summary: &Summary, config: &RunConfig) -> u8 {
+    if summary.internal_errors() > 0 {
+        return 2;
+    }
+    let failed = match (config.min_pass_rate, summary.pass_rate()) {
+        (Some(min), Some(rate)) if rate < min => {
+            println!("{color_red}Pass rate {:.1}% is below MIN_PASS_RATE {:.1}%{color_reset}", rate * 100.0, min * 100.0);
+            true
+        }
+        (Some(_), _) => false,
+        (None, _) => summary.failed() > 0,
+    };
+    if failed { 1 } else { 0 }
+}
+
+/// A progress bar over the selected tests when stderr is a terminal, except with `--quiet`, or with VERBOSITY=verbose
+/// or STREAM, whose output would keep breaking it up.
+fn progress_bar(config: &RunConfig) -> Result<Option<ProgressBar>, AppError> {
+    if config.verbosity == Verbosity::Verbose || config.stream {
+        return Ok(None);
+    }
+    let (tests, _) = ai_test_util::discover(config)?;
+    let (tests, _) = select::select(config, tests);
+    Ok(ProgressBar::new(tests.into_iter().map(|t| t.name).collect(), config.verbosity))
+}
+
+/// Results waiting to be written before the tests are held back.
+const RESULTS_BUFFER: usize = 16;
+
+/// A results CSV being written: the one for spreadsheets, sanitized with RESULTS_SANITIZE, or its raw copy.
+struct ResultsFile {
+    writer: Writer<File>,
+    path: String,
+    sanitized: bool,
+}
+
+impl ResultsFile {
+    fn create(path: &str, header: &[String], sanitized: bool) -> Result<ResultsFile, AppError> {
+        let mut writer = WriterBuilder::new().has_headers(false).from_path(path).map_err(AppError::csv(path))?;
+        writer.write_record(header).map_err(AppError::csv(path))?;
+        Ok(ResultsFile { writer, path: path.to_string(), sanitized })
+    }
+}
+
+/// Owns the results CSVs, the NDJSON report and the run manifest and writes each result as it arrives. Ends when the
+/// run drops its sender, so the files are complete however the run stops, and returns the results written. The results
+/// a resumed run keeps were written before it started.
+async fn write_results(mut rx: mpsc::Receiver<TestResult>, mut files: Vec<ResultsFile>, mut ndjson: Option<(File, &str)>, mut manifest: Manifest, config: &RunConfig, panel: &[String]) -> Result<Vec<TestResult>, AppError> {
+    let kept = config.carried.as_ref().filter(|_| config.resume.is_some());
+    let mut results = Vec::new();
+    while let Some(result) = rx.recv().await {
+        if !kept.is_some_and(|kept| kept.contains_key(&result.name)) {
+            write_result(&result, &mut files, &mut ndjson, &mut manifest, config, panel)?;
+        }
+        results.push(result);
+    }
+    Ok(results)
 }
 
-impl fmt::Display for ErrorLocation {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", match self {
-            ErrorLocation::MatchInput => "matchinput",
-            ErrorLocation::MatchJson => "matchjson",
-            ErrorLocation::Parse => "parse",
-            ErrorLocation::Test => "test"
-        })
+/// Writes one result to every results file, flushing after its row, and only then adds it to the manifest.
+fn write_result(result: &TestResult, files: &mut [ResultsFile], ndjson: &mut Option<(File, &str)>, manifest: &mut Manifest, config: &RunConfig, panel: &[String]) -> Result<(), AppError> {
+    if let Some((file, path)) = ndjson {
+        // A File is unbuffered, so each line is written out whole as soon as the test is done.
+        writeln!(file, "{}", serde_json::to_string(&ResultLine::from(result))?).map_err(AppError::io(*path))?;
     }
+    let fields = row(result, config, panel);
+    for file in files {
+        let written = if file.sanitized {
+            file.writer.write_record(fields.iter().map(|f| report::sanitize(f, config.results_max_cell_len).into_owned()))
+        } else {
+            file.writer.write_record(&fields)
+        };
+        written.map_err(AppError::csv(&file.path))?;
+        file.writer.flush().map_err(AppError::io(&file.path))?;
+    }
+    manifest.finished(result)
 }
 
-#[derive(Debug)]
-struct TestError {
-    content: String,
-    location: ErrorLocation,
-    err: Option<String>
+/// The RESULTS_COLUMNS fields of one result.
+fn row(result: &TestResult, config: &RunConfig, panel: &[String]) -> Vec<String> {
+    let fields = result.fields();
+    config.results_columns.iter().flat_map(|column| match column {
+        Column::Field(i) => vec![fields[*i].clone()],
+        Column::Panel => panel.iter().map(|m| {
+            result.judgement.as_ref().and_then(|j| j.panel.iter().find(|vote| vote.model == *m)).map(|vote| vote.display()).unwrap_or_default()
+        }).collect(),
+        Column::Criteria => config.rubric.iter().flat_map(|r| r.criteria.iter().map(|c| {
+            result.judgement.as_ref().and_then(|j| j.criteria.iter().find(|s| s.name == c.name)).map(|s| s.display()).unwrap_or_default()
+        })).collect(),
+    }).collect()
+}
+
+/// Prints one test result.
+fn show(result: &TestResult) {
+    if result.status == Status::Missing {
+        println!("{color_yellow}Test {} has no saved generation{color_reset}", result.name);
+    } else if result.status == Status::Budget {
+        println!("{color_yellow}Test {} skipped, MAX_COST spent{color_reset}", result.name);
+    } else if result.status == Status::Stopped {
+        println!("{color_yellow}Test {} skipped, MAX_FAILURES reached{color_reset}", result.name);
+    } else if result.status == Status::Skipped {
+        println!("{color_yellow}Test {} skipped{}{color_reset}", result.name, result.error.as_deref().map_or(String::new(), |r| format!(": {}", r)));
+    } else if result.status == Status::ExpectedFailure {
+        println!("Test {} failed as expected at {}", result.name, result.location.map_or("", ErrorLocation::name));
+    } else if result.status == Status::UnexpectedPass {
+        println!("{color_yellow}Test {} passed unexpectedly, {}{color_reset}", result.name, result.error.as_deref().unwrap_or(""));
+        println!("{}", result.output);
+    } else if result.passed() {
+        println!("Test {} passed{}", result.name, if result.status == Status::Human { " (accepted in an earlier review)" } else { "" });
+        println!("{}", result.output);
+    } else {
+        println!("{color_red}Test {} failed.", result.name);
+        println!("Process: {}", result.location.map_or("", ErrorLocation::name));
+        if let Some(m) = &result.error {
+            println!("{}", m)
+        }
+        if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+            println!("Judge: {}", reason)
+        }
+        println!("{}", result.output);
+        print!("{color_reset}");
+        // A comparison failure is most often read from the diff.
+        if let Some(case) = TestCase::parse(&result.name, &result.input).ok().filter(|_| result.location == Some(ErrorLocation::Test)) {
+            println!("{}", compare::colored(&compare::json_diff(case.expected_output, &result.output)));
+        }
+    }
 }
@@ -0,0 +1,87 @@
+use std::fs;
+use crate::{compare, error::AppError, summary::Summary, test_case::TestCase, ErrorLocation, Status, SuiteReport, TestResult};
+
+/// Longest a code block of the report may be, in characters, so that a page of failures still fits a pull request
+/// description; the results CSV has the rest.
+const MAX_BLOCK: usize = 4000;
+
+/// Writes the run as a Markdown page to paste into a pull request or a wiki: the summary table, a table of the failed
+/// tests, then a section for each with where it failed, the error, the judge's reason, the input, the expected and
+/// generated output and their diff.
+pub fn write(path: &str, report: &SuiteReport, summary: &Summary, model: &str, started: &str) -> Result<(), AppError> {
+    let mut page = format!("# Results {}\n\nModel `{}`{}\n\n", started, model, if report.interrupted { ", interrupted" } else { "" });
+    page.push_str(&summary.markdown());
+    let failures: Vec<&TestResult> = report.results.iter().filter(|r| failed(r)).collect();
+    if failures.is_empty() {
+        page.push_str("\nNo failures.\n");
+        fs::write(path, page).map_err(AppError::io(path))?;
+        return Ok(());
+    }
+    page.push_str("\n## Failures\n\n| Test | Failed at | Error |\n|---|---|---|\n");
+    for result in &failures {
+        let quarantined = if result.quarantined { " (quarantined)" } else { "" };
+        let error = result.error.as_deref().and_then(|e| e.lines().next()).unwrap_or("");
+        page.push_str(&format!("| {}{} | {} | {} |\n", cell(&result.name), quarantined, result.location.map_or("", ErrorLocation::name), cell(error)));
+    }
+    for result in &failures {
+        page.push_str(&details(result));
+    }
+    fs::write(path, page).map_err(AppError::io(path))?;
+    Ok(())
+}
+
+fn details(result: &TestResult) -> String {
+    let mut section = format!("\n### {}\n\n", result.name);
+    if let Some(location) = result.location {
+        section.push_str(&format!("Failed at `{}`{}\n\n", location, if result.quarantined { ", quarantined" } else { "" }));
+    }
+    if let Some(error) = result.error.as_deref().filter(|e| !e.is_empty()) {
+        section.push_str(&format!("**Error**\n\n{}\n", fenced(error.trim_end(), "")));
+    }
+    if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+        let quoted: Vec<String> = reason.lines().map(|line| format!("> {}", line)).collect();
+        section.push_str(&format!("**Judge reason**\n\n{}\n\n", quoted.join("\n")));
+    }
+    match TestCase::parse(&result.name, &result.input) {
+        Ok(case) => {
+            section.push_str(&format!("**Input**\n\n{}\n", fenced(case.input.trim(), "")));
+            section.push_str(&format!("**Expected**\n\n{}\n", fenced(case.expected_output.trim(), "")));
+            if !result.output.trim().is_empty() {
+                section.push_str(&format!("**Generated**\n\n{}\n", fenced(result.output.trim(), "")));
+                let diff = compare::json_diff(case.expected_output, &result.output);
+                if !diff.is_empty() {
+                    section.push_str(&format!("**Diff**\n\n{}\n", fenced(&diff, "diff")));
+                }
+            }
+        }
+        Err(_) => {
+            section.push_str(&format!("**Test file**\n\n{}\n", fenced(result.input.trim(), "")));
+            if !result.output.trim().is_empty() {
+                section.push_str(&format!("**Generated**\n\n{}\n", fenced(result.output.trim(), "")));
+            }
+        }
+    }
+    section
+}
+
+/// Whether a result is one of the run's failures, quarantined or not.
+pub(crate) fn failed(result: &TestResult) -> bool {
+    matches!(result.status, Status::Failed | Status::Missing)
+}
+
+/// Keeps a Markdown table cell on one line and its `|` from ending it.
+pub(crate) fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// `text` as a code block cut to MAX_BLOCK characters, fenced with more backticks than any run of them inside it.
+fn fenced(text: &str, language: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    let total = text.chars().count();
+    let mut text: String = text.chars().take(MAX_BLOCK).collect();
+    if total > MAX_BLOCK {
+        text.push_str(&format!("\n… {} more characters", total - MAX_BLOCK));
+    }
+    format!("{}{}\n{}\n{}\n", fence, language, text, fence)
+}
@@ -0,0 +1,96 @@
+use std::collections::{BTreeMap, BTreeSet};
+use csv::Writer;
+use crate::{error::AppError, stats::{self, Significance}, summary::Summary, ErrorLocation, Status, TestResult};
+
+/// One run of a matrix, a model of MODELS (or `--models`) or a prompt of GEN_PROMPTS: its headline numbers and each
+/// test's outcome.
+pub struct MatrixRun {
+    /// The model or prompt the run was with.
+    pub label: String,
+    /// The results CSV of the model's own run.
+    pub results_file: String,
+    passed: usize,
+    failed: usize,
+    pass_rate: Option<f64>,
+    tokens: u32,
+    cost: Option<f64>,
+    duration_ms: u64,
+    outcomes: BTreeMap<String, String>,
+    /// Each test's score for the significance of the differences, see [`stats::score`].
+    scores: BTreeMap<String, f64>,
+}
+
+impl MatrixRun {
+    pub fn new(label: &str, results_file: &str, summary: &Summary, results: &[TestResult]) -> MatrixRun {
+        MatrixRun {
+            label: label.to_string(),
+            results_file: results_file.to_string(),
+            passed: summary.passed(),
+            failed: summary.failed(),
+            pass_rate: summary.pass_rate(),
+            tokens: summary.tokens(),
+            cost: summary.estimated_cost(),
+            duration_ms: summary.duration_ms(),
+            outcomes: results.iter().map(|r| (r.name.clone(), outcome(r))).collect(),
+            scores: stats::scores(results),
+        }
+    }
+}
+
+/// A test's status as the results CSV writes it, with the error location of a failure: `Failed at judge`.
+fn outcome(result: &TestResult) -> String {
+    let status = serde_json::to_value(result.status).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+    match (result.status, result.location) {
+        (Status::Failed, Some(location)) => format!("{} at {}", status, ErrorLocation::name(location)),
+        _ => status,
+    }
+}
+
+/// Prints the runs side by side under `axis`, `Model` or `Prompt`, the highest pass rate first, then how significant
+/// each one's difference from the first run is and every test whose outcome is not the same for all of them.
+pub fn print(runs: &[MatrixRun], axis: &str) {
+    let mut ranked: Vec<&MatrixRun> = runs.iter().collect();
+    ranked.sort_by(|a, b| b.pass_rate.unwrap_or(-1.0).total_cmp(&a.pass_rate.unwrap_or(-1.0)));
+    let width = runs.iter().map(|r| r.label.len()).max().unwrap_or(0).max(axis.len());
+    println!("{:width$}  {:>6}  {:>6}  {:>9}  {:>8}  {:>8}  {:>10}", axis, "Passed", "Failed", "Pass rate", "Tokens", "Cost", "Duration");
+    for run in ranked {
+        let rate = run.pass_rate.map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0));
+        let cost = run.cost.map_or("-".to_string(), |c| format!("{:.4}", c));
+        println!("{:width$}  {:>6}  {:>6}  {:>9}  {:>8}  {:>8}  {:>8.1} s", run.label, run.passed, run.failed, rate, run.tokens, cost, run.duration_ms as f64 / 1000.0);
+    }
+    if let Some((first, rest)) = runs.split_first() {
+        for run in rest {
+            if let Some(significance) = Significance::new(&first.scores, &run.scores) {
+                println!("{} against {}: {}", run.label, first.label, significance.line());
+            }
+        }
+    }
+    let differing: Vec<&String> = names(runs).into_iter().filter(|name| {
+        let outcomes: BTreeSet<Option<&String>> = runs.iter().map(|r| r.outcomes.get(*name)).collect();
+        outcomes.len() > 1
+    }).collect();
+    if !differing.is_empty() {
+        println!("Tests the {}s differ on:", axis.to_lowercase());
+        for name in differing {
+            let outcomes: Vec<String> = runs.iter().map(|r| format!("{} {}", r.label, r.outcomes.get(name).map_or("not run", String::as_str))).collect();
+            println!("  {}: {}", name, outcomes.join(", "));
+        }
+    }
+}
+
+/// Writes the matrix CSV: a row per test, with each run's outcome in a column named after its model or prompt.
+pub fn write(path: &str, runs: &[MatrixRun]) -> Result<(), AppError> {
+    let mut writer = Writer::from_path(path).map_err(AppError::csv(path))?;
+    writer.write_record(["Name"].into_iter().chain(runs.iter().map(|r| r.label.as_str()))).map_err(AppError::csv(path))?;
+    for name in names(runs) {
+        let outcomes = runs.iter().map(|r| r.outcomes.get(name).map_or("", String::as_str));
+        writer.write_record([name.as_str()].into_iter().chain(outcomes)).map_err(AppError::csv(path))?;
+    }
+    writer.flush().map_err(AppError::io(path))?;
+    Ok(())
+}
+
+/// Every test any of the runs ran, in name order.
+fn names(runs: &[MatrixRun]) -> BTreeSet<&String> {
+    runs.iter().flat_map(|r| r.outcomes.keys()).collect()
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use serde::Serialize;
+use crate::{config::RunConfig, error::AppError};
+
+/// Settings that materially change the outputs of a run, written next to the results so runs can be compared later.
+#[derive(Serialize)]
+pub struct RunMetadata<'a> {
+    pub started: String,
+    pub provider: String,
+    pub model: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub fallback_models: &'a [String],
+    pub response_format: Option<&'static str>,
+    pub gen_n: u8,
+    pub gen_policy: String,
+    pub gen_temperature: Option<f32>,
+    pub gen_top_p: Option<f32>,
+    pub gen_max_tokens: Option<u32>,
+    pub gen_seed: Option<i64>,
+    pub gen_stop: &'a [String],
+    pub gen_tool: Option<&'a str>,
+    pub judge_models: &'a [String],
+    pub judge_temperature: f32,
+    pub judge_top_p: Option<f32>,
+    pub judge_max_tokens: Option<u32>,
+    pub judge_seed: Option<i64>,
+    pub shuffle_seed: Option<u64>,
+}
+
+impl<'a> RunMetadata<'a> {
+    pub fn new(config: &'a RunConfig, started: String) -> Self {
+        RunMetadata {
+            started,
+            provider: config.provider.to_string(),
+            model: &config.model,
+            fallback_models: &config.fallback_models,
+            response_format: config.response_format_name(),
+            gen_n: config.gen_n,
+            gen_policy: config.gen_policy.to_string(),
+            gen_temperature: config.gen_params.temperature,
+            gen_top_p: config.gen_params.top_p,
+            gen_max_tokens: config.gen_params.max_tokens,
+            gen_seed: config.gen_params.seed,
+            gen_stop: &config.gen_stop,
+            gen_tool: config.gen_tool.as_ref().map(|t| t.name.as_str()),
+            judge_models: &config.judge_models,
+            judge_temperature: config.judge_temperature(),
+            judge_top_p: config.judge_top_p,
+            judge_max_tokens: config.judge_max_tokens,
+            judge_seed: config.judge_seed,
+            shuffle_seed: config.shuffle,
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string_pretty(self)?).map_err(AppError::io(path))?;
+        Ok(())
+    }
+}
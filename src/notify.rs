@@ -0,0 +1,115 @@
+use std::{fs, path::Path, time::Duration};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::{config::RunConfig, report, serve, summary::Summary, SuiteReport, TestResult};
+
+/// Longest a webhook may take to answer before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a finished run is announced, and the body of a NOTIFY_WEBHOOKS request.
+#[derive(Serialize)]
+pub struct Notification {
+    pub model: String,
+    /// The run's name in RESULTS_DIR, its results file's stem.
+    pub run: String,
+    /// The run's page under NOTIFY_REPORT_URL, or else the path of its HTML report or results CSV.
+    pub report: String,
+    pub interrupted: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub pass_rate: Option<f64>,
+    pub estimated_cost: Option<f64>,
+    /// The newest earlier run of the same model in RESULTS_DIR, which `regressions` are counted against.
+    pub previous_run: Option<String>,
+    /// The tests that passed in the previous run and fail in this one.
+    pub regressions: Vec<String>,
+}
+
+impl Notification {
+    /// `results_path` is the run's results files without their extension, `report_file` the file to point to when
+    /// there is no NOTIFY_REPORT_URL.
+    pub fn new(config: &RunConfig, report: &SuiteReport, summary: &Summary, results_path: &str, report_file: &str) -> Notification {
+        let dir = Path::new(&config.results_dir);
+        let run = Path::new(results_path).file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let previous_run = previous(dir, &run, &config.model);
+        let regressions = previous_run.as_deref().and_then(|previous| {
+            let raw = dir.join(format!("{}.raw.csv", previous));
+            let path = if raw.exists() { raw } else { dir.join(format!("{}.csv", previous)) };
+            TestResult::read(&path.display().to_string()).ok()
+        }).map_or_else(Vec::new, |old| report::changes(&old, &report.results).regressed.iter().map(|r| r.name.clone()).collect());
+        let link = match &config.notify_report_url {
+            Some(base) => format!("{}/runs/{}", base.trim_end_matches('/'), serve::encode(&run)),
+            None => report_file.to_string(),
+        };
+        Notification {
+            model: config.model.clone(),
+            run,
+            report: link,
+            interrupted: report.interrupted,
+            passed: summary.passed(),
+            failed: summary.failed(),
+            pass_rate: summary.pass_rate(),
+            estimated_cost: summary.estimated_cost(),
+            previous_run,
+            regressions,
+        }
+    }
+
+    /// The Slack message: the headline on the first line, then the regressions, the cost and the report.
+    fn slack(&self) -> Value {
+        let rate = self.pass_rate.map_or_else(|| "no tests judged".to_string(), |rate| format!("{:.1}% passed", rate * 100.0));
+        let mut text = format!("*{}* {} `{}`: {}, {} passed, {} failed", self.run, if self.interrupted { "was interrupted for" } else { "finished for" },
+            self.model, rate, self.passed, self.failed);
+        match (&self.previous_run, self.regressions.is_empty()) {
+            (Some(previous), false) => text.push_str(&format!("\n:red_circle: {} newly failing since {}: {}", self.regressions.len(), previous, self.regressions.join(", "))),
+            (Some(previous), true) => text.push_str(&format!("\nNo new failures since {}", previous)),
+            (None, _) => {}
+        }
+        if let Some(cost) = self.estimated_cost {
+            text.push_str(&format!("\nEstimated cost ${:.4}", cost));
+        }
+        text.push_str(&format!("\nReport: {}", self.report));
+        json!({ "text": text })
+    }
+
+    /// Posts the notification to every NOTIFY_SLACK and NOTIFY_WEBHOOKS URL, returning the errors of those that
+    /// failed so the run can report them without failing over them.
+    pub async fn send(&self, config: &RunConfig) -> Vec<String> {
+        let http = match reqwest::Client::builder().timeout(TIMEOUT).build() {
+            Ok(http) => http,
+            Err(e) => return vec![format!("could not send notifications: {}", e)],
+        };
+        let generic = serde_json::to_value(self).unwrap_or_default();
+        let slack = self.slack();
+        let targets = config.notify_slack.iter().map(|url| (url, &slack)).chain(config.notify_webhooks.iter().map(|url| (url, &generic)));
+        let mut errors = Vec::new();
+        for (url, body) in targets {
+            let sent = http.post(url).json(body).send().await.and_then(|response| response.error_for_status());
+            if let Err(e) = sent {
+                errors.push(format!("notification to {} failed: {}", host(url), e.without_url()));
+            }
+        }
+        errors
+    }
+}
+
+/// The newest run in `dir` older than `run` whose metadata names `model`, leaving out model matrices and reviews.
+fn previous(dir: &Path, run: &str, model: &str) -> Option<String> {
+    report::runs(dir).into_iter()
+        .filter(|r| r.as_str() < run && !r.ends_with(" matrix") && !r.ends_with(".reviewed"))
+        .find(|r| {
+            let meta = fs::read_to_string(dir.join(format!("{}.meta.json", r))).ok();
+            meta.and_then(|m| serde_json::from_str::<Value>(&m).ok()).is_some_and(|m| m["model"] == model)
+        })
+}
+
+/// Only the host of a webhook URL is shown, as the path of a Slack webhook is its secret.
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split('/').next().unwrap_or(rest)
+}
+
+/// Whether the run has anywhere to announce itself.
+pub fn enabled(config: &RunConfig) -> bool {
+    !config.notify_slack.is_empty() || !config.notify_webhooks.is_empty()
+}
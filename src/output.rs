@@ -0,0 +1,154 @@
+use std::{io::{self, IsTerminal}, str::FromStr, time::Duration};
+use async_openai::{error::OpenAIError, types::{ChatCompletionRequestMessage, CreateChatCompletionRequest, CreateEmbeddingRequest}};
+use futures::{future::BoxFuture, FutureExt};
+use indicatif::ProgressStyle;
+use inline_colorization::*;
+use crate::{backend::{ChatBackend, Stream}, generation::Generation, redact::Redactor};
+
+/// How much a run prints per test (VERBOSITY, or `--quiet` and `--verbose`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the summary.
+    Quiet,
+    /// Each test's outcome and output.
+    #[default]
+    Normal,
+    /// Also every prompt sent and every raw response.
+    Verbose,
+}
+
+impl FromStr for Verbosity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quiet" => Ok(Verbosity::Quiet),
+            "normal" => Ok(Verbosity::Normal),
+            "verbose" => Ok(Verbosity::Verbose),
+            other => Err(format!("unknown verbosity {:?}, expected quiet, normal or verbose", other)),
+        }
+    }
+}
+
+/// A one-line progress bar on stderr: tests done out of the total, the test being waited for and the time left at the
+/// average pace so far. Results come in filename order, so the test waited for is the first one not done. Lines
+/// printed during the run go through [`ProgressBar::suspend`] or the run's [`Console`], which clear the bar for them.
+pub struct ProgressBar {
+    bar: indicatif::ProgressBar,
+    names: Vec<String>,
+}
+
+impl ProgressBar {
+    /// A bar over the tests named, when stderr is a terminal and the run is not quiet.
+    pub fn new(names: Vec<String>, verbosity: Verbosity) -> Option<ProgressBar> {
+        if !io::stderr().is_terminal() || names.is_empty() || verbosity == Verbosity::Quiet {
+            return None;
+        }
+        let style = ProgressStyle::with_template("[{bar:30}] {pos}/{len} ETA {eta} {msg}").expect("the template is valid").progress_chars("= ");
+        let bar = indicatif::ProgressBar::new(names.len() as u64).with_style(style).with_message(names[0].clone());
+        // Redrawn every second, so the ETA keeps moving during a long test.
+        bar.enable_steady_tick(Duration::from_secs(1));
+        Some(ProgressBar { bar, names })
+    }
+
+    /// Counts one more test done.
+    pub fn advance(&self) {
+        self.bar.inc(1);
+        self.bar.set_message(self.names.get(self.bar.position() as usize).cloned().unwrap_or_default());
+    }
+
+    /// Runs `print` with the bar out of the way, and draws it again after.
+    pub fn suspend<R>(&self, print: impl FnOnce() -> R) -> R {
+        self.bar.suspend(print)
+    }
+
+    /// Erases the bar for good.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Where a run tells the user what happens besides its results, such as a test file skipped or a generation falling
+/// back to another model: on stdout, around the progress bar, unless the run is quiet.
+#[derive(Clone)]
+pub struct Console {
+    verbosity: Verbosity,
+    bar: Option<indicatif::ProgressBar>,
+}
+
+impl Console {
+    pub fn new(verbosity: Verbosity, bar: Option<&ProgressBar>) -> Console {
+        Console { verbosity, bar: bar.map(|b| b.bar.clone()) }
+    }
+
+    pub fn print(&self, line: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        match &self.bar {
+            Some(bar) => bar.suspend(|| println!("{}", line)),
+            None => println!("{}", line),
+        }
+    }
+}
+
+/// A backend that prints every request's messages and every raw response, for VERBOSITY=verbose.
+pub struct Echo<'a> {
+    pub inner: Box<dyn ChatBackend + 'a>,
+    /// The run's redactor, since verbose output usually ends up in a CI log.
+    pub redactor: &'a Redactor,
+}
+
+impl ChatBackend for Echo<'_> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        async move {
+            let mut request = format!("{style_bold}Request to {}{style_reset}\n", req.model);
+            for message in &req.messages {
+                let (role, text) = message_text(message);
+                request.push_str(&format!("{color_bright_black}[{}]{color_reset} {}\n", role, text));
+            }
+            print!("{}", self.redactor.redact(&request));
+            let streamed = stream.is_some_and(|s| s.label.is_some());
+            let generation = self.inner.complete(req, stream).await;
+            match &generation {
+                Ok(g) => {
+                    let mut response = format!("{style_bold}Response{}{style_reset}\n", g.id.as_deref().map_or(String::new(), |id| format!(" {}", id)));
+                    for (i, choice) in g.choices.iter().enumerate() {
+                        let label = if g.choices.len() > 1 { format!("choice {} ", i) } else { String::new() };
+                        let finish = choice.finish_reason.map_or("", crate::generation::finish_reason_name);
+                        // The first choice of a printed stream is on the console already.
+                        let text = if i == 0 && choice.content.is_some() && streamed { "(streamed above)" } else { choice.content.as_deref().or(choice.refusal.as_deref()).unwrap_or("") };
+                        response.push_str(&format!("{color_bright_black}[{}{}]{color_reset} {}\n", label, finish, text));
+                        for call in &choice.tool_calls {
+                            response.push_str(&format!("{color_bright_black}[tool call {}]{color_reset} {}\n", call.name, call.arguments));
+                        }
+                    }
+                    print!("{}", self.redactor.redact(&response));
+                }
+                Err(e) => println!("{color_red}{style_bold}Request failed{style_reset} {}{color_reset}", e),
+            }
+            generation
+        }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        self.inner.embed(req)
+    }
+}
+
+/// A message's role and its text, or its JSON when it is not plain text. An image part reads as `[image]`, not its data.
+pub(crate) fn message_text(message: &ChatCompletionRequestMessage) -> (String, String) {
+    let value = serde_json::to_value(message).unwrap_or_default();
+    let role = value["role"].as_str().unwrap_or("message").to_string();
+    let text = match &value["content"] {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(parts) => parts.iter().map(|part| match part["type"].as_str() {
+            Some("text") => part["text"].as_str().unwrap_or_default().to_string(),
+            Some("image_url") => "[image]".to_string(),
+            _ => part.to_string(),
+        }).collect::<Vec<_>>().join("\n"),
+        serde_json::Value::Null => value.to_string(),
+        content => content.to_string(),
+    };
+    (role, text)
+}
@@ -0,0 +1,56 @@
+use std::{future::Future, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+use tokio::{sync::Semaphore, time::Instant};
+
+/// The stages of a test that call the API: generating its output, and judging it, which takes in the judge requests,
+/// their reasks, JUDGE_BATCH_SIZE batches and the embeddings of COMPARISON=embedding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Generation,
+    Judge,
+}
+
+/// The run's tests as a pipeline: JOBS tests in flight, each moving from the generation stage to the judge stage,
+/// where each stage has its own pool of GEN_JOBS and JUDGE_JOBS request slots. A test that is being judged holds no
+/// generation slot, so the next test's generation goes ahead meanwhile, and generation held back by a provider's rate
+/// limit does not hold back the judge. The slots are handed out in the order they are asked for, so the tests that
+/// started first move on first. A stage without a limit takes as many requests as the tests in flight make.
+pub struct Pipeline {
+    generation: Option<Semaphore>,
+    judge: Option<Semaphore>,
+    /// How long requests waited for a slot of each stage, in microseconds, since the last [`Pipeline::take_waits`].
+    waited: [AtomicU64; 2],
+}
+
+impl Pipeline {
+    pub fn new(generation: Option<usize>, judge: Option<usize>) -> Pipeline {
+        Pipeline { generation: generation.map(Semaphore::new), judge: judge.map(Semaphore::new), waited: Default::default() }
+    }
+
+    /// Whether either stage has a limit of its own.
+    pub fn is_limited(&self) -> bool {
+        self.generation.is_some() || self.judge.is_some()
+    }
+
+    /// Runs `request` once a slot of `stage` is free, holding it until the request is done. Each retry asks for a slot
+    /// again, so the wait before it blocks no other test.
+    pub async fn run<T>(&self, stage: Stage, request: impl Future<Output = T>) -> T {
+        let semaphore = match stage {
+            Stage::Generation => &self.generation,
+            Stage::Judge => &self.judge,
+        };
+        let Some(semaphore) = semaphore else {
+            return request.await;
+        };
+        let asked = Instant::now();
+        let _slot = semaphore.acquire().await.expect("the semaphore is never closed");
+        self.waited[stage as usize].fetch_add(asked.elapsed().as_micros() as u64, Ordering::Relaxed);
+        request.await
+    }
+
+    /// How long requests of the generation and the judge stage waited for a slot since the last call, which shows the
+    /// stage that holds the run back.
+    pub fn take_waits(&self) -> (Duration, Duration) {
+        let take = |stage: Stage| Duration::from_micros(self.waited[stage as usize].swap(0, Ordering::Relaxed));
+        (take(Stage::Generation), take(Stage::Judge))
+    }
+}
@@ -0,0 +1,198 @@
+use std::{collections::HashMap, io::{Read, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, sync::{Mutex, OnceLock}, thread, time::{Duration, Instant, SystemTime}};
+use wasmtime::{Config, Engine, Instance, Module, Store, UpdateDeadline};
+
+/// A validator plugin (VALIDATOR, or a test's `<validator>`): a program that is given a candidate on stdin and passes it
+/// by exiting with status 0, or a `.wasm` module run in process whose `validate` export is given the candidate, so a
+/// validator can be written in any language that compiles to WebAssembly and shipped with the tests it checks.
+#[derive(Clone)]
+pub struct Plugins {
+    pub validator: Option<PathBuf>,
+    pub timeout: Duration,
+}
+
+/// Runs the validator at `path` on a candidate: `Ok(None)` when it passes, or the validator's reason when it does not,
+/// which is the first line it wrote to stdout, or else to stderr. `Err` is for a validator that could not be run or
+/// ran over VALIDATOR_TIMEOUT_MS.
+pub fn validate(plugins: &Plugins, path: &Path, candidate: &str) -> Result<Option<String>, String> {
+    let mut command = match path.extension().and_then(|e| e.to_str()) {
+        Some("wasm") => return validate_wasm(plugins, path, candidate),
+        // A relative path is the validator's file, not a program to look up in PATH.
+        _ if path.is_relative() => Command::new(Path::new(".").join(path)),
+        _ => Command::new(path),
+    };
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        .map_err(|e| format!("cannot run validator {}: {}", path.display(), e))?;
+    // A validator that exits without reading its input closes the pipe, which is its verdict rather than an error.
+    let _ = child.stdin.take().map(|mut stdin| stdin.write_all(candidate.as_bytes()));
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("validator {} failed: {}", path.display(), e))? {
+            break status;
+        }
+        if started.elapsed() > plugins.timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("validator {} exceeded VALIDATOR_TIMEOUT_MS of {}ms", path.display(), plugins.timeout.as_millis()));
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    if status.success() {
+        return Ok(None);
+    }
+    let output = |reader: Option<thread::JoinHandle<String>>| reader.and_then(|r| r.join().ok()).unwrap_or_default();
+    let (stdout, stderr) = (output(stdout), output(stderr));
+    let reason = [stdout, stderr].into_iter().find_map(|text| text.lines().map(str::trim).find(|l| !l.is_empty()).map(str::to_string));
+    Ok(Some(reason.unwrap_or_else(|| format!("validator exited with {}", status))))
+}
+
+/// Reads a pipe to its end on another thread, so a validator writing more than the pipe holds does not block.
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut text = String::new();
+        let _ = pipe.read_to_string(&mut text);
+        text
+    })
+}
+
+/// The engine every module is compiled with, and the thread ticking its epoch so that a module running over
+/// VALIDATOR_TIMEOUT_MS is stopped at its next check.
+fn engine() -> Result<&'static Engine, String> {
+    static ENGINE: OnceLock<Result<Engine, String>> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let engine = Engine::new(Config::new().epoch_interruption(true)).map_err(|e| format!("cannot start the WebAssembly engine: {}", e))?;
+        let ticking = engine.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(5));
+            ticking.increment_epoch();
+        });
+        Ok(engine)
+    }).as_ref().map_err(Clone::clone)
+}
+
+/// The compiled module at `path`, compiled again only when the file changed, as every candidate of every test is
+/// validated with it.
+fn module(path: &Path) -> Result<Module, String> {
+    struct Compiled {
+        modified: Option<SystemTime>,
+        module: Module,
+    }
+    static MODULES: OnceLock<Mutex<HashMap<PathBuf, Compiled>>> = OnceLock::new();
+    let modified = path.metadata().and_then(|m| m.modified()).ok();
+    let mut modules = MODULES.get_or_init(Mutex::default).lock().expect("module cache lock");
+    if let Some(compiled) = modules.get(path).filter(|c| c.modified == modified) {
+        return Ok(compiled.module.clone());
+    }
+    let module = Module::from_file(engine()?, path).map_err(|e| format!("cannot load validator {}: {:#}", path.display(), e))?;
+    modules.insert(path.to_path_buf(), Compiled { modified, module: module.clone() });
+    Ok(module)
+}
+
+/// Runs a `.wasm` validator, a module without imports that exports its `memory`, `alloc(len: i32) -> i32` for the
+/// candidate's bytes to be written to, and `validate(ptr: i32, len: i32) -> i64`. `validate` passes the candidate with
+/// 0, and fails it with the pointer to a UTF-8 reason in the high 32 bits and its length in the low 32.
+fn validate_wasm(plugins: &Plugins, path: &Path, candidate: &str) -> Result<Option<String>, String> {
+    let module = module(path)?;
+    let started = Instant::now();
+    let timeout = plugins.timeout;
+    let mut store = Store::new(engine()?, ());
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| match started.elapsed() > timeout {
+        true => Err(wasmtime::Error::msg("timed out")),
+        false => Ok(UpdateDeadline::Continue(1)),
+    });
+    let failed = |e: wasmtime::Error| match started.elapsed() > timeout {
+        true => format!("validator {} exceeded VALIDATOR_TIMEOUT_MS of {}ms", path.display(), timeout.as_millis()),
+        false => format!("validator {} failed: {:#}", path.display(), e),
+    };
+    let instance = Instance::new(&mut store, &module, &[]).map_err(failed)?;
+    let memory = instance.get_memory(&mut store, "memory").ok_or_else(|| format!("validator {} exports no memory", path.display()))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(failed)?;
+    let run = instance.get_typed_func::<(i32, i32), i64>(&mut store, "validate").map_err(failed)?;
+    let len = i32::try_from(candidate.len()).map_err(|_| format!("the candidate is too long for validator {}", path.display()))?;
+    let ptr = alloc.call(&mut store, len).map_err(failed)?;
+    memory.write(&mut store, ptr as u32 as usize, candidate.as_bytes()).map_err(|e| failed(e.into()))?;
+    let result = run.call(&mut store, (ptr, len)).map_err(failed)?;
+    if result == 0 {
+        return Ok(None);
+    }
+    let (ptr, len) = ((result as u64 >> 32) as usize, result as u32 as usize);
+    let reason = memory.data(&store).get(ptr..ptr + len)
+        .ok_or_else(|| format!("validator {} returned a reason outside its memory", path.display()))?;
+    let reason = String::from_utf8_lossy(reason);
+    Ok(Some(reason.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("validator rejected the candidate").to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Passes a candidate that starts with `{`, and fails any other with `reason`.
+    const BRACE: &str = r#"(module
+        (memory (export "memory") 1)
+        (data (i32.const 0) "expected an object\n")
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "validate") (param $ptr i32) (param $len i32) (result i64)
+            (if (result i64) (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 123))
+                (then (i64.const 0))
+                (else (i64.const 19)))))"#;
+
+    fn plugins(timeout: Duration) -> Plugins {
+        Plugins { validator: None, timeout }
+    }
+
+    fn module(name: &str, wat: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ai_test_util-{}-{}.wasm", name, std::process::id()));
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn a_wasm_validator_passes_or_fails_with_its_reason() {
+        let path = module("brace", BRACE);
+        let plugins = plugins(Duration::from_secs(10));
+        assert_eq!(validate(&plugins, &path, "{\"a\": 1}"), Ok(None));
+        assert_eq!(validate(&plugins, &path, "[1]"), Ok(Some("expected an object".to_string())));
+    }
+
+    #[test]
+    fn a_wasm_validator_is_recompiled_when_it_changes() {
+        let path = module("changed", BRACE);
+        let plugins = plugins(Duration::from_secs(10));
+        assert_eq!(validate(&plugins, &path, "[1]"), Ok(Some("expected an object".to_string())));
+        let passing = BRACE.replace("(else (i64.const 19))", "(else (i64.const 0))");
+        // Sets the modification time apart on file systems that keep it in whole seconds.
+        std::fs::write(&path, wat::parse_str(passing).unwrap()).unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        assert_eq!(validate(&plugins, &path, "[1]"), Ok(None));
+    }
+
+    #[test]
+    fn a_wasm_validator_without_a_reason_still_fails() {
+        let path = module("no_reason", &BRACE.replace("(i64.const 19)", "(i64.const 4294967296)"));
+        let reason = validate(&plugins(Duration::from_secs(10)), &path, "[1]");
+        assert_eq!(reason, Ok(Some("validator rejected the candidate".to_string())));
+    }
+
+    #[test]
+    fn a_wasm_validator_running_too_long_is_stopped() {
+        let path = module("loop", &BRACE.replace("(if (result i64)", "(loop $forever (br $forever)) (if (result i64)"));
+        let started = Instant::now();
+        let err = validate(&plugins(Duration::from_millis(50)), &path, "{}").unwrap_err();
+        assert!(err.ends_with("exceeded VALIDATOR_TIMEOUT_MS of 50ms"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn a_module_without_the_exports_is_an_error() {
+        let path = module("no_exports", r#"(module (memory (export "memory") 1))"#);
+        let err = validate(&plugins(Duration::from_secs(10)), &path, "{}").unwrap_err();
+        assert!(err.contains("alloc"), "{}", err);
+        let trap = module("trap", &BRACE.replace("(i32.load8_u (local.get $ptr))", "(unreachable)"));
+        let err = validate(&plugins(Duration::from_secs(10)), &trap, "{}").unwrap_err();
+        assert!(err.starts_with(&format!("validator {} failed:", trap.display())), "{}", err);
+        let err = validate(&plugins(Duration::from_secs(10)), Path::new("missing.wasm"), "{}").unwrap_err();
+        assert!(err.starts_with("cannot load validator missing.wasm"), "{}", err);
+    }
+}
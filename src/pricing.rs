@@ -0,0 +1,44 @@
+use std::{collections::BTreeMap, fs};
+use serde::Deserialize;
+use crate::{error::AppError, judge::TokenUsage};
+
+/// A model's price in dollars per million tokens.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Prices by model from PRICE_TABLE, with PRICE_PER_MTOK as the price of every token of any other model.
+#[derive(Default)]
+pub struct PriceTable {
+    prices: BTreeMap<String, Price>,
+    fallback: Option<f64>,
+}
+
+impl PriceTable {
+    /// The PRICE_TABLE file is a JSON object of models, each with its `input` and `output` price, as in
+    /// `{"gpt-4o": {"input": 2.5, "output": 10}}`.
+    pub fn load(path: Option<&str>, fallback: Option<f64>) -> Result<PriceTable, AppError> {
+        let prices = match path {
+            Some(path) => serde_json::from_str(&fs::read_to_string(path).map_err(|source| AppError::ConfigRead { var: "PRICE_TABLE", path: path.to_string(), source })?)
+                .map_err(|e| format!("PRICE_TABLE {} is not a JSON object of model prices: {}", path, e))?,
+            None => BTreeMap::new(),
+        };
+        Ok(PriceTable { prices, fallback })
+    }
+
+    /// Whether any price is configured at all.
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty() && self.fallback.is_none()
+    }
+
+    pub fn price(&self, model: &str) -> Option<Price> {
+        self.prices.get(model).copied().or(self.fallback.map(|p| Price { input: p, output: p }))
+    }
+
+    /// What `usage` of `model` cost, if the model has a price.
+    pub fn cost(&self, model: &str, usage: TokenUsage) -> Option<f64> {
+        self.price(model).map(|p| (usage.prompt as f64 * p.input + usage.completion as f64 * p.output) / 1_000_000.0)
+    }
+}
@@ -0,0 +1,113 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+use async_openai::{error::OpenAIError, types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart, CreateChatCompletionRequest, CreateEmbeddingRequest, EmbeddingInput}};
+use futures::{future::BoxFuture, FutureExt};
+use tokio::time::Instant;
+use crate::{backend::{ChatBackend, Stream}, generation::Generation};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Requests-per-minute and tokens-per-minute limits shared by every request of a run (RATE_LIMIT_RPM,
+/// RATE_LIMIT_TPM). A request waits until the last minute's requests leave room for it. Its tokens are estimated
+/// from the prompt and `max_tokens` until the response reports the real usage.
+pub struct RateLimit {
+    pub rpm: Option<u32>,
+    pub tpm: Option<u32>,
+    state: Mutex<Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    next_id: u64,
+    /// The requests sent in the last minute: when, their id and their tokens.
+    sent: VecDeque<(Instant, u64, u32)>,
+}
+
+impl RateLimit {
+    pub fn new(rpm: Option<u32>, tpm: Option<u32>) -> RateLimit {
+        RateLimit { rpm, tpm, state: Mutex::new(Window::default()) }
+    }
+
+    /// Waits for room for a request of `tokens` tokens and counts it as sent, returning its id.
+    async fn acquire(&self, tokens: u32) -> u64 {
+        loop {
+            let wait = {
+                let mut window = self.state.lock().unwrap();
+                let now = Instant::now();
+                while window.sent.front().is_some_and(|(at, _, _)| now.duration_since(*at) >= WINDOW) {
+                    window.sent.pop_front();
+                }
+                let used: u32 = window.sent.iter().map(|(_, _, t)| *t).sum();
+                let requests_ok = self.rpm.is_none_or(|rpm| (window.sent.len() as u32) < rpm);
+                // A request over the whole budget on its own is let through once the window is empty, not held forever.
+                let tokens_ok = self.tpm.is_none_or(|tpm| window.sent.is_empty() || used.saturating_add(tokens) <= tpm);
+                if requests_ok && tokens_ok {
+                    let id = window.next_id;
+                    window.next_id += 1;
+                    window.sent.push_back((now, id, tokens));
+                    return id;
+                }
+                window.sent.front().map_or(Duration::ZERO, |(at, _, _)| WINDOW.saturating_sub(now.duration_since(*at)))
+            };
+            tokio::time::sleep(wait.max(Duration::from_millis(10))).await;
+        }
+    }
+
+    /// Replaces the estimate for request `id` with the tokens it used.
+    fn settle(&self, id: u64, tokens: u32) {
+        if let Some(entry) = self.state.lock().unwrap().sent.iter_mut().find(|(_, i, _)| *i == id) {
+            entry.2 = tokens;
+        }
+    }
+}
+
+/// A backend whose requests wait for a [`RateLimit`].
+pub struct Limited<'a> {
+    pub inner: Box<dyn ChatBackend + 'a>,
+    pub limit: &'a RateLimit,
+}
+
+impl ChatBackend for Limited<'_> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        async move {
+            let prompt: usize = req.messages.iter().map(message_len).sum();
+            let estimate = estimate(prompt) + req.max_tokens.unwrap_or(0) * req.n.unwrap_or(1) as u32;
+            let id = self.limit.acquire(estimate).await;
+            let generation = self.inner.complete(req, stream).await;
+            if let Ok(Some(usage)) = generation.as_ref().map(|g| g.usage.as_ref()) {
+                self.limit.settle(id, usage.total_tokens);
+            }
+            generation
+        }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move {
+            let len = match &req.input {
+                EmbeddingInput::String(s) => s.len(),
+                EmbeddingInput::StringArray(inputs) => inputs.iter().map(String::len).sum(),
+                EmbeddingInput::IntegerArray(tokens) => tokens.len() * 4,
+                EmbeddingInput::ArrayOfIntegerArray(inputs) => inputs.iter().map(Vec::len).sum::<usize>() * 4,
+            };
+            self.limit.acquire(estimate(len)).await;
+            self.inner.embed(req).await
+        }.boxed()
+    }
+}
+
+/// About four bytes of English text per token.
+fn estimate(bytes: usize) -> u32 {
+    (bytes / 4 + 1) as u32
+}
+
+/// An image counts as about a thousand tokens, whatever the size of its encoding.
+const IMAGE_LEN: usize = 4000;
+
+fn message_len(message: &ChatCompletionRequestMessage) -> usize {
+    if let ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage { content: ChatCompletionRequestUserMessageContent::Array(parts), .. }) = message {
+        return parts.iter().map(|part| match part {
+            ChatCompletionRequestUserMessageContentPart::Text(t) => t.text.len(),
+            ChatCompletionRequestUserMessageContentPart::ImageUrl(_) => IMAGE_LEN,
+        }).sum();
+    }
+    serde_json::to_string(message).map_or(0, |json| json.len())
+}
@@ -0,0 +1,106 @@
+use std::{borrow::Cow, env, fs};
+use regex::{Captures, Regex};
+use crate::{error::AppError, TestResult};
+
+/// What a redacted secret is replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// The key formats REDACT finds without being told: OpenAI, Anthropic and other `sk-` keys, AWS access keys, GitHub,
+/// Slack and Google tokens, JWTs, bearer tokens and PEM private keys.
+const BUILT_IN: [&str; 9] = [
+    r"\bsk-[A-Za-z0-9_-]{20,}",
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+    r"\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+    r"\bxox[abprs]-[A-Za-z0-9-]{10,}",
+    r"\bAIza[0-9A-Za-z_-]{35}",
+    r"\beyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}",
+    r"(?i)\bbearer\s+([A-Za-z0-9._~+/-]{20,}=*)",
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+];
+
+/// Environment variables whose names end like this hold secrets, and their values are redacted wherever they appear.
+const SECRET_VARS: [&str; 4] = ["KEY", "TOKEN", "SECRET", "PASSWORD"];
+
+/// Replaces secrets with [`REDACTED`] in everything a run writes: the results CSV and the reports made from them, the
+/// run log and the artifacts. A pattern with a capture group redacts only what the first group matched, so
+/// `password=(\S+)` keeps `password=`.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// With REDACT, on by default, the built-in key formats and the values of the environment's secret variables and of
+    /// API_HEADERS, and the patterns of REDACT_PATTERNS, a file with a regular expression on each line, either way.
+    pub fn from_env(enabled: bool) -> Result<Redactor, AppError> {
+        let mut patterns = Vec::new();
+        if enabled {
+            patterns.extend(BUILT_IN.iter().map(|p| Regex::new(p).expect("the built-in patterns are valid")));
+            let mut secrets: Vec<String> = env::vars()
+                .filter(|(name, value)| SECRET_VARS.iter().any(|s| name.to_uppercase().ends_with(s)) && value.trim().len() >= 8)
+                .map(|(_, value)| regex::escape(value.trim()))
+                .collect();
+            // API_HEADERS is where an API gateway's credentials go.
+            secrets.extend(env::var("API_HEADERS").unwrap_or_default().lines()
+                .filter_map(|line| line.split_once(':').map(|(_, value)| value.trim()))
+                .filter(|value| value.len() >= 8)
+                .map(regex::escape));
+            secrets.sort();
+            secrets.dedup();
+            patterns.extend(secrets.iter().map(|s| Regex::new(s).expect("an escaped value is a valid pattern")));
+        }
+        if let Ok(path) = env::var("REDACT_PATTERNS") {
+            let contents = fs::read_to_string(&path).map_err(AppError::io(format!("REDACT_PATTERNS {}", path)))?;
+            for (i, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                patterns.push(Regex::new(line).map_err(|e| format!("REDACT_PATTERNS {} line {}: {}", path, i + 1, e))?);
+            }
+        }
+        Ok(Redactor { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// `text` with every match of every pattern replaced.
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            if let Cow::Owned(redacted) = pattern.replace_all(&text, |caps: &Captures| match caps.get(1) {
+                Some(group) => {
+                    let whole = caps.get(0).expect("a match has a whole");
+                    format!("{}{}{}", &whole.as_str()[..group.start() - whole.start()], REDACTED, &whole.as_str()[group.end() - whole.start()..])
+                }
+                None => REDACTED.to_string(),
+            }) {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Redacts the text a result carries: the test file, the generation, the error, the judge's reason and the rest.
+    pub fn result(&self, result: &mut TestResult) {
+        if self.is_empty() {
+            return;
+        }
+        let redact = |text: &mut String| {
+            if let Cow::Owned(redacted) = self.redact(text) {
+                *text = redacted;
+            }
+        };
+        redact(&mut result.input);
+        redact(&mut result.output);
+        for text in [&mut result.error, &mut result.candidates, &mut result.reason, &mut result.normalized, &mut result.truncated_input].into_iter().flatten() {
+            redact(text);
+        }
+        if let Some(reason) = result.judgement.as_mut().and_then(|j| j.reason.as_mut()) {
+            redact(reason);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::{fs, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::{backend::ChatBackend, error::AppError, extract_candidates, generate, test_case::TestCase, ErrorLocation, RunConfig, TestError};
+
+/// A baseline BASELINE_MODEL generated, as cached in BASELINE_CACHE.
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    model: String,
+    prompt: String,
+    baseline: String,
+}
+
+/// The expected output for a test without one: its generation prompt run through BASELINE_MODEL, from the cache when
+/// the same model already answered the same prompt. The baseline is the first JSON candidate in the reply.
+pub(crate) async fn baseline(case: &TestCase<'_>, config: &RunConfig, client: &dyn ChatBackend) -> Result<Result<String, TestError>, AppError> {
+    let Some(model) = &config.baseline_model else {
+        return Ok(Ok(case.expected_output.to_string()));
+    };
+    let prompt = match case.render_gen_prompt(config, case.input) {
+        Ok(prompt) => prompt,
+        Err(e) => return Ok(Err(TestError::because(ErrorLocation::MatchInput, "", e))),
+    };
+    let path = Path::new(&config.baseline_cache).join(format!("{:016x}.json", fnv1a(format!("{}\0{}", model, prompt).as_bytes())));
+    if let Some(cached) = fs::read_to_string(&path).ok().and_then(|c| serde_json::from_str::<Cached>(&c).ok()) {
+        if cached.model == *model && cached.prompt == prompt {
+            return Ok(Ok(cached.baseline));
+        }
+    }
+    let failed = |e: TestError| TestError::because(ErrorLocation::Baseline, e.content.clone(), format!("BASELINE_MODEL {}: {}", model, e.message().unwrap_or_default()));
+    let generation = match generate(case, config, client, model, 1, 0).await? {
+        Ok(generation) => generation,
+        Err(e) => return Ok(Err(failed(e))),
+    };
+    let baseline = match extract_candidates(&generation.choices[0], generation.usage.as_ref(), case, config)? {
+        Ok(extracted) => extracted.candidates.into_iter().next().unwrap_or_default(),
+        Err(e) => return Ok(Err(failed(e))),
+    };
+    fs::create_dir_all(&config.baseline_cache).map_err(AppError::io(format!("BASELINE_CACHE {}", config.baseline_cache)))?;
+    let cached = Cached { model: model.clone(), prompt, baseline };
+    fs::write(&path, serde_json::to_string_pretty(&cached)?).map_err(AppError::io(path.display().to_string()))?;
+    Ok(Ok(cached.baseline))
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is stable across releases, so cache keys survive a toolchain update.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
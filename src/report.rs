@@ -0,0 +1,364 @@
+use std::{borrow::Cow, fs, path::Path, str::FromStr};
+use serde::{Deserialize, Serialize};
+use crate::{error::AppError, judge::{Judgement, PanelVote}, ErrorLocation};
+
+/// One test's result as it is written to the results CSV and read back from it. The column names are the serde names,
+/// so adding a field adds a column.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TestResult {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Status")]
+    pub status: Status,
+    /// The whole test file.
+    #[serde(rename = "Input")]
+    pub input: String,
+    #[serde(rename = "Result")]
+    pub output: String,
+    #[serde(rename = "Error Location")]
+    pub location: Option<ErrorLocation>,
+    #[serde(rename = "Error")]
+    pub error: Option<String>,
+    #[serde(rename = "Candidates")]
+    pub candidates: Option<String>,
+    #[serde(rename = "Finish Reason")]
+    pub finish_reason: Option<String>,
+    #[serde(rename = "Judge Confidence")]
+    pub confidence: Option<f32>,
+    #[serde(rename = "Similarity")]
+    pub similarity: Option<f32>,
+    #[serde(rename = "Judge Reason")]
+    pub reason: Option<String>,
+    #[serde(rename = "Score")]
+    pub score: Option<f64>,
+    /// Partial credit from 0 to 1: the score of whichever grader decided the test, or 0 where the candidate failed first.
+    #[serde(rename = "Grade")]
+    pub grade: Option<f64>,
+    /// The comparison, `similarity`, `embedding`, `judge` or `rubric` that gave the grade, or the failed stage.
+    #[serde(rename = "Graded By")]
+    pub graded_by: Option<String>,
+    #[serde(rename = "Judge Votes")]
+    pub votes: Option<String>,
+    #[serde(rename = "Judge Tokens")]
+    pub judge_tokens: Option<u32>,
+    #[serde(rename = "Normalized")]
+    pub normalized: Option<String>,
+    #[serde(rename = "Duration ms")]
+    pub duration_ms: u64,
+    /// Total tokens of the generation request.
+    #[serde(rename = "Generation Tokens")]
+    pub tokens: Option<u32>,
+    #[serde(rename = "Generation Prompt Tokens")]
+    pub generation_prompt_tokens: Option<u32>,
+    #[serde(rename = "Generation Completion Tokens")]
+    pub generation_completion_tokens: Option<u32>,
+    /// Prompt tokens of every judge call, across models.
+    #[serde(rename = "Judge Prompt Tokens")]
+    pub judge_prompt_tokens: Option<u32>,
+    #[serde(rename = "Judge Completion Tokens")]
+    pub judge_completion_tokens: Option<u32>,
+    /// The estimated cost in dollars of the test's generation and judge calls, when every model used has a price.
+    #[serde(rename = "Cost")]
+    pub cost: Option<f64>,
+    /// Listed in QUARANTINE_FILE: the test runs as usual, but the summary keeps it apart.
+    #[serde(rename = "Quarantined")]
+    pub quarantined: bool,
+    /// `static` for the test file's own `<output>`, `reference` for one generated by BASELINE_MODEL.
+    #[serde(rename = "Baseline Source")]
+    pub baseline_source: Option<String>,
+    #[serde(rename = "Baseline Model")]
+    pub baseline_model: Option<String>,
+    /// With PASS_AT_K, each attempt's outcome in order: `passed` or the location it failed at.
+    #[serde(rename = "Attempts")]
+    pub attempts: Option<String>,
+    #[serde(rename = "Pass@1")]
+    pub pass_at_1: Option<bool>,
+    #[serde(rename = "Pass@k")]
+    pub pass_at_k: Option<bool>,
+    /// With PASS_AT_K, the share of attempts that passed.
+    #[serde(rename = "Attempt Pass Rate")]
+    pub attempt_pass_rate: Option<f64>,
+    /// How many tokens INPUT_MAX_TOKENS cut from the input.
+    #[serde(rename = "Truncated Tokens")]
+    pub truncated_tokens: Option<usize>,
+    /// The input as it was sent, marker included, when it was truncated.
+    #[serde(rename = "Truncated Input")]
+    pub truncated_input: Option<String>,
+    /// The response id of the generation request, to match a result with the provider's logs.
+    #[serde(rename = "Generation Request ID")]
+    pub generation_request_id: Option<String>,
+    #[serde(rename = "Generation Retries")]
+    pub generation_retries: Option<u32>,
+    /// The response ids of every judge call, space separated.
+    #[serde(rename = "Judge Request IDs")]
+    pub judge_request_ids: Option<String>,
+    /// The other tests that shared a JUDGE_BATCH_SIZE judge request with this one.
+    #[serde(rename = "Judge Batch")]
+    pub judge_batch: Option<String>,
+    #[serde(rename = "Judge Retries")]
+    pub judge_retries: Option<u32>,
+    /// The directory of the test's ARTIFACTS.
+    #[serde(rename = "Artifacts")]
+    pub artifacts: Option<String>,
+    /// The model that generated the output.
+    #[serde(rename = "Model")]
+    pub model: Option<String>,
+    /// When the test started, in RFC 3339 local time.
+    #[serde(rename = "Started")]
+    pub started: Option<String>,
+    /// How long the generation requests took, retries and PASS_AT_K attempts included.
+    #[serde(rename = "Generation ms")]
+    pub generation_ms: Option<u64>,
+    /// How long finding the JSON candidates in the generated output took, EXTRACT and tool calls included.
+    #[serde(rename = "Extraction ms")]
+    pub extraction_ms: Option<u64>,
+    /// How long the structure test took over every candidate.
+    #[serde(rename = "Structure ms")]
+    pub structure_ms: Option<u64>,
+    /// How long the test waited on the judge and embeddings.
+    #[serde(rename = "Judge ms")]
+    pub judge_ms: Option<u64>,
+    /// 1 for the test's first run, one more for each `run --retry-failed` that ran it again.
+    #[serde(rename = "Attempt")]
+    pub attempt: Option<u32>,
+    /// How long the first streamed generation took to its first token.
+    #[serde(rename = "First Token ms")]
+    pub first_token_ms: Option<u64>,
+    /// The full judgement behind the judge columns; not part of the CSV.
+    #[serde(skip)]
+    pub judgement: Option<Judgement>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    Passed,
+    /// Failed, but the output was accepted in an earlier review.
+    #[serde(rename = "Passed (human)")]
+    Human,
+    #[default]
+    Failed,
+    /// Judged with `--phase judge`, but the saved generations have none for this test.
+    #[serde(rename = "Missing generation")]
+    Missing,
+    /// Not run because of its `<skip>` section.
+    Skipped,
+    /// Failed, as its `<xfail>` section expects.
+    #[serde(rename = "Expected failure")]
+    ExpectedFailure,
+    /// Passed despite its `<xfail>` section.
+    #[serde(rename = "Unexpected pass")]
+    UnexpectedPass,
+    /// Not run because the run had spent its MAX_COST.
+    #[serde(rename = "Skipped (budget)")]
+    Budget,
+    /// Not run because MAX_FAILURES tests had failed.
+    #[serde(rename = "Skipped (max failures)")]
+    Stopped,
+}
+
+/// The RESULTS_COLUMNS name of each fixed column, in the order of [`TestResult::columns`].
+pub const COLUMN_KEYS: [&str; 47] = [
+    "name", "status", "input", "result", "location", "error", "candidates", "finish_reason", "confidence", "similarity",
+    "reason", "score", "grade", "graded_by", "votes", "judge_tokens", "normalized", "duration", "tokens", "generation_prompt_tokens", "generation_completion_tokens",
+    "judge_prompt_tokens", "judge_completion_tokens", "cost", "quarantined", "baseline_source",
+    "baseline_model", "attempts", "pass_at_1", "pass_at_k", "attempt_pass_rate", "truncated_tokens", "truncated_input", "generation_request_id", "generation_retries", "judge_request_ids", "judge_batch", "judge_retries", "artifacts",
+    "model", "started", "generation_ms", "extraction_ms", "structure_ms", "judge_ms", "attempt", "first_token_ms",
+];
+
+/// A RESULTS_COLUMNS entry: a fixed column by its index in [`COLUMN_KEYS`], or the judge panel or rubric columns as a
+/// group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Field(usize),
+    Panel,
+    Criteria,
+}
+
+impl Column {
+    /// Every column in the default order: the fixed ones, then the panel and rubric columns.
+    pub fn all() -> Vec<Column> {
+        (0..COLUMN_KEYS.len()).map(Column::Field).chain([Column::Panel, Column::Criteria]).collect()
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "judges" => Ok(Column::Panel),
+            "criteria" => Ok(Column::Criteria),
+            _ => COLUMN_KEYS.iter().position(|k| *k == s).map(Column::Field)
+                .ok_or_else(|| format!("unknown column {:?}, expected one of {}, judges, criteria", s, COLUMN_KEYS.join(", "))),
+        }
+    }
+}
+
+/// A report written next to the results CSV, chosen with REPORT_FORMAT or `--format`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    /// JUnit XML, `.junit.xml`, for CI test views.
+    Junit,
+    /// A standalone HTML page, `.html`, with every test's details and diff.
+    Html,
+    /// One JSON line per result, `.ndjson`, written as each test finishes.
+    Ndjson,
+    /// A Markdown page, `.md`, with the failures' details, for pull requests and wikis.
+    Markdown,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "junit" => Ok(ReportFormat::Junit),
+            "html" => Ok(ReportFormat::Html),
+            "ndjson" => Ok(ReportFormat::Ndjson),
+            "markdown" => Ok(ReportFormat::Markdown),
+            other => Err(format!("unknown report format {:?}, expected csv, junit, html, ndjson or markdown", other)),
+        }
+    }
+}
+
+/// One result as a line of the NDJSON report.
+#[derive(Serialize)]
+pub struct ResultLine<'a> {
+    pub name: &'a str,
+    pub status: Status,
+    pub passed: bool,
+    pub location: Option<&'static str>,
+    pub error: Option<&'a str>,
+    pub score: Option<f64>,
+    pub grade: Option<f64>,
+    pub duration_ms: u64,
+    pub generation_tokens: Option<u32>,
+    pub judge_tokens: Option<u32>,
+    pub quarantined: bool,
+    pub artifacts: Option<&'a str>,
+    /// Each JUDGE_MODELS judge's vote, when more than one model judges.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub judges: &'a [PanelVote],
+}
+
+impl<'a> From<&'a TestResult> for ResultLine<'a> {
+    fn from(result: &'a TestResult) -> Self {
+        ResultLine {
+            name: &result.name,
+            status: result.status,
+            passed: result.passed(),
+            location: result.location.map(ErrorLocation::name),
+            error: result.error.as_deref(),
+            score: result.score,
+            grade: result.grade,
+            duration_ms: result.duration_ms,
+            generation_tokens: result.tokens,
+            judge_tokens: result.judge_tokens,
+            quarantined: result.quarantined,
+            artifacts: result.artifacts.as_deref(),
+            judges: result.judgement.as_ref().map_or(&[], |j| j.panel.as_slice()),
+        }
+    }
+}
+
+impl TestResult {
+    /// Whether the test is not a failure of the run: it passed, or failed as its `<xfail>` expects.
+    pub fn passed(&self) -> bool {
+        matches!(self.status, Status::Passed | Status::Human | Status::ExpectedFailure | Status::UnexpectedPass)
+    }
+
+    /// The fixed CSV columns, in order.
+    pub fn columns() -> Vec<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(TestResult::default()).expect("TestResult serializes to CSV");
+        let data = writer.into_inner().expect("writing to memory cannot fail");
+        csv::Reader::from_reader(data.as_slice()).headers().expect("the header was just written").iter().map(str::to_string).collect()
+    }
+
+    /// The fixed columns of this result as CSV fields, in the order of [`TestResult::columns`].
+    pub fn fields(&self) -> Vec<String> {
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+        writer.serialize(self).expect("TestResult serializes to CSV");
+        let data = writer.into_inner().expect("writing to memory cannot fail");
+        let record = csv::ReaderBuilder::new().has_headers(false).from_reader(data.as_slice()).records().next();
+        record.expect("one row was just written").expect("the row was just written").iter().map(str::to_string).collect()
+    }
+
+    /// Every row of a results CSV. Columns that are missing, as with RESULTS_COLUMNS, read as their defaults.
+    pub fn read(path: &str) -> Result<Vec<TestResult>, AppError> {
+        let mut reader = csv::Reader::from_path(path).map_err(AppError::csv(path))?;
+        let results = reader.deserialize().collect::<Result<_, _>>().map_err(AppError::csv(path))?;
+        Ok(results)
+    }
+}
+
+/// How each test changed between two runs, matched by name. The results are the new run's, except for removed tests.
+#[derive(Default)]
+pub struct RunChanges<'a> {
+    /// Passed in the old run and fails in the new one.
+    pub regressed: Vec<&'a TestResult>,
+    /// Failed in the old run and passes in the new one.
+    pub fixed: Vec<&'a TestResult>,
+    pub still_failing: Vec<&'a TestResult>,
+    pub still_passing: Vec<&'a TestResult>,
+    /// Only in the new run.
+    pub added: Vec<&'a TestResult>,
+    /// Only in the old run.
+    pub removed: Vec<&'a TestResult>,
+}
+
+pub fn changes<'a>(old: &'a [TestResult], new: &'a [TestResult]) -> RunChanges<'a> {
+    let mut changes = RunChanges::default();
+    for result in new {
+        let list = match old.iter().find(|o| o.name == result.name).map(TestResult::passed) {
+            None => &mut changes.added,
+            Some(true) if result.passed() => &mut changes.still_passing,
+            Some(true) => &mut changes.regressed,
+            Some(false) if result.passed() => &mut changes.fixed,
+            Some(false) => &mut changes.still_failing,
+        };
+        list.push(result);
+    }
+    changes.removed = old.iter().filter(|o| !new.iter().any(|r| r.name == o.name)).collect();
+    changes
+}
+
+/// The stems of the results CSVs in `dir`, newest first, leaving out the raw copies of sanitized ones. Results files are named by their start time, so that is
+/// reverse name order.
+pub fn runs(dir: &Path) -> Vec<String> {
+    let mut runs: Vec<String> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_suffix(".csv").map(str::to_string))
+        .filter(|run| !run.ends_with(".raw"))
+        .collect();
+    runs.sort_by(|a, b| b.cmp(a));
+    runs
+}
+
+/// Makes a CSV cell safe to open in a spreadsheet: a leading `=`, `+`, `-` or `@` that would start a formula gets a
+/// `'` in front unless the cell is a number, control characters other than newline and tab become `\u{..}` escapes, and
+/// cells longer than `max_len` characters are cut short with a marker.
+pub fn sanitize(cell: &str, max_len: Option<usize>) -> Cow<'_, str> {
+    let formula = cell.starts_with(['=', '+', '-', '@']) && cell.parse::<f64>().is_err();
+    let control = cell.chars().any(|c| c.is_control() && c != '\n' && c != '\t');
+    let long = max_len.is_some_and(|max| cell.chars().nth(max).is_some());
+    if !formula && !control && !long {
+        return Cow::Borrowed(cell);
+    }
+    let mut sanitized = String::with_capacity(cell.len() + 1);
+    if formula {
+        sanitized.push('\'');
+    }
+    let chars = cell.chars().count();
+    for c in cell.chars().take(max_len.unwrap_or(chars)) {
+        if c.is_control() && c != '\n' && c != '\t' {
+            sanitized.push_str(&c.escape_unicode().to_string());
+        } else {
+            sanitized.push(c);
+        }
+    }
+    if long {
+        sanitized.push_str(&format!("…[{} more characters]", chars - max_len.unwrap_or(chars)));
+    }
+    Cow::Owned(sanitized)
+}
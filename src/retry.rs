@@ -0,0 +1,70 @@
+use std::{future::Future, time::Duration};
+use async_openai::error::OpenAIError;
+
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// No single wait is longer than this (RETRY_MAX_DELAY_MS).
+    pub max_delay: Duration,
+    /// The share of each wait that is random (RETRY_JITTER, 0 to 1), so that tests failing together do not all retry at
+    /// the same moment.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Runs `call` until it succeeds, fails permanently, or `max_retries` retries of transient failures have been used,
+    /// doubling the delay after each attempt up to `max_delay`.
+    pub async fn run<T, F, Fut>(&self, call: F) -> Result<T, OpenAIError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, OpenAIError>>,
+    {
+        self.run_counted(call).await.0
+    }
+
+    /// Like [`RetryPolicy::run`], also returning how many retries were used.
+    pub async fn run_counted<T, F, Fut>(&self, mut call: F) -> (Result<T, OpenAIError>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, OpenAIError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match call().await {
+                Err(e) if attempt < self.max_retries && is_transient(&e) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return (result, attempt),
+            }
+        }
+    }
+
+    /// The wait before retry `attempt` (from 0): the doubled delay, capped, of which up to `jitter` is taken off at random.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter * fastrand::f64())
+    }
+}
+
+/// Network failures, dropped streams, undecodable (usually proxy or gateway) bodies and server-side errors are worth retrying;
+/// invalid requests are not. async_openai already retries plain 429s internally.
+fn is_transient(e: &OpenAIError) -> bool {
+    match e {
+        OpenAIError::Reqwest(_) | OpenAIError::StreamError(_) | OpenAIError::JSONDeserialize(_) => true,
+        OpenAIError::ApiError(api) => matches!(api.r#type.as_deref(), Some("server_error" | "rate_limit_exceeded" | "timeout")),
+        _ => false,
+    }
+}
+
+/// The HTTP status where the transport reports one, otherwise the API's error body (type, message, param and code).
+pub fn describe(e: &OpenAIError) -> String {
+    match e {
+        OpenAIError::Reqwest(r) => match r.status() {
+            Some(status) => format!("HTTP {}: {}", status, r),
+            None => r.to_string(),
+        },
+        OpenAIError::ApiError(api) => format!("API error {}", api),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,225 @@
+use std::{env, fs, io::{self, IsTerminal, Write}, path::PathBuf};
+use chrono::Local;
+use inline_colorization::*;
+use serde::{Deserialize, Serialize};
+use crate::{compare, error::AppError, test_case::TestCase, tui, ErrorLocation, Status, TestResult};
+
+/// Markdown write-ups of failed tests for a human reviewer, one file per test plus an index, written to REVIEW_DIR.
+pub struct ReviewQueue {
+    dir: PathBuf,
+    entries: Vec<String>,
+}
+
+impl ReviewQueue {
+    pub fn new(dir: &str) -> Result<ReviewQueue, AppError> {
+        fs::create_dir_all(dir).map_err(AppError::io(format!("REVIEW_DIR {}", dir)))?;
+        Ok(ReviewQueue { dir: PathBuf::from(dir), entries: Vec::new() })
+    }
+
+    pub fn add(&mut self, result: &TestResult) -> Result<(), AppError> {
+        let name = &result.name;
+        let location = result.location.map_or("", ErrorLocation::name);
+        let file = format!("{}.md", name.replace(|c: char| !c.is_alphanumeric() && c != '-' && c != '_', "_"));
+        let mut doc = format!("# {}\n\n**Failed at:** `{}`\n\n", name, location);
+        if let Some(err) = &result.error {
+            doc.push_str(&format!("**Error:** {}\n\n", err));
+        }
+        if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+            doc.push_str(&format!("**Judge reason:** {}\n\n", reason));
+        }
+        let case = TestCase::parse(name, &result.input);
+        match &case {
+            Ok(case) => {
+                doc.push_str(&format!("## Description\n\n{}\n\n", case.input.trim()));
+                doc.push_str(&format!("## Expected\n\n```\n{}\n```\n\n", case.expected_output.trim()));
+            }
+            Err(_) => doc.push_str(&format!("## Test file\n\n```\n{}\n```\n\n", result.input.trim())),
+        }
+        let generated = match serde_json::from_str::<serde_json::Value>(&result.output) {
+            Ok(v) => serde_json::to_string_pretty(&v)?,
+            Err(_) => result.output.trim().to_string(),
+        };
+        doc.push_str(&format!("## Generated\n\n```\n{}\n```\n", generated));
+        if let (Ok(case), Some(ErrorLocation::Test)) = (&case, result.location) {
+            doc.push_str(&format!("\n## Diff\n\n```diff\n{}\n```\n", compare::json_diff(case.expected_output, &result.output)));
+        }
+        let path = self.dir.join(&file);
+        fs::write(&path, doc).map_err(AppError::io(path.display().to_string()))?;
+        let summary = result.error.as_deref().and_then(|m| m.lines().next()).unwrap_or("");
+        self.entries.push(format!("- [{}]({}) – `{}` {}", name, file, location, summary));
+        Ok(())
+    }
+
+    pub fn write_index(&self) -> Result<(), AppError> {
+        let mut index = format!("# Failed tests ({})\n\n", self.entries.len());
+        for entry in &self.entries {
+            index.push_str(entry);
+            index.push('\n');
+        }
+        let path = self.dir.join("index.md");
+        fs::write(&path, index).map_err(AppError::io(path.display().to_string()))?;
+        Ok(())
+    }
+}
+
+/// A failed output a reviewer accepted, exported so later runs can accept the identical output without asking again.
+#[derive(Serialize, Deserialize)]
+pub struct Override {
+    pub name: String,
+    pub output: String,
+    pub reviewer: String,
+    pub reviewed_at: String,
+}
+
+pub struct Overrides(Vec<Override>);
+
+impl Overrides {
+    pub fn load(path: &str) -> Result<Overrides, AppError> {
+        let overrides = serde_json::from_str(&fs::read_to_string(path).map_err(|source| AppError::ConfigRead { var: "ACCEPTED_OVERRIDES", path: path.to_string(), source })?)
+            .map_err(|e| format!("ACCEPTED_OVERRIDES {} is not a valid override list: {}", path, e))?;
+        Ok(Overrides(overrides))
+    }
+
+    pub fn accepts(&self, name: &str, output: &str) -> bool {
+        self.0.iter().any(|o| o.name == name && o.output.trim() == output.trim())
+    }
+}
+
+/// A reviewer's verdict on a failed test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The output is actually correct: the test passes as "Passed (human)" and its output is exported as an override.
+    Accepted,
+    /// The failure stands.
+    Rejected,
+}
+
+/// The results CSV under review, and the verdicts given so far.
+pub struct Session {
+    path: String,
+    headers: csv::StringRecord,
+    rows: Vec<csv::StringRecord>,
+    pub results: Vec<TestResult>,
+    /// By result, with the reviewer and time.
+    verdicts: Vec<Option<(Verdict, String, String)>>,
+    reviewer: String,
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Session, AppError> {
+        let mut reader = csv::Reader::from_path(path).map_err(AppError::csv(path))?;
+        let headers = reader.headers().map_err(AppError::csv(path))?.clone();
+        if !headers.iter().any(|h| h == "Status") {
+            return Err(format!("{} has no Status column", path).into());
+        }
+        let rows: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().map_err(AppError::csv(path))?;
+        let results: Vec<TestResult> = rows.iter().map(|r| r.deserialize(Some(&headers))).collect::<Result<_, _>>().map_err(AppError::csv(path))?;
+        let verdicts = vec![None; results.len()];
+        Ok(Session { path: path.to_string(), headers, rows, results, verdicts, reviewer: env::var("USER").unwrap_or("unknown".to_string()) })
+    }
+
+    /// The indexes of the failed results, the ones to review.
+    pub fn failed(&self) -> Vec<usize> {
+        (0..self.results.len()).filter(|&i| self.results[i].status == Status::Failed).collect()
+    }
+
+    pub fn verdict(&self, index: usize) -> Option<Verdict> {
+        self.verdicts[index].as_ref().map(|(verdict, _, _)| *verdict)
+    }
+
+    /// Records a verdict on the result at `index`, or clears it.
+    pub fn set(&mut self, index: usize, verdict: Option<Verdict>) {
+        self.verdicts[index] = verdict.map(|v| (v, self.reviewer.clone(), Local::now().to_rfc3339()));
+    }
+
+    /// Writes the reviewed results next to the original, with accepted tests marked "Passed (human)" and every reviewed
+    /// one with its reviewer and time, and the accepted outputs as an override list.
+    pub fn export(&self) -> Result<(), AppError> {
+        let stem = self.path.strip_suffix(".csv").unwrap_or(&self.path);
+        let reviewed_path = format!("{}.reviewed.csv", stem);
+        let status_col = self.headers.iter().position(|h| h == "Status").expect("load checked the Status column");
+        let mut writer = csv::Writer::from_path(&reviewed_path).map_err(AppError::csv(&reviewed_path))?;
+        let mut headers = self.headers.clone();
+        headers.push_field("Reviewed By");
+        headers.push_field("Reviewed At");
+        writer.write_record(&headers).map_err(AppError::csv(&reviewed_path))?;
+        let mut accepted = Vec::new();
+        for ((row, result), verdict) in self.rows.iter().zip(&self.results).zip(&self.verdicts) {
+            let mut fields: Vec<String> = row.iter().map(str::to_string).collect();
+            let mut reviewed = [String::new(), String::new()];
+            if let Some((verdict, reviewer, at)) = verdict {
+                if *verdict == Verdict::Accepted {
+                    accepted.push(Override { name: result.name.clone(), output: result.output.clone(), reviewer: reviewer.clone(), reviewed_at: at.clone() });
+                    fields[status_col] = "Passed (human)".to_string();
+                }
+                reviewed = [reviewer.clone(), at.clone()];
+            }
+            writer.write_record(fields.iter().chain(&reviewed)).map_err(AppError::csv(&reviewed_path))?;
+        }
+        writer.flush().map_err(AppError::io(&reviewed_path))?;
+        println!("Reviewed results written to {}", reviewed_path);
+        if !accepted.is_empty() {
+            let overrides_path = format!("{}.overrides.json", stem);
+            fs::write(&overrides_path, serde_json::to_string_pretty(&accepted)?).map_err(AppError::io(&overrides_path))?;
+            println!("{} accepted output(s) written to {}", accepted.len(), overrides_path);
+        }
+        Ok(())
+    }
+}
+
+/// Reviews the failed tests of a results CSV and exports the verdicts, see [`Session::export`]. On a terminal this is
+/// the interface of [`tui`] unless `plain` is set; otherwise each failure is printed in turn with a prompt, so a
+/// review can also be scripted.
+pub fn interactive(path: &str, plain: bool) -> Result<(), AppError> {
+    let mut session = Session::load(path)?;
+    if !plain && io::stdin().is_terminal() && io::stdout().is_terminal() {
+        tui::review(&mut session)?;
+    } else {
+        prompt(&mut session)?;
+    }
+    session.export()
+}
+
+/// Steps through the failures one at a time, letting the reviewer accept, reject or skip each one.
+fn prompt(session: &mut Session) -> Result<(), AppError> {
+    let failed = session.failed();
+    for (seen, &index) in failed.iter().enumerate() {
+        let result = &session.results[index];
+        let (name, contents, output) = (&result.name, &result.input, &result.output);
+        println!("{style_bold}[{}/{}] {}{style_reset} failed at {}", seen + 1, failed.len(), name, result.location.map_or("", ErrorLocation::name));
+        if let Some(error) = &result.error {
+            println!("{}", error);
+        }
+        if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+            println!("Judge: {}", reason);
+        }
+        match TestCase::parse(name, contents) {
+            Ok(case) => {
+                println!("{style_bold}Description:{style_reset}\n{}", case.input.trim());
+                println!("{style_bold}Expected:{style_reset}\n{}", case.expected_output.trim());
+                println!("{style_bold}Generated:{style_reset}\n{}", output.trim());
+                println!("{style_bold}Diff:{style_reset}");
+                println!("{}", compare::colored(&compare::json_diff(case.expected_output, output)));
+            }
+            Err(_) => println!("{style_bold}Test file:{style_reset}\n{}\n{style_bold}Generated:{style_reset}\n{}", contents.trim(), output.trim()),
+        }
+        let verdict = loop {
+            print!("[a]ccept, [r]eject, [s]kip, [q]uit? ");
+            io::stdout().flush().map_err(AppError::io("stdout"))?;
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).map_err(AppError::io("stdin"))? == 0 {
+                return Ok(());
+            }
+            match answer.trim() {
+                "a" => break Some(Verdict::Accepted),
+                "r" => break Some(Verdict::Rejected),
+                "s" => break None,
+                "q" => return Ok(()),
+                _ => continue,
+            }
+        };
+        session.set(index, verdict);
+        println!();
+    }
+    Ok(())
+}
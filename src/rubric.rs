@@ -0,0 +1,156 @@
+use std::{collections::HashSet, fs};
+use serde::Deserialize;
+use crate::error::AppError;
+
+/// Named criteria the judge scores one by one instead of giving a single verdict, loaded from the RUBRIC file or a
+/// test's `<rubric>` section.
+#[derive(Clone, Deserialize)]
+pub struct Rubric {
+    #[serde(default)]
+    pub scale: Scale,
+    /// When set the test passes if the weighted mean of criterion scores (normalized to 0–1) reaches it; otherwise every
+    /// criterion has to pass.
+    pub threshold: Option<f64>,
+    /// Lowest score a criterion on the 0–10 scale needs to count as passed.
+    #[serde(default = "default_pass_score")]
+    pub pass_score: f64,
+    pub criteria: Vec<Criterion>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Criterion {
+    pub name: String,
+    pub description: String,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scale {
+    #[default]
+    Boolean,
+    Ten,
+}
+
+#[derive(Clone, Debug)]
+pub struct CriterionScore {
+    pub name: String,
+    pub value: f64,
+    pub passed: bool,
+    pub weight: f64,
+    pub scale: Scale,
+}
+
+impl CriterionScore {
+    /// How the score is written to its CSV column and the HTML report.
+    pub fn display(&self) -> String {
+        match self.scale {
+            Scale::Boolean => (self.value == 1.0).to_string(),
+            Scale::Ten => self.value.to_string(),
+        }
+    }
+}
+
+fn default_pass_score() -> f64 {
+    7.0
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl Rubric {
+    pub fn load(path: &str) -> Result<Rubric, AppError> {
+        let text = fs::read_to_string(path).map_err(|source| AppError::ConfigRead { var: "RUBRIC", path: path.to_string(), source })?;
+        Ok(Rubric::parse(&text, &format!("RUBRIC {}", path))?)
+    }
+
+    /// A rubric from its JSON, `what` naming where it came from in errors: the RUBRIC file or `<rubric>`.
+    pub fn parse(text: &str, what: &str) -> Result<Rubric, String> {
+        let rubric: Rubric = serde_json::from_str(text).map_err(|e| format!("{} is not a valid rubric: {}", what, e))?;
+        if rubric.criteria.is_empty() {
+            return Err(format!("{} has no criteria", what));
+        }
+        let mut names = HashSet::new();
+        for c in &rubric.criteria {
+            if c.name.trim().is_empty() {
+                return Err(format!("{} has a criterion without a name", what));
+            }
+            if !names.insert(c.name.as_str()) {
+                return Err(format!("{} lists criterion {:?} twice", what, c.name));
+            }
+            if c.weight <= 0.0 {
+                return Err(format!("{} criterion {:?} needs a positive weight", what, c.name));
+            }
+        }
+        if rubric.threshold.is_some_and(|t| !(0.0..=1.0).contains(&t)) {
+            return Err(format!("{} threshold must be between 0 and 1", what));
+        }
+        Ok(rubric)
+    }
+
+    /// Appended to the judge prompt in place of the single-verdict instructions.
+    pub fn instructions(&self) -> String {
+        let (value, example) = match self.scale {
+            Scale::Boolean => ("true or false", "true"),
+            Scale::Ten => ("a score from 0 to 10", "7"),
+        };
+        let mut text = format!("\n\nInstead of a single verdict, assess the output against each of these criteria and give {} for each:\n", value);
+        for c in &self.criteria {
+            text.push_str(&format!("- {}: {}\n", c.name, c.description));
+        }
+        text.push_str(&format!("Respond with a JSON object with one key per criterion name and a \"reason\" key explaining the result, e.g. {{\"{}\": {}, \"reason\": \"...\"}}.", self.criteria[0].name, example));
+        text
+    }
+
+    /// The per-criterion scores in rubric order, their weighted total from 0 to 100 and whether they add up to a pass, or
+    /// a description of what was missing.
+    pub fn evaluate(&self, reply: &str) -> Result<(Vec<CriterionScore>, f64, bool, Option<String>), String> {
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(reply)
+            .map_err(|e| format!("judge reply is not a rubric JSON object ({}): {}", e, reply))?;
+        let mut scores = Vec::new();
+        for c in &self.criteria {
+            let value = object.get(&c.name).and_then(|v| self.value(v))
+                .ok_or_else(|| format!("judge reply has no valid score for criterion {:?}: {}", c.name, reply))?;
+            let passed = match self.scale {
+                Scale::Boolean => value == 1.0,
+                Scale::Ten => value >= self.pass_score,
+            };
+            scores.push(CriterionScore { name: c.name.clone(), value, passed, weight: c.weight, scale: self.scale });
+        }
+        let total = self.total(&scores);
+        let passed = match self.threshold {
+            Some(threshold) => total >= threshold,
+            None => scores.iter().all(|s| s.passed),
+        };
+        let reason = object.get("reason").and_then(|r| r.as_str()).map(str::to_string);
+        Ok((scores, total * 100.0, passed, reason))
+    }
+
+    /// The weighted mean of the criterion scores, each normalized to 0–1.
+    fn total(&self, scores: &[CriterionScore]) -> f64 {
+        let max = if self.scale == Scale::Ten { 10.0 } else { 1.0 };
+        let weights: f64 = scores.iter().map(|s| s.weight).sum();
+        scores.iter().map(|s| s.weight * s.value / max).sum::<f64>() / weights
+    }
+
+    /// Why a judgement against the rubric failed: its weighted total below the threshold, or the criteria that failed.
+    pub fn failure(&self, scores: &[CriterionScore]) -> String {
+        match self.threshold {
+            Some(threshold) => format!("rubric score {:.2} is below its threshold {}", self.total(scores), threshold),
+            None => {
+                let failed: Vec<&str> = scores.iter().filter(|s| !s.passed).map(|s| s.name.as_str()).collect();
+                format!("failed criteria: {}", failed.join(", "))
+            }
+        }
+    }
+
+    fn value(&self, value: &serde_json::Value) -> Option<f64> {
+        match (self.scale, value) {
+            (Scale::Boolean, serde_json::Value::Bool(b)) => Some(if *b { 1.0 } else { 0.0 }),
+            (Scale::Ten, serde_json::Value::Number(n)) => n.as_f64().filter(|v| (0.0..=10.0).contains(v)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{fmt::{self, Write as _}, fs::{File, OpenOptions}, io::{LineWriter, Write}, sync::Mutex, time::Instant};
+use async_openai::{error::OpenAIError, types::{CreateChatCompletionRequest, CreateEmbeddingRequest}};
+use chrono::Local;
+use futures::{future::BoxFuture, FutureExt};
+use tracing::{field::{Field, Visit}, span::{Attributes, Id, Record}, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::{filter::filter_fn, layer::Context, registry::LookupSpan, Layer};
+use crate::{backend::{ChatBackend, Stream}, error::AppError, generation::Generation, output, redact::Redactor};
+
+/// Writes a run's tracing events at LOG_LEVEL to its log file, one line per event: the time, the level, the spans the
+/// event happened in with their fields, the message and the event's own fields, as in
+/// `2026-10-14T09:30:12.345 INFO test{name="a.txt"}:generation{model="gpt-4o"}: response latency_ms=812 prompt_tokens=312`.
+pub struct RunLog {
+    file: Mutex<LineWriter<File>>,
+    redactor: Redactor,
+}
+
+/// A span's fields as the log writes them, kept with the span.
+struct SpanFields(String);
+
+impl RunLog {
+    /// Opens the log file at `path`, recording events at `level` and above; the HTTP client's own events are only
+    /// recorded from `warn` up. The file is appended to, so a resumed run's log follows its first start. Every line
+    /// goes through `redactor`.
+    pub fn create<S: Subscriber + for<'a> LookupSpan<'a>>(path: &str, level: Level, redactor: Redactor) -> Result<impl Layer<S>, AppError> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(AppError::io(path))?;
+        let log = RunLog { file: Mutex::new(LineWriter::new(file)), redactor };
+        let enabled = move |metadata: &Metadata<'_>| match metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            true => *metadata.level() <= level,
+            false => *metadata.level() <= level.min(Level::WARN),
+        };
+        Ok(log.with_filter(filter_fn(enabled)))
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RunLog {
+    fn on_new_span(&self, span: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(existing)) = span.extensions_mut().get_mut::<SpanFields>() {
+                existing.push_str(&fields.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        // `name{fields}`, or the name alone, for the span and each of its parents, outermost first, joined with `:`.
+        let context = ctx.event_scope(event).map(|scope| scope.from_root().map(|span| {
+            match span.extensions().get::<SpanFields>().map(|f| f.0.trim_start().to_string()).unwrap_or_default() {
+                fields if fields.is_empty() => span.name().to_string(),
+                fields => format!("{}{{{}}}", span.name(), fields),
+            }
+        }).collect::<Vec<_>>().join(":")).unwrap_or_default();
+        let mut line = format!("{} {}", Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"), event.metadata().level());
+        if !context.is_empty() {
+            let _ = write!(line, " {}:", context);
+        }
+        if let Some(message) = fields.message {
+            let _ = write!(line, " {}", message);
+        }
+        line.push_str(&fields.fields);
+        // A log that cannot be written must not fail the run it describes.
+        let _ = writeln!(self.file.lock().unwrap(), "{}", self.redactor.redact(&line));
+    }
+}
+
+/// An event's or span's fields as ` name=value` pairs, with the event's message apart. Strings are quoted, so a
+/// multi-line error stays on its line.
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    fields: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// A backend that logs every request it sends: the model, how long it took, the tokens used and the request id, or
+/// the error. The prompts and responses themselves are logged at `debug` and `trace`.
+pub struct Traced<'a> {
+    pub inner: Box<dyn ChatBackend + 'a>,
+}
+
+impl ChatBackend for Traced<'_> {
+    fn complete<'a>(&'a self, req: CreateChatCompletionRequest, stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        async move {
+            let model = req.model.clone();
+            for message in &req.messages {
+                let (role, text) = output::message_text(message);
+                tracing::debug!(model, role, text, "request message");
+            }
+            let started = Instant::now();
+            let generation = self.inner.complete(req, stream).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            match &generation {
+                Ok(g) => {
+                    let usage = g.usage.as_ref();
+                    tracing::info!(model, latency_ms, prompt_tokens = usage.map(|u| u.prompt_tokens), completion_tokens = usage.map(|u| u.completion_tokens),
+                        request_id = g.id.as_deref(), choices = g.choices.len(), "response");
+                    for (choice, c) in g.choices.iter().enumerate() {
+                        let finish_reason = c.finish_reason.map(crate::generation::finish_reason_name);
+                        tracing::trace!(choice, finish_reason, content = c.content.as_deref(), refusal = c.refusal.as_deref(), "response choice");
+                    }
+                }
+                Err(e) => tracing::warn!(model, latency_ms, error = %e, "request failed"),
+            }
+            generation
+        }.boxed()
+    }
+
+    fn embed(&self, req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move {
+            let model = req.model.clone();
+            let started = Instant::now();
+            let embedded = self.inner.embed(req).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            match &embedded {
+                Ok(embeddings) => tracing::info!(model, latency_ms, embeddings = embeddings.len(), "embedding response"),
+                Err(e) => tracing::warn!(model, latency_ms, error = %e, "embedding request failed"),
+            }
+            embedded
+        }.boxed()
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{error::AppError, run_suite, structure, RunConfig, RunError, SuiteReport, TestResult};
+
+/// Receives each result of a [`TestRunner`] run as soon as it and every earlier test are done, in filename order.
+pub trait Reporter {
+    fn report(&mut self, result: &TestResult) -> Result<(), AppError>;
+}
+
+/// Runs a suite from another program, such as an integration test harness: the same pipeline as the binary, suite hooks
+/// included, with the results going to a [`Reporter`] instead of the CSV and console. Build the config with
+/// [`RunConfig::from_env`] and adjust its fields as needed. Nothing is printed: the run's notes, such as a skipped
+/// test file, go to tracing, unless the config is given a [`Console`](crate::output::Console).
+pub struct TestRunner {
+    config: RunConfig,
+}
+
+impl TestRunner {
+    pub fn new(config: RunConfig) -> TestRunner {
+        TestRunner { config }
+    }
+
+    pub fn config(&self) -> &RunConfig {
+        &self.config
+    }
+
+    /// Loads the structure test, calls `before_all`, runs every test and calls `after_all`. An `after_all` error is
+    /// returned; the reporter has every result by then.
+    pub async fn run(&mut self, reporter: &mut dyn Reporter) -> Result<SuiteReport, RunError> {
+        structure::verify(&self.config)?;
+        self.config.shared = structure::before_all(&self.config)?;
+        let report = run_suite(&self.config, |result| {
+            let reported = reporter.report(&result);
+            async move { reported }
+        }).await?;
+        structure::after_all(&self.config, &report.results)?;
+        Ok(report)
+    }
+}
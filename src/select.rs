@@ -0,0 +1,112 @@
+use std::{fmt, str::FromStr};
+use regex::Regex;
+use crate::{test_case, RunConfig, TestInfo};
+
+/// A TEST_FILTER pattern, matched against test file names. A pattern with any of `^ $ + ( ) | \ { }` is a regular
+/// expression that matches anywhere in the name; any other pattern is a glob that must match the whole name, where `*`
+/// is any run of characters, `?` any one character and `[...]` one of a set.
+#[derive(Clone, Debug)]
+pub struct TestFilter(Regex);
+
+impl TestFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+}
+
+impl FromStr for TestFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = if s.contains(['^', '$', '+', '(', ')', '|', '\\', '{', '}']) { s.to_string() } else { glob(s) };
+        Regex::new(&pattern).map(TestFilter).map_err(|e| format!("invalid TEST_FILTER {:?}: {}", s, e))
+    }
+}
+
+/// The anchored regular expression for a glob.
+fn glob(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => pattern.push_str(".*"),
+            '?' if !in_class => pattern.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                pattern.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                pattern.push(']');
+            }
+            '!' if in_class && pattern.ends_with('[') => pattern.push('^'),
+            c if in_class => pattern.push(c),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+/// Splits the discovered tests into those selected and the names of the rest. A test is selected when its name matches
+/// TEST_FILTER, it has one of TAGS and none of EXCLUDE_TAGS, each of which selects every test when unset, and in
+/// `--watch` the change affected it. The selected tests are numbered again in filename order.
+pub fn select(config: &RunConfig, tests: Vec<TestInfo>) -> (Vec<TestInfo>, Vec<String>) {
+    let (mut selected, rest): (Vec<TestInfo>, Vec<TestInfo>) = tests.into_iter().partition(|t| is_selected(config, t));
+    for (index, test) in selected.iter_mut().enumerate() {
+        test.index = index;
+    }
+    (selected, rest.into_iter().map(|t| t.name).collect())
+}
+
+fn is_selected(config: &RunConfig, test: &TestInfo) -> bool {
+    let tags = test_case::tags(&test.contents);
+    config.filter.as_ref().is_none_or(|f| f.matches(&test.name))
+        && (config.tags.is_empty() || config.tags.iter().any(|t| tags.contains(&t.as_str())))
+        && !config.exclude_tags.iter().any(|t| tags.contains(&t.as_str()))
+        && config.only.as_ref().is_none_or(|only| only.contains(&test.name))
+}
+
+/// The order of a shuffled run's results, from RESULT_ORDER or `--sort`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResultOrder {
+    /// The order the tests ran in.
+    #[default]
+    Run,
+    /// By test name, as an unshuffled run is.
+    Name,
+}
+
+impl FromStr for ResultOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "run" => Ok(ResultOrder::Run),
+            "name" => Ok(ResultOrder::Name),
+            other => Err(format!("unknown result order {:?}, expected run or name", other)),
+        }
+    }
+}
+
+impl fmt::Display for ResultOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResultOrder::Run => write!(f, "run"),
+            ResultOrder::Name => write!(f, "name"),
+        }
+    }
+}
+
+/// The selected tests in the order they run: by name, or shuffled with the SHUFFLE_SEED. A test's index, the place
+/// of its result in the results, the console and the reports, follows the run unless RESULT_ORDER is `name`.
+pub fn order(config: &RunConfig, mut tests: Vec<TestInfo>) -> Vec<TestInfo> {
+    let Some(seed) = config.shuffle else { return tests };
+    fastrand::Rng::with_seed(seed).shuffle(&mut tests);
+    if config.result_order == ResultOrder::Run {
+        for (index, test) in tests.iter_mut().enumerate() {
+            test.index = index;
+        }
+    }
+    tests
+}
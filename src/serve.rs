@@ -0,0 +1,610 @@
+use std::{convert::Infallible, env, fs, future::Future, net::SocketAddr, path::{Path, PathBuf}, pin::Pin, sync::{Arc, Mutex}, time::Duration};
+use axum::{body::Body, extract::{self, State}, http::{header, HeaderMap, StatusCode}, response::{sse::{Event, Sse}, IntoResponse}, routing::get, Json, Router};
+use futures::StreamExt;
+use hyper_util::{rt::{TokioIo, TokioTimer}, service::TowerToHyperService};
+use serde::{Deserialize, Serialize};
+use tokio::{net::{TcpListener, TcpStream}, sync::{mpsc, Notify}};
+use crate::{config::RunConfig, error::AppError, report::{self, ResultLine}, test_case::TestCase, ErrorLocation, Status, TestResult};
+
+/// Request bodies larger than this are refused; the API only takes small JSON bodies.
+const MAX_BODY: usize = 8192;
+/// How long a client may take to send a request's head, and a `POST` its body, before its connection is closed, so
+/// that an idle client does not hold a connection open for good.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a `POST /api/runs` body can change about the run it starts; everything else comes from the settings, as for
+/// a run from the command line.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunRequest {
+    model: Option<String>,
+    filter: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    dry_run: Option<bool>,
+}
+
+impl RunRequest {
+    /// Applies the request to the settings. A run started over HTTP runs one model, so with more than one in MODELS
+    /// the request has to name it; a model named without JUDGE_MODELS judges itself, as it would from the command line.
+    pub fn apply(self, config: &mut RunConfig) -> Result<(), AppError> {
+        if let Some(model) = self.model {
+            if env::var("JUDGE_MODELS").is_err() {
+                config.judge_models = vec![model.clone()];
+            }
+            config.models = vec![model.clone()];
+            config.model = model;
+        } else if config.models.len() > 1 {
+            return Err("MODELS names more than one model; name the one to run in \"model\"".into());
+        }
+        if let Some(filter) = self.filter {
+            config.filter = Some(filter.parse()?);
+        }
+        if !self.tags.is_empty() {
+            config.tags = self.tags;
+        }
+        if !self.exclude_tags.is_empty() {
+            config.exclude_tags = self.exclude_tags;
+        }
+        if let Some(dry_run) = self.dry_run {
+            config.dry_run = dry_run;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RunState {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// A run started over HTTP, as `/api/runs/<id>` reports it.
+#[derive(Clone, Serialize)]
+struct RunStatus {
+    id: usize,
+    state: RunState,
+    model: Option<String>,
+    /// The number of tests selected, once the run started.
+    total: Option<usize>,
+    done: usize,
+    passed: usize,
+    failed: usize,
+    /// The run's name under `/api/results`, once it finished.
+    run: Option<String>,
+    error: Option<String>,
+    /// Each finished test as a `result` event.
+    #[serde(skip)]
+    events: Vec<String>,
+}
+
+/// The runs started over HTTP since the server started, which run one at a time, and how their progress reaches the
+/// event streams following them.
+#[derive(Default)]
+struct Runs {
+    runs: Mutex<Vec<RunStatus>>,
+    changed: Notify,
+}
+
+impl Runs {
+    fn update(&self, id: usize, update: impl FnOnce(&mut RunStatus)) {
+        if let Some(status) = self.runs.lock().expect("run status lock").get_mut(id) {
+            update(status);
+        }
+        self.changed.notify_waiters();
+    }
+
+    fn get(&self, id: usize) -> Option<RunStatus> {
+        self.runs.lock().expect("run status lock").get(id).cloned()
+    }
+}
+
+/// How a run started over HTTP reports back as it goes.
+pub struct Progress {
+    runs: Arc<Runs>,
+    id: usize,
+}
+
+impl Progress {
+    pub fn started(&self, model: &str, total: usize) {
+        self.runs.update(self.id, |status| {
+            status.model = Some(model.to_string());
+            status.total = Some(total);
+        });
+    }
+
+    pub fn result(&self, result: &TestResult) {
+        let line = serde_json::to_string(&ResultLine::from(result)).unwrap_or_default();
+        self.runs.update(self.id, |status| {
+            status.done += 1;
+            if result.passed() { status.passed += 1 } else { status.failed += 1 }
+            status.events.push(line);
+        });
+    }
+}
+
+/// What every connection shares: the runs started over HTTP, where to send a new one when runs may be started, and
+/// the SERVE_TOKEN starting one takes.
+struct Api {
+    runs: Arc<Runs>,
+    start: Option<mpsc::UnboundedSender<(usize, RunRequest)>>,
+    token: Option<String>,
+}
+
+pub(crate) const STYLE: &str = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:4px 8px;text-align:left}pre{background:#f4f4f4;padding:8px;white-space:pre-wrap}.Failed{color:#b00}.Passed{color:#070}";
+
+/// Serves the runs in `results_dir` read-only over HTTP until the process is stopped:
+///
+/// - `/` lists the runs, newest first
+/// - `/runs/<run>` is one run's results table
+/// - `/runs/<run>/<row>` is one test with its input, expected and generated output and the judge's reason
+/// - `/api/stats` is the stats file of the newest run, `/api/stats/<run>` that of any run
+/// - `/api/results/<run>` is a run's results as a JSON array of NDJSON report lines, `/api/results/<run>/csv` its
+///   results CSV
+///
+/// A run is a results CSV, named by its file stem. Only runs listed in the directory are served, so paths cannot
+/// escape it.
+///
+/// With `start`, runs can also be started, one at a time, and followed:
+///
+/// - `POST /api/runs` starts one with the settings a [`RunRequest`] body changes, answering `202` with its id, or
+///   `409` while another runs; with SERVE_TOKEN set it takes `Authorization: Bearer <token>`
+/// - `/api/runs` lists the runs started since the server started, `/api/runs/<id>` is one run's status
+/// - `/api/runs/<id>/events` streams the run's results as server-sent `result` events, then a `done` event
+///
+/// `start` returns the run's name. Its future is driven here rather than spawned, as a run is not `Send`.
+pub async fn serve<F: Future<Output = Result<String, AppError>>>(results_dir: &str, addr: SocketAddr, start: Option<impl Fn(RunRequest, Progress) -> F>) -> Result<(), AppError> {
+    let listener = TcpListener::bind(addr).await.map_err(AppError::io(format!("--serve {}", addr)))?;
+    println!("Serving {} on http://{}", results_dir, addr);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let api = Arc::new(Api { runs: Arc::default(), start: start.is_some().then_some(tx), token: env::var("SERVE_TOKEN").ok().filter(|t| !t.is_empty()) });
+    let app = router(PathBuf::from(results_dir), api.clone());
+    let mut running: Option<(usize, Pin<Box<F>>)> = None;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else { continue };
+                tokio::spawn(connection(stream, app.clone(), READ_TIMEOUT));
+            }
+            Some((id, request)) = rx.recv(), if running.is_none() => {
+                if let Some(start) = &start {
+                    api.runs.update(id, |status| status.state = RunState::Running);
+                    running = Some((id, Box::pin(start(request, Progress { runs: api.runs.clone(), id }))));
+                }
+            }
+            finished = async { running.as_mut().expect("guarded by the condition").1.as_mut().await }, if running.is_some() => {
+                let (id, _) = running.take().expect("guarded by the condition");
+                if let Err(e) = &finished {
+                    eprintln!("{}", e);
+                }
+                api.runs.update(id, |status| match finished {
+                    Ok(run) => {
+                        status.state = RunState::Finished;
+                        status.run = Some(run);
+                    }
+                    Err(e) => {
+                        status.state = RunState::Failed;
+                        status.error = Some(e.to_string());
+                    }
+                });
+            }
+            // A run handles Ctrl+C itself, so it ends with its results written; the next one stops the server.
+            _ = tokio::signal::ctrl_c(), if running.is_none() => return Ok(()),
+        }
+    }
+}
+
+/// Serves one connection with `app`: a single request, as the UI and the API need no keep-alive, whose head has to
+/// arrive within `read_timeout`.
+async fn connection(stream: TcpStream, app: Router, read_timeout: Duration) {
+    let served = hyper::server::conn::http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(read_timeout)
+        .keep_alive(false)
+        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app))
+        .await;
+    match served {
+        Err(e) if !e.is_timeout() && !e.is_incomplete_message() => eprintln!("--serve: {}", e),
+        _ => {}
+    }
+}
+
+/// What the handlers share: RESULTS_DIR and the runs started over HTTP.
+#[derive(Clone)]
+struct Served {
+    dir: PathBuf,
+    api: Arc<Api>,
+    read_timeout: Duration,
+}
+
+fn router(dir: PathBuf, api: Arc<Api>) -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/runs/{run}", get(run_page))
+        .route("/runs/{run}/{row}", get(test_page))
+        .route("/api/stats", get(latest_stats))
+        .route("/api/stats/{run}", get(stats))
+        .route("/api/results/{run}", get(results_json))
+        .route("/api/results/{run}/csv", get(results_csv))
+        .route("/api/runs", get(list_runs).post(start))
+        .route("/api/runs/{id}", get(run_status))
+        .route("/api/runs/{id}/events", get(events))
+        .fallback(|| async { not_found() })
+        .with_state(Served { dir, api, read_timeout: READ_TIMEOUT })
+}
+
+type Response = axum::response::Response;
+
+fn html(title: &str, body: String) -> Response {
+    let body = format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head><body>{}</body></html>", escape(title), STYLE, body);
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+fn text(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response()
+}
+
+fn not_found() -> Response {
+    text(StatusCode::NOT_FOUND, "404 Not Found".to_string())
+}
+
+/// The run named `run` in RESULTS_DIR, when it is one; only listed runs are served, so a name cannot escape it.
+fn listed(dir: &Path, run: &str) -> Option<String> {
+    report::runs(dir).into_iter().find(|r| r == run)
+}
+
+/// `result`, or the error it failed with as a `500`.
+fn or_error(result: Result<Response, AppError>) -> Response {
+    result.unwrap_or_else(|e| text(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn index(State(served): State<Served>) -> Response {
+    let runs = report::runs(&served.dir);
+    let mut body = String::from("<h1>Runs</h1>");
+    if runs.is_empty() {
+        body.push_str("<p>No results in RESULTS_DIR yet.</p>");
+    }
+    body.push_str("<ul>");
+    for run in &runs {
+        body.push_str(&format!("<li><a href=\"/runs/{}\">{}</a> (<a href=\"/api/stats/{0}\">stats</a>)</li>", encode(run), escape(run)));
+    }
+    body.push_str("</ul>");
+    html("Runs", body)
+}
+
+async fn run_page(State(served): State<Served>, extract::Path(run): extract::Path<String>) -> Response {
+    let Some(run) = listed(&served.dir, &run) else { return not_found() };
+    or_error(results(&served.dir, &run).map(|results| {
+        let passed = results.iter().filter(|r| r.passed()).count();
+        let mut body = format!("<p><a href=\"/\">All runs</a></p><h1>{}</h1><p>{} passed, {} failed</p>", escape(&run), passed, results.len() - passed);
+        body.push_str("<table><tr><th>Test</th><th>Status</th><th>Failed at</th><th>Error</th><th>Duration ms</th></tr>");
+        for (row, result) in results.iter().enumerate() {
+            body.push_str(&format!(
+                "<tr><td><a href=\"/runs/{}/{}\">{}</a></td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                encode(&run), row, escape(&result.name), status_class(result.status), status_label(result.status),
+                result.location.map_or("", ErrorLocation::name),
+                escape(result.error.as_deref().and_then(|e| e.lines().next()).unwrap_or("")),
+                result.duration_ms,
+            ));
+        }
+        body.push_str("</table>");
+        html(&run, body)
+    }))
+}
+
+async fn test_page(State(served): State<Served>, extract::Path((run, row)): extract::Path<(String, String)>) -> Response {
+    let Some(run) = listed(&served.dir, &run) else { return not_found() };
+    let results = match results(&served.dir, &run) {
+        Ok(results) => results,
+        Err(e) => return or_error(Err(e)),
+    };
+    let Some(result) = row.parse::<usize>().ok().and_then(|row| results.get(row)) else { return not_found() };
+    let mut body = format!("<p><a href=\"/runs/{}\">{}</a></p><h1>{}</h1>", encode(&run), escape(&run), escape(&result.name));
+    body.push_str(&format!("<p class=\"{}\">{}", status_class(result.status), status_label(result.status)));
+    if let Some(location) = result.location {
+        body.push_str(&format!(" at <code>{}</code>", location));
+    }
+    body.push_str("</p>");
+    if let Some(error) = &result.error {
+        body.push_str(&format!("<h2>Error</h2><pre>{}</pre>", escape(error)));
+    }
+    if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+        body.push_str(&format!("<h2>Judge reason</h2><p>{}</p>", escape(reason)));
+    }
+    match TestCase::parse(&result.name, &result.input) {
+        Ok(case) => {
+            body.push_str(&format!("<h2>Input</h2><pre>{}</pre>", escape(case.input.trim())));
+            body.push_str(&format!("<h2>Baseline</h2><pre>{}</pre>", escape(case.expected_output.trim())));
+        }
+        Err(_) => body.push_str(&format!("<h2>Test file</h2><pre>{}</pre>", escape(result.input.trim()))),
+    }
+    body.push_str(&format!("<h2>Generated</h2><pre>{}</pre>", escape(result.output.trim())));
+    html(&result.name, body)
+}
+
+/// `/api/stats`: the stats file of the newest run.
+async fn latest_stats(State(served): State<Served>) -> Response {
+    report::runs(&served.dir).first().and_then(|run| stats_file(&served.dir, run)).unwrap_or_else(not_found)
+}
+
+async fn stats(State(served): State<Served>, extract::Path(run): extract::Path<String>) -> Response {
+    listed(&served.dir, &run).and_then(|run| stats_file(&served.dir, &run)).unwrap_or_else(not_found)
+}
+
+async fn results_json(State(served): State<Served>, extract::Path(run): extract::Path<String>) -> Response {
+    let Some(run) = listed(&served.dir, &run) else { return not_found() };
+    or_error(results(&served.dir, &run).map(|results| Json(results.iter().map(ResultLine::from).collect::<Vec<_>>()).into_response()))
+}
+
+async fn results_csv(State(served): State<Served>, extract::Path(run): extract::Path<String>) -> Response {
+    listed(&served.dir, &run).and_then(|run| fs::read_to_string(served.dir.join(format!("{}.csv", run))).ok())
+        .map_or_else(not_found, |body| ([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response())
+}
+
+async fn list_runs(State(served): State<Served>) -> Response {
+    Json(served.api.runs.runs.lock().expect("run status lock").clone()).into_response()
+}
+
+async fn run_status(State(served): State<Served>, extract::Path(id): extract::Path<String>) -> Response {
+    id.parse().ok().and_then(|id| served.api.runs.get(id)).map_or_else(not_found, |status| Json(status).into_response())
+}
+
+/// `POST /api/runs`: queues the run the body asks for, unless one is queued or running already.
+async fn start(State(served): State<Served>, headers: HeaderMap, body: Body) -> Response {
+    let api = &served.api;
+    let Some(tx) = &api.start else {
+        return not_found();
+    };
+    if let Some(token) = &api.token {
+        let authorization = headers.get(header::AUTHORIZATION).and_then(|a| a.to_str().ok());
+        if authorization.and_then(|a| a.strip_prefix("Bearer ")) != Some(token.as_str()) {
+            return text(StatusCode::UNAUTHORIZED, "401 Unauthorized".to_string());
+        }
+    }
+    let body = match tokio::time::timeout(served.read_timeout, axum::body::to_bytes(body, MAX_BODY)).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(_)) => return text(StatusCode::PAYLOAD_TOO_LARGE, format!("the run request is over {} bytes", MAX_BODY)),
+        Err(_) => return text(StatusCode::REQUEST_TIMEOUT, "408 Request Timeout".to_string()),
+    };
+    let request: RunRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        RunRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => return text(StatusCode::BAD_REQUEST, format!("the run request is not valid: {}", e)),
+        }
+    };
+    let mut runs = api.runs.runs.lock().expect("run status lock");
+    if let Some(busy) = runs.iter().find(|r| matches!(r.state, RunState::Queued | RunState::Running)) {
+        return (StatusCode::CONFLICT, Json(serde_json::json!({ "error": "a run is in progress", "id": busy.id }))).into_response();
+    }
+    let id = runs.len();
+    runs.push(RunStatus { id, state: RunState::Queued, model: None, total: None, done: 0, passed: 0, failed: 0, run: None, error: None, events: Vec::new() });
+    if tx.send((id, request)).is_err() {
+        return text(StatusCode::SERVICE_UNAVAILABLE, "503 Service Unavailable".to_string());
+    }
+    (StatusCode::ACCEPTED, Json(serde_json::json!({ "id": id }))).into_response()
+}
+
+/// `/api/runs/<id>/events`: every result so far, then each one as it finishes, until the run ends.
+async fn events(State(served): State<Served>, extract::Path(id): extract::Path<String>) -> Response {
+    let Some(id) = id.parse().ok().filter(|id| served.api.runs.get(*id).is_some()) else { return not_found() };
+    let stream = futures::stream::unfold((served.api.runs.clone(), 0, false), move |(runs, sent, done)| async move {
+        if done {
+            return None;
+        }
+        loop {
+            // Listen before looking, so a result that comes in between is not missed.
+            let notify = runs.clone();
+            let changed = notify.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            let status = runs.get(id)?;
+            if let Some(line) = status.events.get(sent) {
+                return Some((Event::default().event("result").data(line), (runs, sent + 1, false)));
+            }
+            if matches!(status.state, RunState::Finished | RunState::Failed) {
+                let status = serde_json::to_string(&status).unwrap_or_default();
+                return Some((Event::default().event("done").data(status), (runs, sent, true)));
+            }
+            changed.await;
+        }
+    });
+    Sse::new(stream.map(Ok::<_, Infallible>)).into_response()
+}
+
+/// A run's results, from its raw copy when the CSV was sanitized.
+fn results(dir: &Path, run: &str) -> Result<Vec<TestResult>, AppError> {
+    let raw = dir.join(format!("{}.raw.csv", run));
+    let path = if raw.exists() { raw } else { dir.join(format!("{}.csv", run)) };
+    TestResult::read(&path.display().to_string())
+}
+
+/// A run's stats file; a reviewed CSV shares the stats of the run it was reviewed from.
+fn stats_file(dir: &Path, run: &str) -> Option<Response> {
+    let body = fs::read_to_string(dir.join(format!("{}.stats.json", run.strip_suffix(".reviewed").unwrap_or(run)))).ok()?;
+    Some(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+pub(crate) fn status_class(status: Status) -> &'static str {
+    if matches!(status, Status::Passed | Status::Human | Status::ExpectedFailure | Status::UnexpectedPass) { "Passed" } else { "Failed" }
+}
+
+pub(crate) fn status_label(status: Status) -> &'static str {
+    match status {
+        Status::Passed => "Passed",
+        Status::Human => "Passed (human)",
+        Status::Failed => "Failed",
+        Status::Missing => "Missing generation",
+        Status::Skipped => "Skipped",
+        Status::ExpectedFailure => "Expected failure",
+        Status::UnexpectedPass => "Unexpected pass",
+        Status::Budget => "Skipped (budget)",
+        Status::Stopped => "Skipped (max failures)",
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Percent-encodes a run name for use as a path segment.
+pub(crate) fn encode(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+
+    fn api(token: Option<&str>) -> (Arc<Api>, mpsc::UnboundedReceiver<(usize, RunRequest)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Arc::new(Api { runs: Arc::default(), start: Some(tx), token: token.map(str::to_string) }), rx)
+    }
+
+    async fn request(api: &Arc<Api>, request: Request<Body>) -> (StatusCode, String) {
+        let response = router(std::env::temp_dir(), api.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn get(api: &Arc<Api>, uri: &str) -> (StatusCode, String) {
+        request(api, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    async fn post(api: &Arc<Api>, token: Option<&str>, body: &str) -> (StatusCode, String) {
+        let mut post = Request::post("/api/runs");
+        if let Some(token) = token {
+            post = post.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request(api, post.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    fn finish(api: &Api, id: usize) {
+        api.runs.update(id, |status| {
+            status.state = RunState::Finished;
+            status.run = Some("run".to_string());
+        });
+    }
+
+    #[tokio::test]
+    async fn starting_a_run_queues_it() {
+        let (api, mut rx) = api(None);
+        assert_eq!(post(&api, None, r#"{"model": "m", "dry_run": true}"#).await, (StatusCode::ACCEPTED, r#"{"id":0}"#.to_string()));
+        let (id, request) = rx.try_recv().unwrap();
+        assert_eq!((id, request.model.as_deref(), request.dry_run), (0, Some("m"), Some(true)));
+        assert_eq!(get(&api, "/api/runs/0").await.1, r#"{"id":0,"state":"queued","model":null,"total":null,"done":0,"passed":0,"failed":0,"run":null,"error":null}"#);
+    }
+
+    #[tokio::test]
+    async fn a_second_run_conflicts_until_the_first_ends() {
+        let (api, _rx) = api(None);
+        assert_eq!(post(&api, None, "").await.0, StatusCode::ACCEPTED);
+        assert_eq!(post(&api, None, "").await, (StatusCode::CONFLICT, r#"{"error":"a run is in progress","id":0}"#.to_string()));
+        api.runs.update(0, |status| status.state = RunState::Running);
+        assert_eq!(post(&api, None, "").await.0, StatusCode::CONFLICT);
+        finish(&api, 0);
+        assert_eq!(post(&api, None, "").await, (StatusCode::ACCEPTED, r#"{"id":1}"#.to_string()));
+    }
+
+    #[tokio::test]
+    async fn starting_a_run_takes_the_token() {
+        let (api, mut rx) = api(Some("secret"));
+        assert_eq!(post(&api, None, "").await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(post(&api, Some("wrong"), "").await.0, StatusCode::UNAUTHORIZED);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(post(&api, Some("secret"), "").await.0, StatusCode::ACCEPTED);
+        assert!(rx.try_recv().is_ok());
+        // Reading results takes no token.
+        assert_eq!(get(&api, "/api/runs").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_run_request_is_refused() {
+        let (api, mut rx) = api(None);
+        let (status, body) = post(&api, None, r#"{"modle": "m"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with("the run request is not valid: unknown field `modle`"), "{}", body);
+        assert_eq!(post(&api, None, &" ".repeat(MAX_BODY + 1)).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(get(&api, "/api/runs").await.1, "[]");
+    }
+
+    #[tokio::test]
+    async fn runs_cannot_be_started_without_a_runner() {
+        let api = Arc::new(Api { runs: Arc::default(), start: None, token: None });
+        assert_eq!(post(&api, None, "").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn status_follows_the_run() {
+        let (api, _rx) = api(None);
+        assert_eq!(get(&api, "/api/runs/0").await.0, StatusCode::NOT_FOUND);
+        post(&api, None, "").await;
+        let progress = Progress { runs: api.runs.clone(), id: 0 };
+        progress.started("m", 2);
+        let status: serde_json::Value = serde_json::from_str(&get(&api, "/api/runs/0").await.1).unwrap();
+        assert_eq!((&status["model"], &status["total"], &status["done"]), (&"m".into(), &2.into(), &0.into()));
+        finish(&api, 0);
+        let status: serde_json::Value = serde_json::from_str(&get(&api, "/api/runs/0").await.1).unwrap();
+        assert_eq!((&status["state"], &status["run"]), (&"finished".into(), &"run".into()));
+        assert_eq!(get(&api, "/api/runs/x").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn events_stream_the_results_then_done() {
+        let (api, _rx) = api(None);
+        post(&api, None, "").await;
+        api.runs.update(0, |status| status.events.push(r#"{"name":"a.txt"}"#.to_string()));
+        let later = api.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            later.runs.update(0, |status| status.events.push(r#"{"name":"b.txt"}"#.to_string()));
+            finish(&later, 0);
+        });
+        let (status, body) = get(&api, "/api/runs/0/events").await;
+        assert_eq!(status, StatusCode::OK);
+        let events: Vec<&str> = body.split("\n\n").filter(|e| !e.is_empty()).collect();
+        assert_eq!(events[..2], ["event: result\ndata: {\"name\":\"a.txt\"}", "event: result\ndata: {\"name\":\"b.txt\"}"]);
+        assert_eq!(events.len(), 3);
+        assert!(events[2].starts_with("event: done\ndata: {\"id\":0,\"state\":\"finished\""), "{}", events[2]);
+        assert_eq!(get(&api, "/api/runs/1/events").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_runs_are_not_found() {
+        let (api, _rx) = api(None);
+        for uri in ["/nope", "/runs/no%20such%20run", "/api/results/no%20such%20run", "/api/stats/..%2F..%2Fetc"] {
+            assert_eq!(get(&api, uri).await.0, StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_idle_connection_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (api, _rx) = api(None);
+        let app = router(std::env::temp_dir(), api);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            connection(stream, app, Duration::from_millis(100)).await;
+        });
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut rest = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest)).await;
+        assert!(read.is_ok(), "the connection was left open");
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fs, path::{Path, PathBuf}};
+use crate::{baseline, error::AppError, is_case, ErrorLocation, TestResult};
+
+/// Extension of a pending snapshot, added to the test's file name.
+pub const EXTENSION: &str = "new";
+
+/// With SNAPSHOTS on, a test that failed only at the comparison has its generation written next to it as
+/// `<test>.new`, for `--accept` or `--reject`. Any other result removes a stale snapshot of the test. A case of a structured test
+/// file has no snapshot.
+pub fn write(test_dir: &str, result: &TestResult) -> Result<(), AppError> {
+    if is_case(&result.name) {
+        return Ok(());
+    }
+    let path = snapshot_path(test_dir, &result.name);
+    if result.location == Some(ErrorLocation::Test) {
+        propose(test_dir, &result.name, &result.output)?;
+    } else if path.exists() {
+        fs::remove_file(&path).map_err(AppError::io(path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+/// Writes `output` as the pending snapshot of the test `name`, over any earlier one.
+pub fn propose(test_dir: &str, name: &str, output: &str) -> Result<(), AppError> {
+    let path = snapshot_path(test_dir, name);
+    fs::write(&path, output).map_err(AppError::io(path.display().to_string()))
+}
+
+/// The names of the tests with a pending snapshot, in order.
+pub fn pending(test_dir: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    pending_in(Path::new(test_dir), "", EXTENSION, &mut names);
+    names.sort();
+    names
+}
+
+/// Adds the tests in `dir` with a file named after them with `extension` added, a pending snapshot or a draft, and
+/// those in its subdirectories, their names starting with `prefix`.
+pub(crate) fn pending_in(dir: &Path, prefix: &str, extension: &str, names: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else { continue };
+        if entry.file_type().is_ok_and(|t| t.is_dir()) && !file_name.starts_with('.') {
+            pending_in(&entry.path(), &format!("{}{}/", prefix, file_name), extension, names);
+        } else if let Some(name) = file_name.strip_suffix(&format!(".{}", extension)) {
+            names.push(format!("{}{}", prefix, name));
+        }
+    }
+}
+
+/// Promotes the pending snapshots of the tests whose name contains `filter`, or of all tests, into their `<output>`
+/// sections and deletes them. Returns the tests that were updated.
+pub fn accept(test_dir: &str, filter: Option<&str>) -> Result<Vec<String>, AppError> {
+    let mut accepted = Vec::new();
+    for name in matching(test_dir, filter) {
+        let snapshot = snapshot_path(test_dir, &name);
+        let test = Path::new(test_dir).join(&name);
+        let output = fs::read_to_string(&snapshot).map_err(AppError::io(snapshot.display().to_string()))?;
+        let contents = fs::read_to_string(&test).map_err(AppError::io(test.display().to_string()))?;
+        let (_, updated) = baseline::replace_output(&contents, &output).ok_or_else(|| format!("{} has no <output> section", test.display()))?;
+        fs::write(&test, updated).map_err(AppError::io(test.display().to_string()))?;
+        fs::remove_file(&snapshot).map_err(AppError::io(snapshot.display().to_string()))?;
+        accepted.push(name);
+    }
+    Ok(accepted)
+}
+
+/// Deletes the pending snapshots of the tests whose name contains `filter`, or of all tests. Returns the tests whose
+/// snapshot was deleted.
+pub fn reject(test_dir: &str, filter: Option<&str>) -> Result<Vec<String>, AppError> {
+    let rejected = matching(test_dir, filter);
+    for name in &rejected {
+        let snapshot = snapshot_path(test_dir, name);
+        fs::remove_file(&snapshot).map_err(AppError::io(snapshot.display().to_string()))?;
+    }
+    Ok(rejected)
+}
+
+pub fn is_snapshot(file_name: &str) -> bool {
+    Path::new(file_name).extension().is_some_and(|e| e == EXTENSION)
+}
+
+fn matching(test_dir: &str, filter: Option<&str>) -> Vec<String> {
+    pending(test_dir).into_iter().filter(|name| filter.is_none_or(|f| name.contains(f))).collect()
+}
+
+fn snapshot_path(test_dir: &str, name: &str) -> PathBuf {
+    Path::new(test_dir).join(format!("{}.{}", name, EXTENSION))
+}
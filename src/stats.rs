@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use crate::{Status, TestResult};
+
+/// The z value of a two-sided 95% interval.
+const Z95: f64 = 1.959_964;
+/// Resamples of the bootstrap; enough for its p-value and interval to be steady to about a percent.
+const RESAMPLES: usize = 10_000;
+/// The bootstrap's seed, fixed so that comparing the same two runs always gives the same numbers.
+const SEED: u64 = 0x5eed;
+/// The p-value below which a difference is reported as significant.
+pub const ALPHA: f64 = 0.05;
+
+/// A test's share of passes, the number the significance tests compare: its attempt pass rate with PASS_AT_K, else 1
+/// or 0, and 1 when the failure was accepted in review. Tests the summary leaves out of the pass rate, skipped,
+/// quarantined or `<xfail>` ones and those a run did not get to, have none.
+pub fn score(result: &TestResult) -> Option<f64> {
+    if result.quarantined {
+        return None;
+    }
+    match result.status {
+        Status::Human => Some(1.0),
+        Status::Passed | Status::Failed | Status::Missing => Some(result.attempt_pass_rate.unwrap_or(if result.passed() { 1.0 } else { 0.0 })),
+        _ => None,
+    }
+}
+
+/// The scores of a run's tests, by name.
+pub fn scores(results: &[TestResult]) -> BTreeMap<String, f64> {
+    results.iter().filter_map(|r| score(r).map(|s| (r.name.clone(), s))).collect()
+}
+
+/// The Wilson score 95% interval of `passed` out of `total`, which unlike the normal approximation stays inside 0 to 1
+/// and is sound for the few dozen tests of a typical suite.
+pub fn wilson(passed: f64, total: f64) -> (f64, f64) {
+    if total == 0.0 {
+        return (0.0, 1.0);
+    }
+    let p = passed / total;
+    let z2 = Z95 * Z95;
+    let centre = (p + z2 / (2.0 * total)) / (1.0 + z2 / total);
+    let half = Z95 * (p * (1.0 - p) / total + z2 / (4.0 * total * total)).sqrt() / (1.0 + z2 / total);
+    ((centre - half).max(0.0), (centre + half).min(1.0))
+}
+
+/// The exact two-sided McNemar p-value of the discordant pairs: `b` tests that only passed in the first run and `c`
+/// that only passed in the second. Tests that passed or failed in both say nothing about which run is better.
+pub fn mcnemar(b: usize, c: usize) -> f64 {
+    let n = b + c;
+    if n == 0 {
+        return 1.0;
+    }
+    // The binomial probabilities of 0..=min(b, c) under p = 1/2, built up term by term to stay in range.
+    let mut term = 0.5f64.powi(n as i32);
+    let mut tail = 0.0;
+    for i in 0..=b.min(c) {
+        tail += term;
+        term *= (n - i) as f64 / (i + 1) as f64;
+    }
+    (2.0 * tail).min(1.0)
+}
+
+/// Two runs compared over the tests both have a score for.
+pub struct Significance {
+    /// How many tests both runs scored.
+    pub tests: usize,
+    /// The mean score of each run and its 95% interval.
+    pub old: (f64, (f64, f64)),
+    pub new: (f64, (f64, f64)),
+    /// The new run's mean minus the old one's, with its 95% bootstrap interval.
+    pub difference: f64,
+    pub interval: (f64, f64),
+    /// Tests that passed only in the old run, and only in the new one; with PASS_AT_K a test passes with at least half of
+    /// its attempts.
+    pub only_old: usize,
+    pub only_new: usize,
+    pub mcnemar: f64,
+    pub bootstrap: f64,
+}
+
+impl Significance {
+    /// Both runs over their common tests, or none when they have none.
+    pub fn new(old: &BTreeMap<String, f64>, new: &BTreeMap<String, f64>) -> Option<Significance> {
+        let pairs: Vec<(f64, f64)> = old.iter().filter_map(|(name, o)| new.get(name).map(|n| (*o, *n))).collect();
+        if pairs.is_empty() {
+            return None;
+        }
+        let tests = pairs.len() as f64;
+        let (old_sum, new_sum) = (pairs.iter().map(|p| p.0).sum::<f64>(), pairs.iter().map(|p| p.1).sum::<f64>());
+        let only_old = pairs.iter().filter(|(o, n)| *o >= 0.5 && *n < 0.5).count();
+        let only_new = pairs.iter().filter(|(o, n)| *o < 0.5 && *n >= 0.5).count();
+        let differences: Vec<f64> = pairs.iter().map(|(o, n)| n - o).collect();
+        let (interval, bootstrap) = bootstrap(&differences);
+        Some(Significance {
+            tests: pairs.len(),
+            old: (old_sum / tests, wilson(old_sum, tests)),
+            new: (new_sum / tests, wilson(new_sum, tests)),
+            difference: (new_sum - old_sum) / tests,
+            interval,
+            only_old,
+            only_new,
+            mcnemar: mcnemar(only_old, only_new),
+            bootstrap,
+        })
+    }
+
+    /// Whether the difference is unlikely to be noise: McNemar's test of the tests that flipped and the bootstrap of the
+    /// scores both below ALPHA.
+    pub fn significant(&self) -> bool {
+        self.mcnemar < ALPHA && self.bootstrap < ALPHA
+    }
+
+    /// The comparison on one line, such as `82.0% [71.3%, 89.4%] -> 86.0% [75.9%, 92.3%], +4.0 points (95% CI -2.0
+    /// to +10.0) over 50 tests; McNemar p = 0.375, bootstrap p = 0.312: likely noise`.
+    pub fn line(&self) -> String {
+        let rate = |(mean, (low, high)): (f64, (f64, f64))| format!("{:.1}% [{:.1}%, {:.1}%]", mean * 100.0, low * 100.0, high * 100.0);
+        let p = |p: f64| if p < 0.001 { "< 0.001".to_string() } else { format!("= {:.3}", p) };
+        format!(
+            "{} -> {}, {:+.1} points (95% CI {:+.1} to {:+.1}) over {} tests; McNemar p {}, bootstrap p {}: {}",
+            rate(self.old), rate(self.new), self.difference * 100.0, self.interval.0 * 100.0, self.interval.1 * 100.0, self.tests,
+            p(self.mcnemar), p(self.bootstrap), if self.significant() { "significant" } else { "likely noise" },
+        )
+    }
+}
+
+/// The paired bootstrap of the mean of the per-test differences: its 95% percentile interval and the two-sided p-value
+/// of no difference, twice the share of resampled means on the far side of 0.
+fn bootstrap(differences: &[f64]) -> ((f64, f64), f64) {
+    if differences.iter().all(|d| *d == 0.0) {
+        return ((0.0, 0.0), 1.0);
+    }
+    let mut rng = fastrand::Rng::with_seed(SEED);
+    let n = differences.len();
+    let mut means: Vec<f64> = (0..RESAMPLES).map(|_| (0..n).map(|_| differences[rng.usize(..n)]).sum::<f64>() / n as f64).collect();
+    means.sort_by(f64::total_cmp);
+    let interval = (means[RESAMPLES * 25 / 1000], means[RESAMPLES * 975 / 1000 - 1]);
+    let below = means.iter().filter(|m| **m <= 0.0).count() as f64 / RESAMPLES as f64;
+    let above = means.iter().filter(|m| **m >= 0.0).count() as f64 / RESAMPLES as f64;
+    (interval, (2.0 * below.min(above)).min(1.0))
+}
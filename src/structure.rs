@@ -0,0 +1,647 @@
+use std::{cell::Cell, fmt, fs, path::Path, rc::Rc, str::FromStr, time::{Duration, Instant}};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, MultiValue, StdLib, Table, Value, VmState};
+use jsonschema::Validator;
+use inline_colorization::*;
+use crate::{artifacts, config::RunConfig, error::AppError, format::Format, output::Verbosity, plugin, report::TestResult, resolve, test_case::TestCase, ErrorLocation};
+
+/// What STRUCTURE_TEST's `test` receives: the raw candidate string, or the candidate parsed into a Lua table followed by
+/// the raw string.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LuaInput {
+    String,
+    Table,
+}
+
+impl FromStr for LuaInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(LuaInput::String),
+            "table" => Ok(LuaInput::Table),
+            other => Err(format!("unknown Lua input {:?}, expected string or table", other)),
+        }
+    }
+}
+
+/// Reference for the helpers available to structure tests, printed by `--help`.
+pub const LUA_API: &str = "\
+Lua helpers available to STRUCTURE_TEST:
+  json.decode(s)     parse a JSON string into Lua values, raising an error that names the position on invalid JSON
+  json.encode(v)     serialize a Lua value to a JSON string
+  json.null          the value JSON null decodes to
+  deep_equal(a, b)   structural equality of two values, comparing tables key by key
+  typeof(v)          like type(), but tells \"null\", \"array\" and \"object\" apart
+  fail(msg)          fail the test with msg as the reason, from anywhere in the test
+  assert_type(v, t)  fail unless typeof(v) is t, where an integer is also a \"number\"; returns v
+  assert_field(v, p) fail unless the dotted path p, such as \"items.1.id\", leads to a value in v; returns it, and
+                     checks its type with assert_type when given a type as a third argument
+
+Suite hooks, defined in HOOKS_SCRIPT or STRUCTURE_TEST:
+  before_all(config) called once before any test; its JSON-compatible result is ctx.shared in every test
+  after_all(summary) called once after the run with the totals and a tests list of every outcome";
+
+const LUA_HELPERS: &str = r#"
+function deep_equal(a, b)
+    if a == b then return true end
+    if type(a) ~= "table" or type(b) ~= "table" then return false end
+    for k, v in pairs(a) do
+        if not deep_equal(v, b[k]) then return false end
+    end
+    for k in pairs(b) do
+        if a[k] == nil then return false end
+    end
+    return true
+end
+
+local function describe(v)
+    if typeof(v) == "string" then return string.format("%q", v) end
+    return typeof(v)
+end
+
+function assert_type(v, t, label)
+    local actual = typeof(v)
+    if actual ~= t and not (t == "number" and actual == "integer") then
+        fail(string.format("%s is %s, expected %s", label or "value", describe(v), t))
+    end
+    return v
+end
+
+function assert_field(v, path, t)
+    local current = v
+    for key in string.gmatch(path, "[^.]+") do
+        if type(current) ~= "table" then fail(string.format("missing field '%s'", path)) end
+        local next = current[key]
+        if next == nil then next = current[tonumber(key)] end
+        if next == nil then fail(string.format("missing field '%s'", path)) end
+        current = next
+    end
+    if t ~= nil then assert_type(current, t, string.format("field '%s'", path)) end
+    return current
+end
+"#;
+
+/// Replaces the libraries and loaders a sandboxed script must not reach with stand-ins that raise a clear error.
+const SANDBOX: &str = r#"
+local hint = " is not available in the structure test sandbox (SANDBOX=off lifts the restriction)"
+for _, lib in ipairs({"os", "io", "package", "debug"}) do
+    _G[lib] = setmetatable({}, {__index = function(_, key) error(lib .. "." .. tostring(key) .. hint, 2) end})
+end
+for _, name in ipairs({"load", "loadfile", "dofile", "require", "collectgarbage"}) do
+    _G[name] = function() error(name .. hint, 2) end
+end
+string.dump = function() error("string.dump" .. hint, 2) end
+"#;
+
+/// A Lua state for structure tests, limited to the string, table, math and utf8 libraries plus the helpers while
+/// SANDBOX is on.
+fn new_state(config: &RunConfig) -> mlua::Result<Lua> {
+    if !config.sandbox {
+        return Ok(Lua::new());
+    }
+    let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8, LuaOptions::default())?;
+    lua.load(SANDBOX).exec()?;
+    Ok(lua)
+}
+
+/// A Lua file: STRUCTURE_TEST or one of the LUA_LIBS loaded before it.
+pub struct LuaSource {
+    pub path: String,
+    pub source: String,
+}
+
+impl LuaSource {
+    /// The global a library's return value is stored under: its file name without extension.
+    fn module_name(&self) -> &str {
+        Path::new(&self.path).file_stem().and_then(|s| s.to_str()).unwrap_or(&self.path)
+    }
+}
+
+/// Loads the LUA_LIBS files in order. Libraries may define globals directly or return a module, which becomes a global
+/// named after the file.
+fn load_libs(lua: &Lua, libs: &[LuaSource]) -> mlua::Result<()> {
+    for lib in libs {
+        let module: Value = lua.load(&lib.source).set_name(format!("@{}", lib.path)).eval()?;
+        if !module.is_nil() {
+            lua.globals().set(lib.module_name(), module)?;
+        }
+    }
+    Ok(())
+}
+
+/// A loaded structure test: the Lua state after running the libraries and STRUCTURE_TEST, and its test functions in
+/// the order they run.
+struct Script {
+    lua: Lua,
+    tests: Vec<(String, Function)>,
+    budget: Rc<Budget>,
+}
+
+/// A Lua state with the helpers, the limits and LUA_LIBS, after running `source`, the file `var` names.
+fn prepare(config: &RunConfig, source: &LuaSource, var: &str) -> Result<(Lua, Rc<Budget>), AppError> {
+    let lua = new_state(config)?;
+    install_helpers(&lua)?;
+    let budget = limit(&lua, &config.lua_limits)?;
+    load_libs(&lua, &config.lua_libs).map_err(AppError::lua("LUA_LIBS failed to load"))?;
+    lua.load(&source.source).set_name(format!("@{}", source.path)).exec()
+        .map_err(AppError::lua(format!("{} failed to load", var)))?;
+    Ok((lua, budget))
+}
+
+/// Loads a structure test, STRUCTURE_TEST or a test's `<structure>` as `var` says.
+fn load(config: &RunConfig, source: &LuaSource, var: &str) -> Result<Script, AppError> {
+    let (lua, budget) = prepare(config, source, var)?;
+    let mut tests: Vec<(String, Function)> = lua.globals().pairs::<Value, Value>()
+        .filter_map(|pair| match pair {
+            Ok((Value::String(name), Value::Function(f))) => name.to_str().ok().filter(|n| n.starts_with("test")).map(|n| (n.to_string(), f)),
+            _ => None,
+        })
+        .collect();
+    if tests.is_empty() {
+        return Err(format!("{} {} defines no test function", var, source.path).into());
+    }
+    tests.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(Script { lua, tests, budget })
+}
+
+/// Loads the libraries and structure test once at startup so a typo stops the run before any API calls, with the file
+/// and line of the problem. With LUA_SMOKE_TEST each test function is also called on `{}`, where returning false is
+/// fine but raising an error is not.
+pub fn verify(config: &RunConfig) -> Result<(), AppError> {
+    if let Some(hooks) = &config.hooks_script {
+        prepare(config, hooks, "HOOKS_SCRIPT")?;
+    }
+    let Some(source) = &config.structure_test else {
+        return Ok(());
+    };
+    let script = load(config, source, "STRUCTURE_TEST")?;
+    if config.lua_smoke_test {
+        let case = TestCase { name: "smoke test", input: "", expected_output: "{}", comparison: None, extract: None, format: None, tags: Vec::new(), history: Vec::new(), tools: Vec::new(), tool_choice: Default::default(), images: Vec::new(), schema: None, validator: None, structure: None, gen_prompt: None, test_prompt: None, system: None, judge_system: None, vars: Default::default(), examples: Vec::new(), params: Default::default(), rubric: None };
+        let value = serde_json::json!({});
+        let ctx = context(&script.lua, "{}", Some(&value), &case, config)?;
+        for (name, test_func) in &script.tests {
+            script.budget.reset();
+            let called = match config.lua_input {
+                LuaInput::String => test_func.call::<MultiValue>(("{}", ctx.clone())),
+                LuaInput::Table => test_func.call::<MultiValue>((script.lua.to_value(&value)?, "{}", ctx.clone())),
+            };
+            called.map_err(AppError::lua(format!("STRUCTURE_TEST smoke test of {} failed", name)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Built afresh for every call, so nothing about one test can leak into the next through the shared state.
+fn context(lua: &Lua, candidate: &str, parsed: Option<&serde_json::Value>, case: &TestCase<'_>, config: &RunConfig) -> mlua::Result<Table> {
+    let ctx = lua.create_table()?;
+    ctx.set("name", case.name)?;
+    ctx.set("tags", lua.create_sequence_from(case.tags.iter().copied())?)?;
+    let meta = lua.create_table()?;
+    meta.set("comparison", case.comparison(config.comparison).to_string())?;
+    meta.set("format", case.format(config).to_string())?;
+    ctx.set("meta", meta)?;
+    ctx.set("input", case.input)?;
+    ctx.set("expected", case.expected_output)?;
+    ctx.set("raw", candidate)?;
+    if let Some(value) = parsed {
+        ctx.set("value", lua.to_value(value)?)?;
+    }
+    if let Some(shared) = &config.shared {
+        ctx.set("shared", lua.to_value(shared)?)?;
+    }
+    Ok(ctx)
+}
+
+/// The state the suite hooks run in, and the variable naming the file it was loaded from.
+struct Hooks {
+    lua: Lua,
+    budget: Rc<Budget>,
+    var: &'static str,
+}
+
+/// The suite hooks from HOOKS_SCRIPT, or else STRUCTURE_TEST.
+fn hooks(config: &RunConfig) -> Result<Option<Hooks>, AppError> {
+    let (source, var) = match (&config.hooks_script, &config.structure_test) {
+        (Some(hooks), _) => (hooks, "HOOKS_SCRIPT"),
+        (None, Some(structure_test)) => (structure_test, "STRUCTURE_TEST"),
+        (None, None) => return Ok(None),
+    };
+    let (lua, budget) = prepare(config, source, var)?;
+    Ok(Some(Hooks { lua, budget, var }))
+}
+
+/// Calls `before_all(config)` once before any test, if the hooks script defines it. What it returns is converted to
+/// JSON and given to every structure test call as `ctx.shared`, so each test gets its own copy. An error here stops the
+/// run before any API call.
+pub fn before_all(config: &RunConfig) -> Result<Option<serde_json::Value>, AppError> {
+    let Some(Hooks { lua, budget, var }) = hooks(config)? else { return Ok(None) };
+    let Ok(hook) = lua.globals().get::<Function>("before_all") else { return Ok(None) };
+    let table = lua.create_table()?;
+    table.set("model", config.model.as_str())?;
+    table.set("judge_models", lua.create_sequence_from(config.judge_models.iter().map(String::as_str))?)?;
+    table.set("test_dir", config.test_dir.as_str())?;
+    table.set("results_dir", config.results_dir.as_str())?;
+    table.set("comparison", config.comparison.to_string())?;
+    table.set("dry_run", config.dry_run)?;
+    budget.reset();
+    let shared: Value = hook.call(table).map_err(AppError::lua(format!("{} before_all failed", var)))?;
+    if shared.is_nil() {
+        return Ok(None);
+    }
+    let shared = lua.from_value(shared).map_err(|e| format!("{} before_all returned a value that is not JSON: {}", var, e))?;
+    Ok(Some(shared))
+}
+
+/// Calls `after_all(summary)` once after the run, if the hooks script defines it, with the totals and a `tests` list of
+/// every result. The caller reports an error; the results stand either way.
+pub fn after_all(config: &RunConfig, results: &[TestResult]) -> Result<(), AppError> {
+    let Some(Hooks { lua, budget, var }) = hooks(config)? else { return Ok(()) };
+    let Ok(hook) = lua.globals().get::<Function>("after_all") else { return Ok(()) };
+    let passed = results.iter().filter(|r| r.passed()).count();
+    let summary = lua.create_table()?;
+    summary.set("total", results.len())?;
+    summary.set("passed", passed)?;
+    summary.set("failed", results.len() - passed)?;
+    let tests = lua.create_table()?;
+    for (i, result) in results.iter().enumerate() {
+        let test = lua.create_table()?;
+        test.set("name", result.name.as_str())?;
+        test.set("passed", result.passed())?;
+        test.set("status", serde_json::to_value(result.status)?.as_str().unwrap_or_default())?;
+        test.set("location", result.location.map(|l| l.name()))?;
+        test.set("error", result.error.as_deref())?;
+        test.set("output", result.output.as_str())?;
+        test.set("score", result.score)?;
+        test.set("duration_ms", result.duration_ms)?;
+        tests.set(i + 1, test)?;
+    }
+    summary.set("tests", tests)?;
+    if let Some(shared) = &config.shared {
+        summary.set("shared", lua.to_value(shared)?)?;
+    }
+    budget.reset();
+    hook.call::<()>(summary).map_err(AppError::lua(format!("{} after_all failed", var)))
+}
+
+/// LUA_MAX_INSTRUCTIONS and LUA_TIMEOUT_MS, enforced from an instruction hook, and LUA_MAX_MEMORY_MB, enforced by the
+/// allocator of the Lua state.
+pub struct Limits {
+    pub max_instructions: u64,
+    pub timeout: Duration,
+    /// In bytes.
+    pub max_memory: usize,
+}
+
+const HOOK_INTERVAL: u32 = 10_000;
+
+pub const MB: usize = 1024 * 1024;
+
+/// Counts towards the limits since the last `reset`, so every call starts with the full budget.
+struct Budget {
+    instructions: Cell<u64>,
+    started: Cell<Instant>,
+}
+
+impl Budget {
+    fn reset(&self) {
+        self.instructions.set(0);
+        self.started.set(Instant::now());
+    }
+}
+
+fn limit(lua: &Lua, limits: &Limits) -> mlua::Result<Rc<Budget>> {
+    lua.set_memory_limit(limits.max_memory)?;
+    let budget = Rc::new(Budget { instructions: Cell::new(0), started: Cell::new(Instant::now()) });
+    let (max_instructions, timeout, hook_budget) = (limits.max_instructions, limits.timeout, budget.clone());
+    lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), move |_, _| {
+        hook_budget.instructions.set(hook_budget.instructions.get() + HOOK_INTERVAL as u64);
+        if hook_budget.instructions.get() > max_instructions {
+            return Err(mlua::Error::external(LimitExceeded(format!("structure test exceeded execution limit of {} instructions", max_instructions))));
+        }
+        if hook_budget.started.get().elapsed() > timeout {
+            return Err(mlua::Error::external(LimitExceeded(format!("structure test exceeded execution limit of {}ms", timeout.as_millis()))));
+        }
+        Ok(VmState::Continue)
+    });
+    Ok(budget)
+}
+
+fn install_helpers(lua: &Lua) -> mlua::Result<()> {
+    let json = lua.create_table()?;
+    json.set("decode", lua.create_function(|lua, s: String| {
+        let value: serde_json::Value = serde_json::from_str(&s).map_err(|e| mlua::Error::runtime(format!("json.decode: {}", e)))?;
+        lua.to_value(&value)
+    })?)?;
+    json.set("encode", lua.create_function(|lua, v: Value| {
+        let value: serde_json::Value = lua.from_value(v).map_err(|e| mlua::Error::runtime(format!("json.encode: {}", e)))?;
+        Ok(value.to_string())
+    })?)?;
+    json.set("null", lua.null())?;
+    lua.globals().set("json", json)?;
+    lua.globals().set("typeof", lua.create_function(|lua, v: Value| Ok(match &v {
+        Value::LightUserData(_) if v == lua.null() => "null",
+        Value::Table(t) if t.metatable() == Some(lua.array_metatable()) || t.raw_len() > 0 => "array",
+        Value::Table(_) => "object",
+        other => other.type_name(),
+    }))?)?;
+    lua.globals().set("fail", lua.create_function(|_, reason: String| Err::<(), _>(mlua::Error::external(Failure(reason))))?)?;
+    lua.load(LUA_HELPERS).exec()
+}
+
+/// What `fail(msg)` raises: a failed test with its reason rather than an error in the script.
+#[derive(Debug)]
+struct Failure(String);
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// What the instruction hook raises when a call runs over LUA_MAX_INSTRUCTIONS or LUA_TIMEOUT_MS.
+#[derive(Debug)]
+struct LimitExceeded(String);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The error a call's error was raised as, through the Lua functions it passed.
+fn root(e: &mlua::Error) -> &mlua::Error {
+    match e {
+        mlua::Error::CallbackError { cause, .. } | mlua::Error::WithContext { cause, .. } => root(cause),
+        e => e,
+    }
+}
+
+/// The reason given to `fail`, when that is what ended the call.
+fn failure(e: &mlua::Error) -> Option<&str> {
+    root(e).downcast_ref::<Failure>().map(|f| f.0.as_str())
+}
+
+/// Why a call was stopped, when it ran over one of the limits.
+fn limit_exceeded(e: &mlua::Error, limits: &Limits) -> Option<String> {
+    match root(e) {
+        mlua::Error::MemoryError(_) => Some(format!("structure test exceeded memory limit of {} MB", limits.max_memory / MB)),
+        e => e.downcast_ref::<LimitExceeded>().map(|l| l.0.clone()),
+    }
+}
+
+/// A call's error as the test's reason: what went to `fail`, or the Lua error.
+fn reason(e: mlua::Error) -> String {
+    failure(&e).map_or_else(|| format!("Lua error: {}", e), str::to_string)
+}
+
+/// The outcome of running the structure tests over a choice's candidates.
+pub enum Structured<'a> {
+    /// The first candidate to pass every test, and what `normalize` turned it into when the script defines one.
+    Passed { candidate: &'a str, normalized: Option<String> },
+    /// No candidate passed; the error explains the last failure, and the location is where it happened.
+    Failed { location: ErrorLocation, err: Option<String> },
+    NormalizeFailed { candidate: &'a str, err: String },
+}
+
+/// Runs the test's `<schema>` or STRUCTURE_SCHEMA and then the structure tests on each candidate in turn and returns the
+/// first that passes all of them, or the error explaining why none did. Every global function whose name starts with
+/// `test` is a structure test; they run in alphabetical order and a candidate stops at the first one that fails.
+pub fn first_passing<'a>(candidates: &[&'a str], case: &TestCase<'_>, config: &RunConfig) -> Result<Structured<'a>, AppError> {
+    let own_schema = match case.schema.map(|path| test_schema(config, case.name, path)).transpose() {
+        Ok(schema) => schema,
+        Err(err) => return Ok(Structured::Failed { location: ErrorLocation::MatchInput, err: Some(err) }),
+    };
+    let schema = own_schema.as_ref().or(config.structure_schema.as_ref());
+    let validator = case.validator.map(|path| resolve(config, case.name, path)).or(config.plugins.validator.clone());
+    let script = match case.structure {
+        Some(path) => match test_script(config, case.name, path) {
+            Ok(script) => Some(script),
+            Err(err) => return Ok(Structured::Failed { location: ErrorLocation::MatchInput, err: Some(err) }),
+        },
+        None => config.structure_test.as_ref().map(|source| load(config, source, "STRUCTURE_TEST")).transpose()?,
+    };
+    let format = case.format(config);
+    let mut location = ErrorLocation::Parse;
+    let mut last_err = None;
+    for candidate in candidates {
+        let parsed = format.parse(candidate);
+        // With CANONICAL_JSON a YAML or XML candidate goes on as the JSON it reads as, and one that does not read fails.
+        let canonical = match &parsed {
+            _ if !config.canonical_json || format == Format::Json => None,
+            Ok(value) => Some(value.to_string()),
+            Err(e) => {
+                location = ErrorLocation::Parse;
+                last_err = Some(format!("candidate is not valid {}: {}", format.title(), e));
+                continue;
+            }
+        };
+        let text = canonical.as_deref().unwrap_or(candidate);
+        if let Some(schema) = schema {
+            let (at, err) = match &parsed {
+                Ok(value) => (ErrorLocation::Schema, schema_errors(schema, value)),
+                Err(e) => (ErrorLocation::Parse, Some(format!("candidate is not valid {}: {}", format.title(), e))),
+            };
+            if err.is_some() {
+                location = at;
+                last_err = err;
+                continue;
+            }
+        }
+        if let Some(validator) = &validator {
+            let err = match plugin::validate(&config.plugins, validator, text) {
+                Ok(None) => None,
+                Ok(Some(reason)) => Some(format!("validator failed: {}", reason)),
+                Err(e) => Some(e),
+            };
+            if err.is_some() {
+                location = ErrorLocation::Parse;
+                last_err = err;
+                continue;
+            }
+        }
+        let Some(script) = &script else {
+            return Ok(Structured::Passed { candidate, normalized: None });
+        };
+        match script.check(text, &parsed, case, config)? {
+            Checked::Passed(normalized) => return Ok(Structured::Passed { candidate, normalized }),
+            Checked::Failed(err) => {
+                location = ErrorLocation::Parse;
+                last_err = err.or(last_err);
+            }
+            Checked::LimitExceeded(err) => {
+                location = ErrorLocation::LuaLimit;
+                last_err = Some(err);
+            }
+            Checked::NormalizeFailed(err) => return Ok(Structured::NormalizeFailed { candidate, err }),
+        }
+    }
+    let err = if candidates.len() == 1 {
+        last_err
+    } else {
+        Some(format!("none of {} candidates passed the structure test{}", candidates.len(), last_err.map(|e| format!("; last error: {}", e)).unwrap_or_default()))
+    };
+    Ok(Structured::Failed { location, err })
+}
+
+/// The Lua file of a test's `<structure>` section, read relative to its test file and loaded like STRUCTURE_TEST.
+fn test_script(config: &RunConfig, name: &str, path: &str) -> Result<Script, String> {
+    let file = resolve(config, name, path);
+    let source = fs::read_to_string(&file).map_err(|e| format!("cannot read structure test {}: {}", file.display(), e))?;
+    load(config, &LuaSource { path: file.display().to_string(), source }, "structure test").map_err(|e| e.to_string())
+}
+
+/// Loads a test's own `<schema>` and `<structure>` and checks that its `<validator>` exists, as its first candidate
+/// would, for `validate`.
+pub fn check(case: &TestCase<'_>, config: &RunConfig) -> Result<(), String> {
+    if let Some(path) = case.schema {
+        test_schema(config, case.name, path)?;
+    }
+    if let Some(path) = case.structure {
+        test_script(config, case.name, path)?;
+    }
+    if let Some(path) = case.validator {
+        let file = resolve(config, case.name, path);
+        if !file.is_file() {
+            return Err(format!("validator {} does not exist", file.display()));
+        }
+    }
+    Ok(())
+}
+
+/// The JSON Schema of a test's `<schema>` section, read relative to its test file.
+fn test_schema(config: &RunConfig, name: &str, path: &str) -> Result<Validator, String> {
+    let file = resolve(config, name, path);
+    let text = fs::read_to_string(&file).map_err(|e| format!("cannot read schema {}: {}", file.display(), e))?;
+    let schema: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("schema {} is not valid JSON: {}", file.display(), e))?;
+    jsonschema::validator_for(&schema).map_err(|e| format!("schema {} is not a valid schema: {}", file.display(), e))
+}
+
+/// The first few schema violations, each with the instance path it was found at.
+fn schema_errors(schema: &Validator, value: &serde_json::Value) -> Option<String> {
+    let errors: Vec<String> = schema.iter_errors(value).take(3).map(|e| {
+        let path = e.instance_path().to_string();
+        format!("{}: {}", if path.is_empty() { "/" } else { &path }, e)
+    }).collect();
+    (!errors.is_empty()).then(|| format!("schema validation failed: {}", errors.join("; ")))
+}
+
+enum Checked {
+    Passed(Option<String>),
+    Failed(Option<String>),
+    LimitExceeded(String),
+    NormalizeFailed(String),
+}
+
+impl Script {
+    fn check(&self, candidate: &str, parsed: &Result<serde_json::Value, String>, case: &TestCase<'_>, config: &RunConfig) -> Result<Checked, AppError> {
+        let Script { lua, tests, budget } = self;
+        let ctx = context(lua, candidate, parsed.as_ref().ok(), case, config)?;
+        let value = match (config.lua_input, parsed) {
+            (LuaInput::String, _) => None,
+            (LuaInput::Table, Ok(value)) => Some(lua.to_value(value)?),
+            (LuaInput::Table, Err(e)) => return Ok(Checked::Failed(Some(format!("candidate is not valid {}: {}", case.format(config).title(), e)))),
+        };
+        for (name, test_func) in tests {
+            budget.reset();
+            let passed = match &value {
+                None => test_func.call::<MultiValue>((candidate, ctx.clone())),
+                Some(value) => test_func.call::<MultiValue>((value.clone(), candidate, ctx.clone())),
+            };
+            if let Some(err) = passed.as_ref().err().and_then(|e| limit_exceeded(e, &config.lua_limits)) {
+                return Ok(Checked::LimitExceeded(if tests.len() > 1 { format!("{}: {}", name, err) } else { err }));
+            }
+            let verdict = match passed {
+                Ok(values) => verdict(lua, values),
+                Err(e) => match failure(&e) {
+                    Some(reason) => Ok(Verdict { ok: false, reason: Some(reason.to_string()), details: None }),
+                    None => Err(format!("Lua error: {}", e)),
+                },
+            };
+            let failure = match verdict {
+                Ok(Verdict { ok: true, .. }) => continue,
+                Ok(Verdict { reason, details, .. }) => {
+                    if let Some(details) = details {
+                        show_details(case, name, &details, config);
+                    }
+                    reason.map(|r| format!("structure test failed: {}", r))
+                }
+                Err(e) => Some(e),
+            };
+            return Ok(Checked::Failed(match (tests.len() > 1, failure) {
+                (true, Some(e)) => Some(format!("{}: {}", name, e)),
+                (true, None) => Some(format!("{} failed", name)),
+                (false, failure) => failure,
+            }));
+        }
+        let Ok(normalize) = lua.globals().get::<Function>("normalize") else {
+            return Ok(Checked::Passed(None));
+        };
+        budget.reset();
+        let normalized = match &value {
+            None => normalize.call::<Value>((candidate, ctx)),
+            Some(value) => normalize.call::<Value>((value.clone(), candidate, ctx)),
+        };
+        Ok(match normalized.map_err(reason).and_then(|v| serialized(lua, v)) {
+            Ok(normalized) => Checked::Passed(Some(normalized)),
+            Err(err) => Checked::NormalizeFailed(err),
+        })
+    }
+}
+
+/// The details of a failed structure test go to the run log and the artifacts, and are printed with VERBOSITY=verbose.
+fn show_details(case: &TestCase<'_>, test: &str, details: &serde_json::Value, config: &RunConfig) {
+    let text = serde_json::to_string_pretty(details).unwrap_or_default();
+    tracing::debug!(test, details = text.as_str(), "structure test details");
+    artifacts::record(&format!("{}_details.json", test), text.as_str());
+    if config.verbosity == Verbosity::Verbose {
+        config.say(&format!("{color_bright_black}[{} {} details]{color_reset} {}", case.name, test, text));
+    }
+}
+
+/// A string from `normalize` is used as it is; tables are serialized back to JSON.
+fn serialized(lua: &Lua, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.to_string_lossy()),
+        Value::Table(_) => lua.from_value::<serde_json::Value>(value).map(|v| v.to_string()).map_err(|e| format!("normalize returned a table that is not JSON: {}", e)),
+        other => Err(format!("normalize returned {}, expected a string or a table", other.type_name())),
+    }
+}
+
+/// What a test function returned: whether the candidate passed, why not, and any details it gave.
+struct Verdict {
+    ok: bool,
+    reason: Option<String>,
+    details: Option<serde_json::Value>,
+}
+
+/// Accepts `ok`, `ok, reason` or a table `{ok = ..., reason = ..., details = ...}` from the test function, where `pass`
+/// and `message` may stand for `ok` and `reason`. Details that are not JSON are kept as their string form.
+fn verdict(lua: &Lua, values: MultiValue) -> Result<Verdict, String> {
+    let mut values = values.into_iter();
+    let reason = |v: Option<Value>| v.and_then(|r| r.as_string_lossy().filter(|_| r.is_string()));
+    match values.next() {
+        Some(Value::Boolean(ok)) => Ok(Verdict { ok, reason: reason(values.next()), details: None }),
+        Some(Value::Table(t)) => {
+            let field = |name: &str, alias: &str| -> Result<Value, String> {
+                match t.get::<Value>(name).map_err(|e| e.to_string())? {
+                    Value::Nil => t.get::<Value>(alias).map_err(|e| e.to_string()),
+                    value => Ok(value),
+                }
+            };
+            let ok = match field("ok", "pass")? {
+                Value::Boolean(ok) => ok,
+                other => return Err(format!("test returned a table whose ok field is {}, expected a boolean", other.type_name())),
+            };
+            let details = match t.get::<Value>("details").map_err(|e| e.to_string())? {
+                Value::Nil => None,
+                value => Some(lua.from_value(value.clone()).unwrap_or_else(|_| serde_json::Value::String(value.to_string().unwrap_or_default()))),
+            };
+            Ok(Verdict { ok, reason: reason(field("reason", "message").ok()), details })
+        }
+        other => Err(format!("test returned {}, expected a boolean, a boolean and a reason, or {{ok = ..., reason = ...}}", other.map_or("nothing", |v| v.type_name()))),
+    }
+}
@@ -0,0 +1,529 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+use inline_colorization::*;
+use serde::Serialize;
+use crate::{error::AppError, report, serve::escape, snapshot, suite, ErrorLocation, RunConfig, SkipKind, Status, SuiteReport, TestResult};
+
+/// Bumped whenever a field of the stats file changes meaning or goes away.
+const SCHEMA_VERSION: u32 = 1;
+
+/// The headline numbers of a run. The console summary is printed from it and it is written next to the results as
+/// `<results>.stats.json` for scripts.
+#[derive(Default, Serialize)]
+pub struct Summary {
+    schema_version: u32,
+    results_file: String,
+    model: String,
+    judge_models: Vec<String>,
+    total: usize,
+    /// Stopped with Ctrl+C; `total` is the tests finished by then.
+    interrupted: bool,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    /// Tests TEST_FILTER, TAGS or EXCLUDE_TAGS left out.
+    not_selected: usize,
+    /// Skipped entries that are FIFOs, sockets or devices.
+    special_files: usize,
+    broken_symlinks: Vec<String>,
+    /// Snapshots in TEST_DIR waiting for `--accept` or `--reject`.
+    pending_snapshots: usize,
+    internal: usize,
+    /// Tests `--phase judge` had no saved generation for.
+    missing: Vec<String>,
+    /// Tests with a `<skip>` section.
+    skipped_tests: Vec<String>,
+    /// Tests with an `<xfail>` section that failed, which count towards neither passed nor failed.
+    expected_failures: Vec<String>,
+    /// Tests with an `<xfail>` section that passed.
+    unexpected_passes: Vec<String>,
+    /// Tests not run because the run reached MAX_COST.
+    budget_skipped: Vec<String>,
+    /// Tests not run because MAX_FAILURES tests had failed.
+    stopped: Vec<String>,
+    /// Tests whose input INPUT_MAX_TOKENS cut down.
+    truncated_inputs: Vec<String>,
+    /// Quarantined tests, which count towards neither passed nor failed.
+    quarantined: Vec<Quarantined>,
+    /// Passed among the passed and failed tests.
+    pass_rate: Option<f64>,
+    failures_by_location: BTreeMap<&'static str, usize>,
+    /// Passed and failed tests by suite, `.` being the tests directly in TEST_DIR; empty when it has no subdirectories.
+    suites: BTreeMap<String, SuiteCount>,
+    /// The slowest tests and their duration in milliseconds, slowest first.
+    slowest: Vec<(String, u64)>,
+    /// The spread of each stage's time over the tests that went through it, and of the tests' whole duration.
+    latency: Vec<Latency>,
+    duration_ms: u64,
+    generation_tokens: u32,
+    judge_tokens: u32,
+    /// From PRICE_TABLE and PRICE_PER_MTOK, when set.
+    estimated_cost: Option<f64>,
+    /// Models used in the run that have no price, which leaves the cost out.
+    unpriced_models: Vec<String>,
+    /// Tests generated by FALLBACK_MODELS, with the models that generated them.
+    fallbacks: Vec<(String, String)>,
+    /// With PASS_AT_K, the pass rates across the tests that made attempts.
+    pass_at_k: Option<PassAtK>,
+    score_mean: Option<f64>,
+    score_median: Option<f64>,
+    /// The mean grade of the passed and failed tests that have one.
+    grade_mean: Option<f64>,
+    #[serde(skip)]
+    scores: Vec<f64>,
+    criterion_failures: Vec<(String, usize)>,
+    split_votes: Vec<(String, usize, usize)>,
+    disagreements: Vec<String>,
+    weak_passes: Vec<(String, f32)>,
+    /// With PASS_AT_K, the tests with both passing and failing attempts and the share that passed.
+    unstable: Vec<(String, f64)>,
+}
+
+#[derive(Serialize)]
+struct Quarantined {
+    name: String,
+    passed: bool,
+    /// Pass (`P`) or fail (`F`) in the earlier runs in RESULTS_DIR, newest first.
+    recent: String,
+}
+
+#[derive(Default, Serialize)]
+struct SuiteCount {
+    passed: usize,
+    failed: usize,
+    grade_mean: Option<f64>,
+    #[serde(skip)]
+    grades: Vec<f64>,
+}
+
+impl fmt::Display for SuiteCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} passed, {} failed", self.passed, self.failed)?;
+        if self.passed + self.failed > 0 {
+            write!(f, ", {:.1}%", self.passed as f64 * 100.0 / (self.passed + self.failed) as f64)?;
+        }
+        if let Some(grade) = self.grade_mean {
+            write!(f, ", mean grade {:.2}", grade)?;
+        }
+        Ok(())
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[derive(Serialize)]
+struct PassAtK {
+    k: u32,
+    tests: usize,
+    /// The share of tests whose first attempt passed.
+    pass_at_1: f64,
+    /// The share of tests with any passing attempt.
+    pass_at_k: f64,
+    /// The share of passing attempts over all the tests' attempts.
+    attempt_pass_rate: f64,
+}
+
+/// The 50th, 95th and 99th percentile of a stage's time in milliseconds, over the tests it has a time for.
+#[derive(Serialize)]
+struct Latency {
+    stage: &'static str,
+    tests: usize,
+    p50: u64,
+    p95: u64,
+    p99: u64,
+}
+
+impl Latency {
+    fn new(stage: &'static str, mut times: Vec<u64>) -> Option<Latency> {
+        if times.is_empty() {
+            return None;
+        }
+        times.sort_unstable();
+        // The nearest rank, so that each percentile is a time some test took.
+        let percentile = |p: usize| times[(times.len() * p).div_ceil(100).max(1) - 1];
+        Some(Latency { stage, tests: times.len(), p50: percentile(50), p95: percentile(95), p99: percentile(99) })
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}/{}/{} ms", self.stage, self.p50, self.p95, self.p99)
+    }
+}
+
+/// How many of the slowest tests the summary lists.
+const SLOWEST: usize = 5;
+
+/// How many earlier runs the quarantine history covers.
+const QUARANTINE_HISTORY: usize = 5;
+
+impl Summary {
+    pub fn new(report: &SuiteReport, config: &RunConfig, results_file: &str) -> Summary {
+        let mut summary = Summary {
+            schema_version: SCHEMA_VERSION,
+            results_file: Path::new(results_file).file_name().map_or(results_file.into(), |f| f.to_string_lossy().into_owned()),
+            model: config.model.clone(),
+            judge_models: config.judge_models.clone(),
+            total: report.results.len(),
+            interrupted: report.interrupted,
+            skipped: report.skipped.len(),
+            not_selected: report.not_selected.len(),
+            special_files: report.skipped.iter().filter(|s| s.kind == SkipKind::Special).count(),
+            broken_symlinks: report.skipped.iter().filter(|s| s.kind == SkipKind::BrokenSymlink).map(|s| s.path.clone()).collect(),
+            pending_snapshots: snapshot::pending(&config.test_dir).len(),
+            duration_ms: report.duration.as_millis() as u64,
+            ..Default::default()
+        };
+        for result in &report.results {
+            summary.record(result, config.weak_pass_threshold);
+        }
+        summary.fallbacks = report.results.iter()
+            .filter_map(|r| Some((r.name.clone(), r.model.clone().filter(|m| *m != config.model && !config.models.contains(m))?)))
+            .collect();
+        let grades: Vec<f64> = summary.suites.values().flat_map(|s| &s.grades).copied().collect();
+        summary.grade_mean = mean(&grades);
+        for suite in summary.suites.values_mut() {
+            suite.grade_mean = mean(&suite.grades);
+        }
+        if summary.suites.keys().all(|s| s == ".") {
+            summary.suites.clear();
+        }
+        let judged = summary.passed + summary.failed;
+        summary.pass_rate = (judged > 0).then(|| summary.passed as f64 / judged as f64);
+        let mut slowest: Vec<&TestResult> = report.results.iter().filter(|r| r.duration_ms > 0).collect();
+        slowest.sort_by_key(|r| std::cmp::Reverse(r.duration_ms));
+        summary.slowest = slowest.into_iter().take(SLOWEST).map(|r| (r.name.clone(), r.duration_ms)).collect();
+        let times = |ms: fn(&TestResult) -> Option<u64>| report.results.iter().filter_map(ms).collect();
+        summary.latency = [
+            Latency::new("generation", times(|r| r.generation_ms)),
+            Latency::new("extraction", times(|r| r.extraction_ms)),
+            Latency::new("structure", times(|r| r.structure_ms)),
+            Latency::new("judge", times(|r| r.judge_ms)),
+            Latency::new("test", times(|r| Some(r.duration_ms).filter(|ms| *ms > 0))),
+        ].into_iter().flatten().collect();
+        if !summary.quarantined.is_empty() {
+            let earlier: Vec<Vec<TestResult>> = report::runs(Path::new(&config.results_dir)).iter()
+                .filter(|run| !run.ends_with(".reviewed") && format!("{}.csv", run) != summary.results_file)
+                .take(QUARANTINE_HISTORY)
+                .filter_map(|run| TestResult::read(&Path::new(&config.results_dir).join(format!("{}.csv", run)).display().to_string()).ok())
+                .collect();
+            for q in &mut summary.quarantined {
+                q.recent = earlier.iter().filter_map(|run| run.iter().find(|r| r.name == q.name)).map(|r| if r.passed() { 'P' } else { 'F' }).collect();
+            }
+        }
+        let attempted: Vec<&TestResult> = report.results.iter().filter(|r| r.pass_at_1.is_some()).collect();
+        summary.pass_at_k = config.pass_at_k.filter(|_| !attempted.is_empty()).map(|k| PassAtK {
+            k,
+            tests: attempted.len(),
+            pass_at_1: attempted.iter().filter(|r| r.pass_at_1 == Some(true)).count() as f64 / attempted.len() as f64,
+            pass_at_k: attempted.iter().filter(|r| r.pass_at_k == Some(true)).count() as f64 / attempted.len() as f64,
+            attempt_pass_rate: attempted.iter().filter_map(|r| r.attempt_pass_rate).sum::<f64>() / attempted.len() as f64,
+        });
+        summary.unstable = attempted.iter().filter_map(|r| Some((r.name.clone(), r.attempt_pass_rate.filter(|rate| *rate > 0.0 && *rate < 1.0)?))).collect();
+        if !config.prices.is_empty() {
+            let fallbacks = config.fallback_models.iter().filter(|m| summary.fallbacks.iter().any(|(_, used)| used.split(", ").any(|u| u == *m)));
+            summary.unpriced_models = std::iter::once(&config.model).chain(fallbacks).chain(&config.judge_models)
+                .filter(|m| config.prices.price(m).is_none()).cloned().collect();
+            summary.unpriced_models.dedup();
+            summary.estimated_cost = report.results.iter().map(|r| r.cost).sum::<Option<f64>>().filter(|_| summary.unpriced_models.is_empty());
+        }
+        if !summary.scores.is_empty() {
+            let mut scores = summary.scores.clone();
+            scores.sort_by(f64::total_cmp);
+            let mid = scores.len() / 2;
+            summary.score_median = Some(if scores.len().is_multiple_of(2) { (scores[mid - 1] + scores[mid]) / 2.0 } else { scores[mid] });
+            summary.score_mean = Some(scores.iter().sum::<f64>() / scores.len() as f64);
+        }
+        summary
+    }
+
+    fn record(&mut self, result: &TestResult, weak_pass_threshold: f32) {
+        let name = &result.name;
+        if let Some(score) = result.score {
+            self.scores.push(score);
+        }
+        self.generation_tokens += result.tokens.unwrap_or(0);
+        self.judge_tokens += result.judge_tokens.unwrap_or(0);
+        if let Some(judgement) = &result.judgement {
+            let verdicts: Vec<bool> = judgement.panel.iter().filter_map(|vote| vote.passed).collect();
+            if verdicts.iter().any(|v| *v) && verdicts.iter().any(|v| !v) {
+                self.disagreements.push(name.to_string());
+            }
+            if let Some((yes, total)) = judgement.votes.filter(|(yes, total)| *yes != 0 && yes != total) {
+                self.split_votes.push((name.to_string(), yes, total));
+            }
+        }
+        if result.truncated_tokens.is_some() {
+            self.truncated_inputs.push(name.to_string());
+        }
+        if matches!(result.location, Some(ErrorLocation::Internal)) {
+            self.internal += 1;
+            return;
+        }
+        if result.status == Status::Missing {
+            self.missing.push(name.to_string());
+            return;
+        }
+        if result.status == Status::Budget {
+            self.budget_skipped.push(name.to_string());
+            return;
+        }
+        if result.status == Status::Stopped {
+            self.stopped.push(name.to_string());
+            return;
+        }
+        let annotated = match result.status {
+            Status::Skipped => Some(&mut self.skipped_tests),
+            Status::ExpectedFailure => Some(&mut self.expected_failures),
+            Status::UnexpectedPass => Some(&mut self.unexpected_passes),
+            _ => None,
+        };
+        if let Some(list) = annotated {
+            list.push(name.to_string());
+            return;
+        }
+        if result.quarantined {
+            self.quarantined.push(Quarantined { name: name.to_string(), passed: result.passed(), recent: String::new() });
+            return;
+        }
+        let suite = self.suites.entry(Some(suite(name)).filter(|s| !s.is_empty()).unwrap_or(".").to_string()).or_default();
+        suite.grades.extend(result.grade);
+        if !result.passed() {
+            suite.failed += 1;
+            self.failed += 1;
+            *self.failures_by_location.entry(result.location.map_or("unknown", ErrorLocation::name)).or_default() += 1;
+            for c in result.judgement.iter().flat_map(|j| &j.criteria).filter(|c| !c.passed) {
+                match self.criterion_failures.iter_mut().find(|(name, _)| *name == c.name) {
+                    Some((_, count)) => *count += 1,
+                    None => self.criterion_failures.push((c.name.clone(), 1)),
+                }
+            }
+            return;
+        }
+        suite.passed += 1;
+        self.passed += 1;
+        if let Some(confidence) = result.confidence {
+            if confidence < weak_pass_threshold {
+                self.weak_passes.push((name.to_string(), confidence));
+            }
+        }
+    }
+
+    /// Tests that panicked, which point at a bug in the runner.
+    pub fn internal_errors(&self) -> usize {
+        self.internal
+    }
+
+    pub fn passed(&self) -> usize {
+        self.passed
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Generation and judge tokens together.
+    pub fn tokens(&self) -> u32 {
+        self.generation_tokens + self.judge_tokens
+    }
+
+    pub fn estimated_cost(&self) -> Option<f64> {
+        self.estimated_cost
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.duration_ms
+    }
+
+    /// The share of passed tests among the passed and failed ones, or `None` when there were none.
+    pub fn pass_rate(&self) -> Option<f64> {
+        self.pass_rate
+    }
+
+    pub fn print(&self) {
+        match self.pass_rate {
+            Some(rate) => println!("{} passed, {} failed, {:.1}% pass rate, {} tests in {:.1} s", self.passed, self.failed, rate * 100.0, self.total, self.duration_ms as f64 / 1000.0),
+            None => println!("{} passed, {} failed", self.passed, self.failed),
+        }
+        if self.interrupted {
+            println!("{color_yellow}Interrupted after {} tests, the results are partial{color_reset}", self.total);
+        }
+        if self.internal > 0 {
+            println!("{color_red}{} tests hit an internal error (a bug in the runner, not a model failure){color_reset}", self.internal);
+        }
+        if self.not_selected > 0 {
+            println!("Not selected: {}", self.not_selected);
+        }
+        if self.skipped > 0 {
+            println!("{color_yellow}TEST_DIR entries skipped: {}{color_reset}", self.skipped);
+        }
+        if self.special_files > 0 {
+            println!("{color_yellow}  not regular files: {}{color_reset}", self.special_files);
+        }
+        if !self.broken_symlinks.is_empty() {
+            println!("{color_yellow}  broken symlinks: {}{color_reset}", self.broken_symlinks.join(", "));
+        }
+        if !self.failures_by_location.is_empty() {
+            let breakdown: Vec<String> = self.failures_by_location.iter().map(|(l, n)| format!("{} {}", l, n)).collect();
+            println!("Failures by location: {}", breakdown.join(", "));
+        }
+        if !self.suites.is_empty() {
+            println!("Suites:");
+            for (name, suite) in &self.suites {
+                println!("  {}: {}", name, suite);
+            }
+        }
+        if let Some(p) = &self.pass_at_k {
+            println!("pass@1 {:.1}%, pass@{} {:.1}%, {:.1}% of attempts passed ({} tests)", p.pass_at_1 * 100.0, p.k, p.pass_at_k * 100.0, p.attempt_pass_rate * 100.0, p.tests);
+        }
+        if let (Some(mean), Some(median)) = (self.score_mean, self.score_median) {
+            println!("Scores: mean {:.1}, median {:.1}", mean, median);
+        }
+        if let Some(grade) = self.grade_mean {
+            println!("Mean grade: {:.2}", grade);
+        }
+        if !self.criterion_failures.is_empty() {
+            println!("Failed criteria:");
+            for (name, count) in &self.criterion_failures {
+                println!("  {}: {} ({:.0}% of failures)", name, count, *count as f64 * 100.0 / self.failed as f64);
+            }
+        }
+        if !self.slowest.is_empty() {
+            let slowest: Vec<String> = self.slowest.iter().map(|(name, ms)| format!("{} {} ms", name, ms)).collect();
+            println!("Slowest: {}", slowest.join(", "));
+        }
+        if !self.latency.is_empty() {
+            println!("Latency p50/p95/p99: {}", self.latency.iter().map(Latency::to_string).collect::<Vec<_>>().join(", "));
+        }
+        if self.generation_tokens + self.judge_tokens > 0 {
+            println!("Tokens: {} generation, {} judge", self.generation_tokens, self.judge_tokens);
+        }
+        if let Some(cost) = self.estimated_cost {
+            println!("Estimated cost: {:.4} ({} tokens)", cost, self.generation_tokens + self.judge_tokens);
+        }
+        if !self.fallbacks.is_empty() {
+            let fallbacks: Vec<String> = self.fallbacks.iter().map(|(name, model)| format!("{} ({})", name, model)).collect();
+            println!("{color_yellow}Generated with FALLBACK_MODELS: {}{color_reset}", fallbacks.join(", "));
+        }
+        if !self.unpriced_models.is_empty() {
+            println!("{color_yellow}No estimated cost: no price for {}{color_reset}", self.unpriced_models.join(", "));
+        }
+        if !self.split_votes.is_empty() {
+            println!("{color_yellow}Split judge votes (low confidence):");
+            for (name, yes, total) in &self.split_votes {
+                println!("  {} ({}/{} true)", name, yes, total);
+            }
+            print!("{color_reset}");
+        }
+        if !self.disagreements.is_empty() {
+            println!("{color_yellow}Judges disagreed on {} test(s), which may be ambiguous:", self.disagreements.len());
+            for name in &self.disagreements {
+                println!("  {}", name);
+            }
+            print!("{color_reset}");
+        }
+        if !self.weak_passes.is_empty() {
+            println!("{color_yellow}Weak passes (judge confidence below threshold):");
+            for (name, confidence) in &self.weak_passes {
+                println!("  {} ({:.2})", name, confidence);
+            }
+            print!("{color_reset}");
+        }
+        if !self.unstable.is_empty() {
+            println!("{color_yellow}Unstable, attempts both passed and failed:");
+            for (name, rate) in &self.unstable {
+                println!("  {} ({:.0}% of attempts passed)", name, rate * 100.0);
+            }
+            print!("{color_reset}");
+        }
+        if !self.truncated_inputs.is_empty() {
+            println!("{color_yellow}Truncated inputs: {}{color_reset}", self.truncated_inputs.join(", "));
+        }
+        if !self.missing.is_empty() {
+            println!("{color_yellow}Missing generations: {}{color_reset}", self.missing.join(", "));
+        }
+        if !self.skipped_tests.is_empty() {
+            println!("{color_yellow}Skipped: {}{color_reset}", self.skipped_tests.join(", "));
+        }
+        if !self.expected_failures.is_empty() {
+            println!("Expected failures: {}", self.expected_failures.join(", "));
+        }
+        if !self.unexpected_passes.is_empty() {
+            println!("{color_yellow}Unexpected passes, remove their <xfail>: {}{color_reset}", self.unexpected_passes.join(", "));
+        }
+        if !self.budget_skipped.is_empty() {
+            println!("{color_yellow}MAX_COST reached, not run: {}{color_reset}", self.budget_skipped.join(", "));
+        }
+        if !self.stopped.is_empty() {
+            println!("{color_yellow}MAX_FAILURES reached, not run: {}{color_reset}", self.stopped.join(", "));
+        }
+        if !self.quarantined.is_empty() {
+            println!("{color_bright_black}Quarantined:");
+            for q in &self.quarantined {
+                let recent = if q.recent.is_empty() { String::new() } else { format!(", earlier runs {}", q.recent) };
+                println!("  {} {}{}", q.name, if q.passed { "passed" } else { "failed" }, recent);
+            }
+            print!("{color_reset}");
+        }
+        if self.pending_snapshots > 0 {
+            println!("Pending snapshots: {} (promote with --accept, discard with --reject)", self.pending_snapshots);
+        }
+    }
+
+    /// The headline of the HTML report: totals and pass rate, failures by location, the slowest tests and tokens and cost.
+    pub(crate) fn html(&self) -> String {
+        let mut html = format!("<p>{} passed, {} failed", self.passed, self.failed);
+        if let Some(rate) = self.pass_rate {
+            html.push_str(&format!(", {:.1}% pass rate", rate * 100.0));
+        }
+        if let Some(grade) = self.grade_mean {
+            html.push_str(&format!(", mean grade {:.2}", grade));
+        }
+        html.push_str(&format!(", {} tests in {:.1} s</p>", self.total, self.duration_ms as f64 / 1000.0));
+        if !self.failures_by_location.is_empty() {
+            let breakdown: Vec<String> = self.failures_by_location.iter().map(|(l, n)| format!("<code>{}</code> {}", l, n)).collect();
+            html.push_str(&format!("<p>Failures by location: {}</p>", breakdown.join(", ")));
+        }
+        if !self.suites.is_empty() {
+            let suites: Vec<String> = self.suites.iter().map(|(name, suite)| format!("<code>{}</code> {}", escape(name), suite)).collect();
+            html.push_str(&format!("<p>Suites: {}</p>", suites.join(", ")));
+        }
+        if !self.slowest.is_empty() {
+            let slowest: Vec<String> = self.slowest.iter().map(|(name, ms)| format!("{} {} ms", escape(name), ms)).collect();
+            html.push_str(&format!("<p>Slowest: {}</p>", slowest.join(", ")));
+        }
+        if !self.latency.is_empty() {
+            html.push_str(&format!("<p>Latency p50/p95/p99: {}</p>", self.latency.iter().map(Latency::to_string).collect::<Vec<_>>().join(", ")));
+        }
+        if self.generation_tokens + self.judge_tokens > 0 {
+            html.push_str(&format!("<p>Tokens: {} generation, {} judge", self.generation_tokens, self.judge_tokens));
+            if let Some(cost) = self.estimated_cost {
+                html.push_str(&format!(", estimated cost {:.4}", cost));
+            }
+            html.push_str("</p>");
+        }
+        html
+    }
+
+    /// The headline of the Markdown report and the GitHub step summary: a table of the totals, pass rate, tokens, cost
+    /// and duration, then the failures by location.
+    pub(crate) fn markdown(&self) -> String {
+        let mut markdown = "| Passed | Failed | Pass rate | Tokens | Estimated cost | Duration |\n|---:|---:|---:|---:|---:|---:|\n".to_string();
+        markdown.push_str(&format!(
+            "| {} | {} | {} | {} | {} | {:.1}s |\n",
+            self.passed, self.failed, self.pass_rate.map_or("–".to_string(), |rate| format!("{:.1}%", rate * 100.0)),
+            self.tokens(), self.estimated_cost.map_or("–".to_string(), |cost| format!("${:.4}", cost)), self.duration_ms as f64 / 1000.0,
+        ));
+        if !self.failures_by_location.is_empty() {
+            let breakdown: Vec<String> = self.failures_by_location.iter().map(|(l, n)| format!("`{}` {}", l, n)).collect();
+            markdown.push_str(&format!("\nFailures by location: {}\n", breakdown.join(", ")));
+        }
+        markdown
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string_pretty(self)?).map_err(AppError::io(path))?;
+        Ok(())
+    }
+}
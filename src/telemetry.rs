@@ -0,0 +1,250 @@
+use std::{borrow::Cow, env, sync::{Mutex, OnceLock}, time::Duration};
+use opentelemetry::{metrics::{Counter, MeterProvider as _}, trace::{Span as _, Status as SpanStatus, TraceContextExt, TracerProvider as _}, Context, KeyValue, Value};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{error::OTelSdkResult, metrics::SdkMeterProvider, trace::{BatchSpanProcessor, SdkTracerProvider, Span, SpanData, SpanProcessor}, Resource};
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::{filter::filter_fn, registry::LookupSpan, Layer};
+use crate::{config::RunConfig, redact::Redactor, Status, SuiteReport};
+
+/// Longest an export may take before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The tracer provider once [`layer`] started it; runs started over HTTP have none, and export only their metrics.
+static TRACES: OnceLock<SdkTracerProvider> = OnceLock::new();
+/// The counters, started with the first export.
+static METERS: OnceLock<Result<Meters, String>> = OnceLock::new();
+/// `ai_test.model` and `ai_test.run` of the run being traced, which every `test` span gets.
+static RUN: Mutex<Vec<KeyValue>> = Mutex::new(Vec::new());
+
+/// Whether the run is exported to OpenTelemetry, and as which service, from the standard variables:
+/// OTEL_EXPORTER_OTLP_ENDPOINT, a collector taking OTLP over HTTP, and OTEL_SERVICE_NAME. The exporters read
+/// OTEL_EXPORTER_OTLP_HEADERS, OTEL_EXPORTER_OTLP_PROTOCOL and the per-signal variables themselves.
+pub struct Otlp {
+    service: String,
+}
+
+impl Otlp {
+    pub fn from_env() -> Option<Otlp> {
+        env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|e| !e.is_empty())?;
+        let service = env::var("OTEL_SERVICE_NAME").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+        Some(Otlp { service })
+    }
+
+    fn resource(&self) -> Resource {
+        Resource::builder().with_service_name(self.service.clone()).build()
+    }
+}
+
+/// The layer exporting the run's spans, over the spans and `info` events and up of this crate: each test is a trace,
+/// its stages the spans under it. See [`Flatten`] for what each span carries.
+pub fn layer<S: Subscriber + for<'a> LookupSpan<'a>>(otlp: &Otlp, redactor: Redactor) -> Result<impl Layer<S>, String> {
+    let exporter = SpanExporter::builder().with_http().with_timeout(TIMEOUT).build().map_err(|e| format!("cannot start the OTLP trace exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_resource(otlp.resource())
+        .with_span_processor(Flatten { next: BatchSpanProcessor::builder(exporter).build(), redactor })
+        .build();
+    let layer = traced(&provider);
+    let _ = TRACES.set(provider);
+    Ok(layer)
+}
+
+fn traced<S: Subscriber + for<'a> LookupSpan<'a>>(provider: &SdkTracerProvider) -> impl Layer<S> {
+    let exported = |metadata: &Metadata<'_>| metadata.target().starts_with(env!("CARGO_CRATE_NAME")) && *metadata.level() <= Level::INFO;
+    tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_location(false)
+        .with_threads(false)
+        .with_error_fields_to_exceptions(false)
+        .with_filter(filter_fn(exported))
+}
+
+/// Names the run whose `test` spans follow, by its model and results file.
+pub fn start_run(config: &RunConfig, run: &str) {
+    *RUN.lock().unwrap() = vec![KeyValue::new("ai_test.model", config.model.clone()), KeyValue::new("ai_test.run", run.to_string())];
+}
+
+/// Makes the exported spans what a trace view needs: a `test` span gets the run's attributes, and the fields of
+/// every event in a span, such as the tokens and latency of a stage's response or the outcome of a test, become the
+/// span's attributes too, so they can be queried on the span itself. A span whose last event is a warning or worse is
+/// an error. Event names and string attributes go through the redactor.
+#[derive(Debug)]
+struct Flatten<P> {
+    next: P,
+    redactor: Redactor,
+}
+
+impl<P> Flatten<P> {
+    fn redact(&self, value: &mut Value) {
+        if let Value::String(s) = value {
+            if let Cow::Owned(redacted) = self.redactor.redact(s.as_str()) {
+                *value = redacted.into();
+            }
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for Flatten<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if !cx.span().span_context().is_valid() {
+            span.set_attributes(RUN.lock().unwrap().iter().cloned());
+        }
+        self.next.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let mut error = None;
+        for event in span.events.events.iter_mut() {
+            if let Cow::Owned(name) = self.redactor.redact(&event.name) {
+                event.name = name.into();
+            }
+            let mut warning = false;
+            for attribute in event.attributes.iter_mut() {
+                self.redact(&mut attribute.value);
+                match attribute.key.as_str() {
+                    "level" => warning = matches!(attribute.value.as_str().as_ref(), "WARN" | "ERROR"),
+                    "target" => {}
+                    key => match span.attributes.iter_mut().find(|a| a.key.as_str() == key) {
+                        Some(existing) => existing.value = attribute.value.clone(),
+                        None => span.attributes.push(attribute.clone()),
+                    },
+                }
+            }
+            let message = || event.attributes.iter().find(|a| a.key.as_str() == "error").map_or_else(|| event.name.to_string(), |a| a.value.to_string());
+            error = warning.then(message);
+        }
+        for attribute in span.attributes.iter_mut() {
+            self.redact(&mut attribute.value);
+        }
+        if let Some(error) = error {
+            span.status = SpanStatus::error(error);
+        }
+        self.next.on_end(span);
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.next.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.next.shutdown_with_timeout(timeout)
+    }
+}
+
+/// The run's counters: `ai_test.tests` by outcome, `ai_test.tokens` by generation and judge, and `ai_test.cost`.
+struct Meters {
+    provider: SdkMeterProvider,
+    tests: Counter<u64>,
+    tokens: Counter<u64>,
+    cost: Counter<f64>,
+}
+
+impl Meters {
+    fn new(otlp: &Otlp) -> Result<Meters, String> {
+        let exporter = MetricExporter::builder().with_http().with_timeout(TIMEOUT).build().map_err(|e| format!("cannot start the OTLP metric exporter: {}", e))?;
+        let provider = SdkMeterProvider::builder().with_resource(otlp.resource()).with_periodic_exporter(exporter).build();
+        let meter = provider.meter(env!("CARGO_PKG_NAME"));
+        Ok(Meters {
+            tests: meter.u64_counter("ai_test.tests").with_unit("{test}").with_description("Tests run, by outcome").build(),
+            tokens: meter.u64_counter("ai_test.tokens").with_unit("{token}").with_description("Tokens used, by generation and judge").build(),
+            cost: meter.f64_counter("ai_test.cost").with_unit("USD").with_description("Estimated cost of the API calls").build(),
+            provider,
+        })
+    }
+}
+
+/// Counts the run's results, each with the model and run as attributes, then sends what the counters and the traced
+/// spans hold to the collector. `run` is the results file's name.
+pub async fn export(otlp: &Otlp, config: &RunConfig, report: &SuiteReport, run: &str) -> Result<(), String> {
+    let meters = METERS.get_or_init(|| Meters::new(otlp)).as_ref().map_err(Clone::clone)?;
+    let run_attributes = [KeyValue::new("ai_test.model", config.model.clone()), KeyValue::new("ai_test.run", run.to_string())];
+    let with = |key: &'static str, value: &'static str| [run_attributes[0].clone(), run_attributes[1].clone(), KeyValue::new(key, value)];
+    for result in &report.results {
+        meters.tests.add(1, &with("outcome", outcome(result.status)));
+        meters.tokens.add(result.tokens.unwrap_or(0).into(), &with("kind", "generation"));
+        meters.tokens.add(result.judge_tokens.unwrap_or(0).into(), &with("kind", "judge"));
+    }
+    if let Some(cost) = report.results.iter().map(|r| r.cost).sum::<Option<f64>>() {
+        meters.cost.add(cost, &run_attributes);
+    }
+    let (traces, metrics) = (TRACES.get().cloned(), meters.provider.clone());
+    // Flushing waits for the exporters' requests.
+    tokio::task::spawn_blocking(move || {
+        if let Some(traces) = traces {
+            traces.force_flush().map_err(|e| format!("exporting traces failed: {}", e))?;
+        }
+        metrics.force_flush().map_err(|e| format!("exporting metrics failed: {}", e))
+    }).await.map_err(|e| e.to_string())?
+}
+
+fn outcome(status: Status) -> &'static str {
+    match status {
+        Status::Passed | Status::Human | Status::ExpectedFailure | Status::UnexpectedPass => "passed",
+        Status::Failed | Status::Missing => "failed",
+        Status::Skipped | Status::Budget | Status::Stopped => "skipped",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tracing_subscriber::layer::SubscriberExt;
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Collect(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanProcessor for Collect {
+        fn on_start(&self, _: &mut Span, _: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+    }
+
+    fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes.iter().find(|a| a.key.as_str() == key).map(|a| &a.value)
+    }
+
+    #[test]
+    fn spans_carry_their_events_fields_and_a_warning_makes_an_error() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let redactor = Redactor::from_env(true).unwrap();
+        let provider = SdkTracerProvider::builder().with_span_processor(Flatten { next: Collect(spans.clone()), redactor }).build();
+        *RUN.lock().unwrap() = vec![KeyValue::new("ai_test.run", "results.csv")];
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(traced(&provider)), || {
+            let _test = tracing::info_span!("test", name = "a.txt").entered();
+            {
+                let _stage = tracing::info_span!("generation").entered();
+                tracing::info!(prompt_tokens = 3, latency_ms = 5, "response");
+            }
+            tracing::debug!(ignored = true, "not exported");
+            tracing::warn!(error = "the key sk-abcdefghijklmnopqrstuvwxyz was refused", "test failed");
+        });
+        let spans = spans.lock().unwrap();
+        let generation = spans.iter().find(|s| s.name == "generation").unwrap();
+        assert_eq!(attribute(generation, "latency_ms"), Some(&Value::I64(5)));
+        assert_eq!(attribute(generation, "ai_test.run"), None);
+        assert_eq!(generation.status, SpanStatus::Unset);
+        let test = spans.iter().find(|s| s.name == "test").unwrap();
+        assert_eq!(attribute(test, "ai_test.run"), Some(&Value::from("results.csv")));
+        assert_eq!(attribute(test, "name"), Some(&Value::from("a.txt")));
+        assert_eq!(attribute(test, "ignored"), None);
+        assert_eq!(attribute(test, "error"), Some(&Value::from("the key [REDACTED] was refused")));
+        assert_eq!(test.status, SpanStatus::error("the key [REDACTED] was refused"));
+    }
+
+    #[test]
+    fn outcomes_group_the_statuses() {
+        assert_eq!(outcome(Status::ExpectedFailure), "passed");
+        assert_eq!(outcome(Status::Missing), "failed");
+        assert_eq!(outcome(Status::Budget), "skipped");
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use handlebars::{Handlebars, RenderError, RenderErrorReason};
+use serde_json::{Map, Value};
+
+/// The placeholders the runner fills in itself, which also have the older `__name__` spelling.
+pub const BUILT_IN: [&str; 3] = ["description", "baseline", "input"];
+
+/// Fills in a prompt template with Handlebars, in strict mode and without HTML escaping. `{{ name }}` is replaced
+/// with the variable `name`, whose dots are Handlebars paths, so `{{ user.name }}` is the variable `user.name`, and
+/// `__description__`, `__baseline__` and `__input__` with theirs as before. Values are inserted as they are, so a value
+/// that itself contains a placeholder is not filled in again. A placeholder without a value, or a `{{` that is not
+/// one, such as `{{ na me }}`, is an error; write a literal `{{`, as in a JSON example, as `\{{`.
+pub fn render(template: &str, vars: &BTreeMap<&str, &str>) -> Result<String, String> {
+    let mut template = template.to_string();
+    for name in BUILT_IN {
+        template = template.replace(&format!("__{}__", name), &format!("{{{{{}}}}}", name));
+    }
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.render_template(&template, &data(vars)?).map_err(|e| error(&template, e))
+}
+
+/// A variable name: letters, digits, `_`, `-` and `.`.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The variables as Handlebars data, with `a.b` as the field `b` of `a`.
+fn data(vars: &BTreeMap<&str, &str>) -> Result<Value, String> {
+    let mut data = Map::new();
+    for (name, value) in vars {
+        let mut fields = &mut data;
+        let mut path = name.split('.').peekable();
+        while let Some(field) = path.next() {
+            if path.peek().is_none() {
+                fields.insert(field.to_string(), Value::from(*value));
+                break;
+            }
+            // The names are in order, so `a` comes before `a.b`.
+            fields = match fields.entry(field).or_insert_with(|| Value::Object(Map::new())) {
+                Value::Object(nested) => nested,
+                _ => return Err(format!("the variables {} and {} cannot both have a value", name, name.rsplit_once('.').map_or(*name, |(parent, _)| parent))),
+            };
+        }
+    }
+    Ok(Value::Object(data))
+}
+
+fn error(template: &str, e: RenderError) -> String {
+    let at = |line: Option<usize>, column: Option<usize>| match (line, column) {
+        (Some(line), Some(column)) => format!(" at line {}, column {}", line, column),
+        _ => String::new(),
+    };
+    match e.reason() {
+        RenderErrorReason::MissingVariable(Some(name)) => format!("the prompt uses {{{{ {} }}}}, which the test has no value for", name),
+        RenderErrorReason::TemplateError(e) => {
+            let (line, column) = e.pos().unzip();
+            format!("the prompt has a malformed placeholder{}: {}; write a literal {{{{ as \\{{{{", at(line, column), e.reason())
+        }
+        RenderErrorReason::HelperNotFound(_) => {
+            let tag = e.line_no.zip(e.column_no).and_then(|(line, column)| tag(template, line, column));
+            format!("the prompt has a malformed placeholder{}{}: a placeholder is a single name", tag.map_or(String::new(), |t| format!(" {}", t)), at(e.line_no, e.column_no))
+        }
+        _ => format!("the prompt cannot be filled in: {}", e),
+    }
+}
+
+/// The `{{ ... }}` starting at `line` and `column`, both from 1.
+fn tag(template: &str, line: usize, column: usize) -> Option<&str> {
+    let line = template.lines().nth(line - 1)?;
+    let start = line.char_indices().nth(column - 1)?.0;
+    let rest = &line[start..];
+    rest.starts_with("{{").then(|| rest.find("}}").map_or(rest, |end| &rest[..end + 2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &[(&'a str, &'a str)]) -> BTreeMap<&'a str, &'a str> {
+        pairs.iter().copied().collect()
+    }
+
+    #[test]
+    fn fills_in_names_dotted_names_and_the_old_spelling() {
+        let vars = vars(&[("description", "a <b> & c"), ("user.name", "Ann"), ("tone-of-voice", "{{ description }}")]);
+        let rendered = render("__description__ for {{ user.name }}, {{tone-of-voice}}", &vars).unwrap();
+        assert_eq!(rendered, "a <b> & c for Ann, {{ description }}");
+    }
+
+    #[test]
+    fn a_placeholder_without_a_value_is_an_error() {
+        let error = render("Write about {{ topic }}.", &vars(&[("description", "x")])).unwrap_err();
+        assert_eq!(error, "the prompt uses {{ topic }}, which the test has no value for");
+    }
+
+    #[test]
+    fn a_malformed_placeholder_is_an_error() {
+        let vars = vars(&[("name", "Ann")]);
+        let error = render("Hello\nDear {{ na me }}", &vars).unwrap_err();
+        assert!(error.starts_with("the prompt has a malformed placeholder {{ na me }} at line 2, column 6"), "{}", error);
+        let error = render("Reply with {{\"answer\": 1}}", &vars).unwrap_err();
+        assert!(error.starts_with("the prompt has a malformed placeholder at line 1, column"), "{}", error);
+        assert_eq!(render(r#"Reply with \{{"answer": "{{ name }}"}}"#, &vars).unwrap(), r#"Reply with {{"answer": "Ann"}}"#);
+    }
+
+    #[test]
+    fn a_name_cannot_be_both_a_value_and_a_parent() {
+        let error = render("{{ a }}", &vars(&[("a", "1"), ("a.b", "2")])).unwrap_err();
+        assert_eq!(error, "the variables a.b and a cannot both have a value");
+    }
+}
@@ -0,0 +1,422 @@
+use std::{borrow::Cow, collections::{BTreeMap, BTreeSet}, fs, path::{Path, PathBuf}};
+use async_openai::types::FunctionObject;
+use serde::Deserialize;
+use crate::{compare::{self, Comparison}, config::GenParams, extract::Strategy, format::Format, resolve, rubric::Rubric, template, RunConfig};
+
+#[derive(Clone)]
+pub struct TestCase<'a> {
+    pub name: &'a str,
+    pub input: &'a str,
+    pub expected_output: &'a str,
+    pub comparison: Option<Comparison>,
+    /// An `<extract>` section's strategy, in place of EXTRACT.
+    pub extract: Option<Strategy>,
+    /// A `<format>` section's output format, in place of FORMAT.
+    pub format: Option<Format>,
+    /// From a `<tags>smoke, strict</tags>` section.
+    pub tags: Vec<&'a str>,
+    /// In a conversation, the user and assistant turns sent before `input`.
+    pub history: Vec<Turn<'a>>,
+    /// The tools of a `<tools>` section, whose calls are checked instead of the message content.
+    pub tools: Vec<FunctionObject>,
+    pub tool_choice: ToolChoice<'a>,
+    /// The `<image>` sections, attached to the first user message.
+    pub images: Vec<&'a str>,
+    /// A `<schema>` section's JSON Schema file, checked in place of STRUCTURE_SCHEMA.
+    pub schema: Option<&'a str>,
+    /// A `<validator>` section's program or `.wasm` module, run in place of VALIDATOR.
+    pub validator: Option<&'a str>,
+    /// A `<structure>` section's Lua file, run in place of STRUCTURE_TEST.
+    pub structure: Option<&'a str>,
+    /// `<gen_prompt>` and `<test_prompt>` sections, in place of GEN_PROMPT and TEST_PROMPT.
+    pub gen_prompt: Option<&'a str>,
+    pub test_prompt: Option<&'a str>,
+    /// `<system>` and `<judge_system>` sections, in place of GEN_SYSTEM_PROMPT and JUDGE_SYSTEM_PROMPT.
+    pub system: Option<&'a str>,
+    pub judge_system: Option<&'a str>,
+    /// The variables of a `<vars>` section, a YAML mapping, for `{{ name }}` placeholders in the prompts.
+    pub vars: BTreeMap<String, String>,
+    /// The input and output of each `<example>`, sent as earlier turns of the generation request.
+    pub examples: Vec<(&'a str, &'a str)>,
+    /// A `<params>` section's sampling parameters, a YAML mapping, in place of GEN_TEMPERATURE and the others it sets.
+    pub params: GenParams,
+    /// A `<rubric>` section's JSON rubric, judged against in place of RUBRIC.
+    pub rubric: Option<Rubric>,
+}
+
+/// Whether the model of a test with tools has to call one, from a `<tool_choice>` section.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ToolChoice<'a> {
+    /// Some tool has to be called.
+    #[default]
+    Required,
+    /// The model may also answer without a call, which reads as no calls.
+    Auto,
+    /// This tool has to be called.
+    Named(&'a str),
+}
+
+/// A turn of a conversation test, from its `<user>`, `<assistant>` and `<output>` sections.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Turn<'a> {
+    User(&'a str),
+    Assistant(&'a str),
+    /// The model replies here, and the reply has to match this expected output.
+    Checkpoint(&'a str),
+}
+
+impl<'a> TestCase<'a> {
+    pub fn parse(name: &'a str, contents: &'a str) -> Result<TestCase<'a>, String> {
+        if let Some(error) = section(contents, "invalid") {
+            return Err(error.to_string());
+        }
+        section(contents, "output").ok_or("missing <output> section")?;
+        TestCase::parse_without_output(name, contents)
+    }
+
+    /// Like [`TestCase::parse`], but a missing `<output>` section reads as an empty expected output.
+    pub fn parse_without_output(name: &'a str, contents: &'a str) -> Result<TestCase<'a>, String> {
+        if let Some(error) = section(contents, "invalid") {
+            return Err(error.to_string());
+        }
+        // A conversation reads as its first user turn and its first checkpoint.
+        let input = section(contents, "input").or_else(|| section(contents, "user")).ok_or("missing <input> section")?;
+        let expected_output = section(contents, "output").unwrap_or("");
+        let comparison = section(contents, "comparison").map(|c| c.trim().parse()).transpose()?;
+        let extract = section(contents, "extract").map(|e| e.trim().parse()).transpose().map_err(|e| format!("<extract>: {}", e))?;
+        let format = section(contents, "format").map(|f| f.trim().parse()).transpose().map_err(|e| format!("<format>: {}", e))?;
+        let tools = match section(contents, "tools") {
+            Some(tools) => serde_json::from_str::<Vec<serde_json::Value>>(tools).and_then(|tools| tools.into_iter().map(function).collect())
+                .map_err(|e| format!("<tools> is not a JSON array of function definitions: {}", e))?,
+            None => Vec::new(),
+        };
+        let tool_choice = match section(contents, "tool_choice").map(str::trim) {
+            None | Some("required") => ToolChoice::Required,
+            Some("auto") => ToolChoice::Auto,
+            Some(name) if tools.iter().any(|t: &FunctionObject| t.name == name) => ToolChoice::Named(name),
+            Some(name) => return Err(format!("<tool_choice> {:?} is not required, auto or one of the <tools>", name)),
+        };
+        Ok(TestCase { name, input, expected_output, comparison, extract, format, tags: tags(contents), history: Vec::new(), tools, tool_choice, images: sections(contents, "image"),
+            schema: section(contents, "schema").map(str::trim), validator: section(contents, "validator").map(str::trim),
+            structure: section(contents, "structure").map(str::trim), gen_prompt: section(contents, "gen_prompt"), test_prompt: section(contents, "test_prompt"),
+            system: section(contents, "system"), judge_system: section(contents, "judge_system"),
+            vars: section(contents, "vars").map(vars).transpose()?.unwrap_or_default(), examples: examples(contents)?,
+            params: section(contents, "params").map(params).transpose()?.unwrap_or_default(),
+            rubric: section(contents, "rubric").map(|r| Rubric::parse(r, "<rubric>")).transpose()? })
+    }
+
+    /// The generation prompt filled in for `description`, the input or a user turn.
+    pub fn render_gen_prompt(&self, config: &RunConfig, description: &str) -> Result<String, String> {
+        template::render(&self.gen_prompt(config), &self.template_vars([("description", description)]))
+    }
+
+    /// The test prompt filled in for the output being judged.
+    pub fn render_test_prompt(&self, config: &RunConfig, output: &str) -> Result<String, String> {
+        let vars = self.template_vars([("description", self.input), ("baseline", self.expected_output), ("input", output)]);
+        template::render(&self.test_prompt(config), &vars)
+    }
+
+    /// The system message of the generation request, if the test or GEN_SYSTEM_PROMPT has one.
+    pub fn render_system_prompt(&self, config: &RunConfig) -> Result<Option<String>, String> {
+        let Some(prompt) = own_prompt(self, self.system, config).or(config.gen_system_prompt.as_deref().map(Cow::Borrowed)) else { return Ok(None) };
+        template::render(&prompt, &self.template_vars([("description", self.input)])).map(Some)
+    }
+
+    /// The system message of the judge requests, if the test or JUDGE_SYSTEM_PROMPT has one.
+    pub fn render_judge_system_prompt(&self, config: &RunConfig) -> Result<Option<String>, String> {
+        let Some(prompt) = own_prompt(self, self.judge_system, config).or(config.judge_system_prompt.as_deref().map(Cow::Borrowed)) else { return Ok(None) };
+        template::render(&prompt, &self.template_vars([("description", self.input), ("baseline", self.expected_output)])).map(Some)
+    }
+
+    fn template_vars<'v, const N: usize>(&'v self, built_in: [(&'v str, &'v str); N]) -> BTreeMap<&'v str, &'v str> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).chain(built_in).collect()
+    }
+
+    /// The prompt the test is generated from: its `<gen_prompt>` or GEN_PROMPT.
+    pub fn gen_prompt<'c>(&'c self, config: &'c RunConfig) -> Cow<'c, str> {
+        own_prompt(self, self.gen_prompt, config).unwrap_or(Cow::Borrowed(&config.gen_prompt))
+    }
+
+    /// The prompt the test's output is judged with: its `<test_prompt>` or TEST_PROMPT.
+    pub fn test_prompt<'c>(&'c self, config: &'c RunConfig) -> Cow<'c, str> {
+        own_prompt(self, self.test_prompt, config).unwrap_or(Cow::Borrowed(&config.test_prompt))
+    }
+
+    /// The files the test refers to, relative to its test file: its schema, structure test and validator, its images and
+    /// the files its prompt sections may name.
+    pub fn attachments(&self, config: &RunConfig) -> Vec<PathBuf> {
+        let images = self.images.iter().map(|image| image.trim()).filter(|image| !["http://", "https://", "data:"].iter().any(|scheme| image.starts_with(scheme)));
+        let prompts = [self.gen_prompt, self.test_prompt, self.system, self.judge_system].into_iter().flatten().filter_map(prompt_file);
+        [self.schema, self.structure, self.validator].into_iter().flatten().chain(images).chain(prompts).map(|path| resolve(config, self.name, path)).collect()
+    }
+
+    /// The rubric the test is judged against: its `<rubric>` or RUBRIC.
+    pub fn rubric<'c>(&'c self, config: &'c RunConfig) -> Option<&'c Rubric> {
+        self.rubric.as_ref().or(config.rubric.as_ref())
+    }
+
+    pub fn comparison(&self, default: Comparison) -> Comparison {
+        self.comparison.unwrap_or(default)
+    }
+
+    /// The format the test's output is written in: its `<format>` or FORMAT.
+    pub fn format(&self, config: &RunConfig) -> Format {
+        self.format.unwrap_or(config.format)
+    }
+
+    /// Checks that the expected output is usable by the comparison mode before any API call is spent on the test.
+    pub fn validate(&self, config: &RunConfig) -> Result<(), String> {
+        match self.comparison(config.comparison) {
+            Comparison::JsonEqual => {
+                let format = self.format(config);
+                format.parse(self.expected_output).map_err(|e| format!("expected output is not valid {}: {}", format.title(), e))?;
+            }
+            Comparison::Regex => {
+                compare::patterns(self.expected_output)?;
+            }
+            Comparison::Judge | Comparison::Exact | Comparison::Strict | Comparison::Embedding | Comparison::Levenshtein | Comparison::Bleu | Comparison::Rouge => {}
+        }
+        Ok(())
+    }
+}
+
+/// The `<vars>` mapping, whose values that are not strings are written out as JSON.
+fn vars(section: &str) -> Result<BTreeMap<String, String>, String> {
+    let vars: BTreeMap<String, serde_yaml::Value> = serde_yaml::from_str(section).map_err(|e| format!("<vars> is not a YAML mapping: {}", e))?;
+    vars.into_iter().map(|(name, value)| {
+        if !template::is_name(&name) || template::BUILT_IN.contains(&name.as_str()) {
+            return Err(format!("<vars> cannot define {:?}: a name is letters, digits, _, - and ., and not one of {}", name, template::BUILT_IN.join(", ")));
+        }
+        Ok((name, text(value)?))
+    }).collect()
+}
+
+fn params(section: &str) -> Result<GenParams, String> {
+    serde_yaml::from_str(section).map_err(|e| format!("<params> is not a YAML mapping of sampling parameters: {}", e))
+}
+
+/// A prompt section is a file, relative to the test file, when it is a single line naming one, and the prompt itself
+/// otherwise.
+fn own_prompt<'c>(case: &TestCase<'_>, section: Option<&'c str>, config: &RunConfig) -> Option<Cow<'c, str>> {
+    let section = section?;
+    if let Some(prompt) = prompt_file(section).and_then(|line| fs::read_to_string(resolve(config, case.name, line)).ok()) {
+        return Some(Cow::Owned(prompt));
+    }
+    Some(Cow::Borrowed(section))
+}
+
+/// The single line of a prompt section that may name a file.
+fn prompt_file(section: &str) -> Option<&str> {
+    let line = section.trim();
+    (!line.is_empty() && !line.contains('\n') && !line.contains("__")).then_some(line)
+}
+
+/// A function definition, either bare or as a whole `{"type": "function", "function": ...}` tool.
+pub(crate) fn function(mut tool: serde_json::Value) -> Result<FunctionObject, serde_json::Error> {
+    if let Some(function) = tool.get_mut("function") {
+        tool = function.take();
+    }
+    serde_json::from_value(tool)
+}
+
+/// The turns of a conversation test, one with `<user>` sections instead of `<input>`, in file order; `None` for any
+/// other test. A conversation starts with a user turn, may have assistant turns as context, and has at least one
+/// `<output>` checkpoint, each right after a user turn; it ends with one.
+pub fn conversation(contents: &str) -> Result<Option<Vec<Turn<'_>>>, String> {
+    if section(contents, "input").is_some() || section(contents, "user").is_none() {
+        return Ok(None);
+    }
+    let mut turns = Vec::new();
+    let mut rest = contents;
+    while let Some((start, tag)) = ["user", "assistant", "output", "example"].into_iter().filter_map(|tag| Some((rest.find(&format!("<{}>", tag))?, tag))).min() {
+        let open = start + tag.len() + 2;
+        let len = rest[open..].find(&format!("</{}>", tag)).ok_or_else(|| format!("<{}> turn is not closed", tag))?;
+        let text = &rest[open..open + len];
+        match tag {
+            "user" => turns.push(Turn::User(text)),
+            "assistant" => turns.push(Turn::Assistant(text)),
+            "output" => turns.push(Turn::Checkpoint(text)),
+            _ => {}
+        }
+        rest = &rest[open + len + tag.len() + 3..];
+    }
+    if !matches!(turns.first(), Some(Turn::User(_))) {
+        return Err("a conversation must start with a <user> turn".to_string());
+    }
+    if let Some(i) = turns.windows(2).position(|w| matches!(w[1], Turn::Checkpoint(_)) && !matches!(w[0], Turn::User(_))) {
+        return Err(format!("the <output> at turn {} does not follow a <user> turn", i + 2));
+    }
+    if !matches!(turns.last(), Some(Turn::Checkpoint(_))) {
+        return Err("a conversation must end with an <output> checkpoint".to_string());
+    }
+    Ok(Some(turns))
+}
+
+/// The tags of a `<tags>smoke, strict</tags>` section, which a test file that does not parse may still have.
+pub fn tags(contents: &str) -> Vec<&str> {
+    section(contents, "tags").map(|t| t.split(',').map(str::trim).filter(|t| !t.is_empty()).collect()).unwrap_or_default()
+}
+
+/// Where `open` first appears outside the `<example>` blocks, whose `<input>` and `<output>` are not the test's.
+pub fn find_tag(contents: &str, open: &str) -> Option<usize> {
+    let mut from = 0;
+    loop {
+        let at = from + contents[from..].find(open)?;
+        match contents[from..at].find("<example>").filter(|_| open != "<example>") {
+            Some(example) => from += example + contents[from + example..].find("</example>")? + "</example>".len(),
+            None => return Some(at),
+        }
+    }
+}
+
+/// The input and output of each `<example>` block, in file order.
+pub fn examples(contents: &str) -> Result<Vec<(&str, &str)>, String> {
+    sections(contents, "example").into_iter().enumerate().map(|(i, example)| {
+        let missing = |tag| format!("<example> {} has no <{}>", i + 1, tag);
+        Ok((section(example, "input").ok_or_else(|| missing("input"))?, section(example, "output").ok_or_else(|| missing("output"))?))
+    }).collect()
+}
+
+/// The text between the first `<tag>` and the following `</tag>`.
+pub fn section<'a>(contents: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = find_tag(contents, &open)? + open.len();
+    let len = contents[start..].find(&close)?;
+    Some(&contents[start..start + len])
+}
+
+/// The text of every `<tag>` section, in file order.
+pub fn sections<'a>(contents: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = find_tag(rest, &open).map(|i| i + open.len()) {
+        let Some(len) = rest[start..].find(&close) else { break };
+        found.push(&rest[start..start + len]);
+        rest = &rest[start + len + close.len()..];
+    }
+    found
+}
+
+/// Whether a test file holds a list of cases rather than one test, by its extension: `.yaml`, `.yml` or `.json`.
+pub fn is_structured(file: &str) -> bool {
+    Path::new(file).extension().and_then(|e| e.to_str()).is_some_and(|e| matches!(e, "yaml" | "yml" | "json"))
+}
+
+/// One case of a structured test file. `input` and `output` that are not strings are written out as JSON.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: Option<String>,
+    input: serde_yaml::Value,
+    #[serde(alias = "expected_output")]
+    output: Option<serde_yaml::Value>,
+    #[serde(default)]
+    tags: Vec<String>,
+    comparison: Option<String>,
+    extract: Option<String>,
+    format: Option<String>,
+    tools: Option<serde_yaml::Value>,
+    tool_choice: Option<String>,
+    rubric: Option<serde_yaml::Value>,
+    #[serde(default)]
+    images: Vec<String>,
+    schema: Option<String>,
+    validator: Option<String>,
+    structure: Option<String>,
+    gen_prompt: Option<String>,
+    test_prompt: Option<String>,
+    system: Option<String>,
+    judge_system: Option<String>,
+    #[serde(default)]
+    vars: BTreeMap<String, serde_yaml::Value>,
+    #[serde(default)]
+    examples: Vec<Example>,
+    #[serde(default)]
+    params: BTreeMap<String, serde_yaml::Value>,
+    skip: Option<String>,
+    xfail: Option<String>,
+}
+
+/// A few-shot example of a structured test file's case.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Example {
+    input: serde_yaml::Value,
+    output: serde_yaml::Value,
+}
+
+/// A structured test file that is a mapping rather than a bare list of cases, with tags for all its cases.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseFile {
+    #[serde(default)]
+    tags: Vec<String>,
+    cases: Vec<Case>,
+}
+
+/// The tests in the structured test file `file`, in file order: each named `file#name`, or `file#1` and so on for
+/// cases without a name, and written out in the sectioned format every test is read in.
+pub fn cases(file: &str, contents: &str) -> Result<Vec<(String, String)>, String> {
+    let value: serde_yaml::Value = serde_yaml::from_str(contents).map_err(|e| format!("invalid test file: {}", e))?;
+    let CaseFile { tags, cases } = match value {
+        serde_yaml::Value::Sequence(_) => CaseFile { tags: Vec::new(), cases: serde_yaml::from_value(value).map_err(|e| format!("invalid case: {}", e))? },
+        value => serde_yaml::from_value(value).map_err(|e| format!("invalid test file: {}", e))?,
+    };
+    if cases.is_empty() {
+        return Err("test file has no cases".to_string());
+    }
+    let mut names = BTreeSet::new();
+    let mut tests = Vec::new();
+    for (i, case) in cases.into_iter().enumerate() {
+        let name = format!("{}#{}", file, case.name.as_deref().unwrap_or(&(i + 1).to_string()));
+        if !names.insert(name.clone()) {
+            return Err(format!("more than one case is named {}", name));
+        }
+        let mut contents = format!("<input>{}</input>\n", text(case.input)?);
+        if let Some(output) = case.output {
+            contents.push_str(&format!("<output>{}</output>\n", text(output)?));
+        }
+        let case_tags: Vec<String> = tags.iter().chain(&case.tags).cloned().collect();
+        if !case_tags.is_empty() {
+            contents.push_str(&format!("<tags>{}</tags>\n", case_tags.join(", ")));
+        }
+        for image in &case.images {
+            contents.push_str(&format!("<image>{}</image>\n", image));
+        }
+        for example in case.examples {
+            contents.push_str(&format!("<example><input>{}</input><output>{}</output></example>\n", text(example.input)?, text(example.output)?));
+        }
+        if !case.vars.is_empty() {
+            let vars = serde_yaml::to_string(&case.vars).map_err(|e| format!("cannot write the vars of a case: {}", e))?;
+            contents.push_str(&format!("<vars>\n{}</vars>\n", vars));
+        }
+        if !case.params.is_empty() {
+            let params = serde_yaml::to_string(&case.params).map_err(|e| format!("cannot write the params of a case: {}", e))?;
+            contents.push_str(&format!("<params>\n{}</params>\n", params));
+        }
+        let tools = case.tools.map(text).transpose()?;
+        let rubric = case.rubric.map(text).transpose()?;
+        for (tag, value) in [("comparison", case.comparison), ("extract", case.extract), ("format", case.format), ("tools", tools), ("rubric", rubric), ("tool_choice", case.tool_choice), ("schema", case.schema), ("validator", case.validator), ("structure", case.structure), ("gen_prompt", case.gen_prompt), ("test_prompt", case.test_prompt), ("system", case.system), ("judge_system", case.judge_system), ("skip", case.skip), ("xfail", case.xfail)] {
+            if let Some(value) = value {
+                contents.push_str(&format!("<{0}>{1}</{0}>\n", tag, value));
+            }
+        }
+        tests.push((name, contents));
+    }
+    Ok(tests)
+}
+
+/// The contents of a test that could not be read, which fails at `matchinput` with `error`.
+pub fn invalid(error: &str) -> String {
+    format!("<invalid>{}</invalid>", error)
+}
+
+fn text(value: serde_yaml::Value) -> Result<String, String> {
+    match value {
+        serde_yaml::Value::String(s) => Ok(s),
+        value => serde_json::to_string_pretty(&value).map_err(|e| format!("cannot write a case as JSON: {}", e)),
+    }
+}
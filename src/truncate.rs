@@ -0,0 +1,92 @@
+use std::{fmt, str::FromStr};
+use crate::{test_case::TestCase, ErrorLocation, RunConfig, TestError};
+
+/// Tests with this tag fail at `inputtoolong` instead of being truncated.
+pub const NO_TRUNCATE_TAG: &str = "no-truncate";
+
+/// Which part of an input over INPUT_MAX_TOKENS is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TruncateStrategy {
+    Head,
+    Tail,
+    /// The first and last halves of the budget.
+    HeadTail,
+}
+
+impl FromStr for TruncateStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "head" => Ok(TruncateStrategy::Head),
+            "tail" => Ok(TruncateStrategy::Tail),
+            "head+tail" => Ok(TruncateStrategy::HeadTail),
+            other => Err(format!("unknown truncate strategy {:?}, expected head, tail or head+tail", other)),
+        }
+    }
+}
+
+impl fmt::Display for TruncateStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TruncateStrategy::Head => "head",
+            TruncateStrategy::Tail => "tail",
+            TruncateStrategy::HeadTail => "head+tail",
+        })
+    }
+}
+
+/// An input cut down to INPUT_MAX_TOKENS: the text sent in place of the test's input, and how many tokens it lost.
+#[derive(Clone, Debug)]
+pub struct Truncated {
+    pub text: String,
+    pub removed: usize,
+}
+
+/// The test's input cut down to INPUT_MAX_TOKENS, or `None` when it fits or no limit is set. A test tagged
+/// `no-truncate` fails instead.
+pub fn fit(case: &TestCase<'_>, config: &RunConfig) -> Result<Option<Truncated>, TestError> {
+    let Some(max) = config.input_max_tokens else { return Ok(None) };
+    let tokens = tokens(case.input);
+    if tokens.len() <= max {
+        return Ok(None);
+    }
+    if case.tags.contains(&NO_TRUNCATE_TAG) {
+        let err = format!("input has {} tokens, over INPUT_MAX_TOKENS {}, and the test is tagged {}", tokens.len(), max, NO_TRUNCATE_TAG);
+        return Err(TestError::because(ErrorLocation::InputTooLong, "", err));
+    }
+    let removed = tokens.len() - max;
+    let marker = format!("[...truncated {} tokens...]", removed);
+    let head = |n: usize| if n == 0 { "" } else { case.input[..tokens[n - 1].1].trim_end() };
+    let tail = |n: usize| if n == 0 { "" } else { case.input[tokens[tokens.len() - n].0..].trim_start() };
+    let text = match config.input_truncate_strategy {
+        TruncateStrategy::Head => format!("{}\n{}", head(max), marker),
+        TruncateStrategy::Tail => format!("{}\n{}", marker, tail(max)),
+        TruncateStrategy::HeadTail => format!("{}\n{}\n{}", head(max - max / 2), marker, tail(max / 2)),
+    };
+    Ok(Some(Truncated { text, removed }))
+}
+
+/// The byte ranges of the tokens in `text`, estimated without a tokenizer: each run of letters and digits is one token,
+/// and so is every other character that isn't whitespace. The model's own tokenizer may split long words further, so
+/// leave some headroom below the context window.
+fn tokens(text: &str) -> Vec<(usize, usize)> {
+    let mut tokens: Vec<(usize, usize)> = Vec::new();
+    let mut word = false;
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if c.is_alphanumeric() {
+            match tokens.last_mut() {
+                Some(last) if word => last.1 = end,
+                _ => tokens.push((i, end)),
+            }
+            word = true;
+        } else {
+            if !c.is_whitespace() {
+                tokens.push((i, end));
+            }
+            word = false;
+        }
+    }
+    tokens
+}
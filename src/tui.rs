@@ -0,0 +1,137 @@
+use std::io;
+use ratatui::{crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers}, layout::{Constraint, Layout, Rect}, style::{Color, Modifier, Style},
+    text::{Line, Span, Text}, widgets::{Block, List, ListItem, ListState, Paragraph, Wrap}, DefaultTerminal, Frame};
+use crate::{compare, error::AppError, review::{Session, Verdict}, test_case::TestCase, ErrorLocation};
+
+const HELP: &str = "↑/↓ select  a accept as actually correct  r reject  u clear  d diff  PgUp/PgDn scroll  q save and quit";
+
+/// What the interface shows besides the session: the selected failure, how far its panes are scrolled and whether
+/// they show the diff.
+struct View {
+    list: ListState,
+    scroll: u16,
+    diff: bool,
+}
+
+impl View {
+    fn select(&mut self, position: usize) {
+        self.list.select(Some(position));
+        self.scroll = 0;
+    }
+}
+
+/// Reviews a session's failures full screen: the failed tests on the left with their verdicts, and on the right the
+/// selected test's error and judge reason over its input, expected and generated output side by side, or the diff of
+/// the last two. The verdicts are recorded in the session as they are given; quitting leaves them to be exported.
+pub fn review(session: &mut Session) -> Result<(), AppError> {
+    let failed = session.failed();
+    if failed.is_empty() {
+        println!("No failed tests to review");
+        return Ok(());
+    }
+    let mut terminal = ratatui::init();
+    let reviewed = run(&mut terminal, session, &failed);
+    ratatui::restore();
+    reviewed.map_err(AppError::io("terminal"))
+}
+
+fn run(terminal: &mut DefaultTerminal, session: &mut Session, failed: &[usize]) -> io::Result<()> {
+    let mut view = View { list: ListState::default().with_selected(Some(0)), scroll: 0, diff: false };
+    let last = failed.len() - 1;
+    loop {
+        terminal.draw(|frame| draw(frame, session, failed, &mut view))?;
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = view.list.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => view.select((selected + 1).min(last)),
+            KeyCode::Up | KeyCode::Char('k') => view.select(selected.saturating_sub(1)),
+            KeyCode::Home => view.select(0),
+            KeyCode::End => view.select(last),
+            KeyCode::Char('a') | KeyCode::Char('r') => {
+                session.set(failed[selected], Some(if key.code == KeyCode::Char('a') { Verdict::Accepted } else { Verdict::Rejected }));
+                view.select((selected + 1).min(last));
+            }
+            KeyCode::Char('u') => session.set(failed[selected], None),
+            KeyCode::Char('d') => view.diff = !view.diff,
+            KeyCode::PageDown | KeyCode::Char(' ') => view.scroll = view.scroll.saturating_add(10),
+            KeyCode::PageUp => view.scroll = view.scroll.saturating_sub(10),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, session: &Session, failed: &[usize], view: &mut View) {
+    let [main, help] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [list_area, detail] = Layout::horizontal([Constraint::Percentage(25), Constraint::Min(0)]).areas(main);
+    let reviewed = failed.iter().filter(|&&i| session.verdict(i).is_some()).count();
+    let items: Vec<ListItem> = failed.iter().map(|&i| {
+        let (symbol, _, style) = mark(session.verdict(i));
+        ListItem::new(Line::from(vec![Span::styled(format!("{} ", symbol), style), Span::raw(session.results[i].name.as_str())]))
+    }).collect();
+    let list = List::new(items)
+        .block(Block::bordered().title(format!(" Failed tests, {} of {} reviewed ", reviewed, failed.len())))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, list_area, &mut view.list);
+    frame.render_widget(Paragraph::new(HELP).style(Style::new().fg(Color::DarkGray)), help);
+
+    let index = failed[view.list.selected().unwrap_or(0)];
+    let result = &session.results[index];
+    let mut header = Vec::new();
+    if let Some(error) = &result.error {
+        header.push(Line::from(vec![Span::styled("Error: ", Style::new().add_modifier(Modifier::BOLD)), Span::raw(error.as_str())]));
+    }
+    if let Some(reason) = result.reason.as_deref().filter(|r| !r.is_empty()) {
+        header.push(Line::from(vec![Span::styled("Judge: ", Style::new().add_modifier(Modifier::BOLD)), Span::raw(reason)]));
+    }
+    let (symbol, word, style) = mark(session.verdict(index));
+    let verdict = if word.is_empty() { String::new() } else { format!("{} {} ", symbol, word) };
+    let title = Line::from(vec![Span::raw(format!(" {} failed at {} ", result.name, result.location.map_or("", ErrorLocation::name))), Span::styled(verdict, style)]);
+    let height = (header.len() as u16 * 2 + 2).min(detail.height / 3);
+    let [header_area, panes] = Layout::vertical([Constraint::Length(height), Constraint::Min(0)]).areas(detail);
+    frame.render_widget(Paragraph::new(header).wrap(Wrap { trim: false }).block(Block::bordered().title(title)), header_area);
+
+    let case = TestCase::parse(&result.name, &result.input).ok();
+    let shown = |text: &str| compare::pretty(text).unwrap_or_else(|| text.trim().to_string());
+    if view.diff {
+        let lines: Vec<Line> = match &case {
+            Some(case) => compare::json_diff(case.expected_output, &result.output).lines().map(|line| {
+                let color = match line.chars().next() {
+                    Some('+') if !line.starts_with("+++") => Color::Green,
+                    Some('-') if !line.starts_with("---") => Color::Red,
+                    Some('@') => Color::Cyan,
+                    _ => Color::Reset,
+                };
+                Line::styled(line.to_string(), Style::new().fg(color))
+            }).collect(),
+            None => vec![Line::raw("The test file does not parse, so there is no expected output to diff against.")],
+        };
+        pane(frame, panes, " Diff, expected to generated ", Text::from(lines), view.scroll);
+        return;
+    }
+    let [input, expected, generated] = Layout::horizontal([Constraint::Ratio(1, 3); 3]).areas(panes);
+    let (input_text, expected_text) = match &case {
+        Some(case) => (case.input.trim().to_string(), shown(case.expected_output)),
+        None => (result.input.trim().to_string(), String::new()),
+    };
+    pane(frame, input, if case.is_some() { " Input " } else { " Test file " }, Text::raw(input_text), view.scroll);
+    pane(frame, expected, " Expected ", Text::raw(expected_text), view.scroll);
+    pane(frame, generated, " Generated ", Text::raw(shown(&result.output)), view.scroll);
+}
+
+fn pane(frame: &mut Frame, area: Rect, title: &str, text: Text, scroll: u16) {
+    frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }).scroll((scroll, 0)).block(Block::bordered().title(title)), area);
+}
+
+/// The mark of a verdict in the list, its name and its color.
+fn mark(verdict: Option<Verdict>) -> (&'static str, &'static str, Style) {
+    match verdict {
+        Some(Verdict::Accepted) => ("✓", "accepted", Style::new().fg(Color::Green)),
+        Some(Verdict::Rejected) => ("✗", "rejected", Style::new().fg(Color::Red)),
+        None => (" ", "", Style::new()),
+    }
+}
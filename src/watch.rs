@@ -0,0 +1,101 @@
+use std::{collections::{BTreeMap, BTreeSet}, env, fs, path::{Component, Path, PathBuf}, time::SystemTime};
+use crate::{config::RunConfig, snapshot, test_case::TestCase, test_file, TestInfo};
+
+/// The settings naming a file that every test depends on.
+const SHARED_FILES: [&str; 13] = [
+    "GEN_PROMPT", "TEST_PROMPT", "GEN_SYSTEM_PROMPT", "JUDGE_SYSTEM_PROMPT", "STRUCTURE_TEST", "STRUCTURE_SCHEMA", "HOOKS_SCRIPT",
+    "RUBRIC", "VALIDATOR", "GEN_TOOL_SCHEMA", "RESPONSE_SCHEMA", "QUARANTINE_FILE", "ACCEPTED_OVERRIDES",
+];
+
+/// The modification time and size of every watched file: those in TEST_DIR and its subdirectories, and the shared
+/// files the settings name, LUA_LIBS included.
+#[derive(Default)]
+pub struct Files(BTreeMap<PathBuf, (Option<SystemTime>, u64)>);
+
+impl Files {
+    /// Reads the paths from the environment rather than a [`RunConfig`], so a change can still be seen while the
+    /// settings do not load.
+    pub fn scan() -> Files {
+        let mut files = Files::default();
+        if let Ok(test_dir) = env::var("TEST_DIR") {
+            files.scan_dir(Path::new(&test_dir));
+        }
+        for path in shared() {
+            files.stat(path);
+        }
+        files
+    }
+
+    fn scan_dir(&mut self, dir: &Path) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    self.scan_dir(&path);
+                }
+            } else if !snapshot::is_snapshot(&entry.file_name().to_string_lossy()) {
+                // A run writes the snapshots, which must not start the next one.
+                self.stat(path);
+            }
+        }
+    }
+
+    fn stat(&mut self, path: PathBuf) {
+        if let Ok(metadata) = fs::metadata(&path) {
+            self.0.insert(normalize(&path), (metadata.modified().ok(), metadata.len()));
+        }
+    }
+
+    /// The files added, removed or modified since `earlier`.
+    pub fn changed(&self, earlier: &Files) -> BTreeSet<PathBuf> {
+        let modified = self.0.iter().filter(|(path, stat)| earlier.0.get(*path) != Some(stat)).map(|(path, _)| path.clone());
+        let removed = earlier.0.keys().filter(|path| !self.0.contains_key(*path)).cloned();
+        modified.chain(removed).collect()
+    }
+}
+
+fn shared() -> Vec<PathBuf> {
+    let lua_libs = env::var_os("LUA_LIBS").map(|libs| env::split_paths(&libs).collect::<Vec<_>>()).unwrap_or_default();
+    SHARED_FILES.iter().filter_map(|var| env::var(var).ok()).map(PathBuf::from).chain(lua_libs).collect()
+}
+
+/// The names of the tests `changed` affects: a test whose file or one of whose attachments changed. `None` is every
+/// test, for a change to a shared file or to a file in TEST_DIR that is neither a test nor attached to one, such as a
+/// Lua module a structure test loads.
+pub fn affected(config: &RunConfig, tests: &[TestInfo], changed: &BTreeSet<PathBuf>) -> Option<BTreeSet<String>> {
+    let shared: BTreeSet<PathBuf> = shared().iter().map(|path| normalize(path)).collect();
+    if changed.iter().any(|path| shared.contains(path)) {
+        return None;
+    }
+    let mut affected = BTreeSet::new();
+    let mut explained = BTreeSet::new();
+    for test in tests {
+        let mut files = vec![normalize(&Path::new(&config.test_dir).join(test_file(&test.name)))];
+        if let Ok(case) = TestCase::parse(&test.name, &test.contents) {
+            files.extend(case.attachments(config).iter().map(|path| normalize(path)));
+        }
+        for file in files.into_iter().filter(|file| changed.contains(file)) {
+            affected.insert(test.name.clone());
+            explained.insert(file);
+        }
+    }
+    // A test file that was removed explains itself, as there is no test left to run.
+    let removed = |path: &PathBuf| !path.exists();
+    if changed.iter().all(|path| explained.contains(path) || removed(path)) { Some(affected) } else { None }
+}
+
+/// `path` with its `.` components dropped and each `..` taking off the component before it, so the same file is
+/// named the same however it was reached.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,38 @@
+//! A project in a temporary directory for the integration tests.
+// Each test file compiles its own copy and uses only some of it.
+#![allow(dead_code)]
+
+use std::{env, fs, path::PathBuf, process, sync::Mutex};
+use ai_test_util::RunConfig;
+
+/// A test's input and expected output.
+pub const TEST: &str = "<input>one item</input>\n<output>{\"items\": [1]}</output>\n";
+/// Rejects candidates without a non-empty `items` array.
+const STRUCTURE_TEST: &str = "function test(raw)\n    local v = json.decode(raw)\n    local items = assert_field(v, \"items\", \"array\")\n    if #items == 0 then fail(\"no items\") end\n    return true\nend\n";
+
+/// RunConfig reads the environment, which the tests share.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// The directory of the project named `name`, emptied.
+fn project(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ai_test_util-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("tests")).unwrap();
+    fs::create_dir_all(dir.join("results")).unwrap();
+    dir
+}
+
+/// A config for a new project named `name`, with an empty `tests` directory and the structure test above.
+pub fn config(name: &str) -> RunConfig {
+    let dir = project(name);
+    fs::write(dir.join("gen.txt"), "Produce {{ description }}").unwrap();
+    fs::write(dir.join("test.txt"), "Expected {{ baseline }}, got {{ input }}. Answer true or false.").unwrap();
+    fs::write(dir.join("structure.lua"), STRUCTURE_TEST).unwrap();
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let path = |file: &str| dir.join(file).display().to_string();
+    for (key, value) in [("model", "scripted".to_string()), ("TEST_DIR", path("tests")), ("RESULTS_DIR", path("results")),
+        ("GEN_PROMPT", path("gen.txt")), ("TEST_PROMPT", path("test.txt")), ("STRUCTURE_TEST", path("structure.lua")), ("MAX_RETRIES", "0".to_string())] {
+        env::set_var(key, value);
+    }
+    RunConfig::from_env().unwrap()
+}
@@ -0,0 +1,81 @@
+//! Finding the tests in TEST_DIR: entries that cannot be read are skipped and reported, and the rest still run.
+#![cfg(unix)]
+
+mod common;
+
+use std::{fs, os::unix::fs::symlink, path::Path};
+use ai_test_util::{discover, run_suite, SkipKind, Status, TestInfo};
+use common::{config, TEST};
+
+fn names(tests: &[TestInfo]) -> Vec<&str> {
+    tests.iter().map(|t| t.name.as_str()).collect()
+}
+
+#[tokio::test]
+async fn a_dangling_symlink_is_skipped_and_the_run_continues() {
+    let mut config = config("dangling_symlink");
+    let dir = Path::new(&config.test_dir);
+    fs::write(dir.join("a.txt"), TEST).unwrap();
+    fs::write(dir.join("c.txt"), TEST).unwrap();
+    symlink(dir.join("missing.txt"), dir.join("b.txt")).unwrap();
+
+    let (tests, skipped) = discover(&config).unwrap();
+    assert_eq!(names(&tests), ["a.txt", "c.txt"]);
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].kind, SkipKind::BrokenSymlink);
+    assert!(skipped[0].path.ends_with("b.txt"), "{}", skipped[0].path);
+
+    config.dry_run = true;
+    let report = run_suite(&config, |_| async { Ok(()) }).await.unwrap();
+    let statuses: Vec<(&str, Status)> = report.results.iter().map(|r| (r.name.as_str(), r.status)).collect();
+    assert_eq!(statuses, [("a.txt", Status::Passed), ("c.txt", Status::Passed)]);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].kind, SkipKind::BrokenSymlink);
+}
+
+#[test]
+fn a_symlinked_test_file_reads_its_target() {
+    let config = config("symlinked_file");
+    let dir = Path::new(&config.test_dir);
+    let corpus = dir.parent().unwrap().join("corpus");
+    fs::create_dir_all(&corpus).unwrap();
+    let shared = TEST.replace("one item", "a shared item");
+    fs::write(corpus.join("shared.txt"), &shared).unwrap();
+    symlink(corpus.join("shared.txt"), dir.join("linked.txt")).unwrap();
+
+    let (tests, skipped) = discover(&config).unwrap();
+    assert_eq!(names(&tests), ["linked.txt"]);
+    assert_eq!(tests[0].contents, shared);
+    assert!(skipped.is_empty());
+}
+
+#[test]
+fn follow_symlinks_off_skips_symlinked_files() {
+    let mut config = config("symlinks_off");
+    config.follow_symlinks = false;
+    let dir = Path::new(&config.test_dir);
+    fs::write(dir.join("a.txt"), TEST).unwrap();
+    symlink(dir.join("a.txt"), dir.join("b.txt")).unwrap();
+
+    let (tests, skipped) = discover(&config).unwrap();
+    assert_eq!(names(&tests), ["a.txt"]);
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].kind, SkipKind::Symlink);
+    assert!(skipped[0].path.ends_with("b.txt"), "{}", skipped[0].path);
+}
+
+#[test]
+fn a_symlinked_directory_cycle_does_not_loop() {
+    let config = config("directory_cycle");
+    let dir = Path::new(&config.test_dir);
+    fs::create_dir_all(dir.join("suite")).unwrap();
+    fs::write(dir.join("suite/a.txt"), TEST).unwrap();
+    symlink(dir, dir.join("suite/back")).unwrap();
+    symlink(dir.join("suite"), dir.join("again")).unwrap();
+
+    let (tests, skipped) = discover(&config).unwrap();
+    assert_eq!(names(&tests), ["suite/a.txt"]);
+    let mut kinds: Vec<(String, SkipKind)> = skipped.iter().map(|s| (Path::new(&s.path).file_name().unwrap().to_string_lossy().into_owned(), s.kind)).collect();
+    kinds.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(kinds, [("again".to_string(), SkipKind::Symlink), ("back".to_string(), SkipKind::Symlink)]);
+}
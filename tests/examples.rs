@@ -0,0 +1,51 @@
+//! The example structure tests in `examples/`, loaded as STRUCTURE_TEST and run on candidates.
+
+mod common;
+
+use std::fs;
+use ai_test_util::{structure::{self, LuaSource, Structured}, RunConfig, TestCase};
+
+/// A config whose STRUCTURE_TEST is `examples/<example>`.
+fn with_example(example: &str) -> RunConfig {
+    let mut config = common::config(&format!("example_{}", example.trim_end_matches(".lua")));
+    let path = format!("{}/examples/{}", env!("CARGO_MANIFEST_DIR"), example);
+    config.structure_test = Some(LuaSource { source: fs::read_to_string(&path).unwrap(), path });
+    structure::verify(&config).unwrap();
+    config
+}
+
+/// The structure test's verdict on `candidate` for the test `name` with `contents`: `Ok` or the reason it failed.
+fn check(config: &RunConfig, name: &str, contents: &str, candidate: &str) -> Result<(), String> {
+    let case = TestCase::parse(name, contents).unwrap();
+    match structure::first_passing(&[candidate], &case, config).unwrap() {
+        Structured::Passed { .. } => Ok(()),
+        Structured::Failed { err, .. } => Err(err.unwrap_or_default()),
+        Structured::NormalizeFailed { err, .. } => panic!("the example has no normalize: {}", err),
+    }
+}
+
+#[test]
+fn count_items_wants_one_item_per_description_line() {
+    let config = with_example("count_items.lua");
+    let test = "<input>apples\npears\n\nplums\n</input>\n<output>{\"items\": [\"apple\", \"pear\", \"plum\"]}</output>\n";
+    assert_eq!(check(&config, "fruit.txt", test, r#"{"items": ["apple", "pear", "plum"]}"#), Ok(()));
+    assert_eq!(check(&config, "fruit.txt", test, r#"{"items": ["apple", "pear"]}"#), Err("structure test failed: expected 3 items, one per description line, got 2".to_string()));
+    assert_eq!(check(&config, "fruit.txt", test, r#"{"fruit": []}"#), Err("structure test failed: missing array field 'items'".to_string()));
+    assert_eq!(check(&config, "fruit.txt", test, r#"["apple", "pear", "plum"]"#), Err("structure test failed: expected a JSON object".to_string()));
+}
+
+#[test]
+fn strict_tags_rejects_null_fields_only_in_tests_tagged_strict() {
+    let config = with_example("strict_tags.lua");
+    let strict = "<tags>smoke, strict</tags>\n<input>a customer</input>\n<output>{\"name\": \"Ada\"}</output>\n";
+    let lenient = "<tags>smoke</tags>\n<input>a customer</input>\n<output>{\"name\": \"Ada\"}</output>\n";
+    let candidate = r#"{"name": "Ada", "email": null}"#;
+    assert_eq!(check(&config, "lenient.txt", lenient, candidate), Ok(()));
+    assert_eq!(check(&config, "orders/strict.txt", strict, candidate),
+        Err("structure test failed: orders/strict.txt: strict tests may not contain null fields (email)".to_string()));
+    // The reason names whichever test is being checked, so nothing carries over from the call before.
+    assert_eq!(check(&config, "other.txt", strict, candidate),
+        Err("structure test failed: other.txt: strict tests may not contain null fields (email)".to_string()));
+    assert_eq!(check(&config, "lenient.txt", lenient, candidate), Ok(()));
+    assert_eq!(check(&config, "orders/strict.txt", strict, r#"{"name": "Ada"}"#), Ok(()));
+}
@@ -0,0 +1,181 @@
+//! The pipeline end to end, from the generation request through extraction, the Lua structure test and the judge, with
+//! a [`ScriptedBackend`] in place of the model API.
+
+mod common;
+
+use std::{collections::VecDeque, fs, sync::Mutex};
+use ai_test_util::{backend::{ChatBackend, ScriptedBackend, Stream}, error::AppError, generation::{GenChoice, Generation}, judge::JudgeFormat, run_single, run_suite, structure::LuaSource, ErrorLocation, Reporter, RunConfig, Status, TestInfo, TestResult, TestRunner};
+use async_openai::{error::OpenAIError, types::{CreateChatCompletionRequest, CreateEmbeddingRequest, FinishReason}};
+use futures::{future::BoxFuture, FutureExt};
+use common::{config, TEST};
+
+async fn run(name: &str, backend: impl ChatBackend) -> TestResult {
+    run_with(name, backend, |_| ()).await
+}
+
+async fn run_with(name: &str, backend: impl ChatBackend, configure: impl FnOnce(&mut RunConfig)) -> TestResult {
+    let mut config = config(name);
+    configure(&mut config);
+    let test = TestInfo { index: 0, name: format!("{}.txt", name), contents: TEST.to_string() };
+    run_single(test, &config, &backend).await.unwrap()
+}
+
+/// Replies with `generation` to the generation request and `verdict` to the judge.
+fn scripted(generation: &str, verdict: &str) -> ScriptedBackend {
+    ScriptedBackend::new([generation.to_string(), verdict.to_string()], verdict.to_string())
+}
+
+#[tokio::test]
+async fn passes_a_valid_output_the_judge_accepts() {
+    let result = run("happy", scripted("Here it is: {\"items\": [1]}", "true")).await;
+    assert_eq!(result.status, Status::Passed, "{:?}", result.error);
+    assert_eq!(result.location, None);
+    assert_eq!(result.output, "{\"items\": [1]}");
+}
+
+#[tokio::test]
+async fn fails_an_output_without_json() {
+    let result = run("missing_json", scripted("I could not find any items.", "true")).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::MatchJson));
+}
+
+#[tokio::test]
+async fn fails_an_output_the_structure_test_rejects() {
+    let result = run("lua_rejection", scripted("{\"items\": []}", "true")).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::Parse));
+    assert!(result.error.as_deref().is_some_and(|e| e.contains("no items")), "{:?}", result.error);
+}
+
+#[tokio::test]
+async fn fails_an_output_the_judge_rejects() {
+    let result = run("judge_false", scripted("{\"items\": [2]}", "false")).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::Test));
+}
+
+#[tokio::test]
+async fn a_boolean_json_judge_is_decided_by_its_verdict_not_its_score() {
+    let json = |config: &mut RunConfig| config.judge_format = JudgeFormat::Json;
+    let result = run_with("json_false", scripted("{\"items\": [2]}", r#"{"verdict": false, "score": 90, "reason": "the item differs"}"#), json).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::Test));
+    assert!(!result.error.as_deref().unwrap_or("").contains("PASS_THRESHOLD"), "{:?}", result.error);
+    assert_eq!((result.score, result.grade), (Some(90.0), Some(0.0)));
+    let result = run_with("json_true", scripted("{\"items\": [1]}", r#"{"verdict": true, "score": 10}"#), json).await;
+    assert_eq!(result.status, Status::Passed, "{:?}", result.error);
+    assert_eq!((result.score, result.grade), (Some(10.0), Some(1.0)));
+}
+
+#[tokio::test]
+async fn fails_a_response_without_choices() {
+    let result = run("empty_choices", scripted("{\"items\": [1]}", "true").without_choices()).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::EmptyResponse));
+}
+
+#[tokio::test]
+async fn fails_a_truncated_output() {
+    let result = run("truncated", scripted("{\"items\": [1", "true").finishing(FinishReason::Length)).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::Truncated));
+    assert_eq!(result.output, "{\"items\": [1");
+}
+
+#[tokio::test]
+async fn dry_run_suite_passes_every_test_in_order() {
+    let mut config = config("dry_run");
+    config.dry_run = true;
+    fs::write(format!("{}/b.txt", config.test_dir), TEST).unwrap();
+    fs::write(format!("{}/a.txt", config.test_dir), TEST.replace("[1]", "[1, 2]")).unwrap();
+    fs::write(format!("{}/c.txt", config.test_dir), format!("<skip>not yet</skip>\n{}", TEST)).unwrap();
+    let report = run_suite(&config, |_| async { Ok(()) }).await.unwrap();
+    let statuses: Vec<(&str, Status)> = report.results.iter().map(|r| (r.name.as_str(), r.status)).collect();
+    assert_eq!(statuses, [("a.txt", Status::Passed), ("b.txt", Status::Passed), ("c.txt", Status::Skipped)]);
+}
+
+#[tokio::test]
+async fn an_invalid_regex_fails_the_test_before_any_request() {
+    let config = config("invalid_regex");
+    let contents = "<comparison>regex</comparison>\n<input>one item</input>\n<output>\"items\"\n[1\n</output>\n";
+    let test = TestInfo { index: 0, name: "invalid_regex.txt".to_string(), contents: contents.to_string() };
+    // Any request would find no response and panic.
+    let result = run_single(test, &config, &Responses::new([])).await.unwrap();
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::MatchInput));
+    assert!(result.error.as_deref().unwrap().starts_with("invalid pattern \"[1\""), "{:?}", result.error);
+}
+
+/// The names of the results reported.
+struct Names(Vec<String>);
+
+impl Reporter for Names {
+    fn report(&mut self, result: &TestResult) -> Result<(), AppError> {
+        self.0.push(result.name.clone());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_runner_returns_an_after_all_error_once_every_result_is_reported() {
+    let mut config = config("after_all");
+    config.dry_run = true;
+    config.hooks_script = Some(LuaSource { path: "hooks.lua".to_string(), source: "function after_all(summary) error(\"only \" .. summary.passed .. \" passed\") end".to_string() });
+    fs::write(format!("{}/a.txt", config.test_dir), TEST).unwrap();
+    let mut names = Names(Vec::new());
+    let Err(error) = TestRunner::new(config).run(&mut names).await else { panic!("after_all failed") };
+    assert!(error.to_string().contains("only 1 passed"), "{}", error);
+    assert_eq!(names.0, ["a.txt"]);
+}
+
+/// Answers chat requests with the given responses as they are, in order.
+struct Responses(Mutex<VecDeque<Generation>>);
+
+impl Responses {
+    fn new(responses: impl IntoIterator<Item = Vec<GenChoice>>) -> Responses {
+        Responses(Mutex::new(responses.into_iter().map(|choices| Generation { choices, usage: None, id: None, retries: 0, first_token: None, fallback: None }).collect()))
+    }
+}
+
+impl ChatBackend for Responses {
+    fn complete<'a>(&'a self, _req: CreateChatCompletionRequest, _stream: Option<Stream<'a>>) -> BoxFuture<'a, Result<Generation, OpenAIError>> {
+        let response = self.0.lock().unwrap().pop_front().expect("a response for every request");
+        async move { Ok(response) }.boxed()
+    }
+
+    fn embed(&self, _req: CreateEmbeddingRequest) -> BoxFuture<'_, Result<Vec<Vec<f32>>, OpenAIError>> {
+        async move { Ok(Vec::new()) }.boxed()
+    }
+}
+
+fn reply(content: &str) -> GenChoice {
+    GenChoice { content: Some(content.to_string()), finish_reason: Some(FinishReason::Stop), ..Default::default() }
+}
+
+#[tokio::test]
+async fn fails_a_generation_response_with_no_choices_instead_of_panicking() {
+    let result = run("no_choices", Responses::new([vec![]])).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::EmptyResponse));
+    assert_eq!(result.error.as_deref(), Some("response contained no choices"));
+}
+
+#[tokio::test]
+async fn fails_a_judge_response_with_no_choices_instead_of_panicking() {
+    let result = run("judge_no_choices", Responses::new([vec![reply("{\"items\": [1]}")], vec![]])).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::EmptyResponse));
+    assert_eq!(result.error.as_deref(), Some("judge response contained no choices"));
+}
+
+#[tokio::test]
+async fn fails_a_choice_without_content() {
+    let refused = GenChoice { refusal: Some("I can't help with that.".to_string()), finish_reason: Some(FinishReason::Stop), ..Default::default() };
+    let result = run("refusal", Responses::new([vec![refused]])).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::Generation));
+    let result = run("no_content", Responses::new([vec![GenChoice::default()]])).await;
+    assert_eq!(result.status, Status::Failed);
+    assert_eq!(result.location, Some(ErrorLocation::EmptyResponse));
+}